@@ -0,0 +1,353 @@
+use crate::transform::ContentTransform;
+use ego_tree::NodeId;
+use html5ever::QualName;
+use scraper::node::Element;
+use scraper::{ElementRef, Html, Node};
+
+/// Elements chapters can have, anything else is unwrapped (its content is
+/// kept) or, for `REMOVED_ELEMENTS`, removed
+const ALLOWED_ELEMENTS: [&str; 76] = [
+    "html",
+    "head",
+    "title",
+    "meta",
+    "link",
+    "style",
+    "body",
+    "a",
+    "abbr",
+    "address",
+    "article",
+    "aside",
+    "b",
+    "bdi",
+    "bdo",
+    "blockquote",
+    "br",
+    "caption",
+    "cite",
+    "code",
+    "col",
+    "colgroup",
+    "dd",
+    "del",
+    "dfn",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "main",
+    "mark",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "q",
+    "rb",
+    "rp",
+    "rt",
+    "rtc",
+    "ruby",
+    "s",
+    "section",
+    "small",
+    "span",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "time",
+    "tr",
+    "u",
+    "ul",
+    "var",
+    "wbr",
+];
+
+/// Elements that run code, embed other pages or media, or take input. They're
+/// removed along with everything in them.
+const REMOVED_ELEMENTS: [&str; 22] = [
+    "script", "noscript", "iframe", "frame", "frameset", "object", "embed", "applet", "param",
+    "form", "input", "button", "select", "textarea", "template", "canvas", "audio", "video",
+    "source", "track", "svg", "base",
+];
+
+/// Attributes every allowed element can have
+const GLOBAL_ATTRIBUTES: [&str; 10] = [
+    "id",
+    "class",
+    "title",
+    "lang",
+    "xml:lang",
+    "dir",
+    "style",
+    "epub:type",
+    "xmlns",
+    "xmlns:epub",
+];
+
+/// Attributes only some elements can have, as (element, attribute). `link`
+/// is only kept for the chapter's own stylesheet, see `is_local_stylesheet`.
+const ELEMENT_ATTRIBUTES: [(&str, &str); 25] = [
+    ("a", "href"),
+    ("img", "src"),
+    ("img", "alt"),
+    ("img", "width"),
+    ("img", "height"),
+    ("td", "colspan"),
+    ("td", "rowspan"),
+    ("th", "colspan"),
+    ("th", "rowspan"),
+    ("th", "scope"),
+    ("col", "span"),
+    ("colgroup", "span"),
+    ("ol", "start"),
+    ("ol", "type"),
+    ("ol", "reversed"),
+    ("li", "value"),
+    ("blockquote", "cite"),
+    ("q", "cite"),
+    ("time", "datetime"),
+    ("meta", "charset"),
+    ("meta", "name"),
+    ("meta", "content"),
+    ("link", "rel"),
+    ("link", "href"),
+    ("link", "type"),
+];
+
+/// What's done to an element that doesn't pass
+enum Action {
+    Remove,
+    Unwrap,
+    /// Takes out the attributes, and replaces the `style` attribute when
+    /// anything in it had to go
+    FixAttributes {
+        removed: Vec<QualName>,
+        style: Option<String>,
+    },
+}
+
+/// Only lets through the elements and attributes books need, so scripts,
+/// embedded pages, event handlers, redirects, remote stylesheets and tracking
+/// pixels that made it past the strip rules don't end up in the book. Some readers refuse to open books
+/// with them. Elements that aren't known are unwrapped rather than removed, so
+/// their text is kept.
+pub struct Allowlist;
+
+impl ContentTransform for Allowlist {
+    fn apply(&self, html: &str) -> String {
+        let mut document = Html::parse_document(html);
+        let actions: Vec<(NodeId, Action)> = document
+            .tree
+            .root()
+            .descendants()
+            .filter_map(ElementRef::wrap)
+            .filter_map(|element| check(element).map(|action| (element.id(), action)))
+            .collect();
+        if actions.is_empty() {
+            return html.to_string();
+        }
+
+        for (id, action) in actions {
+            match action {
+                Action::Remove => {
+                    if let Some(mut node) = document.tree.get_mut(id) {
+                        node.detach();
+                    }
+                }
+                Action::Unwrap => unwrap(&mut document, id),
+                Action::FixAttributes { removed, style } => {
+                    if let Some(mut node) = document.tree.get_mut(id) {
+                        if let Node::Element(element) = node.value() {
+                            for name in &removed {
+                                element.attrs.remove(name);
+                            }
+                            match style {
+                                Some(style) if style.is_empty() => {
+                                    element.attrs.retain(|name, _| &*name.local != "style")
+                                }
+                                Some(style) => {
+                                    for (name, value) in element.attrs.iter_mut() {
+                                        if &*name.local == "style" {
+                                            *value = style.as_str().into();
+                                        }
+                                    }
+                                }
+                                None => {}
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        document.root_element().html()
+    }
+}
+
+/// What has to be done to the element for it to pass, `None` when it does
+fn check(element_ref: ElementRef) -> Option<Action> {
+    let element = element_ref.value();
+    let name = element.name();
+    let removed = REMOVED_ELEMENTS.contains(&name)
+        || (name == "img" && is_tracking_pixel(element))
+        || (name == "link" && !is_local_stylesheet(element))
+        || (name == "style" && loads_anything(&element_ref.text().collect::<String>()));
+    if removed {
+        return Some(Action::Remove);
+    }
+    if !ALLOWED_ELEMENTS.contains(&name) {
+        return Some(Action::Unwrap);
+    }
+    let removed: Vec<QualName> = element
+        .attrs
+        .iter()
+        .filter(|(attribute, value)| {
+            let attribute = &*attribute.local;
+            let allowed = GLOBAL_ATTRIBUTES.contains(&attribute)
+                || ELEMENT_ATTRIBUTES.contains(&(name, attribute));
+            !allowed || (is_url_attribute(attribute) && is_script_url(value))
+        })
+        .map(|(attribute, _)| attribute.clone())
+        .collect();
+    let style = element.attr("style").and_then(without_loads);
+    if removed.is_empty() && style.is_none() {
+        None
+    } else {
+        Some(Action::FixAttributes { removed, style })
+    }
+}
+
+/// Puts the element's children where it was, and takes it out
+fn unwrap(document: &mut Html, id: NodeId) {
+    let children: Vec<NodeId> = match document.tree.get(id) {
+        Some(node) => node.children().map(|child| child.id()).collect(),
+        None => return,
+    };
+    for child in children {
+        if let Some(mut child) = document.tree.get_mut(child) {
+            child.detach();
+        }
+        if let Some(mut node) = document.tree.get_mut(id) {
+            if node.parent().is_some() {
+                node.insert_id_before(child);
+            }
+        }
+    }
+    if let Some(mut node) = document.tree.get_mut(id) {
+        node.detach();
+    }
+}
+
+/// Images no bigger than a pixel are there to tell the site the chapter was
+/// read, not to be seen
+fn is_tracking_pixel(element: &Element) -> bool {
+    let size = |dimension: &str| {
+        let from_style = element.attr("style").and_then(|style| {
+            style
+                .split(';')
+                .filter_map(|declaration| declaration.split_once(':'))
+                .find(|(property, _)| property.trim().eq_ignore_ascii_case(dimension))
+                .map(|(_, value)| value)
+        });
+        element.attr(dimension).or(from_style).and_then(|value| {
+            let value = value.trim();
+            let digits = value.len() - value.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            value[..digits].parse::<u32>().ok()
+        })
+    };
+    // A pixel wide is enough, the height is often left out
+    matches!(size("width"), Some(0..=1)) || matches!(size("height"), Some(0..=1))
+}
+
+/// A `<link>` to a stylesheet in the book, like the one chapters are made
+/// with. Anything else (remote stylesheets, prefetching) would load from the
+/// site when the chapter is opened.
+fn is_local_stylesheet(element: &Element) -> bool {
+    let is_stylesheet = element
+        .attr("rel")
+        .into_iter()
+        .flat_map(str::split_whitespace)
+        .any(|rel| rel.eq_ignore_ascii_case("stylesheet"));
+    let is_local = match element.attr("href").map(str::trim) {
+        Some(href) => {
+            !href.starts_with("//") && !href.split('/').next().unwrap_or_default().contains(':')
+        }
+        None => false,
+    };
+    is_stylesheet && is_local
+}
+
+/// Whether CSS loads something when it's applied (`url(...)`, `@import`), or
+/// runs code in old readers (`expression(...)`)
+fn loads_anything(css: &str) -> bool {
+    // Escapes and spaces can break the words up, `u\rl(` is still `url(`
+    let css: String = css
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '\\')
+        .collect::<String>()
+        .to_ascii_lowercase();
+    css.contains("url(")
+        || css.contains("image-set(")
+        || css.contains("@import")
+        || css.contains("expression(")
+}
+
+/// The style attribute without the declarations that load anything, `None`
+/// when none do
+fn without_loads(style: &str) -> Option<String> {
+    if !loads_anything(style) {
+        return None;
+    }
+    let declarations: Vec<&str> = style
+        .split(';')
+        .filter(|declaration| !loads_anything(declaration))
+        .map(str::trim)
+        .filter(|declaration| !declaration.is_empty())
+        .collect();
+    Some(declarations.join("; "))
+}
+
+fn is_url_attribute(attribute: &str) -> bool {
+    attribute == "href" || attribute == "src"
+}
+
+/// Urls that run code when they're followed or loaded: `javascript:` and
+/// `vbscript:` ones, and `data:` ones with anything but a picture in them (a
+/// page, or an SVG, which can have scripts of its own)
+fn is_script_url(url: &str) -> bool {
+    // Browsers skip these in the scheme, `java\tscript:` still runs
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if url.starts_with("javascript:") || url.starts_with("vbscript:") {
+        return true;
+    }
+    match url.strip_prefix("data:") {
+        Some(data) => !data.starts_with("image/") || data.starts_with("image/svg"),
+        None => false,
+    }
+}
@@ -0,0 +1,85 @@
+use crate::cache::hash;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task;
+
+/// SQLite database of every chapter downloaded with it, shared by all the
+/// books made with the same `--archive`. Unlike the `ChapterCache` it's never
+/// cleared, so a book can be made again with other options without going back
+/// to the site for its chapters.
+///
+/// Chapters are kept by their url along with the hash of their html and when
+/// they were downloaded.
+#[derive(Clone)]
+pub struct ChapterArchive {
+    path: PathBuf,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl ChapterArchive {
+    /// Opens the archive at `path`, making it when there's none yet
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)?;
+        // Books made at the same time (i.e. with --jobs) each have their own
+        // connection, wait for the others instead of failing on a locked database
+        connection.busy_timeout(Duration::from_secs(30))?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS chapters (
+                url TEXT PRIMARY KEY NOT NULL,
+                sha256 TEXT NOT NULL,
+                fetched_at TEXT NOT NULL,
+                content TEXT NOT NULL
+            );",
+        )?;
+        Ok(ChapterArchive {
+            path: path.to_path_buf(),
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn get(&self, url: &str) -> rusqlite::Result<Option<String>> {
+        let connection = self.connection.clone();
+        let url = url.to_string();
+        task::spawn_blocking(move || {
+            connection
+                .lock()
+                .unwrap()
+                .query_row(
+                    "SELECT content FROM chapters WHERE url = ?1",
+                    params![url],
+                    |row| row.get(0),
+                )
+                .optional()
+        })
+        .await
+        .expect("the archive query panicked")
+    }
+
+    /// Keeps the chapter's html, replacing what was there for the url
+    pub async fn put(&self, url: &str, html: &str) -> rusqlite::Result<()> {
+        let connection = self.connection.clone();
+        let (url, html) = (url.to_string(), html.to_string());
+        task::spawn_blocking(move || {
+            let fetched_at = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+            connection
+                .lock()
+                .unwrap()
+                .execute(
+                    "INSERT OR REPLACE INTO chapters (url, sha256, fetched_at, content)
+                    VALUES (?1, ?2, ?3, ?4)",
+                    params![url, hash(&html), fetched_at, html],
+                )
+                .map(|_| ())
+        })
+        .await
+        .expect("the archive query panicked")
+    }
+}
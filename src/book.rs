@@ -0,0 +1,1934 @@
+use crate::archive::ChapterArchive;
+use crate::cache::ChapterCache;
+use crate::checkpoint::{Checkpoint, Finished};
+use crate::content_size::{text_size, ContentSize, SizeProblem, SuspiciousChapter};
+use crate::cookies::CookieJar;
+use crate::cover::generate_cover;
+use crate::downloader::{Downloader, RateLimit, RetryPolicy};
+use crate::downloader::{CLOUDFLARE_RATE_LIMIT, DEFAULT_PER_HOST_LIMIT};
+use crate::enrich::enrich;
+use crate::epub_zip::StreamingZip;
+use crate::error::{Error, Result};
+use crate::existing::ExistingEpub;
+use crate::extractor::{chapter_xhtml, link_texts, Chapter, ExtractError, Extractor, Overview};
+use crate::extractor::{find_plugin, find_script, GenericExtractor, PageRequest, SiteType};
+use crate::fetcher::{
+    Bandwidth, Fetcher, ReqwestFetcher, DEFAULT_CONNECT_TIMEOUT, DEFAULT_READ_TIMEOUT,
+};
+use crate::filters::{ContentFilter, StripRule};
+use crate::front_matter::{synopsis_xhtml, title_page_xhtml};
+use crate::http_cache::HttpCache;
+use crate::images::{convert_image, embed_images, Image, ImageOptions};
+use crate::language::detect_language;
+use crate::mirror::LocalMirror;
+use crate::ordering::{check_order, listed_chapter_number, sort_by_number, ChapterOrder};
+use crate::progress::{self, ChapterProgress};
+use crate::sanitizer::{escape_attribute, escape_text};
+use crate::selection::ChapterSelection;
+use crate::spool::{Spool, SpooledImage};
+use crate::template::{chapter_body, ChapterTemplate, ChapterValues};
+use crate::text::{xhtml_to_text, TextStyle};
+use crate::titles::{find_title, TitleSource};
+use crate::toc::{group_by_site_volume, group_by_volume, split_volume, toc_xhtml, volume_xhtml};
+use crate::toc::{TocEntry, TocPosition, VolumeGroup};
+use crate::transform::{ContentTransform, TransformPipeline};
+use crate::urls::normalize_chapter_urls;
+
+use futures::future::{self, FutureExt};
+use futures::stream::{self, StreamExt};
+
+use epub_builder::EpubBuilder;
+use epub_builder::EpubContent;
+use epub_builder::ReferenceType;
+use regex::{NoExpand, Regex};
+use uuid::Uuid;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task;
+use tracing::{debug, debug_span, trace, Instrument};
+
+/// Default for `BookOptions::concurrency`
+pub const DEFAULT_CONCURRENCY: usize = 8;
+/// Stylesheet used when `EpubOptions::stylesheet` isn't changed
+pub const DEFAULT_STYLESHEET: &str = include_str!("../assets/stylesheet.css");
+/// Added to the stylesheet for `EpubOptions::vertical`
+pub const VERTICAL_STYLESHEET: &str = include_str!("../assets/vertical.css");
+/// Added to the stylesheet for `EpubOptions::dark_mode`
+pub const DARK_MODE_STYLESHEET: &str = include_str!("../assets/dark.css");
+/// How many chapters `Book::detect_language` looks at
+const LANGUAGE_SAMPLE_CHAPTERS: usize = 3;
+/// Most pages a chapter is taken to have, in case a site's pages lead on
+/// forever
+const MAX_CHAPTER_PAGES: usize = 100;
+/// Default for `BookOptions::user_agent`
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 5.1; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/60.0.3112.90 Safari/537.36";
+
+/// What to do when a chapter can't be downloaded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnError {
+    /// Give up on the whole book
+    Abort,
+    /// Leave the chapter out of the book
+    Skip,
+    /// Put a page saying the chapter is unavailable in its place, so the
+    /// numbering stays the same and `--update` downloads it next time
+    Placeholder,
+}
+
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(OnError::Abort),
+            "skip" => Ok(OnError::Skip),
+            "placeholder" => Ok(OnError::Placeholder),
+            _ => Err(format!(
+                "Unknown error handling {}, expected skip, placeholder or abort",
+                s
+            )),
+        }
+    }
+}
+
+/// Cover to use instead of the one on the novel's page
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoverSource {
+    /// Image at an http(s) url, or in a file
+    Image(String),
+    /// Made with the title and author, as for novels without a cover
+    Generated,
+    /// No cover at all
+    Omit,
+}
+
+impl FromStr for CoverSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" => Err("The cover is empty".to_string()),
+            "generated" => Ok(CoverSource::Generated),
+            "none" => Ok(CoverSource::Omit),
+            source => Ok(CoverSource::Image(source.to_string())),
+        }
+    }
+}
+
+/// Whether the cover or image at `source` is downloaded rather than read
+/// from a file
+pub fn is_http_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Settings for `Book::from_url`, `BookOptions::default()` downloads every
+/// chapter with the extractor picked based on the url
+#[derive(Clone)]
+pub struct BookOptions {
+    /// Extractor to use instead of picking one based on the url, takes
+    /// precedence over `site_type`
+    pub extractor: Option<Arc<dyn Extractor + Send + Sync>>,
+    pub site_type: Option<SiteType>,
+    pub retry_policy: RetryPolicy,
+    /// How many chapters are downloaded at the same time
+    pub concurrency: usize,
+    /// How many downloaded chapters are extracted and run through the
+    /// transforms at the same time, one per CPU by default. Downloads go on
+    /// while chapters are being processed.
+    pub process_concurrency: usize,
+    /// How many requests can be sent to a single host at the same time
+    pub per_host_limit: usize,
+    /// How fast requests can be sent to a single host, no limit by default
+    pub rate_limit: Option<RateLimit>,
+    /// How fast everything together is downloaded, no limit by default
+    pub max_bandwidth: Option<Bandwidth>,
+    /// Stay away from pages the site's robots.txt disallows and wait the crawl
+    /// delay it asks for between requests
+    pub respect_robots: bool,
+    /// Cookies sent with every request, i.e. for a logged in session
+    pub cookies: CookieJar,
+    /// Sent with every request, has to match the browser's when using cookies
+    /// from a Cloudflare challenge it solved
+    pub user_agent: String,
+    /// Proxy every request goes through, i.e. `http://host:8080` or
+    /// `socks5h://127.0.0.1:9050` for Tor. The `HTTP_PROXY` and `HTTPS_PROXY`
+    /// environment variables are used when it isn't set.
+    pub proxy: Option<String>,
+    /// How long to wait for a connection to the site
+    pub connect_timeout: Duration,
+    /// How long to wait for a response, and then for each part of its body,
+    /// before giving up on the request. Timed out requests are retried.
+    pub read_timeout: Duration,
+    /// Sends requests instead of reqwest, i.e. to serve pages from memory with
+    /// a `MemoryFetcher`. `user_agent`, `proxy` and `max_bandwidth` are up to
+    /// it then.
+    pub fetcher: Option<Arc<dyn Fetcher>>,
+    /// Loads a chapter's page again when nothing, or less text than
+    /// `content_size` expects, could be extracted from it, i.e. a
+    /// `BrowserFetcher` that runs the page's JavaScript first. Only for
+    /// chapters on one page that the extractor gets with a plain GET, and not
+    /// when reading from a `mirror`.
+    pub renderer: Option<Arc<dyn Fetcher>>,
+    /// Reuse chapters downloaded by a previous run and keep the new ones around
+    pub cache: bool,
+    /// Database to look chapters up in before downloading them, and to keep
+    /// the downloaded ones in. It's used even without `cache`, but not when
+    /// reading from a `mirror`.
+    pub archive: Option<ChapterArchive>,
+    /// Make a cover with the title and author for novels that don't have one
+    pub generate_cover: bool,
+    /// Title to use instead of the one the extractor found
+    pub title: Option<String>,
+    /// Author to use instead of the one the extractor found
+    pub author: Option<String>,
+    /// Cover to use instead of the novel's, `generate_cover` is only for
+    /// `CoverSource::Image`s that couldn't be read
+    pub cover: Option<CoverSource>,
+    /// Look the novel up on NovelUpdates to fill in the author (when the site
+    /// shows a placeholder), description, alternate titles and tags
+    pub enrich: bool,
+    /// Order of the chapters, `chapters` picks them by their place in it
+    pub chapter_order: ChapterOrder,
+    /// Only download these chapters
+    pub chapters: Option<ChapterSelection>,
+    /// What to do with chapters that fail to download
+    pub on_error: OnError,
+    /// How much text chapters are expected to have, the ones outside of it
+    /// are extracted again or reported
+    pub content_size: ContentSize,
+    /// How the cover and images are scaled down and recompressed
+    pub images: ImageOptions,
+    /// Removed from every chapter, on top of the built in and the extractor's rules
+    pub strip: Vec<StripRule>,
+    /// Run on every chapter after the strip rules, in order, before it's made
+    /// into xhtml
+    pub transforms: Vec<Arc<dyn ContentTransform>>,
+    /// Only let the elements and attributes books need through, after the
+    /// transforms, see `Allowlist`
+    pub allowlist: bool,
+    /// Wrapper for every chapter's content instead of the default one, it's
+    /// put in before the strip rules and transforms run
+    pub template: Option<ChapterTemplate>,
+    /// Where chapter titles are taken from instead of where the extractor
+    /// finds them, chapters without one there keep the extractor's
+    pub title_from: Option<TitleSource>,
+    /// EPUB from an earlier run, chapters already in it are copied over instead
+    /// of downloaded again
+    pub update: Option<Arc<ExistingEpub>>,
+    /// On Ctrl-C stop starting new downloads and return the chapters that were
+    /// downloaded so far, instead of leaving the process to be killed
+    pub finish_on_interrupt: bool,
+    /// Directory to keep a `Checkpoint` of the build in, the chapters are
+    /// spooled to a temporary directory without one. Hold `Checkpoint::lock`
+    /// on it for as long as the book is around.
+    pub checkpoint: Option<PathBuf>,
+    /// Pick up the checkpoint's finished chapters instead of starting over
+    pub resume: bool,
+    /// Pages of the same novel on other sites, for novels split across them.
+    /// Each gets the extractor for its site, and its chapters go after the
+    /// ones before it except for those with a number already in the book.
+    pub more_sources: Vec<String>,
+    /// Directory of `PluginExtractor`s to check for one that handles the site,
+    /// before the built in extractors. Not used when `extractor` or
+    /// `site_type` is set.
+    pub plugin_dir: Option<PathBuf>,
+    /// Directory of `ScriptExtractor`s to check for one that handles the site,
+    /// after the plugins. Not used when `extractor` or `site_type` is set.
+    pub script_dir: Option<PathBuf>,
+    /// Local copy of the site (i.e. made with `wget --mirror`) to read the
+    /// novel from instead of downloading it. Nothing is cached when reading
+    /// from one.
+    pub mirror: Option<LocalMirror>,
+}
+
+impl Default for BookOptions {
+    fn default() -> Self {
+        BookOptions {
+            extractor: None,
+            site_type: None,
+            retry_policy: RetryPolicy::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+            process_concurrency: num_cpus::get(),
+            per_host_limit: DEFAULT_PER_HOST_LIMIT,
+            rate_limit: None,
+            max_bandwidth: None,
+            respect_robots: true,
+            cookies: CookieJar::default(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxy: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            fetcher: None,
+            renderer: None,
+            cache: true,
+            archive: None,
+            generate_cover: true,
+            title: None,
+            author: None,
+            cover: None,
+            enrich: true,
+            chapter_order: ChapterOrder::Site,
+            chapters: None,
+            on_error: OnError::Abort,
+            content_size: ContentSize::default(),
+            images: ImageOptions::default(),
+            strip: Vec::new(),
+            transforms: Vec::new(),
+            allowlist: true,
+            template: None,
+            title_from: None,
+            update: None,
+            finish_on_interrupt: false,
+            checkpoint: None,
+            resume: false,
+            more_sources: Vec::new(),
+            plugin_dir: None,
+            script_dir: None,
+            mirror: None,
+        }
+    }
+}
+
+/// Version of the EPUB standard the book follows. EPUB 3 books have an NCX
+/// table of contents too, for readers that only know EPUB 2.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EpubVersion {
+    /// For old readers that reject EPUB 3, the pages are XHTML 1.1 and there's
+    /// no navigation document
+    V2,
+    V3,
+}
+
+impl FromStr for EpubVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "2" | "2.0" => Ok(EpubVersion::V2),
+            "3" | "3.0" => Ok(EpubVersion::V3),
+            _ => Err(format!("Unknown EPUB version {}, expected 2 or 3", s)),
+        }
+    }
+}
+
+lazy_static! {
+    static ref XML_PROLOG_REGEX: Regex =
+        Regex::new(r"^\s*(<\?xml[^>]*\?>\s*)?(<!DOCTYPE[^>]*>\s*)?").unwrap();
+    static ref EPUB_NAMESPACE_REGEX: Regex =
+        Regex::new(r#"\s+(xmlns:epub|epub:type)="[^"]*""#).unwrap();
+    // `Footnotes` puts notes in `<aside>`, which XHTML 1.1 doesn't have
+    static ref ASIDE_REGEX: Regex = Regex::new(r"<(/?)aside\b").unwrap();
+    /// The random identifier epub-builder gives every book
+    static ref BUILDER_UUID_REGEX: Regex = Regex::new(r"urn:uuid:[0-9a-fA-F-]{36}").unwrap();
+}
+
+const XHTML11_PROLOG: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.1//EN" "http://www.w3.org/TR/xhtml11/DTD/xhtml11.dtd">
+"#;
+
+/// A page of the book as its EPUB version expects it. EPUB 2 pages are XHTML
+/// 1.1, with its doctype and without EPUB 3's namespace and elements.
+fn page_xhtml(xhtml: String, version: EpubVersion) -> String {
+    match version {
+        EpubVersion::V3 => xhtml,
+        EpubVersion::V2 => {
+            let body = XML_PROLOG_REGEX.replace(&xhtml, "");
+            let body = EPUB_NAMESPACE_REGEX.replace_all(&body, "");
+            format!(
+                "{}{}",
+                XHTML11_PROLOG,
+                ASIDE_REGEX.replace_all(&body, "<${1}div")
+            )
+        }
+    }
+}
+
+/// Settings for `Book::build_epub_with`
+#[derive(Clone)]
+pub struct EpubOptions {
+    /// CSS every chapter is styled with
+    pub stylesheet: String,
+    /// Font file (TrueType, OpenType or WOFF) to embed and set the text in
+    pub font: Option<PathBuf>,
+    /// Typeset like a printed Japanese book: text runs top to bottom in
+    /// columns from right to left, and pages turn to the left
+    pub vertical: bool,
+    /// Switch to light text on a dark background in reading apps that follow
+    /// the system's dark mode (`prefers-color-scheme`)
+    pub dark_mode: bool,
+    /// Start the book with a page with its title, author, and where and when
+    /// it was downloaded
+    pub title_page: bool,
+    /// Novel's page, for the title page and the book's metadata
+    pub source: Option<String>,
+    /// Put the novel's description on a page after the title page
+    pub synopsis: bool,
+    /// Where the table of contents page goes, `None` leaves it out. Readers
+    /// have their own table of contents either way.
+    pub inline_toc: Option<TocPosition>,
+    pub version: EpubVersion,
+}
+
+impl Default for EpubOptions {
+    fn default() -> Self {
+        EpubOptions {
+            stylesheet: DEFAULT_STYLESHEET.to_string(),
+            font: None,
+            vertical: false,
+            dark_mode: false,
+            title_page: true,
+            source: None,
+            synopsis: false,
+            inline_toc: Some(TocPosition::Start),
+            version: EpubVersion::V3,
+        }
+    }
+}
+
+/// A chapter that's ready to go into the EPUB, its content is kept on disk
+/// until then
+pub struct DownloadedChapter {
+    /// Position in the novel's chapter list, starting at 1
+    pub number: usize,
+    pub url: String,
+    pub title: String,
+    /// Volume the site lists the chapter under, see `Overview::volumes`
+    pub volume: Option<String>,
+    /// Where the chapter's xhtml was spooled to
+    pub content_file: PathBuf,
+    /// Images used by the chapter, the content already points at these
+    pub images: Vec<SpooledImage>,
+}
+
+impl DownloadedChapter {
+    /// Reads the chapter's xhtml back from the spool
+    pub fn content(&self) -> io::Result<String> {
+        std::fs::read_to_string(&self.content_file)
+    }
+}
+
+/// A downloaded novel
+pub struct Book {
+    pub overview: Overview,
+    /// Goes in the EPUB's `dc:identifier`, library apps tell books apart by
+    /// it. See `book_identifier`, books being updated keep theirs.
+    pub identifier: String,
+    pub cover: Option<Image>,
+    pub chapters: Vec<DownloadedChapter>,
+    /// Chapters that failed to download with the reason why, left out or
+    /// replaced by a placeholder depending on `BookOptions::on_error`
+    pub skipped: Vec<String>,
+    /// Chapters with less or more text than `BookOptions::content_size`
+    /// expects
+    pub suspicious: Vec<SuspiciousChapter>,
+    /// Downloading was stopped with Ctrl-C, so later chapters are missing
+    pub interrupted: bool,
+    // Keeps the chapter files around for as long as the book is
+    _spool: Spool,
+}
+
+/// Normalizes the novel's url to have a slash at the end, extractors expect
+/// chapter urls to start with it. `file:` urls of single pages and urls with
+/// a query are left alone.
+pub fn normalize_site(url: &str) -> String {
+    if url.ends_with('/') || url.starts_with("file:") || url.contains('?') {
+        url.to_string()
+    } else {
+        format!("{}/", url)
+    }
+}
+
+impl Book {
+    /// Downloads the novel at `url`
+    pub async fn from_url(url: &str, options: &BookOptions) -> Result<Book> {
+        let (sources, overview, chapter_sources) = open_novels(url, options).await?;
+        let site = sources[0].site.clone();
+        let downloader = sources[0].downloader.clone();
+
+        // Chapters are numbered from 1 in the order the extractor found them, this
+        // numbering is kept when only some of them are selected
+        let mut chapter_urls: Vec<(usize, String)> = overview
+            .download_urls
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(i, url)| (i + 1, url))
+            .filter(|(number, _)| match &options.chapters {
+                Some(selection) => selection.contains(*number),
+                None => true,
+            })
+            .collect();
+        if chapter_urls.is_empty() {
+            return Err("No chapters to download".into());
+        }
+        let (checkpoint, spool, finished) = match &options.checkpoint {
+            Some(dir) if options.resume => {
+                let (checkpoint, spool, finished) = Checkpoint::resume(dir, &site)?;
+                (Some(checkpoint), spool, finished)
+            }
+            Some(dir) => {
+                let (checkpoint, spool) = Checkpoint::start(dir, &site)?;
+                (Some(checkpoint), spool, Finished::default())
+            }
+            None => (None, Spool::new()?, Finished::default()),
+        };
+
+        let mut chapters = Vec::new();
+        let mut suspicious = Vec::new();
+        if let Some(existing) = &options.update {
+            let (kept, new): (Vec<_>, Vec<_>) = chapter_urls
+                .into_iter()
+                .partition(|(number, url)| existing.contains(*number, url));
+            chapters = existing.copy_chapters(&kept, &spool).await?;
+            progress::info(format!(
+                "{} chapters are already in {}, {} new ones to download",
+                chapters.len(),
+                existing.path().display(),
+                new.len()
+            ));
+            chapter_urls = new;
+        }
+        if options.resume {
+            // Only the checkpoint's chapters that are still wanted, and still at
+            // the same place in the list
+            let finished_urls: HashMap<usize, &str> = finished
+                .chapters
+                .iter()
+                .map(|chapter| (chapter.number, chapter.url.as_str()))
+                .collect();
+            let (resumed, new): (Vec<_>, Vec<_>) = chapter_urls
+                .into_iter()
+                .partition(|(number, url)| finished_urls.get(number) == Some(&url.as_str()));
+            let resumed: HashSet<usize> = resumed.into_iter().map(|(number, _)| number).collect();
+            progress::info(format!(
+                "Resuming with {} chapters finished before, {} left to download",
+                resumed.len(),
+                new.len()
+            ));
+            chapters.extend(
+                finished
+                    .chapters
+                    .into_iter()
+                    .filter(|chapter| resumed.contains(&chapter.number)),
+            );
+            suspicious.extend(
+                finished
+                    .suspicious
+                    .into_iter()
+                    .filter(|chapter| resumed.contains(&chapter.number)),
+            );
+            chapter_urls = new;
+        }
+        let chapter_count = chapter_urls.len();
+        // Chapters are downloaded and extracted by the site they're from
+        let contexts: Vec<Arc<DownloadContext>> = sources
+            .into_iter()
+            .map(|source| {
+                let cache = if options.cache && options.mirror.is_none() {
+                    ChapterCache::for_site(&source.site)
+                } else {
+                    None
+                };
+                if let Some(cache) = &cache {
+                    progress::info(format!("Caching chapters in {}", cache.dir().display()));
+                }
+                let archive = options.archive.clone().filter(|_| options.mirror.is_none());
+                Arc::new(DownloadContext {
+                    chapter_downloader: source.downloader.without_http_cache(),
+                    downloader: source.downloader,
+                    renderer: source.renderer,
+                    cache,
+                    archive,
+                    spool: spool.clone(),
+                    pipeline: TransformPipeline::for_chapters(
+                        ContentFilter::new(source.extractor.strip_rules(), &options.strip),
+                        &options.transforms,
+                        options.allowlist,
+                    ),
+                    extractor: source.extractor,
+                    template: options.template.clone(),
+                    title_from: options.title_from,
+                    content_size: options.content_size,
+                    images: options.images,
+                    novel_title: overview.title.clone(),
+                    author: overview.author.clone(),
+                })
+            })
+            .collect();
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        // Chapters go through two stages: downloading, and then extracting,
+        // transforming and embedding images. Each has its own concurrency so
+        // slow processing doesn't hold up download slots, the channel between
+        // them holds the pages waiting to be processed.
+        let concurrency = options.concurrency.max(1);
+        let (mut fetched_sender, fetched_receiver) = mpsc::channel(concurrency);
+        let downloads: Vec<_> = chapter_urls
+            .into_iter()
+            .map(|(number, url)| {
+                let context = contexts[chapter_sources[number - 1]].clone();
+                let interrupted = interrupted.clone();
+                async move {
+                    if interrupted.load(Ordering::SeqCst) {
+                        return None;
+                    }
+                    let fetched = fetch_chapter(&context, &url)
+                        .instrument(debug_span!("chapter", number, url = url.as_str()))
+                        .await;
+                    Some(match fetched {
+                        Ok(mut pages) => {
+                            let html = pages.remove(0);
+                            let fetched = FetchedChapter {
+                                number,
+                                url,
+                                html,
+                                more_pages: pages,
+                            };
+                            (context, Ok(fetched))
+                        }
+                        Err(error) => (context, Err(FailedChapter { number, url, error })),
+                    })
+                }
+            })
+            .collect();
+        let downloads = stream::iter(downloads)
+            .buffer_unordered(concurrency)
+            .filter_map(future::ready);
+        tokio::spawn(async move {
+            futures::pin_mut!(downloads);
+            while let Some(fetched) = downloads.next().await {
+                // The book was given up on
+                if fetched_sender.send(fetched).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut download_tasks = fetched_receiver
+            .map(
+                |(context, fetched): (_, Result<FetchedChapter, FailedChapter>)| {
+                    tokio::spawn(async move {
+                        let fetched = fetched?;
+                        let (number, url) = (fetched.number, fetched.url.clone());
+                        process_chapter(&context, fetched)
+                            .instrument(debug_span!("chapter", number, url = url.as_str()))
+                            .await
+                            .map(Some)
+                            .map_err(|error| FailedChapter { number, url, error })
+                    })
+                },
+            )
+            .buffer_unordered(options.process_concurrency.max(1));
+
+        let mut cover = match (&options.cover, &overview.img_url) {
+            (Some(CoverSource::Image(source)), _) => match read_cover(&downloader, source).await {
+                Ok(cover) => cover,
+                Err(err) => {
+                    progress::warn(format!("Couldn't get the cover {}: {}", source, err));
+                    None
+                }
+            },
+            (Some(_), _) | (None, None) => None,
+            (None, Some(image_url)) => download_cover(&downloader, image_url).await?,
+        };
+        let generate = match &options.cover {
+            Some(CoverSource::Generated) => true,
+            Some(CoverSource::Omit) => false,
+            _ => options.generate_cover,
+        };
+        if cover.is_none() && generate {
+            cover = generate_cover(&overview.title, &overview.author);
+            match &cover {
+                Some(_) => progress::info("The novel has no cover, made one with its title"),
+                None => progress::warn(
+                    "The novel has no cover and its title can't be set in the built in font",
+                ),
+            }
+        }
+        if let Some(image) = cover {
+            let image_options = options.images;
+            cover = Some(task::spawn_blocking(move || image_options.shrink(image)).await?);
+        }
+
+        let progress = ChapterProgress::start(chapter_count);
+        let mut skipped = Vec::new();
+        let mut placeholders = 0;
+        let mut ctrl_c = if options.finish_on_interrupt {
+            tokio::signal::ctrl_c().boxed().fuse()
+        } else {
+            future::pending::<io::Result<()>>().boxed().fuse()
+        };
+        loop {
+            let task = tokio::select! {
+                task = download_tasks.next() => match task {
+                    Some(task) => task,
+                    None => break,
+                },
+                _ = &mut ctrl_c => {
+                    if interrupted.swap(true, Ordering::SeqCst) {
+                        return Err("Interrupted".into());
+                    }
+                    progress::warn(
+                        "Interrupted, finishing the chapters being downloaded. \
+                         Press Ctrl-C again to quit right away",
+                    );
+                    // Listen for the second Ctrl-C
+                    ctrl_c = tokio::signal::ctrl_c().boxed().fuse();
+                    continue;
+                }
+            };
+            progress.inc();
+            match task? {
+                Ok(Some((chapter, size_problem))) => {
+                    if let Some(checkpoint) = &checkpoint {
+                        checkpoint.record(&chapter, size_problem.as_ref())?;
+                    }
+                    if let Some(problem) = size_problem {
+                        suspicious.push(problem);
+                    }
+                    chapters.push(chapter);
+                }
+                Ok(None) => {}
+                Err(failed) => match options.on_error {
+                    OnError::Abort => return Err(failed.into()),
+                    OnError::Skip => {
+                        progress::warn(format!("Skipping {}", failed));
+                        skipped.push(failed.to_string());
+                    }
+                    OnError::Placeholder => {
+                        progress::warn(format!("Using a placeholder for {}", failed));
+                        chapters.push(write_placeholder(&spool, &failed).await?);
+                        placeholders += 1;
+                        skipped.push(failed.to_string());
+                    }
+                },
+            }
+        }
+        drop(download_tasks);
+        drop(progress);
+        if chapters.len() == placeholders {
+            return Err("None of the chapters could be downloaded".into());
+        }
+        // Chapters copied from an existing EPUB come before the downloaded ones,
+        // even if some of them were missing from it
+        chapters.sort_by_key(|chapter| chapter.number);
+        suspicious.sort_by_key(|chapter: &SuspiciousChapter| chapter.number);
+        for chapter in &mut chapters {
+            chapter.volume = overview.volumes.get(&chapter.url).cloned();
+        }
+
+        let identifier = options
+            .update
+            .as_ref()
+            .and_then(|existing| existing.identifier())
+            .map_or_else(|| book_identifier(&site), str::to_string);
+
+        Ok(Book {
+            overview,
+            identifier,
+            cover,
+            chapters,
+            skipped,
+            suspicious,
+            interrupted: interrupted.load(Ordering::SeqCst),
+            _spool: spool,
+        })
+    }
+
+    /// Splits the book into volumes of `chapters_per_volume` chapters, for
+    /// novels too long for some readers to open in one piece. Each volume's
+    /// title and description say which one it is.
+    pub fn into_volumes(self, chapters_per_volume: usize) -> Vec<Book> {
+        let chapters_per_volume = chapters_per_volume.max(1);
+        let count = self.chapters.chunks(chapters_per_volume).len();
+        if count <= 1 {
+            return vec![self];
+        }
+
+        let mut volumes = Vec::with_capacity(count);
+        let mut chapters = self.chapters.into_iter();
+        for number in 1..=count {
+            let volume_chapters: Vec<DownloadedChapter> =
+                chapters.by_ref().take(chapters_per_volume).collect();
+            let first = volume_chapters.first().map_or(0, |chapter| chapter.number);
+            let last = volume_chapters.last().map_or(0, |chapter| chapter.number);
+
+            let mut overview = self.overview.clone();
+            overview.title = format!("{} (Volume {})", self.overview.title, number);
+            let annotation = format!(
+                "Volume {} of {}, chapters {} to {}.",
+                number, count, first, last
+            );
+            overview.description = Some(match &self.overview.description {
+                Some(description) => format!("{}\n\n{}", annotation, description),
+                None => annotation,
+            });
+
+            volumes.push(Book {
+                overview,
+                // Every volume is a book of its own to library apps
+                identifier: book_identifier(&format!("{}#volume-{}", self.identifier, number)),
+                cover: self.cover.clone(),
+                chapters: volume_chapters,
+                skipped: if number == 1 {
+                    self.skipped.clone()
+                } else {
+                    Vec::new()
+                },
+                suspicious: if number == 1 {
+                    self.suspicious.clone()
+                } else {
+                    Vec::new()
+                },
+                interrupted: self.interrupted,
+                _spool: self._spool.clone(),
+            });
+        }
+        volumes
+    }
+
+    /// Guesses the language from the text of the first few chapters
+    pub fn detect_language(&self) -> Option<&'static str> {
+        let mut text = String::new();
+        for chapter in self.chapters.iter().take(LANGUAGE_SAMPLE_CHAPTERS) {
+            if let Ok(content) = chapter.content() {
+                text.push_str(&xhtml_to_text(&content, TextStyle::Plain));
+                text.push('\n');
+            }
+        }
+        detect_language(&text)
+    }
+
+    /// The entries of the table of contents page, the same as the one
+    /// readers make from the navigation document
+    fn toc_entries(&self, groups: &[VolumeGroup]) -> Vec<TocEntry> {
+        let mut entries = Vec::new();
+        for (volume_number, group) in groups.iter().enumerate() {
+            let chapters = self.chapters[group.chapters.clone()]
+                .iter()
+                .map(|chapter| TocEntry {
+                    href: format!("c{}.xhtml", chapter.number),
+                    title: toc_title(group, chapter).to_string(),
+                    children: Vec::new(),
+                });
+            match &group.title {
+                Some(title) => entries.push(TocEntry {
+                    href: format!("v{}.xhtml", volume_number + 1),
+                    title: title.clone(),
+                    children: chapters.collect(),
+                }),
+                None => entries.extend(chapters),
+            }
+        }
+        entries
+    }
+
+    /// Writes the book out as an EPUB. Chapters and images are streamed from
+    /// the spool into the archive, which is written to a temporary file as
+    /// it's made, so the book never has to fit in memory.
+    pub fn build_epub(&self, writer: impl Write) -> Result<()> {
+        self.build_epub_with(writer, &EpubOptions::default())
+    }
+
+    pub fn build_epub_with(&self, mut writer: impl Write, options: &EpubOptions) -> Result<()> {
+        let mut epub = tempfile::tempfile()?;
+        self.write_epub(epub.try_clone()?, options)?;
+        epub.seek(SeekFrom::Start(0))?;
+        io::copy(&mut epub, &mut writer)?;
+        Ok(())
+    }
+
+    /// Writes the book out as an EPUB at `path`. It's made in a temporary file
+    /// next to `path` that only replaces what's there once it's complete, so a
+    /// book that fails halfway doesn't clobber the one it was to replace.
+    pub fn save_epub(&self, path: &Path, options: &EpubOptions) -> Result<()> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let written = File::create(&tmp_path)
+            .map_err(Error::from)
+            .and_then(|file| self.write_epub(file, options));
+        match written {
+            Ok(()) => Ok(std::fs::rename(&tmp_path, path)?),
+            Err(err) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                Err(err)
+            }
+        }
+    }
+
+    fn write_epub(&self, output: File, options: &EpubOptions) -> Result<()> {
+        let zip = StreamingZip::new(output, self.package_rewrite(options))?;
+        let mut builder = EpubBuilder::new(zip)?;
+        builder.epub_version(match options.version {
+            EpubVersion::V2 => epub_builder::EpubVersion::V20,
+            EpubVersion::V3 => epub_builder::EpubVersion::V30,
+        });
+        builder.metadata("author", self.overview.author.as_str())?;
+        builder.metadata("title", self.overview.title.as_str())?;
+        builder.metadata(
+            "generator",
+            format!("box2epub {}", env!("CARGO_PKG_VERSION")),
+        )?;
+        // EPUB 2 metadata has no place for alternate titles, readers show the
+        // description in the book's details so they go there
+        let also_known_as = if self.overview.alternate_titles.is_empty() {
+            None
+        } else {
+            Some(format!(
+                "Also known as: {}",
+                self.overview.alternate_titles.join(", ")
+            ))
+        };
+        let description = match (&self.overview.description, also_known_as) {
+            (Some(description), Some(also_known_as)) => {
+                Some(format!("{}\n\n{}", description, also_known_as))
+            }
+            (description, also_known_as) => description.clone().or(also_known_as),
+        };
+        if let Some(description) = &description {
+            builder.metadata("description", description.as_str())?;
+        }
+        for genre in &self.overview.genres {
+            builder.metadata("subject", genre.as_str())?;
+        }
+        if let Some(language) = &self.overview.language {
+            builder.metadata("lang", language.as_str())?;
+        }
+        if let Some(cover) = &self.cover {
+            builder.add_cover_image(&cover.path, cover.data.as_slice(), cover.mimetype.as_str())?;
+        }
+
+        let mut stylesheet = options.stylesheet.clone();
+        if let Some(font_path) = &options.font {
+            let file_name = font_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or("The font's file name isn't valid")?;
+            let mimetype = font_mimetype(file_name).ok_or_else(|| {
+                format!(
+                    "{} isn't a font, expected a .ttf, .otf or .woff file",
+                    font_path.display()
+                )
+            })?;
+            let path = format!("fonts/{}", file_name);
+            debug!(font = path.as_str(), "embedding the font");
+            builder.add_resource(&path, File::open(font_path)?, mimetype)?;
+            // Named after the file, so it can't clash with a font the reader has
+            let family = file_name.rsplitn(2, '.').last().unwrap_or(file_name);
+            stylesheet.push_str(&format!(
+                r#"
+@font-face {{
+    font-family: "{0}";
+    src: url("{1}");
+}}
+
+body {{
+    font-family: "{0}", serif;
+}}
+"#,
+                family, path
+            ));
+        }
+        if options.vertical {
+            stylesheet.push_str(VERTICAL_STYLESHEET);
+        }
+        if options.dark_mode {
+            stylesheet.push_str(DARK_MODE_STYLESHEET);
+        }
+        builder.stylesheet(stylesheet.as_bytes())?;
+
+        if options.title_page {
+            let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+            let xhtml = page_xhtml(
+                title_page_xhtml(&self.overview, options.source.as_deref(), &date),
+                options.version,
+            );
+            builder.add_content(
+                EpubContent::new("title.xhtml", xhtml.as_bytes())
+                    .title(self.overview.title.as_str())
+                    .reftype(ReferenceType::TitlePage),
+            )?;
+        }
+        if let (true, Some(description)) = (options.synopsis, &description) {
+            let xhtml = page_xhtml(synopsis_xhtml(description), options.version);
+            builder.add_content(
+                EpubContent::new("synopsis.xhtml", xhtml.as_bytes())
+                    .title("Synopsis")
+                    .reftype(ReferenceType::Preface),
+            )?;
+        }
+
+        // Chapters the site lists by volume, or titled like "Book 3, Chapter 12",
+        // go under an entry for their volume in the table of contents, instead
+        // of one long flat list
+        let volumes: Vec<Option<&str>> = self
+            .chapters
+            .iter()
+            .map(|chapter| chapter.volume.as_deref())
+            .collect();
+        let titles: Vec<&str> = self
+            .chapters
+            .iter()
+            .map(|chapter| chapter.title.as_str())
+            .collect();
+        let groups = group_by_site_volume(&volumes)
+            .or_else(|| group_by_volume(&titles))
+            .unwrap_or_else(|| {
+                vec![VolumeGroup {
+                    title: None,
+                    chapters: 0..self.chapters.len(),
+                }]
+            });
+
+        let toc_page = page_xhtml(toc_xhtml(&self.toc_entries(&groups)), options.version);
+        let toc_content = || {
+            EpubContent::new("contents.xhtml", toc_page.as_bytes())
+                .title("Contents")
+                .reftype(ReferenceType::Toc)
+        };
+        if options.inline_toc == Some(TocPosition::Start) {
+            builder.add_content(toc_content())?;
+        }
+
+        // Chapters can share images, they only need to be stored once
+        let mut added_images = HashSet::new();
+        // The first content requires reftype to be set
+        let mut first_content = true;
+        for (volume_number, group) in groups.iter().enumerate() {
+            let level = match &group.title {
+                Some(title) => {
+                    let xhtml = page_xhtml(volume_xhtml(title), options.version);
+                    let mut content =
+                        EpubContent::new(format!("v{}.xhtml", volume_number + 1), xhtml.as_bytes())
+                            .title(title.as_str())
+                            .level(1);
+                    if first_content {
+                        content = content.reftype(ReferenceType::Text);
+                        first_content = false;
+                    }
+                    builder.add_content(content)?;
+                    2
+                }
+                None => 1,
+            };
+
+            for downloaded in &self.chapters[group.chapters.clone()] {
+                for image in &downloaded.images {
+                    if added_images.insert(image.path.as_str()) {
+                        builder.add_resource(
+                            &image.path,
+                            File::open(&image.file)?,
+                            image.mimetype.as_str(),
+                        )?;
+                    }
+                }
+
+                let title = toc_title(group, downloaded);
+                // Only EPUB 2 chapters have to be changed, EPUB 3 ones are
+                // streamed from the spool as they are
+                let chapter_xhtml: Box<dyn Read> = match options.version {
+                    EpubVersion::V3 => Box::new(File::open(&downloaded.content_file)?),
+                    EpubVersion::V2 => Box::new(Cursor::new(page_xhtml(
+                        downloaded.content()?,
+                        options.version,
+                    ))),
+                };
+                let mut content =
+                    EpubContent::new(format!("c{}.xhtml", downloaded.number), chapter_xhtml)
+                        .title(title)
+                        .level(level);
+                if first_content {
+                    content = content.reftype(ReferenceType::Text);
+                    first_content = false;
+                }
+                builder.add_content(content)?;
+            }
+        }
+        if options.inline_toc == Some(TocPosition::End) {
+            builder.add_content(toc_content())?;
+        }
+
+        debug!(
+            chapters = self.chapters.len(),
+            images = added_images.len(),
+            "generating the EPUB"
+        );
+        // `StreamingZip` already wrote it all to `output`
+        builder.generate(io::sink())?;
+        Ok(())
+    }
+
+    /// How the EPUB's package document and table of contents are rewritten
+    /// for what epub-builder has no settings for: the book's identifier instead of a
+    /// random one, where and when the book and each of its chapters came
+    /// from (`ExistingEpub` reads those back), and for vertical books
+    /// pages turning right to left and telling Kindles the text is vertical.
+    /// EPUB 2 has no page direction, those books only get the Kindle setting.
+    fn package_rewrite(&self, options: &EpubOptions) -> impl Fn(&str, String) -> String {
+        let identifier = escape_text(&self.identifier);
+        let mut metadata = String::new();
+        if let Some(source) = &options.source {
+            metadata.push_str(&format!("<dc:source>{}</dc:source>", escape_text(source)));
+        }
+        metadata.push_str(&format!(
+            r#"<meta name="box2epub:generated" content="{}"/>"#,
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+        ));
+        for chapter in &self.chapters {
+            metadata.push_str(&format!(
+                r#"<meta name="box2epub:chapter" content="c{}.xhtml {}"/>"#,
+                chapter.number,
+                escape_attribute(&chapter.url)
+            ));
+        }
+        if options.vertical {
+            metadata.push_str(r#"<meta name="primary-writing-mode" content="vertical-rl"/>"#);
+        }
+
+        let right_to_left = options.vertical && options.version == EpubVersion::V3;
+        move |name, text| {
+            // The NCX repeats the identifier, they have to match
+            let mut text = BUILDER_UUID_REGEX
+                .replace_all(&text, NoExpand(&identifier))
+                .into_owned();
+            if name.ends_with(".opf") {
+                text = text.replacen("</metadata>", &format!("{}</metadata>", metadata), 1);
+                if right_to_left {
+                    text = text.replacen("<spine", r#"<spine page-progression-direction="rtl""#, 1);
+                }
+            }
+            text
+        }
+    }
+}
+
+/// A chapter's title in the table of contents, without the volume when it's
+/// already under an entry for it
+fn toc_title<'a>(group: &VolumeGroup, chapter: &'a DownloadedChapter) -> &'a str {
+    match (&group.title, split_volume(&chapter.title)) {
+        (Some(_), Some((_, rest))) => rest,
+        _ => chapter.title.as_str(),
+    }
+}
+
+/// The `dc:identifier` of a book made from the novel at `url`, a UUID made
+/// from the url so the book keeps it when it's built again
+pub fn book_identifier(url: &str) -> String {
+    format!(
+        "urn:uuid:{}",
+        Uuid::new_v5(&Uuid::NAMESPACE_URL, url.as_bytes())
+    )
+}
+
+/// Where the chapters of a novel come from, found while reading its overview
+struct NovelSource {
+    site: String,
+    downloader: Downloader,
+    renderer: Option<Downloader>,
+    extractor: Arc<dyn Extractor + Send + Sync>,
+}
+
+/// Fetches the novel's page and its whole chapter list, without downloading
+/// any chapters. For checking an extractor works before a long download.
+pub async fn fetch_overview(url: &str, options: &BookOptions) -> Result<Overview> {
+    let (_, overview, _) = open_novels(url, options).await?;
+    Ok(overview)
+}
+
+/// Opens the novel at `url` and the ones in `BookOptions::more_sources`, and
+/// merges their chapter lists into the first one's overview. Along with the
+/// sources comes which one every chapter in the list is from.
+async fn open_novels(
+    url: &str,
+    options: &BookOptions,
+) -> Result<(Vec<NovelSource>, Overview, Vec<usize>)> {
+    let (source, mut overview) = open_novel(url, options).await?;
+    if let Some(title) = &options.title {
+        overview.title = title.clone();
+    }
+    if let Some(author) = &options.author {
+        overview.author = author.clone();
+    }
+    let mut sources = vec![source];
+    let mut chapter_sources = vec![0; overview.download_urls.len()];
+    if options.more_sources.is_empty() {
+        return Ok((sources, overview, chapter_sources));
+    }
+
+    // The extractor and mirror are for the first site, the others are
+    // recognized by their urls or pages. Only the first one's details go in
+    // the book.
+    let source_options = BookOptions {
+        extractor: None,
+        site_type: None,
+        mirror: None,
+        enrich: false,
+        ..options.clone()
+    };
+    let mut numbers: HashSet<(u32, u32)> = overview
+        .download_urls
+        .iter()
+        .filter_map(|url| {
+            listed_chapter_number(url, overview.chapter_titles.get(url).map(String::as_str))
+        })
+        .collect();
+    for url in &options.more_sources {
+        let (source, mut more) = open_novel(url, &source_options).await?;
+        let listed = overview.download_urls.len();
+        let mut added = Vec::new();
+        let mut already_in = 0;
+        for url in more.download_urls {
+            let title = more.chapter_titles.remove(&url);
+            match listed_chapter_number(&url, title.as_deref()) {
+                Some(number) if numbers.contains(&number) => {
+                    already_in += 1;
+                    continue;
+                }
+                Some(number) => added.push(number),
+                None => {}
+            }
+            if let Some(title) = title {
+                overview.chapter_titles.insert(url.clone(), title);
+            }
+            if let Some(volume) = more.volumes.remove(&url) {
+                overview.volumes.insert(url.clone(), volume);
+            }
+            overview.download_urls.push(url);
+            chapter_sources.push(sources.len());
+        }
+        progress::info(format!(
+            "Adding {} chapters from {}, {} were already in the book",
+            overview.download_urls.len() - listed,
+            source.site,
+            already_in
+        ));
+        // A site can have the same number twice (i.e. a chapter and its
+        // rewrite), only earlier sites' chapters are left out
+        numbers.extend(added);
+        sources.push(source);
+    }
+    Ok((sources, overview, chapter_sources))
+}
+
+async fn open_novel(url: &str, options: &BookOptions) -> Result<(NovelSource, Overview)> {
+    let site = normalize_site(url);
+
+    // The extractor is needed before anything is fetched when the site is
+    // behind Cloudflare, only mirrors and unknown domains wait for the page
+    let plugin = match (&options.extractor, options.site_type, &options.plugin_dir) {
+        (None, None, Some(dir)) => find_plugin(dir, &site),
+        _ => None,
+    };
+    let script = match (&options.extractor, options.site_type, &options.script_dir) {
+        (None, None, Some(dir)) if plugin.is_none() => find_script(dir, &site),
+        _ => None,
+    };
+    let known_extractor = match (&options.extractor, plugin, script) {
+        (Some(extractor), _, _) => Some(extractor.clone()),
+        (None, Some(plugin), _) => {
+            progress::info(format!("Using the {} plugin", plugin.name()));
+            Some(Arc::new(plugin) as Arc<dyn Extractor + Send + Sync>)
+        }
+        (None, None, Some(script)) => {
+            progress::info(format!("Using the {} script", script.name()));
+            Some(Arc::new(script) as Arc<dyn Extractor + Send + Sync>)
+        }
+        (None, None, None) => {
+            options
+                .site_type
+                .or_else(|| SiteType::from_url(&site))
+                .map(|site_type| {
+                    progress::info(format!("Using the {} extractor", site_type.name()));
+                    site_type.extractor(&site)
+                })
+        }
+    };
+    let mut rate_limit = options.rate_limit;
+    let behind_cloudflare =
+        matches!(&known_extractor, Some(extractor) if extractor.behind_cloudflare());
+    if behind_cloudflare && rate_limit.is_none() && options.mirror.is_none() {
+        progress::info(
+            "The site is behind Cloudflare, sending requests slowly so it doesn't \
+             challenge them (--rate changes this)",
+        );
+        rate_limit = Some(CLOUDFLARE_RATE_LIMIT);
+    }
+
+    let fetcher: Arc<dyn Fetcher> = match (&options.fetcher, &options.mirror) {
+        (Some(fetcher), _) => fetcher.clone(),
+        (None, Some(mirror)) => Arc::new(mirror.clone()),
+        (None, None) => {
+            let mut http_client = reqwest::Client::builder()
+                .user_agent(options.user_agent.as_str())
+                .connect_timeout(options.connect_timeout);
+            if let Some(proxy) = &options.proxy {
+                let proxy = reqwest::Proxy::all(proxy.as_str())
+                    .map_err(|err| format!("Invalid proxy {}: {}", proxy, err))?;
+                http_client = http_client.proxy(proxy);
+            }
+            Arc::new(
+                ReqwestFetcher::new(
+                    http_client
+                        .build()
+                        .map_err(|err| format!("Couldn't set up the HTTP client: {}", err))?,
+                )
+                .read_timeout(options.read_timeout)
+                .max_bandwidth(options.max_bandwidth),
+            )
+        }
+    };
+    let downloader = Downloader::new(fetcher, options.retry_policy.clone())
+        .per_host_limit(options.per_host_limit)
+        .rate_limit(rate_limit)
+        .respect_robots(options.respect_robots && options.mirror.is_none())
+        .cookies(options.cookies.clone())
+        .http_cache(if options.cache && options.mirror.is_none() {
+            HttpCache::for_user()
+        } else {
+            None
+        });
+    // Held to the same limits as the downloader, it's the same site
+    let renderer = match (&options.renderer, &options.mirror) {
+        (Some(renderer), None) => Some(downloader.with_fetcher(renderer.clone())),
+        _ => None,
+    };
+
+    let mut home_url = site.clone();
+    let mut home_html = match known_extractor
+        .as_ref()
+        .and_then(|extractor| extractor.overview_request())
+    {
+        Some(request) => {
+            debug!(url = request.url(), "getting the novel from the site's API");
+            home_url = request.url().to_string();
+            fetch_page(&downloader, &request).await?
+        }
+        None => downloader.get_text(&site).await?,
+    };
+
+    let extractor = match known_extractor {
+        Some(extractor) => extractor,
+        None => {
+            let site_type = SiteType::from_html(&home_html).ok_or(
+                "Couldn't tell which site this is, pass --site-type to pick one \
+                     (--site-type generic works on many sites)",
+            )?;
+            progress::info(format!("Using the {} extractor", site_type.name()));
+            site_type.extractor(&site)
+        }
+    };
+
+    if let Some(url) = extractor.proceed_url(&home_html) {
+        progress::info(format!(
+            "Going past the notice on the novel's page to {}",
+            url
+        ));
+        home_html = downloader.get_text(&url).await?;
+        home_url = url;
+    }
+
+    let mut overview = extractor
+        .extract_overview(&home_html, &downloader)
+        .await
+        .map_err(|err| Error::extract(home_url.as_str(), err))?;
+    debug!(
+        title = overview.title.as_str(),
+        author = overview.author.as_str(),
+        cover = ?overview.img_url,
+        chapters = overview.download_urls.len(),
+        "extracted the overview"
+    );
+    if options.enrich && options.mirror.is_none() {
+        enrich(&downloader, &mut overview).await;
+    }
+    let mut links = link_texts(&home_html, &home_url);
+    fetch_chapter_list(
+        &downloader,
+        extractor.as_ref(),
+        &home_html,
+        &mut overview,
+        &mut links,
+    )
+    .await?;
+    if overview.download_urls.is_empty() {
+        let err = ExtractError::MissingElement("chapter links");
+        return Err(Error::extract(home_url, err));
+    }
+    let found = overview.download_urls.len();
+    overview.download_urls = normalize_chapter_urls(&overview.download_urls);
+    for url in &overview.download_urls {
+        if !overview.chapter_titles.contains_key(url) {
+            if let Some(text) = links.remove(url) {
+                overview.chapter_titles.insert(url.clone(), text);
+            }
+        }
+    }
+    if overview.download_urls.len() < found {
+        progress::info(format!(
+            "Ignoring {} duplicate chapter links",
+            found - overview.download_urls.len()
+        ));
+    }
+    match check_order(&overview.download_urls) {
+        Some(mut report) => {
+            if options.chapter_order == ChapterOrder::Number {
+                report.out_of_order = 0;
+            }
+            for warning in report.warnings() {
+                progress::warn(warning);
+            }
+        }
+        None => debug!("too few chapter urls have a number to check their order"),
+    }
+    if options.chapter_order == ChapterOrder::Number {
+        sort_by_number(&mut overview.download_urls);
+    }
+
+    let source = NovelSource {
+        site,
+        downloader,
+        renderer,
+        extractor,
+    };
+    Ok((source, overview))
+}
+
+/// Follows the extractor's requests for the rest of the chapter list, for sites
+/// that paginate it or load it with AJAX. The text of the links on those pages
+/// is added to `links`.
+async fn fetch_chapter_list(
+    downloader: &Downloader,
+    extractor: &(dyn Extractor + Send + Sync),
+    home_html: &str,
+    overview: &mut Overview,
+    links: &mut HashMap<String, String>,
+) -> Result<()> {
+    let mut requested = HashSet::new();
+    let mut next_request = extractor.next_chapter_list_request(home_html);
+    while let Some(request) = next_request {
+        // Stop if the last page links back to one that was already fetched
+        if !requested.insert(request.clone()) {
+            break;
+        }
+        progress::info(format!("Getting more chapters from {}", request.url()));
+        let html = match fetch_page(downloader, &request).await {
+            Ok(html) => html,
+            // What was found so far is still better than nothing
+            Err(err) if !overview.download_urls.is_empty() => {
+                progress::warn(format!(
+                    "Couldn't get the rest of the chapter list, only using the {} chapters found so far: {}",
+                    overview.download_urls.len(),
+                    err
+                ));
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        extractor
+            .extract_chapter_list(&html, overview, downloader)
+            .await
+            .map_err(|err| Error::extract(request.url(), err))?;
+        for (url, text) in link_texts(&html, request.url()) {
+            links.entry(url).or_insert(text);
+        }
+        debug!(
+            url = request.url(),
+            chapters = overview.download_urls.len(),
+            "extracted more of the chapter list"
+        );
+        next_request = extractor.next_chapter_list_request(&html);
+    }
+    Ok(())
+}
+
+/// Sends a request the extractor asked for
+async fn fetch_page(
+    downloader: &Downloader,
+    request: &PageRequest,
+) -> Result<String, crate::downloader::Error> {
+    match request {
+        PageRequest::Get(url) => downloader.get_text(url).await,
+        PageRequest::PostForm { url, form } => downloader.post_form(url, form).await,
+    }
+}
+
+fn font_mimetype(file_name: &str) -> Option<&'static str> {
+    let extension = file_name.rsplit('.').next()?.to_lowercase();
+    match extension.as_str() {
+        "ttf" | "otf" => Some("application/vnd.ms-opentype"),
+        "woff" => Some("application/font-woff"),
+        _ => None,
+    }
+}
+
+/// Downloads the cover, anything that isn't PNG or JPEG is converted to one of
+/// those since that's all EPUB readers are guaranteed to support
+async fn download_cover(downloader: &Downloader, image_url: &str) -> Result<Option<Image>> {
+    let (mimetype, data) = downloader.get_bytes(image_url).await?;
+    Ok(cover_image(mimetype, data))
+}
+
+/// The cover at `source`, downloaded when it's an http(s) url and read from
+/// the file otherwise
+async fn read_cover(downloader: &Downloader, source: &str) -> Result<Option<Image>> {
+    if is_http_url(source) {
+        return download_cover(downloader, source).await;
+    }
+    let path = PathBuf::from(source);
+    let data = tokio::fs::read(&path).await?;
+    let mimetype = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.eq_ignore_ascii_case("png") => Some("image/png"),
+        Some(extension)
+            if extension.eq_ignore_ascii_case("jpg") || extension.eq_ignore_ascii_case("jpeg") =>
+        {
+            Some("image/jpeg")
+        }
+        _ => None,
+    };
+    Ok(cover_image(mimetype.map(str::to_string), data))
+}
+
+/// The cover for an image of type `mimetype`, converted to PNG or JPEG when
+/// it's something else. `None` when it can't be.
+fn cover_image(mimetype: Option<String>, data: Vec<u8>) -> Option<Image> {
+    let (mimetype, data) = match mimetype.as_deref() {
+        Some("image/png") | Some("image/jpeg") => (mimetype.unwrap(), data),
+        _ => match convert_image(&data) {
+            Ok((converted_mimetype, converted)) => {
+                progress::info(format!(
+                    "Converted the cover from {} to {}",
+                    mimetype.as_deref().unwrap_or("unknown type"),
+                    converted_mimetype
+                ));
+                (converted_mimetype.to_string(), converted)
+            }
+            Err(err) => {
+                progress::warn(format!("Couldn't convert the cover photo: {}", err));
+                return None;
+            }
+        },
+    };
+    let path = if mimetype == "image/png" {
+        "cover.png"
+    } else {
+        "cover.jpg"
+    };
+    Some(Image {
+        path: path.to_string(),
+        mimetype,
+        data,
+    })
+}
+
+/// What every chapter download needs, shared between the download and
+/// processing tasks
+struct DownloadContext {
+    downloader: Downloader,
+    /// `downloader` without the HTTP cache, chapter pages are kept in `cache`
+    /// so they'd only be stored twice
+    chapter_downloader: Downloader,
+    /// See `BookOptions::renderer`
+    renderer: Option<Downloader>,
+    cache: Option<ChapterCache>,
+    archive: Option<ChapterArchive>,
+    spool: Spool,
+    extractor: Arc<dyn Extractor + Send + Sync>,
+    pipeline: TransformPipeline,
+    template: Option<ChapterTemplate>,
+    title_from: Option<TitleSource>,
+    content_size: ContentSize,
+    images: ImageOptions,
+    novel_title: String,
+    author: String,
+}
+
+/// A chapter's page, waiting to be processed
+struct FetchedChapter {
+    number: usize,
+    url: String,
+    html: String,
+    /// The chapter's other pages, for sites that split chapters up
+    more_pages: Vec<String>,
+}
+
+/// A chapter whose download failed
+struct FailedChapter {
+    number: usize,
+    url: String,
+    error: Error,
+}
+
+impl fmt::Display for FailedChapter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "chapter {}: {}", self.number, self.error)
+    }
+}
+
+impl From<FailedChapter> for Error {
+    fn from(failed: FailedChapter) -> Self {
+        Error::Chapter {
+            number: failed.number,
+            url: failed.url,
+            source: Box::new(failed.error),
+        }
+    }
+}
+
+/// Title of the page standing in for a chapter that couldn't be downloaded,
+/// `ExistingEpub` goes by it to download the chapter again on `--update`
+pub fn placeholder_title(number: usize) -> String {
+    format!("Chapter {} unavailable", number)
+}
+
+/// Spools a page saying the chapter couldn't be downloaded, with a link to it
+async fn write_placeholder(spool: &Spool, failed: &FailedChapter) -> Result<DownloadedChapter> {
+    let title = placeholder_title(failed.number);
+    let body = format!(
+        r#"<h1>{}</h1>
+        <p>This chapter couldn't be downloaded: {}</p>
+        <p>It's at <a href="{}">{}</a></p>"#,
+        title,
+        escape_text(&failed.error.to_string()),
+        escape_attribute(&failed.url),
+        escape_text(&failed.url)
+    );
+    let content_file = spool
+        .write(
+            &format!("c{}.xhtml", failed.number),
+            chapter_xhtml(&title, &body).as_bytes(),
+        )
+        .await?;
+    Ok(DownloadedChapter {
+        number: failed.number,
+        url: failed.url.clone(),
+        title,
+        volume: None,
+        content_file,
+        images: Vec::new(),
+    })
+}
+
+/// Gets the chapter's pages, more than one for sites that split chapters up
+async fn fetch_chapter(context: &DownloadContext, url: &str) -> Result<Vec<String>> {
+    let mut pages =
+        vec![fetch_chapter_page(context, url, &context.extractor.chapter_request(url)).await?];
+    let mut page_url = url.to_string();
+    let mut seen = HashSet::new();
+    seen.insert(url.to_string());
+    while pages.len() < MAX_CHAPTER_PAGES {
+        let last = pages.last().unwrap();
+        let next = match context
+            .extractor
+            .next_chapter_page(last, &page_url, url, pages.len())
+        {
+            Some(next) if seen.insert(next.clone()) => next,
+            _ => break,
+        };
+        debug!(
+            page = pages.len() + 1,
+            url = next.as_str(),
+            "the chapter goes on"
+        );
+        let request = PageRequest::Get(next.clone());
+        pages.push(fetch_chapter_page(context, &next, &request).await?);
+        page_url = next;
+    }
+    Ok(pages)
+}
+
+/// Gets a page of the chapter from the archive, the cache or the site
+async fn fetch_chapter_page(
+    context: &DownloadContext,
+    url: &str,
+    request: &PageRequest,
+) -> Result<String> {
+    let DownloadContext {
+        chapter_downloader,
+        cache,
+        archive,
+        ..
+    } = context;
+    if let Some(archive) = archive {
+        match archive.get(url).await {
+            Ok(Some(html)) => {
+                debug!("using the archived chapter");
+                return Ok(html);
+            }
+            Ok(None) => {}
+            Err(err) => progress::warn(format!(
+                "Couldn't look {} up in {}: {}",
+                url,
+                archive.path().display(),
+                err
+            )),
+        }
+    }
+    let cached_html = match cache {
+        Some(cache) => cache.get(url).await,
+        None => None,
+    };
+    let html = match cached_html {
+        Some(html) => {
+            debug!("using the cached chapter");
+            html
+        }
+        None => {
+            let html = fetch_page(chapter_downloader, request).await?;
+            if let Some(cache) = cache {
+                cache_page(cache, url, &html).await;
+            }
+            html
+        }
+    };
+    // Cached chapters go in too, they were downloaded by an earlier run
+    if let Some(archive) = archive {
+        archive_page(archive, url, &html).await;
+    }
+    Ok(html)
+}
+
+async fn cache_page(cache: &ChapterCache, url: &str, html: &str) {
+    if let Err(err) = cache.put(url, html).await {
+        progress::warn(format!("Couldn't cache {}: {}", url, err));
+    }
+}
+
+async fn archive_page(archive: &ChapterArchive, url: &str, html: &str) {
+    if let Err(err) = archive.put(url, html).await {
+        progress::warn(format!(
+            "Couldn't archive {} in {}: {}",
+            url,
+            archive.path().display(),
+            err
+        ));
+    }
+}
+
+/// Turns the chapter's page into EPUB ready xhtml and writes it to the spool
+/// along with its images. Chapters whose size looks wrong come with a report
+/// of it.
+async fn process_chapter(
+    context: &Arc<DownloadContext>,
+    fetched: FetchedChapter,
+) -> Result<(DownloadedChapter, Option<SuspiciousChapter>)> {
+    let FetchedChapter {
+        number,
+        url,
+        mut html,
+        more_pages,
+    } = fetched;
+    // Extractors can send requests of their own so they're awaited here, the
+    // transforms are CPU bound so they're kept off the threads running the
+    // downloads
+    let mut extracted = context
+        .extractor
+        .extract_chapter(&html, &context.downloader)
+        .await;
+    let size = extracted
+        .as_ref()
+        .ok()
+        .map(|chapter| text_size(&chapter.content, &chapter.title));
+    let too_short = match size {
+        Some(size) => matches!(
+            context.content_size.check(size),
+            Some(SizeProblem::TooShort(_))
+        ),
+        None => true,
+    };
+    let plain_get = context.extractor.chapter_request(&url) == PageRequest::Get(url.clone());
+    if let Some(renderer) = &context.renderer {
+        if too_short && plain_get && more_pages.is_empty() {
+            if let Some((rendered_html, rendered)) =
+                render_chapter(context, renderer, &url, size).await
+            {
+                html = rendered_html;
+                extracted = Ok(rendered);
+            }
+        }
+    }
+    let mut chapter = extracted.map_err(|err| Error::extract(url.as_str(), err))?;
+    if !more_pages.is_empty() {
+        let mut bodies = vec![chapter_body(&chapter.content)];
+        for page in &more_pages {
+            let more = context
+                .extractor
+                .extract_chapter(page, &context.downloader)
+                .await
+                .map_err(|err| Error::extract(url.as_str(), err))?;
+            let body = chapter_body(&more.content);
+            // Some sites answer pages past the last with the last one again
+            if bodies.last() != Some(&body) {
+                bodies.push(body);
+            }
+        }
+        chapter.content = chapter_xhtml(&chapter.title, &bodies.concat());
+    }
+    trace!(title = chapter.title.as_str(), "extracted the chapter");
+    let processing = context.clone();
+    let chapter_url = url.clone();
+    let (title, content, suspicious) = task::spawn_blocking(move || {
+        let DownloadContext {
+            extractor,
+            pipeline,
+            template,
+            title_from,
+            content_size,
+            novel_title,
+            author,
+            ..
+        } = processing.as_ref();
+        if let Some(source) = title_from {
+            let heading = extractor.title_heading();
+            match find_title(
+                *source,
+                &html,
+                &chapter.content,
+                &chapter_url,
+                heading.as_ref(),
+            ) {
+                Some(title) => {
+                    chapter.content = chapter_xhtml(&title, &chapter_body(&chapter.content));
+                    chapter.title = title;
+                }
+                None => debug!(?source, "no title there, keeping the extractor's"),
+            }
+        }
+        let suspicious = content_size
+            .check(text_size(&chapter.content, &chapter.title))
+            .map(|problem| {
+                let fallback = fallback_content(&html, &chapter.title, content_size);
+                debug!(%problem, found = fallback.is_some(), "the chapter's size looks wrong");
+                let fallback_size = fallback.map(|(content, size)| {
+                    chapter.content = content;
+                    size
+                });
+                SuspiciousChapter {
+                    number,
+                    url: chapter_url.clone(),
+                    problem,
+                    fallback_size,
+                }
+            });
+        let title = extractor.clean_chapter_title(&chapter.title, novel_title);
+        let content = match template {
+            Some(template) => template.render(&ChapterValues {
+                title: &title,
+                content: &chapter_body(&chapter.content),
+                number: Some(number),
+                url: &chapter_url,
+                novel_title,
+                author,
+            }),
+            None => chapter.content,
+        };
+        Ok::<_, Error>((title, pipeline.run(&content), suspicious))
+    })
+    .await??;
+
+    let DownloadContext {
+        downloader,
+        spool,
+        images: image_options,
+        ..
+    } = context.as_ref();
+    let (content, images) = embed_images(downloader, &content, &url).await;
+    let image_options = *image_options;
+    let images = task::spawn_blocking(move || {
+        images
+            .into_iter()
+            .map(|image| image_options.shrink(image))
+            .collect::<Vec<_>>()
+    })
+    .await?;
+    debug!(
+        images = images.len(),
+        bytes = content.len(),
+        "processed the chapter"
+    );
+
+    let content_file = spool
+        .write(&format!("c{}.xhtml", number), content.as_bytes())
+        .await?;
+    let mut spooled_images = Vec::with_capacity(images.len());
+    for image in images {
+        let file = spool.write(&image.path, &image.data).await?;
+        spooled_images.push(SpooledImage {
+            path: image.path,
+            mimetype: image.mimetype,
+            file,
+        });
+    }
+
+    let chapter = DownloadedChapter {
+        number,
+        url,
+        title,
+        volume: None,
+        content_file,
+        images: spooled_images,
+    };
+    Ok((chapter, suspicious))
+}
+
+/// The chapter's page as the renderer loads it and what's extracted from it,
+/// for a chapter that came out empty or too short. Only returned when it has
+/// more text than the `size` extracted before, the page then replaces the one
+/// in the cache and archive so it isn't rendered again next time.
+async fn render_chapter(
+    context: &DownloadContext,
+    renderer: &Downloader,
+    url: &str,
+    size: Option<usize>,
+) -> Option<(String, Chapter)> {
+    debug!(?size, "loading the chapter again with the renderer");
+    let html = match renderer.get_text(url).await {
+        Ok(html) => html,
+        Err(err) => {
+            progress::warn(format!("Couldn't render {}: {}", url, err));
+            return None;
+        }
+    };
+    let chapter = context
+        .extractor
+        .extract_chapter(&html, &context.downloader)
+        .await
+        .ok()?;
+    let rendered_size = text_size(&chapter.content, &chapter.title);
+    debug!(size = rendered_size, "extracted the rendered chapter");
+    if matches!(size, Some(size) if rendered_size <= size) {
+        return None;
+    }
+    if let Some(cache) = &context.cache {
+        cache_page(cache, url, &html).await;
+    }
+    if let Some(archive) = &context.archive {
+        archive_page(archive, url, &html).await;
+    }
+    Some((html, chapter))
+}
+
+/// The chapter's content as the generic extractor finds it, for when the
+/// site's extractor got a size that looks wrong. Only returned when its size
+/// looks right, along with that size.
+fn fallback_content(
+    html: &str,
+    title: &str,
+    content_size: &ContentSize,
+) -> Option<(String, usize)> {
+    let content = GenericExtractor::new("").chapter(html).ok()?.content;
+    let size = text_size(&content, title);
+    match content_size.check(size) {
+        Some(_) => None,
+        None => Some((content, size)),
+    }
+}
@@ -0,0 +1,162 @@
+use crate::fetcher::{FetchError, Fetcher, Request, Response};
+use async_trait::async_trait;
+use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::cdp::browser_protocol::network::{Headers, SetExtraHttpHeadersParams};
+use chromiumoxide::Page;
+use futures::StreamExt;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::StatusCode;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio1::runtime::{self, Handle, Runtime};
+use tracing::debug;
+
+/// Loads pages in a headless Chromium and answers with their markup once the
+/// page's JavaScript ran, for sites that only fill in chapters that way. It's
+/// slow, so it's meant for `BookOptions::renderer` rather than every request.
+///
+/// Chromium is only started for the first page, and found the way
+/// chromiumoxide does unless `chrome` says where it is. The status of the
+/// response isn't known, every page that loads is a 200.
+pub struct BrowserFetcher {
+    chrome: Option<PathBuf>,
+    user_agent: Option<String>,
+    browser: Mutex<Option<Running>>,
+}
+
+/// Chromium once it's started, and the runtime talking to it: chromiumoxide
+/// is on tokio 1 where box2epub is on 0.2, so it gets one of its own
+struct Running {
+    browser: Arc<Browser>,
+    runtime: Runtime,
+}
+
+impl BrowserFetcher {
+    pub fn new() -> Self {
+        BrowserFetcher {
+            chrome: None,
+            user_agent: None,
+            browser: Mutex::new(None),
+        }
+    }
+
+    /// Chromium or Chrome executable to run instead of the one chromiumoxide
+    /// finds
+    pub fn chrome(mut self, chrome: Option<PathBuf>) -> Self {
+        self.chrome = chrome;
+        self
+    }
+
+    /// Sent instead of Chromium's own, which says it's headless
+    pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    /// Chromium and the runtime to drive it from, started the first time
+    async fn browser(&self) -> Result<(Arc<Browser>, Handle), FetchError> {
+        let mut running = self.browser.lock().await;
+        if let Some(running) = &*running {
+            return Ok((running.browser.clone(), running.runtime.handle().clone()));
+        }
+        let mut config = BrowserConfig::builder();
+        if let Some(chrome) = &self.chrome {
+            config = config.chrome_executable(chrome);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            config = config.arg(format!("--user-agent={}", user_agent));
+        }
+        let config = config
+            .build()
+            .map_err(|err| format!("Couldn't start Chromium: {}", err))?;
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(|err| format!("Couldn't start Chromium: {}", err))?;
+        debug!("starting Chromium");
+        let launched = runtime
+            .spawn(async move {
+                let (launched, mut handler) = Browser::launch(config).await?;
+                // Chromium's messages only get through while the handler is
+                // polled
+                tokio1::spawn(async move {
+                    while let Some(event) = handler.next().await {
+                        if event.is_err() {
+                            break;
+                        }
+                    }
+                });
+                Ok::<_, chromiumoxide::error::CdpError>(launched)
+            })
+            .await?
+            .map_err(|err| format!("Couldn't start Chromium: {}", err))?;
+        let launched = Arc::new(launched);
+        let handle = runtime.handle().clone();
+        *running = Some(Running {
+            browser: launched.clone(),
+            runtime,
+        });
+        Ok((launched, handle))
+    }
+}
+
+impl Default for BrowserFetcher {
+    fn default() -> Self {
+        BrowserFetcher::new()
+    }
+}
+
+#[async_trait]
+impl Fetcher for BrowserFetcher {
+    async fn fetch(&self, request: Request) -> Result<Response, FetchError> {
+        if request.form.is_some() {
+            return Err("Chromium can only load pages, not send forms".into());
+        }
+        let (browser, runtime) = self.browser().await?;
+        let (url, html) = runtime
+            .spawn(async move {
+                let page = browser.new_page("about:blank").await?;
+                let loaded = load(&page, &request).await;
+                // Closed however loading went, or the tab stays open for as
+                // long as Chromium runs
+                let closed = page.close().await;
+                let loaded = loaded?;
+                closed?;
+                Ok::<_, FetchError>(loaded)
+            })
+            .await??;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        Ok(Response {
+            url,
+            status: StatusCode::OK,
+            headers,
+            body: html.into_bytes(),
+        })
+    }
+}
+
+/// Loads the request's page in `page`, returns where it ended up and its
+/// markup
+async fn load(page: &Page, request: &Request) -> Result<(String, String), FetchError> {
+    // The cookies from the downloader, Chromium's own jar is empty
+    let headers: serde_json::Map<String, serde_json::Value> = request
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.into())))
+        .collect();
+    if !headers.is_empty() {
+        page.execute(SetExtraHttpHeadersParams::new(Headers::new(headers)))
+            .await?;
+    }
+    page.goto(request.url.as_str()).await?;
+    page.wait_for_navigation().await?;
+    let url = page.url().await?.unwrap_or_else(|| request.url.clone());
+    Ok((url, page.content().await?))
+}
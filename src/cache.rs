@@ -0,0 +1,266 @@
+use crate::book::normalize_site;
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// File in a novel's cache directory with the url of the novel's page, so the
+/// directory can be told apart from the others
+const SITE_FILE: &str = "site.txt";
+
+/// Keeps downloaded chapter html on disk so an interrupted run can pick up where
+/// it left off instead of downloading everything again.
+///
+/// Every novel gets its own directory and every chapter is stored in a file named
+/// after the hash of its url.
+#[derive(Clone)]
+pub struct ChapterCache {
+    dir: PathBuf,
+    site: String,
+}
+
+impl ChapterCache {
+    /// Cache for the novel at `site`, kept in the user's cache directory
+    /// (i.e. `~/.cache/box2epub` on Linux)
+    pub fn for_site(site: &str) -> Option<Self> {
+        user_cache_dir().map(|dir| Self::in_dir(dir, site))
+    }
+
+    pub fn in_dir(root: impl AsRef<Path>, site: &str) -> Self {
+        ChapterCache {
+            dir: root.as_ref().join(hash(site)),
+            site: site.to_string(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub async fn get(&self, url: &str) -> Option<String> {
+        tokio::fs::read_to_string(self.path(url)).await.ok()
+    }
+
+    pub async fn put(&self, url: &str, html: &str) -> io::Result<()> {
+        if tokio::fs::metadata(&self.dir).await.is_err() {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(self.dir.join(SITE_FILE), &self.site).await?;
+        }
+        // Write to a temporary file first so getting killed halfway through a
+        // write doesn't leave a truncated chapter behind
+        let path = self.path(url);
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, html).await?;
+        tokio::fs::rename(&tmp_path, &path).await
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{}.html", hash(url)))
+    }
+}
+
+/// Where everything box2epub caches for the user goes, i.e. `~/.cache/box2epub`
+/// on Linux
+pub fn user_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("box2epub"))
+}
+
+/// A novel's chapters in the cache
+pub struct CachedNovel {
+    /// The novel's page, `None` for ones cached before it was written down
+    pub site: Option<String>,
+    pub dir: PathBuf,
+    pub chapters: usize,
+    /// Bytes taken up by the chapters
+    pub size: u64,
+    /// When the last chapter was cached
+    pub modified: Option<SystemTime>,
+}
+
+/// Novels with chapters in the cache at `root`, the most recently cached first
+pub fn cached_novels(root: &Path) -> io::Result<Vec<CachedNovel>> {
+    let mut novels = Vec::new();
+    for entry in read_dir(root)? {
+        let entry = entry?;
+        // `http` and `checkpoints` are next to the novels' directories, which
+        // are named after a hash
+        if !entry.file_type()?.is_dir() || !is_hash(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let dir = entry.path();
+        let site = fs::read_to_string(dir.join(SITE_FILE)).ok();
+        let mut novel = CachedNovel {
+            site: site.map(|site| site.trim().to_string()),
+            dir,
+            chapters: 0,
+            size: 0,
+            modified: None,
+        };
+        for chapter in fs::read_dir(&novel.dir)? {
+            let chapter = chapter?;
+            if chapter.path().extension().and_then(|ext| ext.to_str()) != Some("html") {
+                continue;
+            }
+            let metadata = chapter.metadata()?;
+            novel.chapters += 1;
+            novel.size += metadata.len();
+            novel.modified = novel.modified.max(metadata.modified().ok());
+        }
+        novels.push(novel);
+    }
+    novels.sort_by_key(|novel| Reverse(novel.modified));
+    Ok(novels)
+}
+
+/// Bytes taken up by everything in `path`, 0 when it isn't there
+pub fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in read_dir(path)? {
+        let entry = entry?;
+        size += if entry.file_type()?.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            entry.metadata()?.len()
+        };
+    }
+    Ok(size)
+}
+
+/// What `prune` removed
+#[derive(Default)]
+pub struct Pruned {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Removes what was cached in `root` before `cutoff`. Checkpoints are removed
+/// as a whole, when nothing in them changed since, so a build is never
+/// resumed with part of its chapters missing.
+pub fn prune(root: &Path, cutoff: SystemTime) -> io::Result<Pruned> {
+    let mut pruned = Pruned::default();
+    let checkpoints = root.join("checkpoints");
+    for entry in read_dir(&checkpoints)? {
+        let entry = entry?;
+        // Lock files are left alone, a build may be holding one
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let dir = entry.path();
+        if newest_modified(&dir)? < Some(cutoff) {
+            pruned.files += count_files(&dir)?;
+            pruned.bytes += dir_size(&dir)?;
+            fs::remove_dir_all(&dir)?;
+        }
+    }
+    prune_files(root, &checkpoints, cutoff, &mut pruned)?;
+    Ok(pruned)
+}
+
+/// Removes the files in `dir` last changed before `cutoff`, and directories
+/// that are left with nothing cached in them
+fn prune_files(dir: &Path, skip: &Path, cutoff: SystemTime, pruned: &mut Pruned) -> io::Result<()> {
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path == skip {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            prune_files(&path, skip, cutoff, pruned)?;
+            let left: Vec<_> = fs::read_dir(&path)?.collect::<io::Result<_>>()?;
+            if left.iter().all(|entry| entry.file_name() == SITE_FILE) {
+                fs::remove_dir_all(&path)?;
+            }
+        } else if entry.file_name() != SITE_FILE {
+            let metadata = entry.metadata()?;
+            if metadata.modified()? < cutoff {
+                fs::remove_file(&path)?;
+                pruned.files += 1;
+                pruned.bytes += metadata.len();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes everything cached for `url` from the cache at `root`: for a
+/// novel's page its chapters and checkpoint, for any other page the page
+/// itself. Returns what was removed.
+pub fn purge(root: &Path, url: &str) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    let site = normalize_site(url);
+    for dir in &[
+        ChapterCache::in_dir(root, &site).dir,
+        root.join("checkpoints").join(hash(&site)),
+    ] {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+            removed.push(dir.clone());
+        }
+    }
+
+    let name = hash(url);
+    let mut files = vec![
+        root.join("http").join(format!("{}.json", name)),
+        root.join("http").join(format!("{}.body", name)),
+    ];
+    for entry in read_dir(root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files.push(entry.path().join(format!("{}.html", name)));
+        }
+    }
+    for file in files {
+        if file.exists() {
+            fs::remove_file(&file)?;
+            removed.push(file);
+        }
+    }
+    Ok(removed)
+}
+
+/// The entries of `dir`, none when it isn't there
+fn read_dir(dir: &Path) -> io::Result<Vec<io::Result<fs::DirEntry>>> {
+    match fs::read_dir(dir) {
+        Ok(entries) => Ok(entries.collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+fn newest_modified(dir: &Path) -> io::Result<Option<SystemTime>> {
+    let mut newest = fs::metadata(dir)?.modified().ok();
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let modified = if entry.file_type()?.is_dir() {
+            newest_modified(&entry.path())?
+        } else {
+            entry.metadata()?.modified().ok()
+        };
+        newest = newest.max(modified);
+    }
+    Ok(newest)
+}
+
+fn count_files(dir: &Path) -> io::Result<usize> {
+    let mut count = 0;
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        count += if entry.file_type()?.is_dir() {
+            count_files(&entry.path())?
+        } else {
+            1
+        };
+    }
+    Ok(count)
+}
+
+fn is_hash(name: &str) -> bool {
+    name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub(crate) fn hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
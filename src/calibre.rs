@@ -0,0 +1,89 @@
+use crate::extractor::Overview;
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A calibre library to add books to once they're written, with calibre's
+/// `calibredb`
+#[derive(Debug, Clone, Default)]
+pub struct CalibreLibrary {
+    /// Library directory (or content server url), calibre's default library
+    /// when it isn't set
+    pub path: Option<PathBuf>,
+}
+
+/// Which book of a series split into volumes is being added
+pub struct SeriesPosition<'a> {
+    pub name: &'a str,
+    pub index: usize,
+}
+
+impl CalibreLibrary {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        CalibreLibrary { path }
+    }
+
+    /// Adds the book at `path` with the novel's metadata. A book with the
+    /// same title and author that's already in the library (i.e. from an
+    /// earlier `update`) has its file replaced instead of being added twice.
+    pub fn add(
+        &self,
+        path: &Path,
+        overview: &Overview,
+        site: &str,
+        series: Option<SeriesPosition>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut command = Command::new("calibredb");
+        command.arg("add");
+        if let Some(library) = &self.path {
+            command.arg("--with-library").arg(library);
+        }
+        command
+            .arg("--automerge")
+            .arg("overwrite")
+            .arg("--title")
+            .arg(&overview.title)
+            .arg("--authors")
+            .arg(&overview.author)
+            .arg("--identifier")
+            .arg(format!("url:{}", site));
+        if !overview.genres.is_empty() {
+            // Commas separate the tags, a genre with one in it becomes several
+            let tags: Vec<&str> = overview
+                .genres
+                .iter()
+                .flat_map(|genre| genre.split(','))
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            command.arg("--tags").arg(tags.join(","));
+        }
+        if let Some(language) = &overview.language {
+            command.arg("--languages").arg(language);
+        }
+        if let Some(series) = series {
+            command
+                .arg("--series")
+                .arg(series.name)
+                .arg("--series-index")
+                .arg(series.index.to_string());
+        }
+
+        let output = command.arg(path).output().map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                "Adding books to calibre needs its calibredb installed and on the PATH".to_string()
+            } else {
+                format!("Couldn't run calibredb: {}", err)
+            }
+        })?;
+        if !output.status.success() {
+            return Err(format!(
+                "calibredb couldn't add the book: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use encoding_rs::{Encoding, BIG5, EUC_JP, EUC_KR, GBK, SHIFT_JIS, UTF_8, WINDOWS_1252};
+use regex::bytes::Regex;
+use tracing::debug;
+
+lazy_static! {
+    // <meta charset="gbk"> or <meta http-equiv="Content-Type" content="text/html; charset=gbk">
+    static ref META_CHARSET_REGEX: Regex =
+        Regex::new(r#"(?i)<meta[^>]+charset\s*=\s*["']?\s*([a-z0-9_:.-]+)"#).unwrap();
+}
+
+/// How far into the page to look for a `<meta>` charset, browsers stop at
+/// the first 1024 bytes
+const META_PRESCAN_BYTES: usize = 1024;
+
+/// Encodings old sites tend to be in, tried in order when a page doesn't say
+/// what it's in and isn't UTF-8
+const SNIFFED_ENCODINGS: [&Encoding; 5] = [GBK, BIG5, SHIFT_JIS, EUC_JP, EUC_KR];
+
+/// Decodes a page, working out its charset from (in order) a byte order mark,
+/// the Content-Type header, a `<meta>` tag and finally its content. A header
+/// saying UTF-8 is ignored when the page isn't, some servers say that for
+/// everything.
+pub fn decode_page(content_type: Option<&str>, data: &[u8]) -> String {
+    if let Some((encoding, _)) = Encoding::for_bom(data) {
+        return encoding.decode(data).0.into_owned();
+    }
+    let declared = content_type
+        .and_then(header_charset)
+        .filter(|&encoding| encoding != UTF_8 || std::str::from_utf8(data).is_ok())
+        .or_else(|| meta_charset(data));
+    let encoding = match declared {
+        Some(encoding) => encoding,
+        None if std::str::from_utf8(data).is_ok() => UTF_8,
+        None => {
+            let encoding = sniff(data);
+            debug!(encoding = encoding.name(), "guessed the page's charset");
+            encoding
+        }
+    };
+    encoding.decode_without_bom_handling(data).0.into_owned()
+}
+
+/// The charset parameter of a Content-Type
+fn header_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let mut pair = param.splitn(2, '=');
+        let name = pair.next()?.trim();
+        let value = pair.next()?.trim().trim_matches('"');
+        if name.eq_ignore_ascii_case("charset") {
+            Encoding::for_label(value.as_bytes())
+        } else {
+            None
+        }
+    })
+}
+
+fn meta_charset(data: &[u8]) -> Option<&'static Encoding> {
+    let head = &data[..data.len().min(META_PRESCAN_BYTES)];
+    let encoding = Encoding::for_label(&META_CHARSET_REGEX.captures(head)?[1])?;
+    // A page can't really be in UTF-16 if the tag could be read as ASCII
+    if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+        Some(UTF_8)
+    } else {
+        Some(encoding)
+    }
+}
+
+/// Guesses the encoding of text that isn't UTF-8: of the encodings that
+/// decode it without errors, the one that gives the most common CJK
+/// characters wins. Windows-1252 decodes anything, so it's what's left.
+fn sniff(data: &[u8]) -> &'static Encoding {
+    SNIFFED_ENCODINGS
+        .iter()
+        // The first of equally likely encodings wins
+        .rev()
+        .filter_map(|&encoding| {
+            let text = encoding.decode_without_bom_handling_and_without_replacement(data)?;
+            Some((encoding, score(&text, encoding)))
+        })
+        .filter(|&(_, score)| score > 0)
+        .max_by_key(|&(_, score)| score)
+        .map_or(WINDOWS_1252, |(encoding, _)| encoding)
+}
+
+/// The most frequent characters in Chinese (simplified and traditional),
+/// Japanese and Korean text. Decoding with the wrong encoding still gives
+/// ideographs, but hardly ever these.
+const COMMON_CHARACTERS: &str =
+    "的一是不了在人有我他这這个個们們来來说說你她到上着著就那么麼要也没沒和看道时時会會过過中大\
+                                 이의다는에을를하고가지한도서";
+
+/// Characters that show up in real Chinese, Japanese or Korean text count for
+/// the encoding, ones that only come out of decoding with the wrong one
+/// (private use, rare ideographs, halfwidth katakana) against it. Chinese
+/// encodings have kana too, but Chinese text doesn't use it.
+fn score(text: &str, encoding: &Encoding) -> i64 {
+    let japanese = encoding == SHIFT_JIS || encoding == EUC_JP;
+    text.chars()
+        .filter(|c| !c.is_ascii())
+        .map(|c| match c as u32 {
+            _ if COMMON_CHARACTERS.contains(c) => 10,
+            // Hiragana and katakana
+            0x3040..=0x30FF if japanese => 3,
+            0x3040..=0x30FF => -5,
+            // CJK punctuation, fullwidth forms, ideographs and Hangul
+            0x3000..=0x303F | 0xFF01..=0xFF5E | 0x4E00..=0x9FFF | 0xAC00..=0xD7A3 => 1,
+            _ => -5,
+        })
+        .sum()
+}
@@ -0,0 +1,242 @@
+use crate::book::DownloadedChapter;
+use crate::cache::{hash, user_cache_dir};
+use crate::content_size::SuspiciousChapter;
+use crate::error::{Error, Result};
+use crate::spool::{Spool, SpooledImage};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+const SPOOL_DIR: &str = "spool";
+
+/// Where the checkpoint for the novel at `site` is kept, i.e.
+/// `~/.cache/box2epub/checkpoints/<hash of the url>` on Linux
+pub fn checkpoint_dir(site: &str) -> Option<PathBuf> {
+    user_cache_dir().map(|dir| dir.join("checkpoints").join(hash(site)))
+}
+
+/// First line of the journal, so a checkpoint isn't resumed for another novel
+#[derive(Serialize, Deserialize)]
+struct Header {
+    site: String,
+}
+
+/// A line of the journal for every chapter that made it through the pipeline,
+/// its content and images are in the checkpoint's spool
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    number: usize,
+    url: String,
+    title: String,
+    /// Relative to the spool directory
+    content_file: String,
+    images: Vec<EntryImage>,
+    suspicious: Option<SuspiciousChapter>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntryImage {
+    /// Path inside the EPUB, which is also where it is in the spool
+    path: String,
+    mimetype: String,
+}
+
+/// A build's progress kept on disk, so one that crashed or was killed can be
+/// resumed without downloading and processing the chapters it had finished.
+///
+/// Chapters are spooled into the checkpoint's directory instead of a temporary
+/// one, and each is added to a journal once it's done. A chapter that was
+/// being written when the build stopped isn't in the journal yet and goes
+/// through the pipeline again. Putting the EPUB together only reads the
+/// spool, so a build that stopped there resumes with nothing to download.
+pub struct Checkpoint {
+    dir: PathBuf,
+    journal: Mutex<File>,
+}
+
+/// Keeps other builds out of a checkpoint until it's dropped, see
+/// `Checkpoint::lock`
+pub struct CheckpointLock {
+    _file: File,
+}
+
+/// What a resumed checkpoint had finished
+#[derive(Default)]
+pub struct Finished {
+    pub chapters: Vec<DownloadedChapter>,
+    pub suspicious: Vec<SuspiciousChapter>,
+}
+
+impl Checkpoint {
+    /// Locks the checkpoint in `dir` for this build. Another build of the
+    /// same novel would start over in it, throwing away the chapters spooled
+    /// so far, so it's turned away while the lock is held.
+    ///
+    /// The lock is a file next to `dir`, as starting over removes `dir`.
+    pub fn lock(dir: &Path) -> Result<CheckpointLock> {
+        let path = dir.with_extension("lock");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(CheckpointLock { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(Error::Other(format!(
+                "Another build of this novel is using its checkpoint in {}, wait for it to finish",
+                dir.display()
+            ))),
+            Err(TryLockError::Error(err)) => Err(err.into()),
+        }
+    }
+
+    /// Starts a checkpoint in `dir` for the novel at `site`, throwing away the
+    /// one that was there. Returns the spool to write chapters to.
+    pub fn start(dir: &Path, site: &str) -> Result<(Checkpoint, Spool)> {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        fs::create_dir_all(dir)?;
+        let header = serde_json::to_string(&Header {
+            site: site.to_string(),
+        })?;
+        write_atomically(&dir.join(JOURNAL_FILE), &format!("{}\n", header))?;
+        Self::open(dir)
+    }
+
+    /// Picks up the checkpoint in `dir`, along with the chapters it had
+    /// finished. Starts a new one when there's none.
+    pub fn resume(dir: &Path, site: &str) -> Result<(Checkpoint, Spool, Finished)> {
+        let journal = match fs::read_to_string(dir.join(JOURNAL_FILE)) {
+            Ok(journal) => journal,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let (checkpoint, spool) = Self::start(dir, site)?;
+                return Ok((checkpoint, spool, Finished::default()));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let mut lines = journal.lines();
+        let header_line = lines.next().unwrap_or_default();
+        match serde_json::from_str::<Header>(header_line) {
+            Ok(header) if header.site == site => {}
+            _ => {
+                return Err(Error::Other(format!(
+                    "The checkpoint in {} isn't for {}",
+                    dir.display(),
+                    site
+                )))
+            }
+        }
+
+        let spool_dir = dir.join(SPOOL_DIR);
+        let mut kept = vec![header_line];
+        let mut finished = Finished::default();
+        for line in lines {
+            // Only the last line can be cut short, by the build stopping while
+            // it was written
+            let entry: Entry = match serde_json::from_str(line) {
+                Ok(entry) => entry,
+                Err(_) => break,
+            };
+            let content_file = spool_dir.join(&entry.content_file);
+            if !content_file.is_file() {
+                continue;
+            }
+            kept.push(line);
+            finished.chapters.push(DownloadedChapter {
+                number: entry.number,
+                url: entry.url,
+                title: entry.title,
+                volume: None,
+                content_file,
+                images: entry
+                    .images
+                    .into_iter()
+                    .map(|image| SpooledImage {
+                        file: spool_dir.join(&image.path),
+                        path: image.path,
+                        mimetype: image.mimetype,
+                    })
+                    .collect(),
+            });
+            finished.suspicious.extend(entry.suspicious);
+        }
+        // Without the cut short line, so new chapters don't get appended to it
+        let mut journal = kept.join("\n");
+        journal.push('\n');
+        write_atomically(&dir.join(JOURNAL_FILE), &journal)?;
+
+        let (checkpoint, spool) = Self::open(dir)?;
+        Ok((checkpoint, spool, finished))
+    }
+
+    fn open(dir: &Path) -> Result<(Checkpoint, Spool)> {
+        let journal = OpenOptions::new()
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))?;
+        let checkpoint = Checkpoint {
+            dir: dir.to_path_buf(),
+            journal: Mutex::new(journal),
+        };
+        Ok((checkpoint, Spool::in_dir(dir.join(SPOOL_DIR))?))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Adds a chapter that's in the spool to the journal
+    pub fn record(
+        &self,
+        chapter: &DownloadedChapter,
+        suspicious: Option<&SuspiciousChapter>,
+    ) -> Result<()> {
+        let spool_dir = self.dir.join(SPOOL_DIR);
+        let content_file = chapter
+            .content_file
+            .strip_prefix(&spool_dir)
+            .map_err(|_| Error::Other("The chapter isn't in the checkpoint's spool".to_string()))?;
+        let entry = Entry {
+            number: chapter.number,
+            url: chapter.url.clone(),
+            title: chapter.title.clone(),
+            content_file: content_file.to_string_lossy().into_owned(),
+            images: chapter
+                .images
+                .iter()
+                .map(|image| EntryImage {
+                    path: image.path.clone(),
+                    mimetype: image.mimetype.clone(),
+                })
+                .collect(),
+            suspicious: suspicious.cloned(),
+        };
+        let line = format!("{}\n", serde_json::to_string(&entry)?);
+        let mut journal = self.journal.lock().unwrap();
+        journal.write_all(line.as_bytes())?;
+        journal.flush()?;
+        Ok(())
+    }
+
+    /// Removes the checkpoint in `dir`, once the book it was for is written
+    pub fn remove(dir: &Path) -> io::Result<()> {
+        match fs::remove_dir_all(dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Replaces `path` with `text` so that it's either the old or the new one if
+/// the build stops halfway through
+fn write_atomically(path: &Path, text: &str) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, text)?;
+    fs::rename(&tmp_path, path)
+}
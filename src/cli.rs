@@ -0,0 +1,989 @@
+use box2epub::book::{CoverSource, EpubVersion, OnError};
+use box2epub::calibre::CalibreLibrary;
+use box2epub::delivery::SmtpServer;
+use box2epub::downloader::RateLimit;
+use box2epub::extractor::SiteType;
+use box2epub::fetcher::Bandwidth;
+use box2epub::filters::StripRule;
+use box2epub::format::OutputFormat;
+use box2epub::images::ImageQuality;
+use box2epub::naming::NameTemplate;
+use box2epub::ordering::ChapterOrder;
+use box2epub::paragraphs::Paragraphs;
+use box2epub::selection::ChapterSelection;
+use box2epub::titles::TitleSource;
+use box2epub::toc::TocPosition;
+use box2epub::watch::Interval;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// What box2epub was asked to do
+pub enum Command {
+    /// Make a book from every novel in `Args::sites`, or add the new chapters
+    /// to `Args::update`
+    Build(Args),
+    /// Print the chapters found on the novel's page, as JSON when the flag is
+    /// set
+    ListChapters(Args, bool),
+    /// Download the chapters of the book at the path again and report the
+    /// ones that changed since it was made. `Args::sites` is empty when the
+    /// novel's page is left to the book's manifest.
+    Verify(PathBuf, Args),
+    /// Keep checking the novel for new chapters and add them to the book
+    Watch(Watch, Args),
+    /// Serve the books in the directory to e-readers as an OPDS catalog
+    Serve(PathBuf, SocketAddr),
+    Cache(CacheCommand),
+}
+
+/// `watch` options, on top of the ones for building the book
+pub struct Watch {
+    /// Book to add new chapters to, it's made on the first check if it isn't
+    /// there yet. `None` for every book in `Args::library`.
+    pub book: Option<PathBuf>,
+    /// How long to wait between checks
+    pub every: Interval,
+    /// Shell command to run after the book was written
+    pub exec: Option<String>,
+}
+
+/// `cache` subcommands
+pub enum CacheCommand {
+    /// Print where the cache is
+    Path,
+    /// Remove everything in it
+    Clear,
+    /// List the novels with chapters in it
+    Ls,
+    /// Print how much room it takes up
+    Size,
+    /// Remove what was cached longer ago than the interval
+    Prune(Interval),
+    /// Remove what's cached for the novel's or chapter's url
+    Purge(String),
+}
+
+/// Options for downloading novels and writing books
+pub struct Args {
+    /// Novel homepages, or copies of them on disk. More than one (from several
+    /// urls or `--batch`) makes a batch, `output` is a directory then.
+    pub sites: Vec<String>,
+    /// Make one book from all of `sites`, for novels split across sites
+    pub merge: bool,
+    /// How many books of a batch to make at the same time
+    pub jobs: usize,
+    /// Show the novel's details and chapters to change before downloading it
+    pub interactive: bool,
+    /// Extractor to use instead of picking one based on the url
+    pub site_type: Option<SiteType>,
+    /// TOML file with CSS selectors for a site without a built in extractor
+    pub site_config: Option<PathBuf>,
+    /// Directory with WASM extractor plugins, instead of the one in the
+    /// config directory
+    pub plugin_dir: Option<PathBuf>,
+    /// Directory with Rhai extractor scripts, instead of the one in the
+    /// config directory
+    pub script_dir: Option<PathBuf>,
+    /// Where to write the book, defaults to a name derived from the novel title
+    pub output: Option<PathBuf>,
+    /// Name for books written without `output`, or in the batch's directory
+    pub name_template: Option<NameTemplate>,
+    /// Directory to keep the book in, in a folder of its own, along with an
+    /// index of where every book there came from
+    pub library: Option<PathBuf>,
+    /// EPUB, a Kindle format converted from it, Markdown or text
+    pub format: OutputFormat,
+    /// Write Markdown or text to one file per chapter, in the output directory
+    pub split_chapters: bool,
+    /// Split the book into volumes with this many chapters each
+    pub split_every: Option<usize>,
+    /// Stylesheet to use instead of the built in one
+    pub css: Option<PathBuf>,
+    /// Font file to embed and set the text in
+    pub font: Option<PathBuf>,
+    /// Typeset the book vertically (`--vertical`) or not (`--horizontal`),
+    /// `None` leaves it up to the book's language
+    pub vertical: Option<bool>,
+    /// Add styles for reading apps' dark mode to the stylesheet
+    pub dark_mode: bool,
+    /// Leave out the page with the title, author and source
+    pub no_title_page: bool,
+    /// Leave novels without a cover coverless, instead of making one
+    pub no_generated_cover: bool,
+    /// Title of the book instead of the one on the novel's page
+    pub title: Option<String>,
+    /// Author of the book instead of the one on the novel's page
+    pub author: Option<String>,
+    /// Cover of the book instead of the novel's
+    pub cover: Option<CoverSource>,
+    /// TOML file with the title, author and cover, for the ones not given
+    /// with `title`, `author` and `cover`
+    pub metadata: Option<PathBuf>,
+    /// Add a page with the novel's description
+    pub synopsis: bool,
+    /// Leave out the table of contents page
+    pub no_inline_toc: bool,
+    /// Where the table of contents page goes
+    pub toc_position: TocPosition,
+    /// EPUB 3 (the default) or 2 for old readers
+    pub epub_version: EpubVersion,
+    /// Language of the book, i.e. `en`, instead of the page's or a detected one
+    pub lang: Option<String>,
+    /// How many times to retry a failed download before giving up
+    pub retries: Option<u32>,
+    /// Seconds to wait for a connection
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for a response or the next part of one
+    pub read_timeout: Option<u64>,
+    /// How many chapters to download at the same time
+    pub concurrency: Option<usize>,
+    /// How many downloaded chapters to process at the same time
+    pub process_concurrency: Option<usize>,
+    /// How many requests to send to a single host at the same time
+    pub per_host: Option<usize>,
+    /// How fast requests can be sent to a single host, i.e. `2/s`
+    pub rate: Option<RateLimit>,
+    /// How fast everything together is downloaded, i.e. `500k`
+    pub max_bandwidth: Option<Bandwidth>,
+    /// Fetch pages the site's robots.txt disallows and don't wait its crawl delay
+    pub ignore_robots: bool,
+    /// Cookies in the Netscape `cookies.txt` format
+    pub cookie_file: Option<PathBuf>,
+    /// Cookies for the novel's site, written like a `Cookie` header (`k=v; k2=v2`)
+    pub cookies: Vec<String>,
+    /// User agent to send instead of the built in one
+    pub user_agent: Option<String>,
+    /// Proxy for every request, falls back to the `ALL_PROXY` environment variable
+    pub proxy: Option<String>,
+    /// Always download chapters instead of reusing ones cached by a previous run
+    pub no_cache: bool,
+    /// SQLite database every downloaded chapter is kept in, and looked up in
+    /// before downloading it
+    pub archive: Option<PathBuf>,
+    /// Load chapters that come out empty or too short again in a headless
+    /// Chromium, for sites that fill them in with JavaScript
+    pub render_js: bool,
+    /// Chromium or Chrome to run for `render_js` instead of the one found
+    pub chrome: Option<PathBuf>,
+    /// Don't look the novel up on NovelUpdates
+    pub no_enrich: bool,
+    /// Order of the chapters, the site's or sorted by their number
+    pub sort_by: ChapterOrder,
+    /// Only download these chapters, i.e. `200-350` or `1,5,10-20`
+    pub chapters: Option<ChapterSelection>,
+    /// What to do with chapters that fail to download
+    pub on_error: OnError,
+    /// Leave out chapters that are copies of earlier ones instead of only
+    /// reporting them
+    pub drop_duplicates: bool,
+    /// Fewest characters of text a chapter is expected to have
+    pub min_chapter_size: Option<usize>,
+    /// Most characters of text a chapter is expected to have
+    pub max_chapter_size: Option<usize>,
+    /// Longest side in pixels of the cover and images, bigger ones are scaled
+    /// down
+    pub max_image_size: Option<NonZeroU32>,
+    /// JPEG quality to recompress the cover and images with
+    pub image_quality: Option<ImageQuality>,
+    /// Removed from every chapter, `css:<selector>` or `re:<regex>`
+    pub strip: Vec<StripRule>,
+    /// TOML file with more changes to make to every chapter
+    pub transforms: Option<PathBuf>,
+    /// Chapter template to use instead of the built in one
+    pub template: Option<PathBuf>,
+    /// Where to take chapter titles from instead of where the extractor finds
+    /// them
+    pub title_from: Option<TitleSource>,
+    /// Repair mojibake and normalize quotes, dashes and ellipses
+    pub fix_typography: bool,
+    /// Turn `<div>`s and text split up with `<br>`s into paragraphs
+    pub paragraphs: Option<Paragraphs>,
+    /// Turn "[TN: ...]" notes into footnotes
+    pub footnotes: bool,
+    /// Take comments and whitespace readers don't show out of chapters
+    pub minify: bool,
+    /// Take the colors sites set on the text out of chapters
+    pub strip_colors: bool,
+    /// Keep every element and attribute of the chapters, instead of only the
+    /// ones books need
+    pub no_allowlist: bool,
+    /// Email address to send the book to, or the name of a delivery profile
+    pub send_to: Option<String>,
+    /// SMTP server to send the book through
+    pub smtp: Option<SmtpServer>,
+    /// Address to send the book from
+    pub from: Option<String>,
+    /// calibre library to add the book to
+    pub add_to_calibre: Option<CalibreLibrary>,
+    /// Only print warnings, no progress bar
+    pub quiet: bool,
+    /// How much debugging output to log, `-v` for box2epub's and `-vv` for
+    /// everything including the HTTP client's
+    pub verbose: u8,
+    /// Write the debugging output to this file instead of the terminal
+    pub log_file: Option<PathBuf>,
+    /// EPUB from an earlier run to add the new chapters to
+    pub update: Option<PathBuf>,
+    /// Only print what was found on the novel's page, don't download chapters
+    pub dry_run: bool,
+    /// Fail when the book doesn't pass validation, instead of only warning
+    pub strict: bool,
+    /// Pick up where the last build of the novel stopped, going by its
+    /// checkpoint
+    pub resume: bool,
+}
+
+/// Names of the subcommands and the options that work without one, anything
+/// else first is taken as `build`'s so `box2epub <url>` keeps working
+const SUBCOMMANDS: [&str; 13] = [
+    "build",
+    "update",
+    "watch",
+    "list-chapters",
+    "verify",
+    "diff",
+    "serve",
+    "cache",
+    "help",
+    "-h",
+    "--help",
+    "-V",
+    "--version",
+];
+
+impl Command {
+    pub fn parse() -> Command {
+        let mut args: Vec<String> = std::env::args().collect();
+        match args.get(1) {
+            Some(first) if !SUBCOMMANDS.contains(&first.as_str()) => {
+                args.insert(1, "build".to_string())
+            }
+            _ => {}
+        }
+        let matches = app().get_matches_from(args);
+
+        match matches.subcommand() {
+            ("build", Some(matches)) => Command::Build(Args::from_matches(matches)),
+            ("update", Some(matches)) => {
+                let mut args = Args::from_matches(matches);
+                args.update = matches.value_of("epub").map(PathBuf::from);
+                Command::Build(args)
+            }
+            ("list-chapters", Some(matches)) => {
+                Command::ListChapters(Args::from_matches(matches), matches.is_present("json"))
+            }
+            ("watch", Some(matches)) => Command::Watch(
+                Watch {
+                    book: matches.value_of("epub").map(PathBuf::from),
+                    every: parsed(matches, "every").unwrap_or_else(|| Interval::hours(1)),
+                    exec: matches.value_of("exec").map(str::to_string),
+                },
+                Args::from_matches(matches),
+            ),
+            ("verify", Some(matches)) => Command::Verify(
+                PathBuf::from(matches.value_of("book").unwrap()),
+                Args::from_matches(matches),
+            ),
+            ("serve", Some(matches)) => Command::Serve(
+                PathBuf::from(matches.value_of("dir").unwrap_or(".")),
+                SocketAddr::new(
+                    parsed(matches, "bind").unwrap_or_else(|| [127, 0, 0, 1].into()),
+                    parsed(matches, "port").unwrap_or(8080),
+                ),
+            ),
+            ("cache", Some(matches)) => Command::Cache(match matches.subcommand() {
+                ("clear", _) => CacheCommand::Clear,
+                ("ls", _) => CacheCommand::Ls,
+                ("size", _) => CacheCommand::Size,
+                ("prune", Some(matches)) => {
+                    CacheCommand::Prune(parsed(matches, "older-than").unwrap())
+                }
+                ("purge", Some(matches)) => {
+                    CacheCommand::Purge(matches.value_of("url").unwrap().to_string())
+                }
+                _ => CacheCommand::Path,
+            }),
+            // clap already printed the help when there isn't a subcommand
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("box2epub")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Downloads web novels as EPUBs")
+        .settings(&[
+            AppSettings::SubcommandRequiredElseHelp,
+            AppSettings::VersionlessSubcommands,
+        ])
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Makes a book from one or more novels")
+                .arg(
+                    Arg::with_name("url")
+                        .help("Novel's page, a directory saved with wget or a file:// url")
+                        .multiple(true)
+                        .required_unless_one(&["batch", "library"]),
+                )
+                .arg(
+                    Arg::with_name("batch")
+                        .long("batch")
+                        .value_name("FILE")
+                        .help("Also make books from the urls in this file, one per line"),
+                )
+                .arg(
+                    Arg::with_name("merge")
+                        .long("merge")
+                        .help("Make one book from the urls, for a novel split across sites"),
+                )
+                .arg(
+                    Arg::with_name("jobs")
+                        .long("jobs")
+                        .short("j")
+                        .value_name("COUNT")
+                        .help("How many books of a batch to make at the same time, one by default")
+                        .validator(parses::<NonZeroUsize>),
+                )
+                .arg(
+                    Arg::with_name("interactive")
+                        .long("interactive")
+                        .short("i")
+                        .help("Change the title, author, cover and chapters before downloading them")
+                        .conflicts_with_all(&["jobs", "dry-run"]),
+                )
+                .args(&download_args())
+                .args(&book_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("update")
+                .about("Adds the chapters that came out since to a book from an earlier run")
+                .arg(
+                    Arg::with_name("epub")
+                        .help("Book to update")
+                        .required_unless("library")
+                        .conflicts_with("library"),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .help("Novel's page")
+                        .required_unless("library"),
+                )
+                .args(&download_args())
+                .args(&book_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Checks for new chapters every so often and adds them to a book")
+                .arg(
+                    Arg::with_name("epub")
+                        .help("Book to keep up to date, made on the first check if it isn't there")
+                        .required_unless("library")
+                        .conflicts_with("library"),
+                )
+                .arg(
+                    Arg::with_name("url")
+                        .help("Novel's page")
+                        .required_unless("library"),
+                )
+                .arg(
+                    Arg::with_name("every")
+                        .long("every")
+                        .value_name("INTERVAL")
+                        .help("How long to wait between checks, i.e. 30m or 6h, 1h by default")
+                        .validator(parses::<Interval>),
+                )
+                .arg(
+                    Arg::with_name("exec")
+                        .long("exec")
+                        .value_name("COMMAND")
+                        .help("Shell command to run after new chapters were added, with the book's path in $BOX2EPUB_BOOK"),
+                )
+                .args(&download_args())
+                .args(&book_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("list-chapters")
+                .about("Prints the chapters found on the novel's page")
+                .arg(Arg::with_name("url").help("Novel's page").required(true))
+                .arg(chapters_arg())
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the chapters as JSON"),
+                )
+                .args(&download_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .alias("diff")
+                .about("Reports the chapters the site changed since a book was made")
+                .arg(Arg::with_name("book").help("Book to check").required(true))
+                .arg(
+                    Arg::with_name("url")
+                        .help("Novel's page, the one the book was made from by default"),
+                )
+                .args(&download_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serves the books in a directory as an OPDS catalog for e-reader apps")
+                .arg(
+                    Arg::with_name("dir")
+                        .help("Directory with the books, the current one by default"),
+                )
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .value_name("PORT")
+                        .help("Port to listen on, 8080 by default")
+                        .validator(parses::<u16>),
+                )
+                .arg(
+                    Arg::with_name("bind")
+                        .long("bind")
+                        .value_name("ADDRESS")
+                        .help("Address to listen on, 127.0.0.1 (only this computer) by default")
+                        .validator(parses::<IpAddr>),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Manages the download cache")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("path").about("Prints where the cache is"))
+                .subcommand(
+                    SubCommand::with_name("clear").about("Removes everything in the cache"),
+                )
+                .subcommand(
+                    SubCommand::with_name("ls")
+                        .about("Lists the novels with chapters in the cache"),
+                )
+                .subcommand(
+                    SubCommand::with_name("size").about("Prints how much room the cache takes up"),
+                )
+                .subcommand(
+                    SubCommand::with_name("prune")
+                        .about("Removes what was cached a while ago")
+                        .arg(
+                            Arg::with_name("older-than")
+                                .long("older-than")
+                                .value_name("INTERVAL")
+                                .help("Remove what was cached longer ago than this, i.e. 30d")
+                                .required(true)
+                                .validator(parses::<Interval>),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("purge")
+                        .about("Removes what's cached for a novel or chapter")
+                        .arg(
+                            Arg::with_name("url")
+                                .help("The novel's or chapter's url")
+                                .required(true),
+                        ),
+                ),
+        )
+}
+
+fn chapters_arg() -> Arg<'static, 'static> {
+    Arg::with_name("chapters")
+        .long("chapters")
+        .value_name("RANGES")
+        .help("Only these chapters, i.e. 200-350 or 1,5,10-20")
+        .validator(parses::<ChapterSelection>)
+}
+
+/// Options for finding and downloading the novel, shared by every subcommand
+/// that goes to the site
+fn download_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("site-type")
+            .long("site-type")
+            .value_name("NAME")
+            .help("Extractor to use instead of picking one based on the url")
+            .validator(parses::<SiteType>)
+            .conflicts_with("site-config"),
+        Arg::with_name("site-config")
+            .long("site-config")
+            .value_name("FILE")
+            .help("TOML file with CSS selectors for a site without an extractor"),
+        Arg::with_name("plugin-dir")
+            .long("plugin-dir")
+            .value_name("DIR")
+            .help("Directory with WASM extractor plugins, ~/.config/box2epub/plugins by default"),
+        Arg::with_name("script-dir")
+            .long("script-dir")
+            .value_name("DIR")
+            .help(
+                "Directory with Rhai extractor scripts, ~/.config/box2epub/extractors by default",
+            ),
+        Arg::with_name("retries")
+            .long("retries")
+            .value_name("COUNT")
+            .help("How many times to retry a failed download")
+            .validator(parses::<u32>),
+        Arg::with_name("connect-timeout")
+            .long("connect-timeout")
+            .value_name("SECONDS")
+            .help("How long to wait for a connection, 15 seconds by default")
+            .validator(parses::<u64>),
+        Arg::with_name("read-timeout")
+            .long("read-timeout")
+            .value_name("SECONDS")
+            .help("How long a response can stall before it's retried, 30 seconds by default")
+            .validator(parses::<u64>),
+        Arg::with_name("concurrency")
+            .long("concurrency")
+            .value_name("COUNT")
+            .help("How many chapters to download at the same time")
+            .validator(parses::<usize>),
+        Arg::with_name("process-concurrency")
+            .long("process-concurrency")
+            .value_name("COUNT")
+            .help(
+                "How many downloaded chapters to process at the same time, one per CPU by default",
+            )
+            .validator(parses::<usize>),
+        Arg::with_name("per-host")
+            .long("per-host")
+            .value_name("COUNT")
+            .help("How many requests to send to a single host at the same time")
+            .validator(parses::<usize>),
+        Arg::with_name("rate")
+            .long("rate")
+            .value_name("RATE")
+            .help("Maximum request rate for a single host, i.e. 2/s or 30/m")
+            .validator(parses::<RateLimit>),
+        Arg::with_name("max-bandwidth")
+            .long("max-bandwidth")
+            .value_name("BYTES")
+            .help("Maximum download speed across all requests, i.e. 500k or 2M per second")
+            .validator(parses::<Bandwidth>),
+        Arg::with_name("ignore-robots")
+            .long("ignore-robots")
+            .help("Fetch pages the site's robots.txt disallows, for sites that allowed it"),
+        Arg::with_name("cookie-file")
+            .long("cookie-file")
+            .value_name("FILE")
+            .help("Cookies in the Netscape cookies.txt format"),
+        Arg::with_name("cookie")
+            .long("cookie")
+            .value_name("COOKIES")
+            .help("Cookies for the novel's site, i.e. \"name=value; other=value\"")
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("user-agent")
+            .long("user-agent")
+            .value_name("UA")
+            .help("User agent to send instead of the built in one"),
+        Arg::with_name("proxy")
+            .long("proxy")
+            .value_name("URL")
+            .help("HTTP or SOCKS5 proxy for every request, defaults to $ALL_PROXY"),
+        Arg::with_name("sort-by")
+            .long("sort-by")
+            .value_name("ORDER")
+            .help("site to keep the site's chapter order, number to sort by chapter number")
+            .validator(parses::<ChapterOrder>),
+        Arg::with_name("no-cache")
+            .long("no-cache")
+            .help("Always download instead of reusing what earlier runs downloaded"),
+        Arg::with_name("archive")
+            .long("archive")
+            .value_name("FILE")
+            .help("SQLite database to keep every downloaded chapter in and reuse them from"),
+        Arg::with_name("render-js")
+            .long("render-js")
+            .help("Load chapters that come out empty or too short again in a headless Chromium, needs the browser feature"),
+        Arg::with_name("chrome")
+            .long("chrome")
+            .value_name("PATH")
+            .help("Chromium or Chrome to run for --render-js, instead of the one found")
+            .requires("render-js"),
+        Arg::with_name("no-enrich")
+            .long("no-enrich")
+            .help("Don't fill in the author, description and tags from NovelUpdates"),
+        Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .help("Only print warnings, no progress bar"),
+        Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .multiple(true)
+            .help("Log what's going on, -vv for everything including the HTTP client"),
+        Arg::with_name("log-file")
+            .long("log-file")
+            .value_name("FILE")
+            .help("Write the log to this file instead of the terminal"),
+    ]
+}
+
+/// Options for what goes into the book and how it's written
+fn book_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("PATH")
+            .help("Where to write the book, the directory for a batch"),
+        Arg::with_name("name-template")
+            .long("name-template")
+            .value_name("TEMPLATE")
+            .help("Name books like \"{title} - {author} [{chapters}ch]\", a / makes directories")
+            .validator(parses::<NameTemplate>),
+        Arg::with_name("library")
+            .long("library")
+            .value_name("DIR")
+            .help("Keep the book in a folder of this library, updating it if it's already there")
+            .conflicts_with("output"),
+        Arg::with_name("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("epub, mobi, azw3, md, txt, json or ndjson")
+            .validator(parses::<OutputFormat>),
+        Arg::with_name("split-chapters")
+            .long("split-chapters")
+            .help("Write Markdown or text to one file per chapter"),
+        Arg::with_name("split-every")
+            .long("split-every")
+            .value_name("COUNT")
+            .help("Split the book into volumes with this many chapters each")
+            .validator(parses::<usize>),
+        Arg::with_name("css")
+            .long("css")
+            .value_name("FILE")
+            .help("Stylesheet to use instead of the built in one"),
+        Arg::with_name("font")
+            .long("font")
+            .value_name("FILE")
+            .help("Font file to embed and set the text in"),
+        Arg::with_name("vertical").long("vertical").help(
+            "Typeset the book vertically with pages turning to the left, like Japanese books are",
+        ),
+        Arg::with_name("horizontal")
+            .long("horizontal")
+            .help("Typeset Japanese books horizontally, instead of vertically")
+            .conflicts_with("vertical"),
+        Arg::with_name("dark-mode")
+            .long("dark-mode")
+            .help("Show light text on a dark background in apps that follow the system's dark mode"),
+        Arg::with_name("no-title-page")
+            .long("no-title-page")
+            .help("Leave out the page with the title, author and where the book is from"),
+        Arg::with_name("no-generated-cover")
+            .long("no-generated-cover")
+            .help("Don't make a cover with the title and author when the novel has none"),
+        Arg::with_name("title")
+            .long("title")
+            .value_name("TITLE")
+            .help("Title of the book, instead of the one on the novel's page"),
+        Arg::with_name("author")
+            .long("author")
+            .value_name("AUTHOR")
+            .help("Author of the book, instead of the one on the novel's page"),
+        Arg::with_name("cover")
+            .long("cover")
+            .value_name("COVER")
+            .help("Image file or url to use as the cover, or generated or none")
+            .validator(parses::<CoverSource>),
+        Arg::with_name("metadata")
+            .long("metadata")
+            .value_name("FILE")
+            .help("TOML file with the title, author and cover, for the ones not given with their options"),
+        Arg::with_name("synopsis")
+            .long("synopsis")
+            .help("Add a page with the novel's description after the title page"),
+        Arg::with_name("no-inline-toc")
+            .long("no-inline-toc")
+            .help("Leave out the table of contents page, readers have their own"),
+        Arg::with_name("toc-position")
+            .long("toc-position")
+            .value_name("POSITION")
+            .help("Where the table of contents page goes, start (the default) or end")
+            .validator(parses::<TocPosition>)
+            .conflicts_with("no-inline-toc"),
+        Arg::with_name("epub-version")
+            .long("epub-version")
+            .value_name("VERSION")
+            .help("3 (the default), or 2 for old readers that can't open EPUB 3 books")
+            .validator(parses::<EpubVersion>),
+        Arg::with_name("lang")
+            .long("lang")
+            .value_name("LANG")
+            .help("Language of the book, i.e. en, instead of a detected one"),
+        chapters_arg(),
+        Arg::with_name("on-error")
+            .long("on-error")
+            .value_name("ACTION")
+            .help("What to do with chapters that fail to download: abort, skip or placeholder")
+            .validator(parses::<OnError>),
+        Arg::with_name("keep-going")
+            .long("keep-going")
+            .help("Same as --on-error skip")
+            .conflicts_with("on-error"),
+        Arg::with_name("drop-duplicates")
+            .long("drop-duplicates")
+            .help("Leave out chapters with the same text as an earlier one, instead of only reporting them"),
+        Arg::with_name("min-chapter-size")
+            .long("min-chapter-size")
+            .value_name("CHARS")
+            .help("Chapters with less text than this are reported, 300 characters by default")
+            .validator(parses::<usize>),
+        Arg::with_name("max-chapter-size")
+            .long("max-chapter-size")
+            .value_name("CHARS")
+            .help("Chapters with more text than this are reported, 100000 characters by default")
+            .validator(parses::<usize>),
+        Arg::with_name("max-image-size")
+            .long("max-image-size")
+            .value_name("PIXELS")
+            .help("Scale the cover and images down so their longest side is at most this")
+            .validator(parses::<NonZeroU32>),
+        Arg::with_name("image-quality")
+            .long("image-quality")
+            .value_name("1-100")
+            .help("Recompress JPEG covers and images with this quality")
+            .validator(parses::<ImageQuality>),
+        Arg::with_name("strip")
+            .long("strip")
+            .value_name("RULE")
+            .help("Remove matches from every chapter, css:<selector> or re:<regex>")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(parses::<StripRule>),
+        Arg::with_name("transforms")
+            .long("transforms")
+            .value_name("FILE")
+            .help("TOML file with replacements and strip rules to run on every chapter"),
+        Arg::with_name("template")
+            .long("template")
+            .value_name("FILE")
+            .help("xhtml template to wrap every chapter in, with {{ title }}, {{ content }} and so on"),
+        Arg::with_name("title-from")
+            .long("title-from")
+            .value_name("SOURCE")
+            .help("Take chapter titles from their heading, the page's <title> or the url")
+            .validator(parses::<TitleSource>),
+        Arg::with_name("paragraphs")
+            .long("paragraphs")
+            .value_name("HEURISTICS")
+            .help("Turn divs and text split up with <br>s into paragraphs, divs,breaks=2 by default")
+            .min_values(0)
+            .require_equals(true)
+            .validator(parses::<Paragraphs>),
+        Arg::with_name("fix-typography")
+            .long("fix-typography")
+            .help("Repair mojibake, curl quotes and normalize dashes and ellipses"),
+        Arg::with_name("footnotes")
+            .long("footnotes")
+            .help("Turn translator notes like [TN: ...] into footnotes readers show as popups"),
+        Arg::with_name("minify")
+            .long("minify")
+            .help("Take comments and extra whitespace out of chapters for a smaller book"),
+        Arg::with_name("strip-colors")
+            .long("strip-colors")
+            .help("Take the text and background colors sites set out of chapters, for dark mode"),
+        Arg::with_name("no-allowlist")
+            .long("no-allowlist")
+            .help("Keep every tag and attribute of the chapters, not only the ones books need"),
+        Arg::with_name("send-to")
+            .long("send-to")
+            .value_name("ADDRESS")
+            .help("Email the book to this address (i.e. a Kindle's) or with this delivery profile"),
+        Arg::with_name("smtp")
+            .long("smtp")
+            .value_name("URL")
+            .help("SMTP server to send the book through, i.e. smtps://user@smtp.example.com")
+            .validator(parses::<SmtpServer>),
+        Arg::with_name("from")
+            .long("from")
+            .value_name("ADDRESS")
+            .help("Address to send the book from, the SMTP username by default"),
+        Arg::with_name("add-to-calibre")
+            .long("add-to-calibre")
+            .value_name("LIBRARY")
+            .help("Add the book to calibre, to the library given with --add-to-calibre=DIR or the default one")
+            .min_values(0)
+            .require_equals(true),
+        Arg::with_name("strict")
+            .long("strict")
+            .help("Fail instead of writing a book that doesn't pass validation"),
+        Arg::with_name("dry-run")
+            .long("dry-run")
+            .help("Only print what was found on the novel's page"),
+        Arg::with_name("resume")
+            .long("resume")
+            .help("Continue the last build of the novel from where it crashed or was stopped")
+            .conflicts_with("dry-run"),
+    ]
+}
+
+/// Validator for options parsed with `FromStr`
+fn parses<T: FromStr>(value: String) -> Result<(), String>
+where
+    T::Err: Display,
+{
+    value
+        .parse::<T>()
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+/// Value of an option checked by `parses`
+fn parsed<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    matches.value_of(name).and_then(|value| value.parse().ok())
+}
+
+impl Args {
+    /// Reads the options of a subcommand, the ones it doesn't have are left at
+    /// their defaults
+    fn from_matches(matches: &ArgMatches) -> Args {
+        let mut batch_sites = Vec::new();
+        if let Some(path) = matches.value_of("batch") {
+            let list = std::fs::read_to_string(path).unwrap_or_else(|err| {
+                clap::Error::with_description(
+                    &format!("Couldn't read {}: {}", path, err),
+                    clap::ErrorKind::Io,
+                )
+                .exit()
+            });
+            batch_sites.extend(
+                list.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+        let sites: Vec<String> = matches
+            .values_of("url")
+            .into_iter()
+            .flatten()
+            .map(str::to_string)
+            .chain(batch_sites)
+            .collect();
+
+        let path = |name| matches.value_of(name).map(PathBuf::from);
+        let string = |name| matches.value_of(name).map(str::to_string);
+        let strings = |name| {
+            matches
+                .values_of(name)
+                .into_iter()
+                .flatten()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        // reqwest picks up HTTP_PROXY and HTTPS_PROXY by itself, but not this one
+        let proxy = string("proxy").or_else(|| {
+            std::env::var("ALL_PROXY")
+                .or_else(|_| std::env::var("all_proxy"))
+                .ok()
+                .filter(|proxy| !proxy.is_empty())
+        });
+
+        Args {
+            sites,
+            merge: matches.is_present("merge"),
+            jobs: parsed(matches, "jobs").map_or(1, NonZeroUsize::get),
+            interactive: matches.is_present("interactive"),
+            site_type: parsed(matches, "site-type"),
+            site_config: path("site-config"),
+            plugin_dir: path("plugin-dir"),
+            script_dir: path("script-dir"),
+            output: path("output"),
+            name_template: parsed(matches, "name-template"),
+            library: path("library"),
+            format: parsed(matches, "format").unwrap_or(OutputFormat::Epub),
+            split_chapters: matches.is_present("split-chapters"),
+            split_every: parsed(matches, "split-every"),
+            css: path("css"),
+            font: path("font"),
+            vertical: if matches.is_present("vertical") {
+                Some(true)
+            } else if matches.is_present("horizontal") {
+                Some(false)
+            } else {
+                None
+            },
+            dark_mode: matches.is_present("dark-mode"),
+            no_title_page: matches.is_present("no-title-page"),
+            no_generated_cover: matches.is_present("no-generated-cover"),
+            title: string("title"),
+            author: string("author"),
+            cover: parsed(matches, "cover"),
+            metadata: path("metadata"),
+            synopsis: matches.is_present("synopsis"),
+            no_inline_toc: matches.is_present("no-inline-toc"),
+            toc_position: parsed(matches, "toc-position").unwrap_or(TocPosition::Start),
+            epub_version: parsed(matches, "epub-version").unwrap_or(EpubVersion::V3),
+            lang: string("lang"),
+            retries: parsed(matches, "retries"),
+            connect_timeout: parsed(matches, "connect-timeout"),
+            read_timeout: parsed(matches, "read-timeout"),
+            concurrency: parsed(matches, "concurrency"),
+            process_concurrency: parsed(matches, "process-concurrency"),
+            per_host: parsed(matches, "per-host"),
+            rate: parsed(matches, "rate"),
+            max_bandwidth: parsed(matches, "max-bandwidth"),
+            ignore_robots: matches.is_present("ignore-robots"),
+            cookie_file: path("cookie-file"),
+            cookies: strings("cookie"),
+            user_agent: string("user-agent"),
+            proxy,
+            no_cache: matches.is_present("no-cache"),
+            render_js: matches.is_present("render-js"),
+            chrome: path("chrome"),
+            archive: path("archive"),
+            no_enrich: matches.is_present("no-enrich"),
+            sort_by: parsed(matches, "sort-by").unwrap_or(ChapterOrder::Site),
+            chapters: parsed(matches, "chapters"),
+            on_error: if matches.is_present("keep-going") {
+                OnError::Skip
+            } else {
+                parsed(matches, "on-error").unwrap_or(OnError::Abort)
+            },
+            drop_duplicates: matches.is_present("drop-duplicates"),
+            min_chapter_size: parsed(matches, "min-chapter-size"),
+            max_chapter_size: parsed(matches, "max-chapter-size"),
+            max_image_size: parsed(matches, "max-image-size"),
+            image_quality: parsed(matches, "image-quality"),
+            strip: strings("strip")
+                .iter()
+                .filter_map(|rule| rule.parse().ok())
+                .collect(),
+            transforms: path("transforms"),
+            template: path("template"),
+            title_from: parsed(matches, "title-from"),
+            paragraphs: if matches.is_present("paragraphs") {
+                Some(parsed(matches, "paragraphs").unwrap_or_default())
+            } else {
+                None
+            },
+            fix_typography: matches.is_present("fix-typography"),
+            footnotes: matches.is_present("footnotes"),
+            minify: matches.is_present("minify"),
+            strip_colors: matches.is_present("strip-colors"),
+            no_allowlist: matches.is_present("no-allowlist"),
+            send_to: string("send-to"),
+            smtp: parsed(matches, "smtp"),
+            from: string("from"),
+            add_to_calibre: if matches.is_present("add-to-calibre") {
+                Some(CalibreLibrary::new(path("add-to-calibre")))
+            } else {
+                None
+            },
+            quiet: matches.is_present("quiet"),
+            verbose: matches.occurrences_of("verbose").min(u64::from(u8::MAX)) as u8,
+            log_file: path("log-file"),
+            update: None,
+            dry_run: matches.is_present("dry-run"),
+            strict: matches.is_present("strict"),
+            resume: matches.is_present("resume"),
+        }
+    }
+}
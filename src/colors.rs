@@ -0,0 +1,85 @@
+use crate::transform::ContentTransform;
+use html5ever::tendril::StrTendril;
+use html5ever::{LocalName, Namespace, QualName};
+use scraper::{Html, Node};
+
+/// CSS properties taken out of `style` attributes
+const COLOR_PROPERTIES: [&str; 4] = [
+    "color",
+    "background",
+    "background-color",
+    "background-image",
+];
+
+/// Attributes that set a color the old way, i.e. `<font color="#000">`
+const COLOR_ATTRIBUTES: [&str; 2] = ["color", "bgcolor"];
+
+/// Takes the colors sites set on the text out of the chapters, i.e.
+/// `style="color:#000;background:#fff"`. They keep readers in dark mode from
+/// changing the colors, so the text is black on black or a white box is left
+/// in the middle of the page. The rest of the style is kept.
+pub struct StripColors;
+
+impl ContentTransform for StripColors {
+    fn apply(&self, html: &str) -> String {
+        let mut document = Html::parse_document(html);
+        let ids: Vec<_> = document
+            .tree
+            .root()
+            .descendants()
+            .filter(|node| match node.value() {
+                Node::Element(element) => {
+                    element.attr("style").is_some()
+                        || COLOR_ATTRIBUTES
+                            .iter()
+                            .any(|name| element.attr(name).is_some())
+                }
+                _ => false,
+            })
+            .map(|node| node.id())
+            .collect();
+        if ids.is_empty() {
+            return html.to_string();
+        }
+
+        for id in ids {
+            if let Some(mut node) = document.tree.get_mut(id) {
+                if let Node::Element(element) = node.value() {
+                    for name in COLOR_ATTRIBUTES.iter() {
+                        element.attrs.remove(&attribute(name));
+                    }
+                    let style = match element.attr("style") {
+                        Some(style) => strip_colors(style),
+                        None => continue,
+                    };
+                    if style.is_empty() {
+                        element.attrs.remove(&attribute("style"));
+                    } else {
+                        element
+                            .attrs
+                            .insert(attribute("style"), StrTendril::from(style));
+                    }
+                }
+            }
+        }
+        document.root_element().html()
+    }
+}
+
+/// The declarations of the style attribute that don't set a color
+fn strip_colors(style: &str) -> String {
+    style
+        .split(';')
+        .filter(|declaration| {
+            let property = declaration.split(':').next().unwrap_or("");
+            let property = property.trim().to_ascii_lowercase();
+            !property.is_empty() && !COLOR_PROPERTIES.contains(&property.as_str())
+        })
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn attribute(name: &str) -> QualName {
+    QualName::new(None, Namespace::from(""), LocalName::from(name))
+}
@@ -0,0 +1,105 @@
+use crate::text::{xhtml_to_text, TextStyle};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Default for `ContentSize::min`, shorter chapters are usually the selector
+/// catching an author's note or an empty wrapper
+pub const DEFAULT_MIN_CHAPTER_SIZE: usize = 300;
+/// Default for `ContentSize::max`, longer chapters are usually the selector
+/// catching the whole page with its menus and comments
+pub const DEFAULT_MAX_CHAPTER_SIZE: usize = 100_000;
+
+/// How many characters of text a chapter is expected to have. Chapters
+/// outside of it are extracted again with the generic extractor's content
+/// detection, and reported when that doesn't do better.
+#[derive(Debug, Clone, Copy)]
+pub struct ContentSize {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Default for ContentSize {
+    fn default() -> Self {
+        ContentSize {
+            min: DEFAULT_MIN_CHAPTER_SIZE,
+            max: DEFAULT_MAX_CHAPTER_SIZE,
+        }
+    }
+}
+
+impl ContentSize {
+    /// What's off about a chapter with `size` characters of text, if anything
+    pub fn check(&self, size: usize) -> Option<SizeProblem> {
+        if size < self.min {
+            Some(SizeProblem::TooShort(size))
+        } else if size > self.max {
+            Some(SizeProblem::TooLong(size))
+        } else {
+            None
+        }
+    }
+}
+
+/// A chapter has less or more text than `ContentSize` expects, with how much
+/// it has
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum SizeProblem {
+    TooShort(usize),
+    TooLong(usize),
+}
+
+impl fmt::Display for SizeProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SizeProblem::TooShort(size) => write!(
+                f,
+                "only {} characters of text, the extractor probably missed the chapter",
+                size
+            ),
+            SizeProblem::TooLong(size) => write!(
+                f,
+                "{} characters of text, the extractor probably took the whole page",
+                size
+            ),
+        }
+    }
+}
+
+/// A chapter whose size looked wrong
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuspiciousChapter {
+    pub number: usize,
+    pub url: String,
+    pub problem: SizeProblem,
+    /// Size of the content the generic extractor found instead, which went
+    /// into the book
+    pub fallback_size: Option<usize>,
+}
+
+impl fmt::Display for SuspiciousChapter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "chapter {} ({}) had {}",
+            self.number, self.url, self.problem
+        )?;
+        if let Some(size) = self.fallback_size {
+            write!(
+                f,
+                ", used the {} characters found by looking for the text instead",
+                size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Characters of text in a chapter's xhtml, leaving out its heading and
+/// whitespace
+pub fn text_size(xhtml: &str, title: &str) -> usize {
+    let text = xhtml_to_text(xhtml, TextStyle::Plain);
+    text.replacen(title, "", 1)
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .count()
+}
@@ -0,0 +1,184 @@
+use reqwest::Url;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A cookie and which urls it's sent to
+#[derive(Debug, Clone)]
+struct Cookie {
+    /// Host without a leading dot
+    domain: String,
+    include_subdomains: bool,
+    path: String,
+    secure: bool,
+    name: String,
+    value: String,
+}
+
+impl Cookie {
+    fn matches(&self, url: &Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host,
+            None => return false,
+        };
+        let domain_matches = host == self.domain
+            || (self.include_subdomains && host.ends_with(&format!(".{}", self.domain)));
+        domain_matches
+            && url.path().starts_with(&self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+}
+
+/// Cookies sent along with requests, i.e. for a logged in session on a site
+/// with members-only chapters. Cookies the site sets are kept too.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// Reads cookies exported in the Netscape `cookies.txt` format, which
+    /// browser extensions and curl can write. Expired cookies are left out.
+    pub fn from_netscape_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+        Self::parse_netscape(&text)
+    }
+
+    pub fn parse_netscape(text: &str) -> Result<Self, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+
+        let mut jar = CookieJar::default();
+        for line in text.lines() {
+            // curl marks HttpOnly cookies by prefixing them like a comment
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 7 {
+                return Err(format!("Invalid cookie line: {}", line));
+            }
+            let expires: u64 = fields[4].parse().unwrap_or(0);
+            if expires != 0 && expires < now {
+                continue;
+            }
+            jar.set(Cookie {
+                domain: fields[0].trim_start_matches('.').to_lowercase(),
+                include_subdomains: fields[1].eq_ignore_ascii_case("TRUE"),
+                path: fields[2].to_string(),
+                secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                name: fields[5].to_string(),
+                value: fields[6].trim_end().to_string(),
+            });
+        }
+        Ok(jar)
+    }
+
+    /// Adds cookies written like a `Cookie` header (`k=v; k2=v2`), sent to
+    /// `url`'s host and its subdomains
+    pub fn add_header(&mut self, url: &str, header: &str) -> Result<(), String> {
+        let domain = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .ok_or_else(|| format!("Can't tell which host the cookies are for: {}", url))?;
+        for pair in header
+            .split(';')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (name, value) = split_pair(pair)
+                .ok_or_else(|| format!("Invalid cookie {}, expected name=value", pair))?;
+            self.set(Cookie {
+                domain: domain.clone(),
+                include_subdomains: true,
+                path: "/".to_string(),
+                secure: false,
+                name: name.to_string(),
+                value: value.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Value for the `Cookie` header of a request to `url`
+    pub fn header_for(&self, url: &Url) -> Option<String> {
+        let pairs: Vec<String> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url))
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        if pairs.is_empty() {
+            None
+        } else {
+            Some(pairs.join("; "))
+        }
+    }
+
+    /// Keeps a cookie from a `Set-Cookie` header of a response from `url`, or
+    /// removes it if it's being expired
+    pub fn store(&mut self, url: &Url, set_cookie: &str) {
+        let mut parts = set_cookie.split(';').map(str::trim);
+        let (name, value) = match parts.next().and_then(split_pair) {
+            Some(pair) => pair,
+            None => return,
+        };
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return,
+        };
+
+        let mut cookie = Cookie {
+            domain: host,
+            include_subdomains: false,
+            path: "/".to_string(),
+            secure: false,
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        let mut expired = false;
+        for attribute in parts {
+            let (key, value) = split_pair(attribute).unwrap_or((attribute, ""));
+            match key.to_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    cookie.domain = value.trim_start_matches('.').to_lowercase();
+                    cookie.include_subdomains = true;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => expired = matches!(value.parse::<i64>(), Ok(age) if age <= 0),
+                _ => {}
+            }
+        }
+
+        if expired {
+            self.cookies
+                .retain(|existing| !same_cookie(existing, &cookie));
+        } else {
+            self.set(cookie);
+        }
+    }
+
+    fn set(&mut self, cookie: Cookie) {
+        match self
+            .cookies
+            .iter_mut()
+            .find(|existing| same_cookie(existing, &cookie))
+        {
+            Some(existing) => *existing = cookie,
+            None => self.cookies.push(cookie),
+        }
+    }
+}
+
+fn same_cookie(a: &Cookie, b: &Cookie) -> bool {
+    a.name == b.name && a.domain == b.domain && a.path == b.path
+}
+
+fn split_pair(pair: &str) -> Option<(&str, &str)> {
+    let equals = pair.find('=')?;
+    Some((pair[..equals].trim(), pair[equals + 1..].trim()))
+}
@@ -0,0 +1,182 @@
+use crate::images::Image;
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use image::{ImageOutputFormat, Rgb, RgbImage};
+
+/// Font the title and author are set in, see `assets/DejaVu-LICENSE`
+const COVER_FONT: &[u8] = include_bytes!("../assets/DejaVuSerif-Bold.ttf");
+
+const WIDTH: u32 = 1200;
+const HEIGHT: u32 = 1800;
+const MARGIN: f32 = 120.0;
+/// Distance between baselines, as a multiple of the font's height
+const LINE_SPACING: f32 = 1.15;
+const TITLE_SIZES: [f32; 6] = [150.0, 128.0, 110.0, 92.0, 76.0, 64.0];
+const AUTHOR_SIZE: f32 = 64.0;
+
+/// Backgrounds to pick from, all dark enough for the light text
+const BACKGROUNDS: [[u8; 3]; 8] = [
+    [38, 70, 83],
+    [42, 59, 94],
+    [97, 40, 56],
+    [54, 82, 55],
+    [86, 56, 96],
+    [122, 62, 38],
+    [40, 40, 48],
+    [24, 88, 96],
+];
+const TEXT_COLOR: [u8; 3] = [250, 246, 235];
+
+/// Makes a cover with the title and the author on a colored background, for
+/// novels whose page doesn't have one. The color is picked by the title so a
+/// novel gets the same cover every time.
+///
+/// Returns `None` when the title has characters the font doesn't have, i.e.
+/// Japanese or Chinese ones. The author is left out for the same reason.
+pub fn generate_cover(title: &str, author: &str) -> Option<Image> {
+    let font = FontRef::try_from_slice(COVER_FONT).expect("The cover font is broken");
+    if !can_draw(&font, title) {
+        return None;
+    }
+    let background = BACKGROUNDS[title.bytes().fold(0usize, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte.into())
+    }) % BACKGROUNDS.len()];
+    let mut cover = RgbImage::from_pixel(WIDTH, HEIGHT, Rgb(background));
+    let max_width = WIDTH as f32 - 2.0 * MARGIN;
+
+    // As big as the title goes while fitting above the author, long titles
+    // settle for the smallest size
+    let (scale, lines) = TITLE_SIZES
+        .iter()
+        .map(|&size| {
+            let scale = PxScale::from(size);
+            (scale, wrap(&font, scale, title, max_width))
+        })
+        .find(|(scale, lines)| {
+            let scaled = font.as_scaled(*scale);
+            lines.len() as f32 * scaled.height() * LINE_SPACING <= HEIGHT as f32 * 0.55
+                && lines
+                    .iter()
+                    .all(|line| line_width(&font, *scale, line) <= max_width)
+        })
+        .unwrap_or_else(|| {
+            let scale = PxScale::from(TITLE_SIZES[TITLE_SIZES.len() - 1]);
+            (scale, wrap(&font, scale, title, max_width))
+        });
+    let scaled = font.as_scaled(scale);
+    let line_height = scaled.height() * LINE_SPACING;
+    // Centered on the upper part of the cover
+    let mut baseline =
+        HEIGHT as f32 * 0.4 - lines.len() as f32 * line_height / 2.0 + scaled.ascent();
+    for line in &lines {
+        draw_line(&mut cover, &font, scale, line, baseline);
+        baseline += line_height;
+    }
+
+    let rule_y = (HEIGHT as f32 * 0.74) as u32;
+    for y in rule_y..rule_y + 4 {
+        for x in WIDTH / 2 - 100..WIDTH / 2 + 100 {
+            cover.put_pixel(x, y, Rgb(TEXT_COLOR));
+        }
+    }
+
+    if can_draw(&font, author) {
+        let scale = PxScale::from(AUTHOR_SIZE);
+        let mut baseline = HEIGHT as f32 * 0.8 + font.as_scaled(scale).ascent();
+        for line in wrap(&font, scale, author, max_width) {
+            draw_line(&mut cover, &font, scale, &line, baseline);
+            baseline += font.as_scaled(scale).height() * LINE_SPACING;
+        }
+    }
+
+    let mut data = Vec::new();
+    image::DynamicImage::ImageRgb8(cover)
+        .write_to(&mut data, ImageOutputFormat::Png)
+        .ok()?;
+    Some(Image {
+        path: "cover.png".to_string(),
+        mimetype: "image/png".to_string(),
+        data,
+    })
+}
+
+/// Whether the font has every character of `text` that isn't whitespace
+fn can_draw(font: &FontRef, text: &str) -> bool {
+    !text.trim().is_empty()
+        && text
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .all(|c| font.glyph_id(c).0 != 0)
+}
+
+/// Breaks `text` into lines no wider than `max_width` where it can, a word
+/// that's wider by itself gets its own line
+fn wrap(font: &FontRef, scale: PxScale, text: &str, max_width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{} {}", line, word)
+        };
+        if line.is_empty() || line_width(font, scale, &candidate) <= max_width {
+            line = candidate;
+        } else {
+            lines.push(line);
+            line = word.to_string();
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn line_width(font: &FontRef, scale: PxScale, line: &str) -> f32 {
+    let scaled = font.as_scaled(scale);
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in line.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+/// Draws `line` centered across the cover, sitting on `baseline`
+fn draw_line(cover: &mut RgbImage, font: &FontRef, scale: PxScale, line: &str, baseline: f32) {
+    let scaled = font.as_scaled(scale);
+    let mut x = (WIDTH as f32 - line_width(font, scale, line)) / 2.0;
+    let mut previous = None;
+    for c in line.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            x += scaled.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(scale, point(x, baseline));
+        x += scaled.h_advance(id);
+        previous = Some(id);
+        let outlined = match font.outline_glyph(glyph) {
+            Some(outlined) => outlined,
+            // Spaces have no outline
+            None => continue,
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|glyph_x, glyph_y, coverage| {
+            let x = bounds.min.x as i64 + i64::from(glyph_x);
+            let y = bounds.min.y as i64 + i64::from(glyph_y);
+            if x < 0 || y < 0 || x >= i64::from(WIDTH) || y >= i64::from(HEIGHT) {
+                return;
+            }
+            let pixel = cover.get_pixel_mut(x as u32, y as u32);
+            for (channel, text) in pixel.0.iter_mut().zip(TEXT_COLOR.iter()) {
+                *channel = (f32::from(*channel) * (1.0 - coverage) + f32::from(*text) * coverage)
+                    .round() as u8;
+            }
+        });
+    }
+}
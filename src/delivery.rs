@@ -0,0 +1,362 @@
+use crate::mirror::percent_decode;
+use rand::Rng;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::trace;
+
+/// Largest book Send to Kindle takes by email
+pub const DEFAULT_MAX_SIZE: u64 = 50 * 1024 * 1024;
+/// Where the password for the SMTP server is read from when it isn't in the url
+pub const PASSWORD_VARIABLE: &str = "BOX2EPUB_SMTP_PASSWORD";
+/// Longest a MIME encoded word in a header can be
+const MAX_ENCODED_WORD: usize = 75;
+
+/// An SMTP server to send books through, i.e.
+/// `smtps://me%40example.com@smtp.example.com` (TLS from the start, port 465
+/// by default) or `smtp://smtp.example.com` (STARTTLS, port 587 by default).
+/// Mail is never sent unencrypted.
+#[derive(Clone)]
+pub struct SmtpServer {
+    host: String,
+    port: u16,
+    /// TLS from the start instead of upgrading with STARTTLS
+    implicit_tls: bool,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl FromStr for SmtpServer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url = Url::parse(s).map_err(|err| format!("Invalid SMTP server {}: {}", s, err))?;
+        let implicit_tls = match url.scheme() {
+            "smtps" => true,
+            "smtp" => false,
+            scheme => {
+                return Err(format!(
+                    "Unsupported SMTP scheme {}, use smtps or smtp",
+                    scheme
+                ))
+            }
+        };
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("No host in the SMTP server {}", s))?
+            .to_string();
+        let username = Some(percent_decode(url.username())).filter(|name| !name.is_empty());
+        let password = url.password().map(percent_decode);
+        Ok(SmtpServer {
+            host,
+            port: url.port().unwrap_or(if implicit_tls { 465 } else { 587 }),
+            implicit_tls,
+            username,
+            password,
+        })
+    }
+}
+
+/// The delivery profiles `--send-to` looks in, i.e.
+/// `~/.config/box2epub/delivery.toml` on Linux
+pub fn delivery_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("box2epub").join("delivery.toml"))
+}
+
+/// Where to email books to and how, from `--send-to` or a profile
+#[derive(Clone)]
+pub struct Delivery {
+    /// i.e. the Kindle's `@kindle.com` address
+    pub to: String,
+    /// Has to be on the Kindle's approved list, the SMTP username by default
+    pub from: String,
+    pub smtp: SmtpServer,
+    /// Books bigger than this aren't sent, in bytes
+    pub max_size: u64,
+}
+
+/// A profile in the delivery config file
+#[derive(Deserialize)]
+struct Profile {
+    to: String,
+    from: Option<String>,
+    smtp: String,
+    username: Option<String>,
+    password: Option<String>,
+    /// In megabytes
+    max_size: Option<u64>,
+}
+
+impl Delivery {
+    pub fn new(to: &str, from: Option<&str>, smtp: SmtpServer) -> Result<Self, Box<dyn Error>> {
+        let from = from
+            .map(str::to_string)
+            .or_else(|| smtp.username.clone().filter(|name| name.contains('@')))
+            .ok_or("The address to send from has to be set, with --from")?;
+        Ok(Delivery {
+            to: to.to_string(),
+            from,
+            smtp,
+            max_size: DEFAULT_MAX_SIZE,
+        })
+    }
+
+    /// Reads the profile called `name` from a TOML file of profiles:
+    ///
+    /// ```toml
+    /// [kindle]
+    /// to = "me@kindle.com"
+    /// from = "me@example.com"
+    /// smtp = "smtps://smtp.example.com"
+    /// username = "me@example.com"
+    /// # Or in BOX2EPUB_SMTP_PASSWORD
+    /// password = "app password"
+    /// # Megabytes, 50 by default
+    /// max_size = 25
+    /// ```
+    pub fn from_profile(path: impl AsRef<Path>, name: &str) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+        let mut profiles: HashMap<String, Profile> = toml::from_str(&config)?;
+        let profile = profiles
+            .remove(name)
+            .ok_or_else(|| format!("There's no profile {} in {}", name, path.display()))?;
+        let mut smtp: SmtpServer = profile.smtp.parse()?;
+        smtp.username = profile.username.or(smtp.username);
+        smtp.password = profile.password.or(smtp.password);
+        let mut delivery = Delivery::new(&profile.to, profile.from.as_deref(), smtp)?;
+        if let Some(megabytes) = profile.max_size {
+            delivery.max_size = megabytes * 1024 * 1024;
+        }
+        Ok(delivery)
+    }
+
+    /// Emails the book at `path` as an attachment, after checking it isn't too
+    /// big to be accepted
+    pub async fn send(
+        &self,
+        path: &Path,
+        mimetype: &str,
+        subject: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let data = tokio::fs::read(path).await?;
+        if data.len() as u64 > self.max_size {
+            return Err(format!(
+                "{} is {:.1} MB, more than the {} MB that can be sent. Split it with --split-every",
+                path.display(),
+                data.len() as f64 / (1024.0 * 1024.0),
+                self.max_size / (1024 * 1024)
+            )
+            .into());
+        }
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "book".into());
+        let message = self.message(subject, &file_name, mimetype, &data);
+
+        let smtp = &self.smtp;
+        let tcp = TcpStream::connect((smtp.host.as_str(), smtp.port)).await?;
+        let tls = tokio_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+        if smtp.implicit_tls {
+            let stream = tls.connect(&smtp.host, tcp).await?;
+            let mut connection = SmtpConnection::new(stream);
+            connection.reply(220).await?;
+            self.deliver(connection, &message).await
+        } else {
+            let mut connection = SmtpConnection::new(tcp);
+            connection.reply(220).await?;
+            connection.command("EHLO box2epub", 250).await?;
+            connection.command("STARTTLS", 220).await?;
+            let stream = tls.connect(&smtp.host, connection.into_inner()).await?;
+            self.deliver(SmtpConnection::new(stream), &message).await
+        }
+    }
+
+    async fn deliver<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut connection: SmtpConnection<S>,
+        message: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let capabilities = connection.command("EHLO box2epub", 250).await?;
+        if let Some(username) = &self.smtp.username {
+            let password = match &self.smtp.password {
+                Some(password) => password.clone(),
+                None => std::env::var(PASSWORD_VARIABLE).map_err(|_| {
+                    format!("No password for {}, set {}", username, PASSWORD_VARIABLE)
+                })?,
+            };
+            let offers_plain = capabilities.lines().any(|line| {
+                line.to_uppercase().starts_with("AUTH") && line.to_uppercase().contains("PLAIN")
+            });
+            if offers_plain {
+                let credentials = base64::encode(format!("\0{}\0{}", username, password));
+                connection
+                    .command(&format!("AUTH PLAIN {}", credentials), 235)
+                    .await?;
+            } else {
+                connection.command("AUTH LOGIN", 334).await?;
+                connection.command(&base64::encode(username), 334).await?;
+                connection.command(&base64::encode(&password), 235).await?;
+            }
+        }
+        connection
+            .command(&format!("MAIL FROM:<{}>", self.from), 250)
+            .await?;
+        connection
+            .command(&format!("RCPT TO:<{}>", self.to), 250)
+            .await?;
+        connection.command("DATA", 354).await?;
+        connection.send_data(message).await?;
+        connection.reply(250).await?;
+        // The mail was accepted, it doesn't matter how this goes
+        let _ = connection.command("QUIT", 221).await;
+        Ok(())
+    }
+
+    /// The email with the book attached, as it's sent to the SMTP server
+    pub fn message(&self, subject: &str, file_name: &str, mimetype: &str, data: &[u8]) -> String {
+        let boundary = format!("box2epub-{:016x}", rand::thread_rng().gen::<u64>());
+        let mut message = format!(
+            "From: <{from}>\r\n\
+             To: <{to}>\r\n\
+             Subject: {subject}\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: multipart/mixed; boundary=\"{boundary}\"\r\n\
+             \r\n\
+             --{boundary}\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             \r\n\
+             Sent by box2epub\r\n\
+             --{boundary}\r\n\
+             Content-Type: {mimetype}; name=\"{name}\"\r\n\
+             Content-Disposition: attachment; filename=\"{name}\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             \r\n",
+            from = self.from,
+            to = self.to,
+            subject = encode_header(subject),
+            boundary = boundary,
+            mimetype = mimetype,
+            name = encode_header(&file_name.replace('"', "'")),
+        );
+        let encoded = base64::encode(data);
+        // Lines can't be longer than 76 characters, base64 is all ASCII
+        for line in encoded.as_bytes().chunks(76) {
+            message.push_str(std::str::from_utf8(line).unwrap());
+            message.push_str("\r\n");
+        }
+        message.push_str(&format!("--{}--\r\n", boundary));
+        message
+    }
+}
+
+/// Header values have to be ASCII, anything else goes in MIME encoded words.
+/// They're also on one line: control characters (i.e. a line break in a
+/// scraped title) are made spaces, so they can't start headers of their own.
+fn encode_header(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if value.is_ascii() {
+        return value;
+    }
+    // An encoded word can't be longer than 75 characters, so a long title is
+    // split into several on lines of their own. Each has whole characters,
+    // as many as fit in 60 characters of base64.
+    let max_bytes = (MAX_ENCODED_WORD - "=?UTF-8?B??=".len()) / 4 * 3;
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in value.chars() {
+        if word.len() + c.len_utf8() > max_bytes {
+            words.push(format!("=?UTF-8?B?{}?=", base64::encode(&word)));
+            word.clear();
+        }
+        word.push(c);
+    }
+    words.push(format!("=?UTF-8?B?{}?=", base64::encode(&word)));
+    words.join("\r\n ")
+}
+
+/// Sends commands and reads the replies, one at a time
+struct SmtpConnection<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SmtpConnection<S> {
+    fn new(stream: S) -> Self {
+        SmtpConnection {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    /// The stream underneath, to start TLS on. The server doesn't send
+    /// anything after agreeing to STARTTLS so nothing buffered is lost.
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<String, Box<dyn Error>> {
+        trace!(
+            command = command.split(' ').next().unwrap_or_default(),
+            "SMTP"
+        );
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+        stream.flush().await?;
+        self.reply(expected).await
+    }
+
+    /// Sends the message, lines starting with a dot get another one so they
+    /// aren't taken for the end of it
+    async fn send_data(&mut self, message: &str) -> Result<(), Box<dyn Error>> {
+        let mut data = String::with_capacity(message.len() + 5);
+        for line in message.split_terminator("\r\n") {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data.push_str(".\r\n");
+        let stream = self.stream.get_mut();
+        stream.write_all(data.as_bytes()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    /// Reads a reply, which can go over several lines (`250-...` until
+    /// `250 ...`), and fails unless its code is `expected`
+    async fn reply(&mut self, expected: u16) -> Result<String, Box<dyn Error>> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                return Err("The SMTP server closed the connection".into());
+            }
+            let line = line.trim_end();
+            let code: u16 = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| format!("Unexpected reply from the SMTP server: {}", line))?;
+            reply.push_str(line.get(4..).unwrap_or_default());
+            reply.push('\n');
+            if line.as_bytes().get(3) == Some(&b'-') {
+                continue;
+            }
+            if code != expected {
+                return Err(format!("The SMTP server answered {}", line).into());
+            }
+            return Ok(reply);
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use crate::charset::decode_page;
+use crate::cookies::CookieJar;
+use crate::fetcher::{FetchError, Fetcher, Request, Response};
+use crate::http_cache::HttpCache;
+use crate::progress;
+use crate::robots::{Robots, ROBOTS_USER_AGENT};
+use rand::Rng;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::{StatusCode, Url};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::Semaphore;
+use tracing::{debug, trace};
+
+/// Default for `Downloader::per_host_limit`
+pub const DEFAULT_PER_HOST_LIMIT: usize = 4;
+/// Rate sites behind Cloudflare get when no limit was set, slow enough that
+/// Cloudflare doesn't take the downloads for a bot
+pub const CLOUDFLARE_RATE_LIMIT: RateLimit = RateLimit {
+    requests: 1,
+    per: Duration::from_secs(3),
+};
+
+/// What Cloudflare's "checking your browser" pages have in them
+const CHALLENGE_MARKERS: [&str; 4] = [
+    "cf-browser-verification",
+    "cf_chl_",
+    "challenge-platform",
+    "<title>Just a moment...</title>",
+];
+
+/// Why a url couldn't be fetched
+#[derive(Debug, Error)]
+pub enum Error {
+    /// No response at all, i.e. the connection failed
+    #[error("Couldn't fetch {0}: {1}")]
+    Fetch(String, #[source] FetchError),
+    /// The site answered with an error status, i.e. 404
+    #[error("{0} answered with {1}")]
+    Status(String, StatusCode),
+    /// The site answered with an anti-bot challenge (i.e. Cloudflare's) instead
+    /// of the page, retrying won't help
+    #[error(
+        "{0} answered with a Cloudflare challenge. Open it in a browser, then pass the \
+         browser's cookies with --cookie-file and its user agent with --user-agent"
+    )]
+    Challenge(String),
+    /// The site's robots.txt asks not to fetch the url
+    #[error("{0} is disallowed by the site's robots.txt, --ignore-robots fetches it anyway")]
+    Disallowed(String),
+}
+
+/// How many times, and how long to wait between, attempts at fetching a url
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of tries, including the first one
+    pub attempts: u32,
+    /// Delay before the first retry, doubled for every retry after that
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Randomize delays so parallel downloads don't all retry at the same moment
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay to wait after the given (zero based) failed attempt
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        if self.jitter {
+            // Somewhere between half and all of the delay
+            let millis = delay.as_millis() as u64;
+            Duration::from_millis(rand::thread_rng().gen_range(millis / 2, millis + 1))
+        } else {
+            delay
+        }
+    }
+}
+
+/// Maximum request rate for a single host, i.e. `2/s` or `30/m`
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    fn per_second(self) -> f64 {
+        f64::from(self.requests) / self.per.as_secs_f64()
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid rate {}, expected something like 2/s or 30/m", s);
+        let (requests, unit) = match s.find('/') {
+            Some(slash) => (&s[..slash], &s[slash + 1..]),
+            None => (s, "s"),
+        };
+        let requests: u32 = requests.trim().parse().map_err(|_| invalid())?;
+        let per = match unit.trim() {
+            "s" | "sec" => Duration::from_secs(1),
+            "m" | "min" => Duration::from_secs(60),
+            "h" | "hour" => Duration::from_secs(60 * 60),
+            _ => return Err(invalid()),
+        };
+        if requests == 0 {
+            return Err(invalid());
+        }
+        Ok(RateLimit { requests, per })
+    }
+}
+
+/// Token bucket holding up to a `RateLimit`'s worth of requests, so a burst of
+/// that size is allowed before requests get spaced out
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: f64::from(limit.requests),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes a token and returns how long to wait before using it. Tokens can be
+    /// taken before they're there, which queues up requests in the order they came.
+    fn take(&mut self) -> Duration {
+        self.take_many(1.0)
+    }
+
+    /// Takes `count` tokens at once, i.e. one per byte read
+    pub(crate) fn take_many(&mut self, count: f64) -> Duration {
+        let now = Instant::now();
+        let refilled = now.duration_since(self.updated).as_secs_f64() * self.limit.per_second();
+        self.tokens = (self.tokens + refilled).min(f64::from(self.limit.requests)) - count;
+        self.updated = now;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.limit.per_second())
+        }
+    }
+}
+
+/// Limits for requests to a single host
+struct Host {
+    connections: Semaphore,
+    rate: Option<Mutex<TokenBucket>>,
+    /// Its robots.txt and a bucket for its crawl delay, fetched before the
+    /// first request to it
+    robots: tokio::sync::Mutex<Option<Arc<HostRobots>>>,
+}
+
+struct HostRobots {
+    robots: Robots,
+    crawl_delay: Option<Mutex<TokenBucket>>,
+}
+
+/// A response body along with its Content-Type
+struct Body {
+    content_type: Option<String>,
+    data: Vec<u8>,
+}
+
+/// Fetches pages, retrying transient failures according to a `RetryPolicy`.
+///
+/// Clones share their connection limits.
+#[derive(Clone)]
+pub struct Downloader {
+    fetcher: Arc<dyn Fetcher>,
+    retry_policy: RetryPolicy,
+    per_host_limit: usize,
+    rate_limit: Option<RateLimit>,
+    respect_robots: bool,
+    hosts: Arc<Mutex<HashMap<String, Arc<Host>>>>,
+    cookies: Arc<Mutex<CookieJar>>,
+    http_cache: Option<HttpCache>,
+}
+
+impl Downloader {
+    pub fn new(fetcher: Arc<dyn Fetcher>, retry_policy: RetryPolicy) -> Self {
+        Downloader {
+            fetcher,
+            retry_policy,
+            per_host_limit: DEFAULT_PER_HOST_LIMIT,
+            rate_limit: None,
+            respect_robots: false,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+            cookies: Arc::new(Mutex::new(CookieJar::default())),
+            http_cache: None,
+        }
+    }
+
+    /// Maximum number of requests sent to a single host at the same time, so
+    /// small sites aren't hammered while images from a CDN can still be fetched
+    /// alongside the chapters
+    pub fn per_host_limit(mut self, limit: usize) -> Self {
+        self.per_host_limit = limit.max(1);
+        self
+    }
+
+    /// Maximum number of requests sent to a single host per second (or minute),
+    /// to stay under the radar of sites that ban IPs
+    pub fn rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.rate_limit = limit;
+        self
+    }
+
+    /// Stay away from urls the site's robots.txt disallows and wait its
+    /// crawl delay between requests
+    pub fn respect_robots(mut self, respect: bool) -> Self {
+        self.respect_robots = respect;
+        self
+    }
+
+    /// Cookies to start out with, i.e. from a logged in browser session
+    pub fn cookies(self, cookies: CookieJar) -> Self {
+        *self.cookies.lock().unwrap() = cookies;
+        self
+    }
+
+    /// Cache for GET responses, they're revalidated with a conditional request
+    /// instead of downloaded again
+    pub fn http_cache(mut self, cache: Option<HttpCache>) -> Self {
+        self.http_cache = cache;
+        self
+    }
+
+    /// This downloader without the HTTP cache, for pages that are cached some
+    /// other way. It shares this one's limits, robots.txt rules and cookies.
+    pub fn without_http_cache(&self) -> Self {
+        Downloader {
+            http_cache: None,
+            ..self.clone()
+        }
+    }
+
+    /// A downloader that sends its requests with `fetcher` instead, sharing
+    /// this one's limits, robots.txt rules and cookies. Its responses aren't
+    /// put in the HTTP cache.
+    pub fn with_fetcher(&self, fetcher: Arc<dyn Fetcher>) -> Self {
+        Downloader {
+            fetcher,
+            http_cache: None,
+            ..self.clone()
+        }
+    }
+
+    fn host(&self, url: &str) -> Arc<Host> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        self.hosts
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    connections: Semaphore::new(self.per_host_limit),
+                    rate: self
+                        .rate_limit
+                        .map(|limit| Mutex::new(TokenBucket::new(limit))),
+                    robots: tokio::sync::Mutex::new(None),
+                })
+            })
+            .clone()
+    }
+
+    /// Sends the request, only returns an error if the last attempt failed or
+    /// the error isn't worth retrying (i.e. a 404)
+    async fn fetch(&self, request: Request) -> Result<Response, Error> {
+        let url = request.url.as_str();
+        let host = self.host(url);
+        let robots = if self.respect_robots {
+            let robots = self.robots(&host, url).await;
+            let allowed = Url::parse(url).map_or(true, |url| robots.robots.allows(&url));
+            if !allowed {
+                return Err(Error::Disallowed(url.to_string()));
+            }
+            Some(robots)
+        } else {
+            None
+        };
+        let mut attempt = 0;
+        loop {
+            // The connection slot is held until the whole body is read, but not
+            // while waiting to retry
+            let permit = host.connections.acquire().await;
+            if let Some(rate) = &host.rate {
+                let wait = rate.lock().unwrap().take();
+                if wait > Duration::from_secs(0) {
+                    trace!(url, ?wait, "waiting for the rate limit");
+                }
+                tokio::time::delay_for(wait).await;
+            }
+            if let Some(delay) = robots
+                .as_ref()
+                .and_then(|robots| robots.crawl_delay.as_ref())
+            {
+                let wait = delay.lock().unwrap().take();
+                if wait > Duration::from_secs(0) {
+                    trace!(url, ?wait, "waiting for the crawl delay");
+                }
+                tokio::time::delay_for(wait).await;
+            }
+            let mut request = request.clone();
+            if let Some(cookies) = self.cookie_header(url) {
+                trace!(url, "sending cookies");
+                request.headers.insert(header::COOKIE, cookies);
+            }
+            debug!(url, attempt, "sending request");
+            let result = match self.fetcher.fetch(request).await {
+                Ok(resp) => {
+                    debug!(url, status = resp.status.as_u16(), "got response");
+                    self.store_cookies(&resp);
+                    let failed = resp.status.is_client_error() || resp.status.is_server_error();
+                    if might_be_challenge(&resp) {
+                        debug!(url, "response might be a Cloudflare challenge");
+                        let body = String::from_utf8_lossy(&resp.body);
+                        if !failed || CHALLENGE_MARKERS.iter().any(|marker| body.contains(marker)) {
+                            Err(Error::Challenge(url.to_string()))
+                        } else {
+                            Err(Error::Status(url.to_string(), resp.status))
+                        }
+                    } else if failed {
+                        Err(Error::Status(url.to_string(), resp.status))
+                    } else {
+                        Ok(resp)
+                    }
+                }
+                Err(err) => {
+                    debug!(url, error = %err, "request failed");
+                    Err(Error::Fetch(url.to_string(), err))
+                }
+            };
+            drop(permit);
+
+            match result {
+                Err(err) if attempt + 1 < self.retry_policy.attempts && is_retryable(&err) => {
+                    let delay = self.retry_policy.delay(attempt);
+                    progress::info(format!("Retrying {} in {:?} ({})", url, delay, err));
+                    tokio::time::delay_for(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The host's robots.txt, fetched the first time it's needed. A site
+    /// without one, or one that can't be fetched, allows everything.
+    async fn robots(&self, host: &Host, url: &str) -> Arc<HostRobots> {
+        let mut robots = host.robots.lock().await;
+        if let Some(robots) = &*robots {
+            return robots.clone();
+        }
+        let parsed = match Url::parse(url).and_then(|url| url.join("/robots.txt")) {
+            Ok(robots_url) => self.fetch_robots(robots_url.as_str()).await,
+            Err(_) => Robots::allow_all(),
+        };
+        let fetched = Arc::new(HostRobots {
+            crawl_delay: parsed.crawl_delay.map(|delay| {
+                Mutex::new(TokenBucket::new(RateLimit {
+                    requests: 1,
+                    per: delay,
+                }))
+            }),
+            robots: parsed,
+        });
+        *robots = Some(fetched.clone());
+        fetched
+    }
+
+    async fn fetch_robots(&self, url: &str) -> Robots {
+        debug!(url, "fetching robots.txt");
+        let request = Request {
+            url: url.to_string(),
+            form: None,
+            headers: HeaderMap::new(),
+        };
+        match self.fetcher.fetch(request).await {
+            Ok(resp) if resp.status.is_success() => {
+                let robots = Robots::parse(
+                    &decode_page(resp.content_type(), &resp.body),
+                    ROBOTS_USER_AGENT,
+                );
+                if let Some(delay) = robots.crawl_delay {
+                    progress::info(format!(
+                        "{} asks for {:?} between requests (--ignore-robots changes this)",
+                        url, delay
+                    ));
+                }
+                robots
+            }
+            Ok(resp) => {
+                debug!(url, status = resp.status.as_u16(), "no robots.txt");
+                Robots::allow_all()
+            }
+            Err(err) => {
+                debug!(url, error = %err, "couldn't fetch robots.txt");
+                Robots::allow_all()
+            }
+        }
+    }
+
+    fn cookie_header(&self, url: &str) -> Option<HeaderValue> {
+        let url = Url::parse(url).ok()?;
+        let cookies = self.cookies.lock().unwrap().header_for(&url)?;
+        HeaderValue::from_str(&cookies).ok()
+    }
+
+    fn store_cookies(&self, resp: &Response) {
+        let url = match Url::parse(&resp.url) {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        let mut cookies = self.cookies.lock().unwrap();
+        for set_cookie in resp.headers.get_all(header::SET_COOKIE) {
+            if let Ok(set_cookie) = set_cookie.to_str() {
+                cookies.store(&url, set_cookie);
+            }
+        }
+    }
+
+    pub async fn get_text(&self, url: &str) -> Result<String, Error> {
+        let body = self.get(url).await?;
+        Ok(decode_page(body.content_type.as_deref(), &body.data))
+    }
+
+    /// Sends a POST request with a url encoded form, i.e. for AJAX endpoints
+    pub async fn post_form(&self, url: &str, form: &[(String, String)]) -> Result<String, Error> {
+        let resp = self
+            .fetch(Request {
+                url: url.to_string(),
+                form: Some(form.to_vec()),
+                headers: HeaderMap::new(),
+            })
+            .await?;
+        Ok(decode_page(resp.content_type(), &resp.body))
+    }
+
+    /// Downloads binary data (i.e. images), along with its mimetype from the
+    /// Content-Type header
+    pub async fn get_bytes(&self, url: &str) -> Result<(Option<String>, Vec<u8>), Error> {
+        let body = self.get(url).await?;
+        let mimetype = body
+            .content_type
+            .as_deref()
+            .map(|v| v.split(';').next().unwrap_or(v).trim().to_lowercase());
+        Ok((mimetype, body.data))
+    }
+
+    /// GET request that goes through the HTTP cache, if there is one
+    async fn get(&self, url: &str) -> Result<Body, Error> {
+        let cached = match &self.http_cache {
+            Some(cache) => cache.get(url).await,
+            None => None,
+        };
+        let mut headers = HeaderMap::new();
+        if let Some(cached) = &cached {
+            cached.add_validators(&mut headers);
+        }
+        let resp = self
+            .fetch(Request {
+                url: url.to_string(),
+                form: None,
+                headers,
+            })
+            .await?;
+
+        match cached {
+            Some(cached) if resp.status == StatusCode::NOT_MODIFIED => {
+                debug!(url, "not modified, using the cached response");
+                Ok(Body {
+                    content_type: cached.content_type().map(str::to_string),
+                    data: cached.data,
+                })
+            }
+            _ => {
+                if let Some(cache) = &self.http_cache {
+                    if let Err(err) = cache.put(url, &resp.headers, &resp.body).await {
+                        progress::warn(format!("Couldn't cache {}: {}", url, err));
+                    }
+                }
+                Ok(Body {
+                    content_type: resp.content_type().map(str::to_string),
+                    data: resp.body,
+                })
+            }
+        }
+    }
+}
+
+/// Server errors, rate limiting and connection problems are usually transient,
+/// anything else (i.e. 404) will fail the same way next time
+fn is_retryable(err: &Error) -> bool {
+    match err {
+        // Connection errors and timeouts
+        Error::Fetch(..) => true,
+        Error::Status(_, status) => {
+            status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+        }
+        Error::Challenge(_) | Error::Disallowed(_) => false,
+    }
+}
+
+/// Cloudflare marks its challenges with a header these days, older ones can
+/// only be told apart by their body
+fn might_be_challenge(resp: &Response) -> bool {
+    let header = |name: &str| resp.headers.get(name).and_then(|v| v.to_str().ok());
+    if header("cf-mitigated") == Some("challenge") {
+        return true;
+    }
+    let status = resp.status;
+    (status == StatusCode::FORBIDDEN || status == StatusCode::SERVICE_UNAVAILABLE)
+        && matches!(header("server"), Some(server) if server.eq_ignore_ascii_case("cloudflare"))
+}
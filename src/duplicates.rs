@@ -0,0 +1,145 @@
+use crate::book::DownloadedChapter;
+use crate::text::{xhtml_to_text, TextStyle};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+
+/// How many words in a row make up a shingle
+const SHINGLE_WORDS: usize = 5;
+/// Hashes in a chapter's MinHash signature, split into bands of
+/// `ROWS_PER_BAND`. Chapters that agree on a whole band are compared in full,
+/// so pairs that are 90% the same almost always are and unrelated ones hardly
+/// ever.
+const SIGNATURE_LENGTH: usize = 64;
+const ROWS_PER_BAND: usize = 4;
+/// How much of two chapters' shingles have to be the same for the later one
+/// to count as a duplicate
+pub const DEFAULT_SIMILARITY: f64 = 0.9;
+
+/// A chapter whose text is (nearly) the same as an earlier one's, which is
+/// what aggregators that list a chapter twice under different numbers end up
+/// serving
+#[derive(Debug, Clone)]
+pub struct Duplicate {
+    pub number: usize,
+    pub url: String,
+    /// Number of the earlier chapter it's a copy of
+    pub original: usize,
+    /// Share of the text that's the same, 1.0 for an exact copy
+    pub similarity: f64,
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "chapter {} ({}) is {:.0}% the same as chapter {}",
+            self.number,
+            self.url,
+            self.similarity * 100.0,
+            self.original
+        )
+    }
+}
+
+/// A chapter's text cut up for comparing
+struct Fingerprint {
+    shingles: HashSet<u64>,
+    signature: [u64; SIGNATURE_LENGTH],
+}
+
+impl Fingerprint {
+    fn new(text: &str) -> Option<Self> {
+        let words: Vec<String> = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        if words.is_empty() {
+            return None;
+        }
+        // Chapters shorter than a shingle are one shingle
+        let shingles: HashSet<u64> = words
+            .windows(SHINGLE_WORDS.min(words.len()))
+            .map(|shingle| hash((0, shingle)))
+            .collect();
+
+        let mut signature = [u64::MAX; SIGNATURE_LENGTH];
+        for &shingle in &shingles {
+            for (seed, min) in signature.iter_mut().enumerate() {
+                *min = (*min).min(hash((seed + 1, shingle)));
+            }
+        }
+        Some(Fingerprint {
+            shingles,
+            signature,
+        })
+    }
+
+    /// Jaccard similarity of the shingles
+    fn similarity(&self, other: &Fingerprint) -> f64 {
+        let shared = self.shingles.intersection(&other.shingles).count();
+        shared as f64 / (self.shingles.len() + other.shingles.len() - shared) as f64
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Chapters at least `min_similarity` the same as one before them, in order.
+/// Chapters without any text (i.e. only an image) are never duplicates.
+pub fn find_duplicates(
+    chapters: &[DownloadedChapter],
+    min_similarity: f64,
+) -> io::Result<Vec<Duplicate>> {
+    let mut fingerprints = Vec::with_capacity(chapters.len());
+    for chapter in chapters {
+        let text = xhtml_to_text(&chapter.content()?, TextStyle::Plain);
+        // The heading differs even between copies
+        let text = text.replacen(chapter.title.as_str(), "", 1);
+        fingerprints.push(Fingerprint::new(&text));
+    }
+
+    // Chapters with the same rows in a band, by band
+    let mut buckets: HashMap<(usize, &[u64]), Vec<usize>> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for (i, fingerprint) in fingerprints.iter().enumerate() {
+        let fingerprint = match fingerprint {
+            Some(fingerprint) => fingerprint,
+            None => continue,
+        };
+        let mut candidates = Vec::new();
+        for (band, rows) in fingerprint.signature.chunks(ROWS_PER_BAND).enumerate() {
+            let bucket = buckets.entry((band, rows)).or_default();
+            candidates.extend(bucket.iter().copied());
+            bucket.push(i);
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let best = candidates
+            .into_iter()
+            // The first of equally similar chapters is the original
+            .rev()
+            .filter_map(|j| {
+                let similarity = fingerprints[j].as_ref()?.similarity(fingerprint);
+                Some((j, similarity))
+            })
+            .filter(|&(_, similarity)| similarity >= min_similarity)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        if let Some((j, similarity)) = best {
+            duplicates.push(Duplicate {
+                number: chapters[i].number,
+                url: chapters[i].url.clone(),
+                original: chapters[j].number,
+                similarity,
+            });
+        }
+    }
+    Ok(duplicates)
+}
@@ -0,0 +1,150 @@
+use crate::downloader::Downloader;
+use crate::extractor::{all_texts, first_text, paragraphs_text, Overview};
+use crate::progress;
+
+use reqwest::Url;
+use scraper::{Html, Selector};
+use tracing::debug;
+
+lazy_static! {
+    static ref SEARCH_RESULT_SELECTOR: Selector = Selector::parse(".search_title a").unwrap();
+    static ref TITLE_SELECTOR: Selector = Selector::parse(".seriestitlenu").unwrap();
+    static ref ASSOCIATED_SELECTOR: Selector = Selector::parse("#editassociated").unwrap();
+    static ref AUTHOR_SELECTOR: Selector = Selector::parse("#showauthors a").unwrap();
+    static ref GENRE_SELECTOR: Selector = Selector::parse("#seriesgenre a, #showtags a").unwrap();
+    static ref DESCRIPTION_SELECTOR: Selector = Selector::parse("#editdescription").unwrap();
+}
+
+/// NovelUpdates' search, the novel's title goes in the `s` parameter
+const SEARCH_URL: &str = "https://www.novelupdates.com/?post_type=seriesplans";
+/// How many search results are checked for one with a matching title
+const CHECKED_RESULTS: usize = 3;
+/// Authors aggregator sites show when they don't know it
+const PLACEHOLDER_AUTHORS: [&str; 6] = ["", "no_author", "updating", "unknown", "n/a", "author"];
+
+/// What NovelUpdates knows about a novel
+#[derive(Debug, Default)]
+pub struct Metadata {
+    pub title: String,
+    pub alternate_titles: Vec<String>,
+    pub authors: Vec<String>,
+    pub genres: Vec<String>,
+    pub description: Option<String>,
+}
+
+/// Looks the novel up on NovelUpdates and fills in what the site left out or
+/// got wrong: a placeholder author, the description, alternate titles and
+/// more tags. Nothing changes if it can't be found there.
+pub async fn enrich(downloader: &Downloader, overview: &mut Overview) {
+    match find_metadata(downloader, &overview.title).await {
+        Ok(Some(metadata)) => {
+            debug!(?metadata, "found the novel on NovelUpdates");
+            progress::info("Filling in the novel's details from NovelUpdates");
+            apply(overview, metadata);
+        }
+        Ok(None) => debug!("the novel isn't on NovelUpdates"),
+        Err(err) => progress::warn(format!(
+            "Couldn't look the novel up on NovelUpdates (--no-enrich skips this): {}",
+            err
+        )),
+    }
+}
+
+/// Searches for the title and returns the first result that's the same novel,
+/// going by its title or one of its alternate titles
+async fn find_metadata(
+    downloader: &Downloader,
+    title: &str,
+) -> Result<Option<Metadata>, Box<dyn std::error::Error>> {
+    let mut search_url = Url::parse(SEARCH_URL)?;
+    search_url
+        .query_pairs_mut()
+        .append_pair("s", title)
+        .finish();
+    let search_html = downloader.get_text(search_url.as_str()).await?;
+    let results: Vec<String> = Html::parse_document(&search_html)
+        .select(&SEARCH_RESULT_SELECTOR)
+        .filter_map(|link| link.value().attr("href"))
+        .take(CHECKED_RESULTS)
+        .map(str::to_string)
+        .collect();
+
+    let wanted = comparable(title);
+    for url in results {
+        let metadata = parse_series_page(&downloader.get_text(&url).await?);
+        let matches = std::iter::once(&metadata.title)
+            .chain(&metadata.alternate_titles)
+            .any(|candidate| comparable(candidate) == wanted);
+        if matches {
+            return Ok(Some(metadata));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_series_page(html: &str) -> Metadata {
+    let document = Html::parse_document(html);
+    // Alternate titles are separated by <br>s
+    let alternate_titles = document
+        .select(&ASSOCIATED_SELECTOR)
+        .next()
+        .map(|element| {
+            element
+                .text()
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    Metadata {
+        title: first_text(&document, &TITLE_SELECTOR).unwrap_or_default(),
+        alternate_titles,
+        authors: all_texts(&document, &AUTHOR_SELECTOR),
+        genres: all_texts(&document, &GENRE_SELECTOR),
+        description: document
+            .select(&DESCRIPTION_SELECTOR)
+            .next()
+            .map(paragraphs_text)
+            .filter(|description| !description.is_empty()),
+    }
+}
+
+fn apply(overview: &mut Overview, metadata: Metadata) {
+    let author = overview.author.trim().to_lowercase();
+    if PLACEHOLDER_AUTHORS.contains(&author.as_str()) && !metadata.authors.is_empty() {
+        overview.author = metadata.authors.join(", ");
+    }
+    if overview.description.is_none() {
+        overview.description = metadata.description;
+    }
+    for title in std::iter::once(metadata.title).chain(metadata.alternate_titles) {
+        let known = comparable(&title) == comparable(&overview.title)
+            || overview
+                .alternate_titles
+                .iter()
+                .any(|known| comparable(known) == comparable(&title));
+        if !title.is_empty() && !known {
+            overview.alternate_titles.push(title);
+        }
+    }
+    for genre in metadata.genres {
+        if !overview
+            .genres
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(&genre))
+        {
+            overview.genres.push(genre);
+        }
+    }
+}
+
+/// Lowercase letters and digits of a title, so punctuation and spacing
+/// differences between sites don't matter
+fn comparable(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
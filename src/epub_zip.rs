@@ -0,0 +1,69 @@
+use epub_builder::Zip;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// epub-builder's `Zip` writing the archive straight to `W` as files are
+/// added, where its `ZipLibrary` builds up the whole compressed book in
+/// memory first.
+///
+/// `rewrite` gets the package document and NCX (by their path and text) on
+/// their way in, and returns what's written instead.
+pub struct StreamingZip<W: Write + Seek, F: Fn(&str, String) -> String> {
+    zip: ZipWriter<W>,
+    rewrite: F,
+}
+
+impl<W: Write + Seek, F: Fn(&str, String) -> String> StreamingZip<W, F> {
+    pub fn new(output: W, rewrite: F) -> io::Result<Self> {
+        let mut zip = ZipWriter::new(output);
+        // Readers only recognize the book with the mimetype first and
+        // uncompressed
+        zip.start_file(
+            "mimetype",
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(b"application/epub+zip")?;
+        Ok(StreamingZip { zip, rewrite })
+    }
+}
+
+impl<W: Write + Seek, F: Fn(&str, String) -> String> Zip for StreamingZip<W, F> {
+    fn write_file<P: AsRef<Path>, R: Read>(
+        &mut self,
+        path: P,
+        mut content: R,
+    ) -> epub_builder::Result<()> {
+        // Paths inside the archive always use slashes
+        let name = path.as_ref().to_string_lossy().replace('\\', "/");
+        if name == "mimetype" {
+            return Ok(());
+        }
+        let failed = |err: &dyn std::fmt::Display| format!("Couldn't write {}: {}", name, err);
+        self.zip
+            .start_file(name.as_str(), FileOptions::default())
+            .map_err(|err| failed(&err))?;
+        if name.ends_with(".opf") || name.ends_with(".ncx") {
+            let mut text = String::new();
+            content
+                .read_to_string(&mut text)
+                .map_err(|err| failed(&err))?;
+            let text = (self.rewrite)(&name, text);
+            self.zip
+                .write_all(text.as_bytes())
+                .map_err(|err| failed(&err))?;
+        } else {
+            io::copy(&mut content, &mut self.zip).map_err(|err| failed(&err))?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the archive in `W`, nothing is written to `to`
+    fn generate<T: Write>(&mut self, _to: T) -> epub_builder::Result<()> {
+        self.zip
+            .finish()
+            .map_err(|err| format!("Couldn't finish the EPUB: {}", err))?;
+        Ok(())
+    }
+}
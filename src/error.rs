@@ -0,0 +1,107 @@
+use crate::downloader;
+use crate::extractor::ExtractError;
+use crate::validate::Problem;
+use std::io;
+use thiserror::Error;
+use zip::result::ZipError;
+
+/// Why a novel couldn't be downloaded or its book couldn't be written
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A page couldn't be downloaded, even after retrying
+    #[error(transparent)]
+    Network(#[from] downloader::Error),
+    /// A page was downloaded but the extractor couldn't make sense of it,
+    /// usually because the site changed its markup
+    #[error("Couldn't extract {url}: {source}")]
+    Extract {
+        url: String,
+        #[source]
+        source: ExtractError,
+    },
+    /// One of the novel's chapters failed, `source` says how
+    #[error("Couldn't get chapter {number}: {source}")]
+    Chapter {
+        /// Position in the novel's chapter list, starting at 1
+        number: usize,
+        url: String,
+        #[source]
+        source: Box<Error>,
+    },
+    /// The book has problems that would make readers reject it
+    #[error("The book doesn't pass validation, it has {} problems", .0.len())]
+    Invalid(Vec<Problem>),
+    /// The EPUB couldn't be put together, or an existing one couldn't be read
+    #[error("{0}")]
+    Epub(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Anything else, i.e. options that don't go together
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// A request the extractor sent itself failing is a network error, not
+    /// one of making sense of the page
+    pub fn extract(url: impl Into<String>, source: ExtractError) -> Self {
+        match source {
+            ExtractError::Network(err) => Error::Network(err),
+            source => Error::Extract {
+                url: url.into(),
+                source,
+            },
+        }
+    }
+}
+
+impl From<epub_builder::Error> for Error {
+    fn from(err: epub_builder::Error) -> Self {
+        Error::Epub(format!("Couldn't build the EPUB: {}", err))
+    }
+}
+
+impl From<ZipError> for Error {
+    fn from(err: ZipError) -> Self {
+        Error::Epub(format!(
+            "Couldn't read or write the EPUB's archive: {}",
+            err
+        ))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Io(err.into())
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Error::Other(format!("A download task failed: {}", err))
+    }
+}
+
+/// For the modules that still return boxed errors, i.e. reading config files
+impl From<Box<dyn std::error::Error>> for Error {
+    fn from(err: Box<dyn std::error::Error>) -> Self {
+        match err.downcast::<io::Error>() {
+            Ok(err) => Error::Io(*err),
+            Err(err) => Error::Other(err.to_string()),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.to_string())
+    }
+}
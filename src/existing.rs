@@ -0,0 +1,230 @@
+use crate::book::{placeholder_title, DownloadedChapter};
+use crate::error::{Error, Result};
+use crate::progress;
+use crate::sanitizer::decode_entities;
+use crate::spool::{Spool, SpooledImage};
+
+use regex::{Regex, RegexBuilder};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+lazy_static! {
+    static ref CHAPTER_ENTRY_REGEX: Regex = Regex::new(r"^OEBPS/c(\d+)\.xhtml$").unwrap();
+    static ref NAV_POINT_REGEX: Regex = RegexBuilder::new(
+        r#"<navLabel>\s*<text>(.*?)</text>\s*</navLabel>\s*<content src="([^"]*)""#
+    )
+    .dot_matches_new_line(true)
+    .build()
+    .unwrap();
+    static ref TITLE_REGEX: Regex = RegexBuilder::new(r"<title>(.*?)</title>")
+        .dot_matches_new_line(true)
+        .build()
+        .unwrap();
+    static ref IMAGE_SRC_REGEX: Regex =
+        Regex::new(r#"<img\b[^>]*?\ssrc="(images/[^"]+)""#).unwrap();
+    static ref IDENTIFIER_REGEX: Regex =
+        Regex::new(r"<dc:identifier\b[^>]*>([^<]+)</dc:identifier>").unwrap();
+    static ref CHAPTER_URL_REGEX: Regex =
+        Regex::new(r#"<meta name="box2epub:chapter" content="c(\d+)\.xhtml ([^"]*)"/>"#).unwrap();
+}
+
+/// An EPUB written by an earlier run, so `--update` only has to download the
+/// chapters that came out since.
+///
+/// Chapters are recognized by the `c<number>.xhtml` files box2epub names them
+/// with, numbered by their position in the novel's chapter list back then.
+/// They're matched to the novel's chapters by the url the package document
+/// gives for each, since the numbers shift when the site inserts or removes
+/// one.
+pub struct ExistingEpub {
+    path: PathBuf,
+    /// Chapter number to its title in the table of contents
+    chapters: BTreeMap<usize, String>,
+    /// Chapter url to its number in the EPUB. Books made before box2epub
+    /// wrote the urls down have none, their chapters go by number instead.
+    urls: Option<HashMap<String, usize>>,
+    /// The book's `dc:identifier`, kept when it's updated
+    identifier: Option<String>,
+}
+
+impl ExistingEpub {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut archive = ZipArchive::new(File::open(&path)?)?;
+
+        let numbers: Vec<usize> = archive
+            .file_names()
+            .filter_map(|name| CHAPTER_ENTRY_REGEX.captures(name))
+            .filter_map(|capture| capture[1].parse().ok())
+            .collect();
+        if numbers.is_empty() {
+            return Err(Error::Epub(format!(
+                "{} has no chapters made by box2epub",
+                path.display()
+            )));
+        }
+
+        let toc = read_entry(&mut archive, "OEBPS/toc.ncx").unwrap_or_default();
+        let toc_titles: HashMap<&str, String> = NAV_POINT_REGEX
+            .captures_iter(&toc)
+            .map(|capture| {
+                let src = capture.get(2).unwrap().as_str();
+                (src, decode_entities(capture[1].trim()))
+            })
+            .collect();
+
+        let mut chapters = BTreeMap::new();
+        for number in numbers {
+            let file_name = format!("c{}.xhtml", number);
+            let title = match toc_titles.get(file_name.as_str()) {
+                Some(title) => title.clone(),
+                None => {
+                    let content = read_entry(&mut archive, &format!("OEBPS/{}", file_name))?;
+                    TITLE_REGEX
+                        .captures(&content)
+                        .map(|capture| decode_entities(capture[1].trim()))
+                        .unwrap_or(file_name)
+                }
+            };
+            // Chapters that couldn't be downloaded last time are tried again
+            if title == placeholder_title(number) {
+                continue;
+            }
+            chapters.insert(number, title);
+        }
+
+        let opf = read_entry(&mut archive, "OEBPS/content.opf").unwrap_or_default();
+        let identifier = IDENTIFIER_REGEX
+            .captures(&opf)
+            .map(|capture| decode_entities(capture[1].trim()));
+        let urls: HashMap<String, usize> = CHAPTER_URL_REGEX
+            .captures_iter(&opf)
+            .filter_map(|capture| Some((decode_entities(&capture[2]), capture[1].parse().ok()?)))
+            .collect();
+        let urls = if urls.is_empty() {
+            progress::warn(format!(
+                "{} doesn't say where its chapters came from, they're matched by their \
+                 number, so chapters the site inserted or removed since throw the rest off",
+                path.display()
+            ));
+            None
+        } else {
+            Some(urls)
+        };
+
+        Ok(ExistingEpub {
+            path,
+            chapters,
+            urls,
+            identifier,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn identifier(&self) -> Option<&str> {
+        self.identifier.as_deref()
+    }
+
+    /// Number in the EPUB of the chapter at `url`, the `number`th in the
+    /// novel's chapter list now. None when the EPUB doesn't have it, or has
+    /// a placeholder for it.
+    pub fn find(&self, number: usize, url: &str) -> Option<usize> {
+        let number = match &self.urls {
+            Some(urls) => *urls.get(url)?,
+            None => number,
+        };
+        Some(number).filter(|number| self.chapters.contains_key(number))
+    }
+
+    pub fn contains(&self, number: usize, url: &str) -> bool {
+        self.find(number, url).is_some()
+    }
+
+    /// Number of chapters in the EPUB
+    pub fn len(&self) -> usize {
+        self.chapters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chapters.is_empty()
+    }
+
+    /// Copies chapters (by their number in the novel's chapter list now, along
+    /// with their url) and the images they use out of the EPUB and into the
+    /// spool, as if they were just downloaded. Chapters that moved in the list
+    /// since get their new number.
+    pub async fn copy_chapters(
+        &self,
+        chapters: &[(usize, String)],
+        spool: &Spool,
+    ) -> Result<Vec<DownloadedChapter>> {
+        let mut archive = ZipArchive::new(File::open(&self.path)?)?;
+        let mut copied = Vec::with_capacity(chapters.len());
+        for (number, url) in chapters {
+            let old_number = match self.find(*number, url) {
+                Some(old_number) => old_number,
+                None => continue,
+            };
+            let title = self.chapters[&old_number].clone();
+            let content = read_entry(&mut archive, &format!("OEBPS/c{}.xhtml", old_number))?;
+
+            let mut images = Vec::new();
+            for capture in IMAGE_SRC_REGEX.captures_iter(&content) {
+                let image_path = &capture[1];
+                let data = match read_entry_bytes(&mut archive, &format!("OEBPS/{}", image_path)) {
+                    Ok(data) => data,
+                    // The chapter still points at it, but there's nothing to copy
+                    Err(_) => continue,
+                };
+                images.push(SpooledImage {
+                    path: image_path.to_string(),
+                    mimetype: image_mimetype(image_path).to_string(),
+                    file: spool.write(image_path, &data).await?,
+                });
+            }
+
+            copied.push(DownloadedChapter {
+                number: *number,
+                url: url.clone(),
+                title,
+                // Filled in from the overview like for downloaded chapters
+                volume: None,
+                content_file: spool
+                    .write(&format!("c{}.xhtml", number), content.as_bytes())
+                    .await?,
+                images,
+            });
+        }
+        Ok(copied)
+    }
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String> {
+    String::from_utf8(read_entry_bytes(archive, name)?)
+        .map_err(|_| Error::Epub(format!("{} in the EPUB isn't UTF-8", name)))
+}
+
+fn read_entry_bytes(archive: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>> {
+    let mut entry = archive.by_name(name)?;
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Mimetype for the extensions `embed_images` gives images
+fn image_mimetype(path: &str) -> &'static str {
+    match path.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("jpg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
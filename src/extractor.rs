@@ -1,15 +1,77 @@
-mod boxn;
-pub use boxn::BoxnExtractor;
+mod ao3;
+pub use ao3::Ao3Extractor;
 
-mod rwn;
-pub use rwn::RwnExtractor;
+mod config;
+pub use config::ConfigExtractor;
 
-#[derive(Debug)]
+mod fanfiction;
+pub use fanfiction::FanFictionExtractor;
+
+mod generic;
+pub use generic::GenericExtractor;
+
+mod madara;
+pub use madara::MadaraExtractor;
+
+mod plugin;
+pub use plugin::{find_plugin, plugins_dir, PluginExtractor};
+
+mod royalroad;
+pub use royalroad::RoyalRoadExtractor;
+
+mod script;
+pub use script::{find_script, scripts_dir, ScriptExtractor};
+
+mod scribblehub;
+pub use scribblehub::ScribbleHubExtractor;
+
+mod syosetu;
+pub use syosetu::SyosetuExtractor;
+
+mod wattpad;
+pub use wattpad::WattpadExtractor;
+
+mod wuxiaworld;
+pub use wuxiaworld::WuxiaworldExtractor;
+
+use crate::downloader::{self, Downloader};
+use crate::filters::StripRule;
+use crate::template::{ChapterValues, DEFAULT_TEMPLATE};
+use crate::titles::clean_title;
+use crate::urls::{chapter_page, clean_chapter_url};
+use async_trait::async_trait;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::debug;
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Overview {
     pub title: String,
     pub author: String,
     pub img_url: Option<String>,
+    pub description: Option<String>,
+    /// Other names the novel goes by, i.e. its original title
+    pub alternate_titles: Vec<String>,
+    /// Genres and tags, as the site lists them
+    pub genres: Vec<String>,
+    /// Language code from the page, i.e. `en-US`
+    pub language: Option<String>,
+    /// Whether the novel is ongoing or completed, in the site's words
+    pub status: Option<String>,
     pub download_urls: Vec<String>,
+    /// Volume each chapter url is in, for sites that group their chapter list
+    /// by volume. Chapters that aren't in one are left out.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub volumes: HashMap<String, String>,
+    /// Chapter titles as the chapter list has them, by url, to show chapters
+    /// by name before they're downloaded. Not every chapter has one.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub chapter_titles: HashMap<String, String>,
 }
 
 #[derive(Debug)]
@@ -18,7 +80,405 @@ pub struct Chapter {
     pub content: String,
 }
 
-pub trait Extractor {
-    fn extract_overview(&self, html: &str) -> Overview;
-    fn extract_chapter(&self, html: &str) -> Chapter;
+/// Why a page couldn't be turned into an `Overview` or `Chapter`
+#[derive(Debug, Error)]
+pub enum ExtractError {
+    /// Nothing on the page matched what the extractor looks for, usually means
+    /// the site changed its markup or served an error page
+    #[error("No {0} found")]
+    MissingElement(&'static str),
+    /// The response couldn't be parsed, i.e. an API answered with something
+    /// other than the JSON the extractor expects
+    #[error("Couldn't parse the response: {0}")]
+    Malformed(String),
+    /// A `PluginExtractor` or `ScriptExtractor` couldn't be run or said what
+    /// went wrong itself
+    #[error("{0}")]
+    Plugin(String),
+    /// A request the extractor sent itself failed
+    #[error(transparent)]
+    Network(#[from] downloader::Error),
+}
+
+/// Another page needed to get the whole chapter list
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PageRequest {
+    Get(String),
+    /// POST with a url encoded form, how AJAX endpoints usually want it
+    PostForm {
+        url: String,
+        form: Vec<(String, String)>,
+    },
+}
+
+impl PageRequest {
+    pub fn url(&self) -> &str {
+        match self {
+            PageRequest::Get(url) => url,
+            PageRequest::PostForm { url, .. } => url,
+        }
+    }
+}
+
+/// Finds a novel's details and its chapters in the pages fetched for them.
+///
+/// The extraction methods are handed the book's `Downloader` for sites that
+/// need more than the page (i.e. an API call for each chapter's text), its
+/// requests get the same retries, rate limits and cookies as the book's own.
+/// Requests that can be known up front are better made through the
+/// `*_request` methods, which the book sends and caches itself.
+#[async_trait]
+pub trait Extractor: Send + Sync {
+    /// For sites that put an interstitial in front of the novel's page (i.e. a
+    /// content warning to click through), the url of the page behind it.
+    /// `html` is what was fetched for the novel's url.
+    fn proceed_url(&self, _html: &str) -> Option<String> {
+        None
+    }
+
+    /// Whether the site is behind Cloudflare, which starts answering with
+    /// challenges when requests come in quickly. Requests to it are slowed down
+    /// to `CLOUDFLARE_RATE_LIMIT` unless a rate limit was set.
+    fn behind_cloudflare(&self) -> bool {
+        false
+    }
+
+    /// For sites whose pages are filled in by JavaScript from an API, the
+    /// request for the novel's data (i.e. JSON) that `extract_overview` gets
+    /// instead of the novel's page
+    fn overview_request(&self) -> Option<PageRequest> {
+        None
+    }
+
+    /// What to fetch for the chapter at `url` and hand to `extract_chapter`,
+    /// the page itself unless the extractor gets chapters from an API
+    fn chapter_request(&self, url: &str) -> PageRequest {
+        PageRequest::Get(url.to_string())
+    }
+
+    async fn extract_overview(
+        &self,
+        html: &str,
+        downloader: &Downloader,
+    ) -> Result<Overview, ExtractError>;
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError>;
+
+    /// For sites that split chapters over several pages, the url of the page
+    /// after `html`. It's the `page`th page (counting from 1) of the chapter
+    /// at `chapter_url`, fetched from `page_url`. The content of every page
+    /// goes into the chapter.
+    fn next_chapter_page(
+        &self,
+        _html: &str,
+        _page_url: &str,
+        _chapter_url: &str,
+        _page: usize,
+    ) -> Option<String> {
+        None
+    }
+
+    /// For sites that paginate the chapter list or load it with AJAX, the
+    /// request for the next part of it. `html` is the page fetched last,
+    /// starting with the novel's page.
+    fn next_chapter_list_request(&self, _html: &str) -> Option<PageRequest> {
+        None
+    }
+
+    /// Adds the chapters on a page from `next_chapter_list_request` to the
+    /// overview's list found so far, keeping it in reading order. Their volumes
+    /// go in `overview.volumes`, for sites that have them.
+    async fn extract_chapter_list(
+        &self,
+        _html: &str,
+        _overview: &mut Overview,
+        _downloader: &Downloader,
+    ) -> Result<(), ExtractError> {
+        Ok(())
+    }
+
+    /// Site specific junk to remove from chapters (ads, watermarks), on top of
+    /// the rules every chapter gets
+    fn strip_rules(&self) -> Vec<StripRule> {
+        Vec::new()
+    }
+
+    /// Where the chapter's title is on its page when titles are taken from
+    /// headings (`--title-from heading`), for sites that show it outside of
+    /// the chapter's content. The first heading in the content is used
+    /// without one.
+    fn title_heading(&self) -> Option<Selector> {
+        None
+    }
+
+    /// Cleans up a chapter title for the table of contents. Extractors can strip
+    /// their site's own quirks before handing it to the generic `clean_title`.
+    fn clean_chapter_title(&self, title: &str, novel_title: &str) -> String {
+        clean_title(title, novel_title)
+    }
+}
+
+/// The sites there is an extractor for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SiteType {
+    /// Sites running the Madara WordPress theme (boxnovel, readwebnovels, ...)
+    Madara,
+    RoyalRoad,
+    ScribbleHub,
+    Wuxiaworld,
+    Ao3,
+    FanFiction,
+    Wattpad,
+    Syosetu,
+    /// Any other site, the content and chapter list are found by heuristics
+    Generic,
+}
+
+impl SiteType {
+    pub const ALL: [SiteType; 9] = [
+        SiteType::Madara,
+        SiteType::RoyalRoad,
+        SiteType::ScribbleHub,
+        SiteType::Wuxiaworld,
+        SiteType::Ao3,
+        SiteType::FanFiction,
+        SiteType::Wattpad,
+        SiteType::Syosetu,
+        SiteType::Generic,
+    ];
+
+    /// Name used to pick the site type on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            SiteType::Madara => "madara",
+            SiteType::RoyalRoad => "royalroad",
+            SiteType::ScribbleHub => "scribblehub",
+            SiteType::Wuxiaworld => "wuxiaworld",
+            SiteType::Ao3 => "ao3",
+            SiteType::FanFiction => "fanfiction",
+            SiteType::Wattpad => "wattpad",
+            SiteType::Syosetu => "syosetu",
+            SiteType::Generic => "generic",
+        }
+    }
+
+    /// Picks the site type from the domain of the novel url
+    pub fn from_url(url: &str) -> Option<SiteType> {
+        let url = Url::parse(url).ok()?;
+        let host = url.host_str()?;
+        let host = host.strip_prefix("www.").unwrap_or(host);
+        match host {
+            "boxnovel.com" | "readwebnovels.net" => Some(SiteType::Madara),
+            "royalroad.com" => Some(SiteType::RoyalRoad),
+            "scribblehub.com" => Some(SiteType::ScribbleHub),
+            "wuxiaworld.com" => Some(SiteType::Wuxiaworld),
+            "archiveofourown.org" | "ao3.org" => Some(SiteType::Ao3),
+            "fanfiction.net" | "m.fanfiction.net" | "fictionpress.com" | "m.fictionpress.com" => {
+                Some(SiteType::FanFiction)
+            }
+            "wattpad.com" | "m.wattpad.com" => Some(SiteType::Wattpad),
+            "ncode.syosetu.com" | "novel18.syosetu.com" => Some(SiteType::Syosetu),
+            _ => None,
+        }
+    }
+
+    /// Guesses the site type from the markup of the novel's homepage, for mirrors
+    /// and domains that aren't known by `from_url`
+    pub fn from_html(html: &str) -> Option<SiteType> {
+        if html.contains(r#"id="chapters""#) && html.contains("fic-title") {
+            Some(SiteType::RoyalRoad)
+        } else if html.contains("fic_title") && html.contains("toc_ol") {
+            Some(SiteType::ScribbleHub)
+        } else if html.contains("novel-body") && html.contains("chapter-item") {
+            Some(SiteType::Wuxiaworld)
+        } else if html.contains(r#"id="profile_top""#) && html.contains(r#"id="storytext""#) {
+            Some(SiteType::FanFiction)
+        } else if html.contains(r#"id="novel_ex""#) || html.contains("p-eplist") {
+            Some(SiteType::Syosetu)
+        } else if html.contains("summary_image") && html.contains("author-content") {
+            Some(SiteType::Madara)
+        } else {
+            None
+        }
+    }
+
+    pub fn extractor(self, site: &str) -> Arc<dyn Extractor + Send + Sync> {
+        match self {
+            SiteType::Madara => Arc::new(MadaraExtractor::new(site)),
+            SiteType::RoyalRoad => Arc::new(RoyalRoadExtractor::new(site)),
+            SiteType::ScribbleHub => Arc::new(ScribbleHubExtractor::new(site)),
+            SiteType::Wuxiaworld => Arc::new(WuxiaworldExtractor::new(site)),
+            SiteType::Ao3 => Arc::new(Ao3Extractor::new(site)),
+            SiteType::FanFiction => Arc::new(FanFictionExtractor::new(site)),
+            SiteType::Wattpad => Arc::new(WattpadExtractor::new(site)),
+            SiteType::Syosetu => Arc::new(SyosetuExtractor::new(site)),
+            SiteType::Generic => Arc::new(GenericExtractor::new(site)),
+        }
+    }
+}
+
+impl FromStr for SiteType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // What the Madara extractor was called when it only did one site each
+        if s == "boxn" || s == "rwn" {
+            return Ok(SiteType::Madara);
+        }
+        SiteType::ALL
+            .iter()
+            .copied()
+            .find(|site_type| site_type.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = SiteType::ALL.iter().map(|t| t.name()).collect();
+                format!(
+                    "Unknown site type {}, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+lazy_static! {
+    static ref HTML_SELECTOR: Selector = Selector::parse("html").unwrap();
+    static ref LINK_SELECTOR: Selector = Selector::parse("a[href]").unwrap();
+    static ref PARAGRAPH_SELECTOR: Selector = Selector::parse("p").unwrap();
+}
+
+/// Wraps the chapter content in the xhtml document that ends up in the EPUB,
+/// with the default template. `BookOptions::template` wraps it again later.
+pub(crate) fn chapter_xhtml(title: &str, body: &str) -> String {
+    DEFAULT_TEMPLATE.render(&ChapterValues {
+        title,
+        content: body,
+        ..ChapterValues::default()
+    })
+}
+
+/// Trimmed text of the first element matching `selector`, if it isn't empty
+pub(crate) fn first_text(document: &Html, selector: &Selector) -> Option<String> {
+    document
+        .select(selector)
+        .next()
+        .map(|element| element.text().collect::<String>().trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+/// Selectors for the same element tried in order, for sites whose skins put
+/// it in different places. Whichever one matched is logged.
+#[derive(Clone)]
+pub(crate) struct SelectorChain {
+    selectors: Vec<(String, Selector)>,
+}
+
+impl SelectorChain {
+    pub(crate) fn new<S: AsRef<str>>(selectors: &[S]) -> Result<Self, String> {
+        let selectors = selectors
+            .iter()
+            .map(|selector| {
+                let selector = selector.as_ref();
+                Selector::parse(selector)
+                    .map(|parsed| (selector.to_string(), parsed))
+                    .map_err(|err| format!("Invalid selector {}: {:?}", selector, err))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if selectors.is_empty() {
+            return Err("No selectors to try".to_string());
+        }
+        Ok(SelectorChain { selectors })
+    }
+
+    /// The first element matching the first selector that matches anything
+    pub(crate) fn first<'a>(&self, document: &'a Html) -> Option<ElementRef<'a>> {
+        self.selectors.iter().find_map(|(name, selector)| {
+            let element = document.select(selector).next()?;
+            debug!(selector = name.as_str(), "matched the selector");
+            Some(element)
+        })
+    }
+}
+
+/// Trimmed text of every element matching `selector`, without duplicates
+pub(crate) fn all_texts(document: &Html, selector: &Selector) -> Vec<String> {
+    let mut texts: Vec<String> = Vec::new();
+    for element in document.select(selector) {
+        let text = element.text().collect::<String>().trim().to_string();
+        if !text.is_empty() && !texts.contains(&text) {
+            texts.push(text);
+        }
+    }
+    texts
+}
+
+/// The element's paragraphs separated by blank lines, or all of its text if it
+/// doesn't have any
+pub(crate) fn paragraphs_text(element: ElementRef) -> String {
+    let paragraphs: Vec<String> = element
+        .select(&PARAGRAPH_SELECTOR)
+        .map(|p| p.text().collect::<String>().trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect();
+    if paragraphs.is_empty() {
+        element.text().collect::<String>().trim().to_string()
+    } else {
+        paragraphs.join("\n\n")
+    }
+}
+
+/// The `lang` attribute of the page's `<html>`
+fn page_language(document: &Html) -> Option<String> {
+    document
+        .select(&HTML_SELECTOR)
+        .next()
+        .and_then(|html| html.value().attr("lang"))
+        .map(str::trim)
+        .filter(|lang| !lang.is_empty())
+        .map(str::to_string)
+}
+
+/// Text of every link on a page, by the (cleaned up) url it points to. The
+/// links in the chapter list are the chapters' titles, whatever the
+/// extractor.
+pub(crate) fn link_texts(html: &str, page_url: &str) -> HashMap<String, String> {
+    let document = Html::parse_document(html);
+    let mut texts = HashMap::new();
+    for link in document.select(&LINK_SELECTOR) {
+        let text = link.text().collect::<Vec<_>>().join(" ");
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            continue;
+        }
+        let url = clean_chapter_url(&absolute_url(page_url, link.value().attr("href").unwrap()));
+        texts.entry(url).or_insert(text);
+    }
+    texts
+}
+
+/// A link on the chapter's page to its next page, see `chapter_page`. Links to
+/// other chapters and to pages of the comments (which have a fragment) aren't
+/// taken for one.
+pub(crate) fn next_page_link(
+    html: &str,
+    page_url: &str,
+    chapter_url: &str,
+    page: usize,
+) -> Option<String> {
+    let document = Html::parse_document(html);
+    document
+        .select(&LINK_SELECTOR)
+        .filter_map(|link| link.value().attr("href"))
+        .filter(|href| !href.contains('#'))
+        .map(|href| absolute_url(page_url, href.trim()))
+        .find(|url| chapter_page(url, chapter_url) == Some(page + 1))
+}
+
+/// Resolves links that are relative to the site (chapter links, cover images)
+fn absolute_url(site: &str, href: &str) -> String {
+    Url::parse(site)
+        .and_then(|site| site.join(href))
+        .map_or_else(|_| href.to_string(), String::from)
 }
@@ -0,0 +1,245 @@
+use crate::downloader::Downloader;
+use crate::extractor::{all_texts, chapter_xhtml, first_text, page_language, paragraphs_text};
+use crate::extractor::{Chapter, ExtractError, Extractor, Overview};
+use crate::filters::StripRule;
+use crate::sanitizer::escape_text;
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref WORK_ID_REGEX: Regex = Regex::new(r"/works/(\d+)").unwrap();
+    static ref CAUTION_SELECTOR: Selector = Selector::parse("p.caution").unwrap();
+    static ref TITLE_SELECTOR: Selector = Selector::parse("h2.title.heading").unwrap();
+    static ref AUTHOR_SELECTOR: Selector = Selector::parse(r#"h3.byline a[rel="author"]"#).unwrap();
+    static ref SUMMARY_SELECTOR: Selector =
+        Selector::parse("div.preface div.summary blockquote.userstuff").unwrap();
+    // Rating, warnings, categories, fandoms, relationships, characters and
+    // freeform tags, in the order AO3 lists them
+    static ref TAG_SELECTOR: Selector = Selector::parse("dl.work.meta dd.tags a.tag").unwrap();
+    static ref LANGUAGE_SELECTOR: Selector = Selector::parse("dl.work.meta dd.language").unwrap();
+    static ref CHAPTER_COUNT_SELECTOR: Selector = Selector::parse("dl.stats dd.chapters").unwrap();
+    static ref CHAPTER_OPTION_SELECTOR: Selector =
+        Selector::parse("#chapter_index select option").unwrap();
+
+    static ref CHAPTER_SELECTOR: Selector = Selector::parse("#chapters > div.chapter").unwrap();
+    static ref CHAPTER_TITLE_SELECTOR: Selector =
+        Selector::parse("div.chapter.preface h3.title").unwrap();
+    static ref CHAPTER_NOTES_SELECTOR: Selector =
+        Selector::parse("div.chapter.preface div.notes:not(.end) blockquote.userstuff").unwrap();
+    static ref CHAPTER_END_NOTES_SELECTOR: Selector =
+        Selector::parse("div.end.notes blockquote.userstuff").unwrap();
+    static ref USERSTUFF_SELECTOR: Selector = Selector::parse(r#"div.userstuff[role="article"]"#).unwrap();
+    // One-shots have no chapter preface, just the text
+    static ref ONE_SHOT_SELECTOR: Selector = Selector::parse("#chapters > div.userstuff").unwrap();
+}
+
+const BASE_URL: &str = "https://archiveofourown.org";
+
+/// archiveofourown.org. Works are downloaded a chapter at a time, unless the
+/// url asks for the entire work (`?view_full_work=true`), then it all comes
+/// from that one page and ends up as a single chapter. Adult works show a
+/// content warning first, it's clicked through by asking for `view_adult`.
+#[derive(Clone)]
+pub struct Ao3Extractor {
+    /// The work's url, without any parameters
+    work_url: String,
+    full_work: bool,
+}
+
+impl Ao3Extractor {
+    pub fn new(site: &str) -> Self {
+        let work_url = match WORK_ID_REGEX.captures(site) {
+            Some(capture) => format!("{}/works/{}", BASE_URL, &capture[1]),
+            None => site.trim_end_matches('/').to_string(),
+        };
+        let full_work = Url::parse(site)
+            .map(|url| {
+                url.query_pairs()
+                    .any(|(key, value)| key == "view_full_work" && value == "true")
+            })
+            .unwrap_or(false);
+        Ao3Extractor {
+            work_url,
+            full_work,
+        }
+    }
+
+    fn chapter_url(&self, chapter_id: &str) -> String {
+        format!(
+            "{}/chapters/{}?view_adult=true",
+            self.work_url,
+            chapter_id.trim()
+        )
+    }
+}
+
+#[async_trait]
+impl Extractor for Ao3Extractor {
+    fn proceed_url(&self, html: &str) -> Option<String> {
+        let document = Html::parse_document(html);
+        if document.select(&CAUTION_SELECTOR).next().is_some() {
+            Some(format!("{}?view_adult=true", self.work_url))
+        } else {
+            None
+        }
+    }
+
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let document = Html::parse_document(html);
+        // Errors, log in walls and the content warning don't have a title
+        let title =
+            first_text(&document, &TITLE_SELECTOR).ok_or(ExtractError::MissingElement("work"))?;
+        let authors = all_texts(&document, &AUTHOR_SELECTOR);
+        let author = if authors.is_empty() {
+            // Works posted without an account are by "Anonymous" and unlinked
+            "Anonymous".to_string()
+        } else {
+            authors.join(", ")
+        };
+        let description = document
+            .select(&SUMMARY_SELECTOR)
+            .next()
+            .map(paragraphs_text)
+            .filter(|summary| !summary.is_empty());
+        let genres = all_texts(&document, &TAG_SELECTOR);
+        let language = document
+            .select(&LANGUAGE_SELECTOR)
+            .next()
+            .and_then(|dd| dd.value().attr("lang"))
+            .map(str::to_string)
+            .or_else(|| page_language(&document));
+        // "3/3" is finished, "3/?" or "3/10" still being written
+        let status = first_text(&document, &CHAPTER_COUNT_SELECTOR).map(|count| {
+            let mut parts = count.split('/');
+            if parts.next() == parts.next() {
+                "completed".to_string()
+            } else {
+                "ongoing".to_string()
+            }
+        });
+
+        let download_urls = if self.full_work {
+            vec![format!(
+                "{}?view_adult=true&view_full_work=true",
+                self.work_url
+            )]
+        } else {
+            let chapters: Vec<String> = document
+                .select(&CHAPTER_OPTION_SELECTOR)
+                .filter_map(|option| option.value().attr("value"))
+                .map(|id| self.chapter_url(id))
+                .collect();
+            if chapters.is_empty() {
+                // The whole work is on its own page when it only has one chapter
+                vec![format!("{}?view_adult=true", self.work_url)]
+            } else {
+                chapters
+            }
+        };
+        Ok(Overview {
+            title,
+            author,
+            img_url: None,
+            description,
+            alternate_titles: Vec::new(),
+            genres,
+            language,
+            status,
+            download_urls,
+            volumes: HashMap::new(),
+            chapter_titles: HashMap::new(),
+        })
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let document = Html::parse_document(html);
+        let chapters: Vec<ElementRef> = document.select(&CHAPTER_SELECTOR).collect();
+
+        if chapters.is_empty() {
+            let text = document
+                .select(&ONE_SHOT_SELECTOR)
+                .next()
+                .ok_or(ExtractError::MissingElement("chapter content"))?;
+            let title = first_text(&document, &TITLE_SELECTOR)
+                .ok_or(ExtractError::MissingElement("work title"))?;
+            return Ok(Chapter {
+                title: title.clone(),
+                content: chapter_xhtml(&title, &text.inner_html()),
+            });
+        }
+
+        if chapters.len() == 1 {
+            let chapter = chapters[0];
+            let title =
+                chapter_title(chapter).ok_or(ExtractError::MissingElement("chapter title"))?;
+            return Ok(Chapter {
+                title: title.clone(),
+                content: chapter_xhtml(&title, &chapter_body(chapter)?),
+            });
+        }
+
+        // The entire work, each chapter under its own heading
+        let title = first_text(&document, &TITLE_SELECTOR)
+            .ok_or(ExtractError::MissingElement("work title"))?;
+        let mut body = String::new();
+        for chapter in chapters {
+            if let Some(chapter_title) = chapter_title(chapter) {
+                body.push_str(&format!("<h2>{}</h2>", escape_text(&chapter_title)));
+            }
+            body.push_str(&chapter_body(chapter)?);
+        }
+        Ok(Chapter {
+            title: title.clone(),
+            content: chapter_xhtml(&title, &body),
+        })
+    }
+
+    fn strip_rules(&self) -> Vec<StripRule> {
+        // "Chapter Text" and "Notes:" headings meant for screen readers
+        vec![StripRule::Selector(Selector::parse("h3.landmark").unwrap())]
+    }
+}
+
+fn chapter_title(chapter: ElementRef) -> Option<String> {
+    chapter
+        .select(&CHAPTER_TITLE_SELECTOR)
+        .next()
+        .map(|title| title.text().collect::<String>().trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// The chapter's text, with the author's notes before and after it
+fn chapter_body(chapter: ElementRef) -> Result<String, ExtractError> {
+    let text = chapter
+        .select(&USERSTUFF_SELECTOR)
+        .next()
+        .ok_or(ExtractError::MissingElement("chapter content"))?;
+    let notes = |selector: &Selector| -> String {
+        chapter
+            .select(selector)
+            .map(|notes| {
+                format!(
+                    r#"<blockquote class="notes"><p><strong>Notes:</strong></p>{}</blockquote>"#,
+                    notes.inner_html()
+                )
+            })
+            .collect()
+    };
+    Ok(format!(
+        "{}{}{}",
+        notes(&CHAPTER_NOTES_SELECTOR),
+        text.inner_html(),
+        notes(&CHAPTER_END_NOTES_SELECTOR)
+    ))
+}
@@ -0,0 +1,305 @@
+use crate::downloader::Downloader;
+use crate::extractor::{absolute_url, all_texts, chapter_xhtml, first_text, page_language};
+use crate::extractor::{next_page_link, Extractor, SelectorChain};
+use crate::extractor::{Chapter, ExtractError, Overview, PageRequest};
+use crate::filters::StripRule;
+use crate::titles::clean_title;
+use async_trait::async_trait;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use tracing::trace;
+
+/// CSS selectors for a site, as written in the config file
+#[derive(Deserialize)]
+struct SiteConfig {
+    title: String,
+    author: Option<String>,
+    cover: Option<String>,
+    description: Option<String>,
+    /// Every element matching it is a genre or tag
+    genres: Option<String>,
+    status: Option<String>,
+    chapter_links: String,
+    /// Link to the next page of the chapter list
+    next_page: Option<String>,
+    chapter_title: String,
+    /// Link to the next page of a chapter, for sites that split them up
+    chapter_next_page: Option<String>,
+    /// Where the title is for `--title-from heading`
+    title_heading: Option<String>,
+    /// Regexes for junk to remove from chapter titles
+    #[serde(default)]
+    title_strip: Vec<String>,
+    content: Selectors,
+    /// Junk to remove from chapters, `css:<selector>` or `re:<regex>`
+    #[serde(default)]
+    strip: Vec<String>,
+    /// Set when the site lists the newest chapter first
+    #[serde(default)]
+    newest_first: bool,
+}
+
+/// A selector, or several to try in order
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Selectors {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Extractor driven by CSS selectors from a TOML file, so new sites (or sites
+/// that changed their markup) can be supported without recompiling.
+///
+/// ```toml
+/// title = "div.post-title h1"
+/// author = "div.author-content a"
+/// cover = "div.summary_image img"
+/// description = "div.summary__content"
+/// genres = "div.genres-content a"
+/// status = "div.post-status div.summary-content"
+/// chapter_links = "li.wp-manga-chapter a"
+/// next_page = "a.next-page"
+/// newest_first = true
+///
+/// chapter_title = "li.active"
+/// chapter_next_page = "a.next-part"
+/// title_heading = "div.text-left h3"
+/// title_strip = [" - Some Site$"]
+/// content = ["div.text-left", "div.reading-content"]
+/// strip = ["css:div.ads", "re:Read more at some-site\\.com"]
+/// ```
+///
+/// `cover` can match an `<img>` (its `data-src` or `src` is used) or a `<meta>`
+/// tag (its `content` is used). `next_page` is followed for as long as it's
+/// there, for sites that split the chapter list over several pages, and
+/// `chapter_next_page` the same for chapters. Without it links that add a
+/// page number to the chapter's url are followed (`?page=2`, `/2`, `_2.html`).
+/// `content` can be a list of selectors, the first one that matches is used.
+/// `title_heading` is where `--title-from heading` looks for the title, the
+/// first heading in the content is used without it. `title_strip` is removed
+/// from chapter titles before the usual cleanup, and
+/// `strip` from chapters along with the built in rules.
+#[derive(Clone)]
+pub struct ConfigExtractor {
+    site: String,
+    title: Selector,
+    author: Option<Selector>,
+    cover: Option<Selector>,
+    description: Option<Selector>,
+    genres: Option<Selector>,
+    status: Option<Selector>,
+    chapter_links: Selector,
+    next_page: Option<Selector>,
+    newest_first: bool,
+    chapter_title: Selector,
+    chapter_next_page: Option<Selector>,
+    title_heading: Option<Selector>,
+    title_strip: Vec<Regex>,
+    content: SelectorChain,
+    strip: Vec<StripRule>,
+}
+
+impl ConfigExtractor {
+    pub fn from_file(path: impl AsRef<Path>, site: &str) -> Result<Self, Box<dyn Error>> {
+        let config = std::fs::read_to_string(path)?;
+        Self::from_toml(&config, site)
+    }
+
+    pub fn from_toml(config: &str, site: &str) -> Result<Self, Box<dyn Error>> {
+        let config: SiteConfig = toml::from_str(config)?;
+        let optional =
+            |selector: &Option<String>| selector.as_deref().map(parse_selector).transpose();
+
+        Ok(ConfigExtractor {
+            site: site.to_string(),
+            title: parse_selector(&config.title)?,
+            author: optional(&config.author)?,
+            cover: optional(&config.cover)?,
+            description: optional(&config.description)?,
+            genres: optional(&config.genres)?,
+            status: optional(&config.status)?,
+            chapter_links: parse_selector(&config.chapter_links)?,
+            next_page: optional(&config.next_page)?,
+            newest_first: config.newest_first,
+            chapter_title: parse_selector(&config.chapter_title)?,
+            chapter_next_page: optional(&config.chapter_next_page)?,
+            title_heading: optional(&config.title_heading)?,
+            title_strip: config
+                .title_strip
+                .iter()
+                .map(|pattern| Regex::new(pattern))
+                .collect::<Result<_, _>>()?,
+            content: match &config.content {
+                Selectors::One(selector) => SelectorChain::new(&[selector])?,
+                Selectors::Many(selectors) => SelectorChain::new(selectors)?,
+            },
+            strip: config
+                .strip
+                .iter()
+                .map(|rule| rule.parse())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Chapter links on a page of the chapter list, oldest first
+    fn chapter_urls(&self, document: &Html) -> Vec<String> {
+        let mut download_urls: Vec<String> = document
+            .select(&self.chapter_links)
+            .filter_map(|link| link.value().attr("href"))
+            .map(|href| absolute_url(&self.site, href.trim()))
+            .collect();
+        if self.newest_first {
+            download_urls.reverse();
+        }
+        download_urls
+    }
+}
+
+fn parse_selector(selector: &str) -> Result<Selector, String> {
+    Selector::parse(selector).map_err(|err| format!("Invalid selector {}: {:?}", selector, err))
+}
+
+#[async_trait]
+impl Extractor for ConfigExtractor {
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &self.title).unwrap_or_else(|| "no_title".into());
+        let author = self
+            .author
+            .as_ref()
+            .and_then(|selector| first_text(&document, selector))
+            .unwrap_or_else(|| "no_author".into());
+        let img_url = self
+            .cover
+            .as_ref()
+            .and_then(|selector| document.select(selector).next())
+            .and_then(|element| {
+                let element = element.value();
+                element
+                    .attr("data-src")
+                    .or_else(|| element.attr("src"))
+                    .or_else(|| element.attr("content"))
+            })
+            .map(|src| absolute_url(&self.site, src.trim()));
+        let description = self
+            .description
+            .as_ref()
+            .and_then(|selector| first_text(&document, selector));
+        let genres = self
+            .genres
+            .as_ref()
+            .map(|selector| all_texts(&document, selector))
+            .unwrap_or_default();
+        let language = page_language(&document);
+        let status = self
+            .status
+            .as_ref()
+            .and_then(|selector| first_text(&document, selector));
+
+        let download_urls = self.chapter_urls(&document);
+        if download_urls.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+
+        Ok(Overview {
+            title,
+            author,
+            img_url,
+            description,
+            alternate_titles: Vec::new(),
+            genres,
+            language,
+            status,
+            download_urls,
+            volumes: HashMap::new(),
+            chapter_titles: HashMap::new(),
+        })
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &self.chapter_title)
+            .ok_or(ExtractError::MissingElement("chapter title"))?;
+
+        let content_element = self
+            .content
+            .first(&document)
+            .ok_or(ExtractError::MissingElement("chapter content"))?;
+
+        let content = chapter_xhtml(&title, &content_element.inner_html());
+
+        Ok(Chapter { title, content })
+    }
+
+    fn next_chapter_page(
+        &self,
+        html: &str,
+        page_url: &str,
+        chapter_url: &str,
+        page: usize,
+    ) -> Option<String> {
+        let next_page = match &self.chapter_next_page {
+            Some(next_page) => next_page,
+            None => return next_page_link(html, page_url, chapter_url, page),
+        };
+        let document = Html::parse_document(html);
+        let href = document.select(next_page).next()?.value().attr("href")?;
+        Some(absolute_url(page_url, href.trim()))
+    }
+    fn next_chapter_list_request(&self, html: &str) -> Option<PageRequest> {
+        let next_page = self.next_page.as_ref()?;
+        let document = Html::parse_document(html);
+        let href = document.select(next_page).next()?.value().attr("href")?;
+        Some(PageRequest::Get(absolute_url(&self.site, href.trim())))
+    }
+
+    fn strip_rules(&self) -> Vec<StripRule> {
+        self.strip.clone()
+    }
+
+    fn title_heading(&self) -> Option<Selector> {
+        self.title_heading.clone()
+    }
+
+    async fn extract_chapter_list(
+        &self,
+        html: &str,
+        overview: &mut Overview,
+        _downloader: &Downloader,
+    ) -> Result<(), ExtractError> {
+        let document = Html::parse_document(html);
+        let urls = self.chapter_urls(&document);
+        trace!(
+            chapters = urls.len(),
+            "found chapter links on the next page"
+        );
+        if self.newest_first {
+            // Later pages have older chapters
+            overview.download_urls.splice(0..0, urls);
+        } else {
+            overview.download_urls.extend(urls);
+        }
+        Ok(())
+    }
+    fn clean_chapter_title(&self, title: &str, novel_title: &str) -> String {
+        let title = self
+            .title_strip
+            .iter()
+            .fold(title.to_string(), |title, regex| {
+                regex.replace_all(&title, "").into_owned()
+            });
+        clean_title(&title, novel_title)
+    }
+}
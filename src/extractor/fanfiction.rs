@@ -0,0 +1,238 @@
+use crate::downloader::Downloader;
+use crate::extractor::{absolute_url, chapter_xhtml, first_text, paragraphs_text};
+use crate::extractor::{Chapter, ExtractError, Extractor, Overview, SelectorChain};
+use crate::language::language_code;
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref STORY_REGEX: Regex = Regex::new(r"/s/(\d+)(?:/\d+(?:/([^/?#]+))?)?").unwrap();
+    static ref CHAPTER_NUMBER_REGEX: Regex = Regex::new(r"^\d+\.\s*").unwrap();
+    static ref TITLE_SELECTOR: Selector = Selector::parse("#profile_top > b.xcontrast_txt").unwrap();
+    static ref AUTHOR_SELECTOR: Selector =
+        Selector::parse(r#"#profile_top > a.xcontrast_txt[href^="/u/"]"#).unwrap();
+    static ref IMAGE_SELECTOR: Selector = Selector::parse("#profile_top img.cimage").unwrap();
+    static ref SUMMARY_SELECTOR: Selector =
+        Selector::parse("#profile_top > div.xcontrast_txt").unwrap();
+    // "Rated: Fiction T - English - Romance/Drama - Harry P. - Chapters: 12 - ..."
+    static ref DETAILS_SELECTOR: Selector = Selector::parse("#profile_top > span.xgray").unwrap();
+    static ref FANDOM_SELECTOR: Selector = Selector::parse("#pre_story_links a").unwrap();
+    // The chapter dropdown is on the page twice, above and below the text
+    static ref CHAPTER_SELECT_SELECTOR: Selector = Selector::parse("select#chap_select").unwrap();
+    static ref OPTION_SELECTOR: Selector = Selector::parse("option").unwrap();
+    static ref SELECTED_OPTION_SELECTOR: Selector = Selector::parse("option[selected]").unwrap();
+    static ref CONTENT_SELECTORS: SelectorChain =
+        SelectorChain::new(&["div#storytext", "div.storytext"]).unwrap();
+}
+
+/// Genres a story can be filed under, "Hurt/Comfort" is one of them so the
+/// slashes between genres can't just be split on
+const GENRES: [&str; 21] = [
+    "Adventure",
+    "Angst",
+    "Crime",
+    "Drama",
+    "Family",
+    "Fantasy",
+    "Friendship",
+    "General",
+    "Horror",
+    "Humor",
+    "Hurt/Comfort",
+    "Mystery",
+    "Parody",
+    "Poetry",
+    "Romance",
+    "Sci-Fi",
+    "Spiritual",
+    "Supernatural",
+    "Suspense",
+    "Tragedy",
+    "Western",
+];
+
+/// fanfiction.net (and fictionpress.com, which runs on the same software).
+/// Every chapter of a story is at `/s/<id>/<chapter>/<slug>`, the chapter list
+/// comes from the dropdown on the story's page. Both sites are behind
+/// Cloudflare.
+#[derive(Clone)]
+pub struct FanFictionExtractor {
+    /// `https://www.fanfiction.net`, the mobile site has different markup
+    base_url: String,
+    story_id: Option<String>,
+    slug: Option<String>,
+}
+
+impl FanFictionExtractor {
+    pub fn new(site: &str) -> Self {
+        let domain = Url::parse(site)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .map(|host| {
+                let host = host.strip_prefix("www.").unwrap_or(&host);
+                host.strip_prefix("m.").unwrap_or(host).to_string()
+            })
+            .unwrap_or_else(|| "fanfiction.net".into());
+        let capture = STORY_REGEX.captures(site);
+        FanFictionExtractor {
+            base_url: format!("https://www.{}", domain),
+            story_id: capture
+                .as_ref()
+                .and_then(|capture| capture.get(1))
+                .map(|id| id.as_str().to_string()),
+            slug: capture
+                .as_ref()
+                .and_then(|capture| capture.get(2))
+                .map(|slug| slug.as_str().to_string()),
+        }
+    }
+
+    fn chapter_url(&self, story_id: &str, chapter: &str) -> String {
+        match &self.slug {
+            Some(slug) => format!("{}/s/{}/{}/{}", self.base_url, story_id, chapter, slug),
+            None => format!("{}/s/{}/{}", self.base_url, story_id, chapter),
+        }
+    }
+}
+
+#[async_trait]
+impl Extractor for FanFictionExtractor {
+    fn behind_cloudflare(&self) -> bool {
+        true
+    }
+
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let document = Html::parse_document(html);
+        // Removed stories and Cloudflare's pages don't have the profile header
+        let title =
+            first_text(&document, &TITLE_SELECTOR).ok_or(ExtractError::MissingElement("story"))?;
+        let author = first_text(&document, &AUTHOR_SELECTOR).unwrap_or_else(|| "no_author".into());
+        // Covers link to a thumbnail, the same path ending in 180 is the full size
+        let img_url = document
+            .select(&IMAGE_SELECTOR)
+            .next()
+            .and_then(|img| img.value().attr("src"))
+            .map(|src| absolute_url(&self.base_url, &src.trim().replace("/75/", "/180/")));
+        let description = document
+            .select(&SUMMARY_SELECTOR)
+            .next()
+            .map(paragraphs_text)
+            .filter(|summary| !summary.is_empty());
+
+        let details = first_text(&document, &DETAILS_SELECTOR).unwrap_or_default();
+        let details: Vec<&str> = details.split(" - ").map(str::trim).collect();
+        // The language always comes right after the rating, the genres are left
+        // out when there aren't any
+        let language = details
+            .get(1)
+            .and_then(|language| language_code(language))
+            .map(str::to_string);
+        let mut genres: Vec<String> = details
+            .get(2)
+            .and_then(|details| split_genres(details))
+            .unwrap_or_default();
+        // The fandom is the last link of "Books > Harry Potter"
+        if let Some(fandom) = document.select(&FANDOM_SELECTOR).last() {
+            let fandom = fandom.text().collect::<String>().trim().to_string();
+            if !fandom.is_empty() {
+                genres.insert(0, fandom);
+            }
+        }
+        let status = if details.contains(&"Status: Complete") {
+            "completed"
+        } else {
+            "ongoing"
+        };
+
+        let story_id = self
+            .story_id
+            .as_deref()
+            .ok_or(ExtractError::MissingElement("story id in the url"))?;
+        let chapters: Vec<String> = document
+            .select(&CHAPTER_SELECT_SELECTOR)
+            .next()
+            .map(|select| {
+                select
+                    .select(&OPTION_SELECTOR)
+                    .filter_map(|option| option.value().attr("value"))
+                    .map(|chapter| self.chapter_url(story_id, chapter.trim()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // One-shots don't have the dropdown
+        let download_urls = if chapters.is_empty() {
+            vec![self.chapter_url(story_id, "1")]
+        } else {
+            chapters
+        };
+
+        Ok(Overview {
+            title,
+            author,
+            img_url,
+            description,
+            alternate_titles: Vec::new(),
+            genres,
+            language,
+            status: Some(status.to_string()),
+            download_urls,
+            volumes: HashMap::new(),
+            chapter_titles: HashMap::new(),
+        })
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let document = Html::parse_document(html);
+        // "3. The Title" in the dropdown, one-shots are titled after the story
+        let title = document
+            .select(&CHAPTER_SELECT_SELECTOR)
+            .next()
+            .and_then(|select| select.select(&SELECTED_OPTION_SELECTOR).next())
+            .map(|option| {
+                let text = option.text().collect::<String>();
+                CHAPTER_NUMBER_REGEX.replace(text.trim(), "").to_string()
+            })
+            .filter(|title| !title.is_empty())
+            .or_else(|| first_text(&document, &TITLE_SELECTOR))
+            .ok_or(ExtractError::MissingElement("chapter title"))?;
+        let body: String = CONTENT_SELECTORS
+            .first(&document)
+            .ok_or(ExtractError::MissingElement("chapter content"))?
+            .children()
+            .filter_map(ElementRef::wrap)
+            .map(|element| element.html())
+            .collect();
+
+        Ok(Chapter {
+            title: title.clone(),
+            content: chapter_xhtml(&title, &body),
+        })
+    }
+}
+
+/// Splits "Romance/Hurt/Comfort" into its genres, `None` if it isn't a list of
+/// genres (i.e. the characters, when a story has no genre)
+fn split_genres(text: &str) -> Option<Vec<String>> {
+    let mut genres = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let genre = GENRES.iter().find(|genre| {
+            rest.starts_with(*genre)
+                && matches!(rest[genre.len()..].chars().next(), None | Some('/'))
+        })?;
+        genres.push(genre.to_string());
+        rest = rest[genre.len()..].trim_start_matches('/');
+    }
+    Some(genres)
+}
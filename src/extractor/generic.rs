@@ -0,0 +1,304 @@
+use crate::downloader::Downloader;
+use crate::extractor::{absolute_url, all_texts, chapter_xhtml, first_text, page_language};
+use crate::extractor::{next_page_link, Chapter, ExtractError, Extractor, Overview};
+use crate::sanitizer::escape_text;
+use async_trait::async_trait;
+use ego_tree::NodeId;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::HashMap;
+use tracing::trace;
+
+lazy_static! {
+    static ref META_TITLE_SELECTOR: Selector =
+        Selector::parse(r#"meta[property="og:title"]"#).unwrap();
+    static ref META_AUTHOR_SELECTOR: Selector =
+        Selector::parse(r#"meta[name="author"], meta[property="book:author"]"#).unwrap();
+    static ref META_IMAGE_SELECTOR: Selector =
+        Selector::parse(r#"meta[property="og:image"]"#).unwrap();
+    static ref META_DESCRIPTION_SELECTOR: Selector =
+        Selector::parse(r#"meta[property="og:description"], meta[name="description"]"#).unwrap();
+    static ref H1_SELECTOR: Selector = Selector::parse("h1").unwrap();
+    static ref HEADING_SELECTOR: Selector = Selector::parse("h1, h2, h3").unwrap();
+    static ref TITLE_SELECTOR: Selector = Selector::parse("title").unwrap();
+    static ref AUTHOR_SELECTOR: Selector =
+        Selector::parse(r#"[rel="author"], .author a, .author-content a"#).unwrap();
+    static ref TAG_SELECTOR: Selector = Selector::parse(r#"a[rel~="tag"]"#).unwrap();
+    static ref LINK_SELECTOR: Selector = Selector::parse("a[href]").unwrap();
+    static ref TEXT_BLOCK_SELECTOR: Selector = Selector::parse("p, div, pre, td").unwrap();
+    static ref CHAPTER_TEXT_REGEX: Regex =
+        Regex::new(r"(?i)\b(chapter|ch\.?|episode|ep\.?|part)\s*(\d+)").unwrap();
+    static ref CHAPTER_HREF_REGEX: Regex =
+        Regex::new(r"(?i)(chapter|/ch|episode)[-_/]?(\d+)").unwrap();
+}
+
+/// Text blocks shorter than this are usually captions, buttons or bylines
+const MIN_BLOCK_LENGTH: usize = 25;
+/// Elements that never hold the chapter itself
+const SKIPPED_ELEMENTS: [&str; 8] = [
+    "nav", "header", "footer", "aside", "script", "style", "form", "noscript",
+];
+/// Elements a chapter list is grouped by, the nearest one around each link
+const LIST_CONTAINERS: [&str; 7] = ["ul", "ol", "tbody", "table", "div", "section", "nav"];
+
+/// Works on sites there is no extractor for, by guessing. The chapter list is
+/// the biggest group of links that look like chapters ("Chapter 12",
+/// `/chapter-12/`) and a chapter's content is the element with the most
+/// paragraph text, like browsers' reader modes do.
+#[derive(Clone)]
+pub struct GenericExtractor {
+    site: String,
+}
+
+impl GenericExtractor {
+    pub fn new(site: &str) -> Self {
+        GenericExtractor {
+            site: site.to_string(),
+        }
+    }
+
+    /// The chapter on its page, for the trait and for the book to fall back on
+    /// when another extractor's content looks wrong
+    pub fn chapter(&self, html: &str) -> Result<Chapter, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = document
+            .select(&HEADING_SELECTOR)
+            .map(|heading| heading.text().collect::<String>().trim().to_string())
+            .find(|heading| CHAPTER_TEXT_REGEX.is_match(heading))
+            .or_else(|| first_text(&document, &H1_SELECTOR))
+            .or_else(|| first_text(&document, &TITLE_SELECTOR))
+            .ok_or(ExtractError::MissingElement("chapter title"))?;
+
+        let content =
+            main_content(&document).ok_or(ExtractError::MissingElement("chapter content"))?;
+        let body: String = content
+            .children()
+            .filter_map(|child| match ElementRef::wrap(child) {
+                Some(element) if is_skipped(element) => None,
+                Some(element) => Some(element.html()),
+                None => match child.value() {
+                    Node::Text(text) if !text.trim().is_empty() => {
+                        Some(format!("<p>{}</p>", escape_text(text.trim())))
+                    }
+                    _ => None,
+                },
+            })
+            .collect();
+
+        Ok(Chapter {
+            title: title.clone(),
+            content: chapter_xhtml(&title, &body),
+        })
+    }
+
+    /// The largest group of chapter links on the novel's page, oldest first
+    fn chapter_urls(&self, document: &Html) -> Vec<String> {
+        let site_host = Url::parse(&self.site)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+
+        let mut groups: HashMap<Option<NodeId>, Vec<(String, Option<u32>)>> = HashMap::new();
+        for link in document.select(&LINK_SELECTOR) {
+            let href = link.value().attr("href").unwrap_or_default().trim();
+            let text = link.text().collect::<String>();
+            let number = chapter_number(&text, href);
+            if number.is_none() || href.starts_with('#') {
+                continue;
+            }
+            let url = absolute_url(&self.site, href);
+            // Links to other sites are ads or recommendations
+            let host = Url::parse(&url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string));
+            if host != site_host {
+                continue;
+            }
+            let container = link
+                .ancestors()
+                .filter_map(ElementRef::wrap)
+                .find(|element| LIST_CONTAINERS.contains(&element.value().name()))
+                .map(|element| element.id());
+            let group = groups.entry(container).or_default();
+            if !group.iter().any(|(known, _)| *known == url) {
+                group.push((url, number));
+            }
+        }
+
+        let links = groups
+            .into_values()
+            .max_by_key(Vec::len)
+            .unwrap_or_default();
+        trace!(
+            links = links.len(),
+            "picked the largest group of chapter links"
+        );
+
+        // Lists going from newest to oldest are turned around
+        let newest_first = match (links.first(), links.last()) {
+            (Some((_, Some(first))), Some((_, Some(last)))) => first > last,
+            _ => false,
+        };
+        let mut urls: Vec<String> = links.into_iter().map(|(url, _)| url).collect();
+        if newest_first {
+            urls.reverse();
+        }
+        urls
+    }
+}
+
+#[async_trait]
+impl Extractor for GenericExtractor {
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = meta_content(&document, &META_TITLE_SELECTOR)
+            .or_else(|| first_text(&document, &H1_SELECTOR))
+            .or_else(|| first_text(&document, &TITLE_SELECTOR))
+            .unwrap_or_else(|| "no_title".into());
+        let author = meta_content(&document, &META_AUTHOR_SELECTOR)
+            .or_else(|| first_text(&document, &AUTHOR_SELECTOR))
+            .unwrap_or_else(|| "no_author".into());
+        let img_url =
+            meta_content(&document, &META_IMAGE_SELECTOR).map(|src| absolute_url(&self.site, &src));
+        let description = meta_content(&document, &META_DESCRIPTION_SELECTOR);
+        let genres = all_texts(&document, &TAG_SELECTOR);
+        let language = page_language(&document);
+
+        let download_urls = self.chapter_urls(&document);
+        if download_urls.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+
+        Ok(Overview {
+            title,
+            author,
+            img_url,
+            description,
+            alternate_titles: Vec::new(),
+            genres,
+            language,
+            status: None,
+            download_urls,
+            volumes: HashMap::new(),
+            chapter_titles: HashMap::new(),
+        })
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        self.chapter(html)
+    }
+
+    fn next_chapter_page(
+        &self,
+        html: &str,
+        page_url: &str,
+        chapter_url: &str,
+        page: usize,
+    ) -> Option<String> {
+        next_page_link(html, page_url, chapter_url, page)
+    }
+}
+
+/// The number in a link that looks like it goes to a chapter
+fn chapter_number(text: &str, href: &str) -> Option<u32> {
+    CHAPTER_TEXT_REGEX
+        .captures(text)
+        .or_else(|| CHAPTER_HREF_REGEX.captures(href))
+        .and_then(|capture| capture[2].parse().ok())
+}
+
+fn meta_content(document: &Html, selector: &Selector) -> Option<String> {
+    document
+        .select(selector)
+        .filter_map(|meta| meta.value().attr("content"))
+        .map(str::trim)
+        .find(|content| !content.is_empty())
+        .map(str::to_string)
+}
+
+fn is_skipped(element: ElementRef) -> bool {
+    SKIPPED_ELEMENTS.contains(&element.value().name())
+}
+
+/// Finds the element holding the chapter text: every text block scores its
+/// length for its parent and half of it for its grandparent, and scores are
+/// lowered by how much of an element's text is links (menus, comment lists)
+fn main_content(document: &Html) -> Option<ElementRef<'_>> {
+    let mut scores: HashMap<NodeId, f64> = HashMap::new();
+    for block in document.select(&TEXT_BLOCK_SELECTOR) {
+        if block
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .any(is_skipped)
+        {
+            continue;
+        }
+        // Only the block's own text, so nested divs aren't counted twice
+        let text: String = block
+            .children()
+            .filter_map(|child| match child.value() {
+                Node::Text(text) => Some(&text.text[..]),
+                _ => None,
+            })
+            .chain(
+                block
+                    .children()
+                    .filter_map(ElementRef::wrap)
+                    .filter(|child| !TEXT_BLOCK_SELECTOR.matches(child))
+                    .flat_map(|child| child.text()),
+            )
+            .collect();
+        let length = text.trim().chars().count();
+        if length < MIN_BLOCK_LENGTH {
+            continue;
+        }
+        let score = length as f64 + text.matches(',').count() as f64 * 10.0;
+
+        // Text right inside a div (separated by <br>) counts for the div itself
+        let parent = if block.value().name() == "p" {
+            block.parent().and_then(ElementRef::wrap)
+        } else {
+            Some(block)
+        };
+        if let Some(parent) = parent {
+            *scores.entry(parent.id()).or_default() += score;
+            if let Some(grandparent) = parent.parent().and_then(ElementRef::wrap) {
+                *scores.entry(grandparent.id()).or_default() += score / 2.0;
+            }
+        }
+    }
+
+    scores
+        .into_iter()
+        .filter_map(|(id, score)| {
+            let element = document.tree.get(id).and_then(ElementRef::wrap)?;
+            Some((element, score * (1.0 - link_density(element))))
+        })
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(element, _)| element)
+}
+
+/// How much of the element's text is in links, from 0 to 1
+fn link_density(element: ElementRef) -> f64 {
+    let length = element
+        .text()
+        .map(|text| text.chars().count())
+        .sum::<usize>();
+    if length == 0 {
+        return 1.0;
+    }
+    let link_length = element
+        .select(&LINK_SELECTOR)
+        .flat_map(|link| link.text())
+        .map(|text| text.chars().count())
+        .sum::<usize>();
+    link_length as f64 / length as f64
+}
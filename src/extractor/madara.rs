@@ -0,0 +1,410 @@
+use crate::downloader::Downloader;
+use crate::extractor::{absolute_url, all_texts, chapter_xhtml, first_text, Extractor};
+use crate::extractor::{link_texts, page_language, paragraphs_text};
+use crate::extractor::{Chapter, ExtractError, Overview, PageRequest, SelectorChain};
+use crate::filters::StripRule;
+use crate::progress;
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+use tracing::trace;
+
+lazy_static! {
+    // Titles often have "HOT" or "NEW" badges in a <span> next to them
+    static ref TITLE_SELECTOR: Selector = Selector::parse("div.post-title h1").unwrap();
+    static ref BREADCRUMB_SELECTOR: Selector = Selector::parse("ol.breadcrumb li a").unwrap();
+    static ref AUTHOR_SELECTOR: Selector = Selector::parse("div.author-content a").unwrap();
+    static ref IMAGE_SELECTOR: Selector = Selector::parse("div.summary_image img").unwrap();
+    static ref DESCRIPTION_SELECTOR: Selector = Selector::parse("div.summary__content").unwrap();
+    static ref GENRE_SELECTOR: Selector = Selector::parse("div.genres-content a").unwrap();
+    static ref DETAIL_SELECTOR: Selector = Selector::parse("div.post-content_item").unwrap();
+    static ref DETAIL_HEADING_SELECTOR: Selector = Selector::parse("div.summary-heading").unwrap();
+    static ref DETAIL_CONTENT_SELECTOR: Selector = Selector::parse("div.summary-content").unwrap();
+    static ref CHAPTERS_HOLDER_SELECTOR: Selector =
+        Selector::parse("#manga-chapters-holder").unwrap();
+    // Where skins without the holder keep the novel's post id
+    static ref RATING_POST_ID_SELECTOR: Selector = Selector::parse("input.rating-post-id").unwrap();
+    static ref SHORTLINK_SELECTOR: Selector = Selector::parse(r#"link[rel="shortlink"]"#).unwrap();
+    static ref BODY_SELECTOR: Selector = Selector::parse("body").unwrap();
+    // The chapter list, both on the novel's page and from the AJAX endpoints
+    static ref CHAPTER_LINK_SELECTOR: Selector = Selector::parse("li.wp-manga-chapter > a").unwrap();
+
+    static ref CHAPTER_HEADING_SELECTOR: Selector = Selector::parse("#chapter-heading").unwrap();
+    static ref PAGE_TITLE_SELECTOR: Selector = Selector::parse("title").unwrap();
+    static ref SITE_NAME_SELECTOR: Selector =
+        Selector::parse(r#"meta[property="og:site_name"]"#).unwrap();
+    // Skins put the chapter in different wrappers, the innermost goes first
+    static ref CONTENT_SELECTORS: SelectorChain = SelectorChain::new(&[
+        "div.text-left",
+        "div.reading-content",
+        "div.entry-content",
+    ])
+    .unwrap();
+    // "Chapter 12 - Novel Title - Site Name"
+    // "https://example.com/?p=1234"
+    static ref SHORTLINK_ID_REGEX: Regex = Regex::new(r"[?&]p=(\d+)").unwrap();
+    // "postid-1234" in the body's classes
+    static ref POST_ID_CLASS_REGEX: Regex = Regex::new(r"\bpostid-(\d+)\b").unwrap();
+    static ref TITLE_SUFFIX_REGEX: Regex = Regex::new(r"\s+[-–|]\s+([^-–|]+)$").unwrap();
+}
+
+/// Sites running the Madara WordPress theme, which dozens of aggregators
+/// (boxnovel, readwebnovels, ...) use with little more than a different skin.
+/// Sites that only show the newest chapters on the novel's page get the full
+/// list from the theme's AJAX endpoints.
+#[derive(Clone)]
+pub struct MadaraExtractor {
+    /// The novel's url
+    site: String,
+    /// Where WordPress is installed, `admin-ajax.php` is under it. The root of
+    /// the novel's domain unless set with `base_url`.
+    base_url: String,
+}
+
+impl MadaraExtractor {
+    pub fn new(site: &str) -> Self {
+        let base_url = Url::parse(site)
+            .and_then(|url| url.join("/"))
+            .map_or_else(|_| site.to_string(), String::from);
+        MadaraExtractor {
+            site: site.to_string(),
+            base_url,
+        }
+    }
+
+    /// For sites where WordPress isn't at the root of the domain, i.e.
+    /// `https://example.com/novels/`
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = if base_url.ends_with('/') {
+            base_url.to_string()
+        } else {
+            format!("{}/", base_url)
+        };
+        self
+    }
+
+    /// Chapter links on the novel's page or in the AJAX chapter list, oldest first
+    fn chapter_urls(&self, html: &str) -> Vec<String> {
+        let document = Html::parse_document(html);
+        let mut download_urls: Vec<String> = document
+            .select(&CHAPTER_LINK_SELECTOR)
+            .filter_map(|link| link.value().attr("href"))
+            // Saved copies of the site have relative links
+            .map(|href| absolute_url(&self.site, href.trim()))
+            // Anything else in the list isn't a chapter of this novel
+            .filter(|url| url.starts_with(self.site.as_str()))
+            .collect();
+        // reverse because the list goes from newest to oldest but we want oldest to newest
+        download_urls.reverse();
+        download_urls
+    }
+
+    /// The request for the full chapter list, when the novel's page loads it
+    /// with AJAX. Older versions of the theme answer at `admin-ajax.php` with
+    /// the novel's post id, newer ones at `ajax/chapters/` under the novel.
+    /// Skins that leave out both the list and its holder get asked at
+    /// `admin-ajax.php` too, when the post id is somewhere else on the page.
+    fn chapters_request(&self, document: &Html) -> Option<PageRequest> {
+        let holder = match document.select(&CHAPTERS_HOLDER_SELECTOR).next() {
+            Some(holder) => holder,
+            None if document.select(&CHAPTER_LINK_SELECTOR).next().is_none() => {
+                let id = post_id(document)?;
+                trace!(
+                    id = id.as_str(),
+                    "no chapter list on the page, asking for it with AJAX"
+                );
+                return Some(self.admin_ajax_request(&id));
+            }
+            None => return None,
+        };
+        trace!("the chapter list is loaded with AJAX");
+        match holder.value().attr("data-id").map(str::trim) {
+            Some(id) if !id.is_empty() => Some(self.admin_ajax_request(id)),
+            _ => Some(PageRequest::PostForm {
+                url: format!("{}ajax/chapters/", self.site),
+                form: Vec::new(),
+            }),
+        }
+    }
+
+    fn admin_ajax_request(&self, post_id: &str) -> PageRequest {
+        PageRequest::PostForm {
+            url: format!("{}wp-admin/admin-ajax.php", self.base_url),
+            form: vec![
+                ("action".into(), "manga_get_chapters".into()),
+                ("manga".into(), post_id.to_string()),
+            ],
+        }
+    }
+
+    /// The novel's details on its page, with the chapters on it and the
+    /// request for the rest of them
+    fn overview(&self, html: &str) -> (Overview, Option<PageRequest>) {
+        let document = Html::parse_document(html);
+        let title = document
+            .select(&TITLE_SELECTOR)
+            .next()
+            .map(own_text)
+            .filter(|title| !title.is_empty())
+            // The last link in the breadcrumbs is the novel
+            .or_else(|| {
+                document
+                    .select(&BREADCRUMB_SELECTOR)
+                    .last()
+                    .map(|link| link.text().collect::<String>().trim().to_string())
+            })
+            .unwrap_or_else(|| "no_title".into());
+        let authors = all_texts(&document, &AUTHOR_SELECTOR);
+        let author = if authors.is_empty() {
+            "no_author".to_string()
+        } else {
+            authors.join(", ")
+        };
+        // Covers are lazy loaded on most skins
+        let img_url = document
+            .select(&IMAGE_SELECTOR)
+            .next()
+            .and_then(|img| {
+                let img = img.value();
+                img.attr("data-src")
+                    .or_else(|| img.attr("data-lazy-src"))
+                    .or_else(|| img.attr("src"))
+            })
+            .map(|src| absolute_url(&self.site, src.trim()));
+        let description = document
+            .select(&DESCRIPTION_SELECTOR)
+            .next()
+            .map(paragraphs_text)
+            .filter(|description| !description.is_empty());
+        let alternate_titles = detail(&document, "Alternative")
+            .map(|titles| {
+                titles
+                    .split(&[',', ';'][..])
+                    .map(str::trim)
+                    .filter(|title| !title.is_empty() && *title != "Updating")
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let genres = all_texts(&document, &GENRE_SELECTOR);
+        let language = page_language(&document);
+        let status = detail(&document, "Status");
+
+        let overview = Overview {
+            title,
+            author,
+            img_url,
+            description,
+            alternate_titles,
+            genres,
+            language,
+            status,
+            download_urls: self.chapter_urls(html),
+            volumes: HashMap::new(),
+            chapter_titles: HashMap::new(),
+        };
+        (overview, self.chapters_request(&document))
+    }
+
+    /// Replaces the chapters on the novel's page with the full list from the
+    /// theme's AJAX endpoints. The ones on the page are kept if that fails.
+    async fn fetch_chapter_list(
+        &self,
+        request: &PageRequest,
+        overview: &mut Overview,
+        downloader: &Downloader,
+    ) -> Result<(), ExtractError> {
+        progress::info(format!("Getting the chapter list from {}", request.url()));
+        let html = match request {
+            PageRequest::Get(url) => downloader.get_text(url).await,
+            PageRequest::PostForm { url, form } => downloader.post_form(url, form).await,
+        };
+        let html = match html {
+            Ok(html) => html,
+            Err(err) if !overview.download_urls.is_empty() => {
+                progress::warn(format!(
+                    "Couldn't get the whole chapter list, only using the {} chapters on the novel's page: {}",
+                    overview.download_urls.len(),
+                    err
+                ));
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let urls = self.chapter_urls(&html);
+        if urls.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+        let mut titles = link_texts(&html, request.url());
+        for url in &urls {
+            if let Some(title) = titles.remove(url) {
+                overview.chapter_titles.insert(url.clone(), title);
+            }
+        }
+        overview.download_urls = urls;
+        Ok(())
+    }
+}
+
+/// The novel's WordPress post id, from the rating form, the page's short link
+/// or the body's classes
+fn post_id(document: &Html) -> Option<String> {
+    let rating = document
+        .select(&RATING_POST_ID_SELECTOR)
+        .filter_map(|input| input.value().attr("value"))
+        .map(str::trim)
+        .find(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()));
+    if let Some(id) = rating {
+        return Some(id.to_string());
+    }
+    let shortlink = document
+        .select(&SHORTLINK_SELECTOR)
+        .filter_map(|link| link.value().attr("href"))
+        .find_map(|href| SHORTLINK_ID_REGEX.captures(href));
+    if let Some(capture) = shortlink {
+        return Some(capture[1].to_string());
+    }
+    let classes = document
+        .select(&BODY_SELECTOR)
+        .next()?
+        .value()
+        .attr("class")?;
+    POST_ID_CLASS_REGEX
+        .captures(classes)
+        .map(|capture| capture[1].to_string())
+}
+
+#[async_trait]
+impl Extractor for MadaraExtractor {
+    async fn extract_overview(
+        &self,
+        html: &str,
+        downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let (mut overview, chapters_request) = self.overview(html);
+        if let Some(request) = chapters_request {
+            self.fetch_chapter_list(&request, &mut overview, downloader)
+                .await?;
+        }
+        if overview.download_urls.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+        Ok(overview)
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let document = Html::parse_document(html);
+        // Skins without the heading only have the title in <title>, after
+        // which comes the site's name
+        let title = match first_text(&document, &CHAPTER_HEADING_SELECTOR) {
+            Some(heading) => heading,
+            None => {
+                let title = first_text(&document, &PAGE_TITLE_SELECTOR)
+                    .ok_or(ExtractError::MissingElement("chapter title"))?;
+                strip_site_name(&title, &self.site, site_name(&document))
+            }
+        };
+
+        let content_element = CONTENT_SELECTORS
+            .first(&document)
+            .ok_or(ExtractError::MissingElement("chapter content"))?;
+
+        let content = chapter_xhtml(&title, &content_element.inner_html());
+
+        Ok(Chapter { title, content })
+    }
+
+    fn title_heading(&self) -> Option<Selector> {
+        Some(CHAPTER_HEADING_SELECTOR.clone())
+    }
+
+    /// Ad slots and notices sites running the theme put in chapters
+    fn strip_rules(&self) -> Vec<StripRule> {
+        vec![
+            StripRule::Selector(Selector::parse("div.c-ads, div.code-block, div.ad").unwrap()),
+            StripRule::Regex(
+                Regex::new(r"(?i)if you find any errors \(.*?\),? please let us know.*?as soon as possible\.?")
+                    .unwrap(),
+            ),
+            StripRule::Regex(
+                Regex::new(r"(?i)tip: you can use left, right, a and d keyboard keys to browse between chapters\.?")
+                    .unwrap(),
+            ),
+        ]
+    }
+}
+
+/// Madara lists the novel's details as heading and content pairs, this finds
+/// the content of the one headed `heading` (i.e. "Status")
+fn detail(document: &Html, heading: &str) -> Option<String> {
+    document
+        .select(&DETAIL_SELECTOR)
+        .find(|item| {
+            matches!(
+                item.select(&DETAIL_HEADING_SELECTOR).next(),
+                Some(element) if element.text().collect::<String>().trim().eq_ignore_ascii_case(heading)
+            )
+        })
+        .and_then(|item| item.select(&DETAIL_CONTENT_SELECTOR).next())
+        .map(|element| element.text().collect::<String>().trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+/// Text directly in the element, leaving out its children's (i.e. badges)
+fn own_text(element: ElementRef) -> String {
+    element
+        .children()
+        .filter_map(|child| child.value().as_text().map(|text| text.to_string()))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// `og:site_name`, what the site calls itself
+fn site_name(document: &Html) -> Option<String> {
+    document
+        .select(&SITE_NAME_SELECTOR)
+        .next()
+        .and_then(|meta| meta.value().attr("content"))
+        .map(str::to_string)
+}
+
+/// Removes the site's name from the end of a page title, going by
+/// `og:site_name` or the site's domain ("Box Novel" and "boxnovel.com" both
+/// match boxnovel.com)
+fn strip_site_name(title: &str, site: &str, site_name: Option<String>) -> String {
+    let comparable = |name: &str| -> String {
+        name.trim_end_matches(".com")
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let domain = Url::parse(site)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .map(|host| {
+            let host = host.strip_prefix("www.").unwrap_or(&host);
+            comparable(host.split('.').next().unwrap_or(host))
+        });
+    let title = title.trim();
+    match TITLE_SUFFIX_REGEX.captures(title) {
+        Some(capture) => {
+            let suffix = comparable(&capture[1]);
+            let is_site = Some(&suffix) == domain.as_ref()
+                || site_name.map(|name| comparable(&name)).as_ref() == Some(&suffix);
+            if is_site {
+                title[..capture.get(0).unwrap().start()].to_string()
+            } else {
+                title.to_string()
+            }
+        }
+        None => title.to_string(),
+    }
+}
@@ -0,0 +1,432 @@
+use crate::downloader::Downloader;
+use crate::extractor::{absolute_url, chapter_xhtml};
+use crate::extractor::{Chapter, ExtractError, Extractor, Overview};
+use crate::progress;
+use async_trait::async_trait;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wasmi::{Externals, FuncInstance, FuncRef, HostError, ModuleImportResolver};
+use wasmi::{ImportsBuilder, MemoryRef, Module, ModuleInstance, ModuleRef};
+use wasmi::{RuntimeArgs, RuntimeValue, Signature, Trap, TrapKind, ValueType};
+
+/// How many instructions a plugin can run in one call before it's stopped, so
+/// one stuck in a loop doesn't hang the build. That's about a second's worth.
+const MAX_GAS: u64 = 100_000_000;
+
+/// Index of the `env.gas` function the metering adds to plugins
+const GAS_FUNC_INDEX: usize = 0;
+
+/// Where plugins are looked for without `--plugin-dir`, i.e.
+/// `~/.config/box2epub/plugins` on Linux
+pub fn plugins_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("box2epub").join("plugins"))
+}
+
+/// A novel's details as a plugin or a `ScriptExtractor` gives them
+#[derive(Deserialize)]
+pub(super) struct NovelDetails {
+    title: String,
+    #[serde(default)]
+    author: Option<String>,
+    #[serde(default)]
+    cover: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    alternate_titles: Vec<String>,
+    #[serde(default)]
+    genres: Vec<String>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    chapters: Vec<ChapterLink>,
+}
+
+/// A chapter in the chapter list, just its url or with its title too
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(super) enum ChapterLink {
+    Url(String),
+    Titled {
+        url: String,
+        #[serde(default)]
+        title: Option<String>,
+    },
+}
+
+/// A chapter as a plugin or a `ScriptExtractor` gives it, the content is the
+/// markup that goes in its `<body>`
+#[derive(Deserialize)]
+pub(super) struct ChapterDetails {
+    title: String,
+    content: String,
+}
+
+/// What a plugin's `extract_overview` returns, as JSON
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PluginOverview {
+    Overview(NovelDetails),
+    Error { error: String },
+}
+
+/// What a plugin's `extract_chapter` returns, as JSON
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PluginChapter {
+    Chapter(ChapterDetails),
+    Error { error: String },
+}
+
+impl NovelDetails {
+    /// Relative urls are resolved against `site`
+    pub(super) fn into_overview(self, site: &str) -> Overview {
+        let mut overview = Overview {
+            title: self.title,
+            author: self.author.unwrap_or_else(|| "no_author".into()),
+            img_url: self.cover.map(|cover| absolute_url(site, cover.trim())),
+            description: self.description,
+            alternate_titles: self.alternate_titles,
+            genres: self.genres,
+            language: self.language,
+            status: self.status,
+            download_urls: Vec::new(),
+            volumes: HashMap::new(),
+            chapter_titles: HashMap::new(),
+        };
+        add_chapters(site, self.chapters, &mut overview);
+        overview
+    }
+}
+
+/// Adds `chapters` to the end of the overview's chapter list
+pub(super) fn add_chapters(site: &str, chapters: Vec<ChapterLink>, overview: &mut Overview) {
+    for chapter in chapters {
+        let (url, title) = match chapter {
+            ChapterLink::Url(url) => (url, None),
+            ChapterLink::Titled { url, title } => (url, title),
+        };
+        let url = absolute_url(site, url.trim());
+        if let Some(title) = title {
+            overview.chapter_titles.insert(url.clone(), title);
+        }
+        overview.download_urls.push(url);
+    }
+}
+
+impl ChapterDetails {
+    pub(super) fn into_chapter(self) -> Chapter {
+        Chapter {
+            content: chapter_xhtml(&self.title, &self.content),
+            title: self.title,
+        }
+    }
+}
+
+/// Extractor compiled to WebAssembly, for sites box2epub doesn't know that
+/// need more than the CSS selectors of a `ConfigExtractor`. Plugins are
+/// `.wasm` files in the plugins directory, the one whose `domains` has the
+/// novel's site is used.
+///
+/// A plugin exports its `memory` and these functions, strings go in and out
+/// as UTF-8 in that memory and come back packed in an `i64` as the pointer in
+/// the high 32 bits and the length in the low ones:
+///
+/// - `alloc(len: i32) -> i32`, room for a page to be copied into
+/// - `domains() -> i64`, a JSON array of the domains it handles, subdomains
+///   included: `["some-site.com"]`
+/// - `extract_overview(ptr: i32, len: i32) -> i64`, given the novel's page:
+///   `{"title": "...", "author": "...", "cover": "...", "description": "...",
+///   "alternate_titles": ["..."], "genres": ["..."], "status": "...",
+///   "language": "en", "chapters": [{"url": "...", "title": "..."}]}`, only
+///   `title` and `chapters` are required and a chapter can be just its url
+/// - `extract_chapter(ptr: i32, len: i32) -> i64`, given a chapter's page:
+///   `{"title": "...", "content": "<p>...</p>"}`
+///
+/// Either can return `{"error": "..."}` instead. Relative urls are resolved
+/// against the novel's, and the content goes through the same cleanup as any
+/// other extractor's. Plugins can't import anything, every call gets a fresh
+/// instance so they don't need to free what they allocate. Each call can run
+/// `MAX_GAS` instructions, plugins are metered when they're loaded and one
+/// that runs longer is stopped with an error.
+#[derive(Clone)]
+pub struct PluginExtractor {
+    name: String,
+    site: String,
+    module: Arc<Module>,
+    domains: Vec<String>,
+}
+
+impl PluginExtractor {
+    pub fn from_file(path: impl AsRef<Path>, site: &str) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let module: parity_wasm::elements::Module =
+            parity_wasm::deserialize_buffer(&std::fs::read(path)?)?;
+        let module = pwasm_utils::inject_gas_counter(module, &Default::default())
+            .map_err(|_| "it can't be metered")?;
+        let module = Module::from_parity_wasm_module(module)?;
+        let mut plugin = PluginExtractor {
+            name: path
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+            site: site.to_string(),
+            module: Arc::new(module),
+            domains: Vec::new(),
+        };
+        plugin.domains = serde_json::from_str(&plugin.call("domains", None)?)?;
+        Ok(plugin)
+    }
+
+    /// The plugin's file name without `.wasm`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the plugin handles the site at `url`
+    pub fn handles(&self, url: &str) -> bool {
+        let host = match Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+        {
+            Some(host) => host,
+            None => return false,
+        };
+        self.domains.iter().any(|domain| {
+            let domain = domain.trim_start_matches("www.");
+            host == domain || host.ends_with(&format!(".{}", domain))
+        })
+    }
+
+    /// Calls the plugin's `export` with `input` copied into its memory, and
+    /// returns the string it points to
+    fn call(&self, export: &str, input: Option<&str>) -> Result<String, String> {
+        let mut gas = Gas { left: MAX_GAS };
+        let imports = ImportsBuilder::new().with_resolver("env", &GasResolver);
+        let instance = ModuleInstance::new(&self.module, &imports)
+            .and_then(|instance| instance.run_start(&mut gas).map_err(Into::into))
+            .map_err(|err| format!("Couldn't start the plugin {}: {}", self.name, err))?;
+        let memory = instance
+            .export_by_name("memory")
+            .and_then(|memory| memory.as_memory().cloned())
+            .ok_or_else(|| format!("The plugin {} doesn't export its memory", self.name))?;
+        let args = match input {
+            Some(input) => {
+                let len = RuntimeValue::I32(input.len() as i32);
+                let ptr = match self.invoke(&instance, "alloc", &[len], &mut gas)? {
+                    Some(RuntimeValue::I32(ptr)) => ptr,
+                    _ => {
+                        return Err(format!(
+                            "The plugin {}'s alloc didn't return a pointer",
+                            self.name
+                        ))
+                    }
+                };
+                memory.set(ptr as u32, input.as_bytes()).map_err(|err| {
+                    format!(
+                        "Couldn't copy the page into the plugin {}: {}",
+                        self.name, err
+                    )
+                })?;
+                vec![RuntimeValue::I32(ptr), len]
+            }
+            None => Vec::new(),
+        };
+        match self.invoke(&instance, export, &args, &mut gas)? {
+            Some(RuntimeValue::I64(packed)) => self.read_string(&memory, packed as u64),
+            _ => Err(format!(
+                "The plugin {}'s {} didn't return a string",
+                self.name, export
+            )),
+        }
+    }
+
+    fn invoke(
+        &self,
+        instance: &ModuleRef,
+        export: &str,
+        args: &[RuntimeValue],
+        gas: &mut Gas,
+    ) -> Result<Option<RuntimeValue>, String> {
+        instance
+            .invoke_export(export, args, gas)
+            .map_err(|err| match err.as_host_error() {
+                Some(err) if err.downcast_ref::<OutOfGas>().is_some() => format!(
+                    "The plugin {}'s {} ran for too long and was stopped",
+                    self.name, export
+                ),
+                _ => format!("The plugin {}'s {} failed: {}", self.name, export, err),
+            })
+    }
+
+    fn read_string(&self, memory: &MemoryRef, packed: u64) -> Result<String, String> {
+        let (ptr, len) = ((packed >> 32) as u32, packed as u32);
+        let bytes = memory
+            .get(ptr, len as usize)
+            .map_err(|err| format!("The plugin {} returned a bad string: {}", self.name, err))?;
+        String::from_utf8(bytes).map_err(|_| {
+            format!(
+                "The plugin {} returned a string that isn't UTF-8",
+                self.name
+            )
+        })
+    }
+
+    fn call_json<T: for<'de> Deserialize<'de>>(
+        &self,
+        export: &str,
+        html: &str,
+    ) -> Result<T, ExtractError> {
+        let json = self
+            .call(export, Some(html))
+            .map_err(ExtractError::Plugin)?;
+        serde_json::from_str(&json).map_err(|err| {
+            ExtractError::Plugin(format!(
+                "The plugin {}'s {} returned something other than the expected JSON: {}",
+                self.name, export, err
+            ))
+        })
+    }
+
+    /// `call_json` off the async runtime's threads, so a slow plugin doesn't
+    /// hold up the downloads
+    async fn call_json_blocking<T: for<'de> Deserialize<'de> + Send + 'static>(
+        &self,
+        export: &'static str,
+        html: &str,
+    ) -> Result<T, ExtractError> {
+        let plugin = self.clone();
+        let html = html.to_string();
+        tokio::task::spawn_blocking(move || plugin.call_json(export, &html))
+            .await
+            .map_err(|err| {
+                ExtractError::Plugin(format!(
+                    "The plugin {}'s {} crashed: {}",
+                    self.name, export, err
+                ))
+            })?
+    }
+}
+
+/// Gas left for the plugin call, the metering calls `env.gas` with what each
+/// block of instructions costs before running it
+struct Gas {
+    left: u64,
+}
+
+#[derive(Debug)]
+struct OutOfGas;
+
+impl fmt::Display for OutOfGas {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "out of gas")
+    }
+}
+
+impl HostError for OutOfGas {}
+
+impl Externals for Gas {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        match index {
+            GAS_FUNC_INDEX => {
+                let cost: u32 = args.nth_checked(0)?;
+                match self.left.checked_sub(u64::from(cost)) {
+                    Some(left) => {
+                        self.left = left;
+                        Ok(None)
+                    }
+                    None => Err(TrapKind::Host(Box::new(OutOfGas)).into()),
+                }
+            }
+            _ => Err(TrapKind::Unreachable.into()),
+        }
+    }
+}
+
+/// Resolves the `env.gas` import the metering adds, and nothing else
+struct GasResolver;
+
+impl ModuleImportResolver for GasResolver {
+    fn resolve_func(&self, field: &str, _signature: &Signature) -> Result<FuncRef, wasmi::Error> {
+        if field == "gas" {
+            Ok(FuncInstance::alloc_host(
+                Signature::new(&[ValueType::I32][..], None),
+                GAS_FUNC_INDEX,
+            ))
+        } else {
+            Err(wasmi::Error::Instantiation(format!(
+                "plugins can't import env.{}",
+                field
+            )))
+        }
+    }
+}
+
+/// The plugin in `dir` that handles the site at `url`. Plugins that can't be
+/// loaded are reported and passed over.
+pub fn find_plugin(dir: &Path, url: &str) -> Option<PluginExtractor> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return None,
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("wasm")))
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .find_map(|path| match PluginExtractor::from_file(&path, url) {
+            Ok(plugin) if plugin.handles(url) => Some(plugin),
+            Ok(_) => None,
+            Err(err) => {
+                progress::warn(format!(
+                    "Couldn't load the plugin {}: {}",
+                    path.display(),
+                    err
+                ));
+                None
+            }
+        })
+}
+
+#[async_trait]
+impl Extractor for PluginExtractor {
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        match self.call_json_blocking("extract_overview", html).await? {
+            PluginOverview::Overview(details) => {
+                let overview = details.into_overview(&self.site);
+                if overview.download_urls.is_empty() {
+                    return Err(ExtractError::MissingElement("chapter links"));
+                }
+                Ok(overview)
+            }
+            PluginOverview::Error { error } => Err(ExtractError::Plugin(error)),
+        }
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        match self.call_json_blocking("extract_chapter", html).await? {
+            PluginChapter::Chapter(details) => Ok(details.into_chapter()),
+            PluginChapter::Error { error } => Err(ExtractError::Plugin(error)),
+        }
+    }
+}
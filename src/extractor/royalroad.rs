@@ -0,0 +1,146 @@
+use crate::downloader::Downloader;
+use crate::extractor::{absolute_url, all_texts, chapter_xhtml, first_text, Extractor};
+use crate::extractor::{page_language, paragraphs_text};
+use crate::extractor::{Chapter, ExtractError, Overview, SelectorChain};
+use async_trait::async_trait;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::{HashMap, HashSet};
+use tracing::trace;
+
+lazy_static! {
+    static ref TITLE_SELECTOR: Selector = Selector::parse("div.fic-title h1").unwrap();
+    static ref AUTHOR_SELECTOR: Selector = Selector::parse("div.fic-title h4 a").unwrap();
+    static ref IMAGE_SELECTOR: Selector = Selector::parse("div.cover-art-container img").unwrap();
+    static ref DESCRIPTION_SELECTOR: Selector = Selector::parse("div.description").unwrap();
+    static ref TAG_SELECTOR: Selector = Selector::parse("span.tags a.fiction-tag").unwrap();
+    static ref LABEL_SELECTOR: Selector = Selector::parse("div.fiction-info span.label").unwrap();
+    static ref CHAPTER_LINK_SELECTOR: Selector =
+        Selector::parse("table#chapters tbody tr td:first-child a").unwrap();
+
+    static ref CHAPTER_TITLE_SELECTOR: Selector = Selector::parse("div.fic-header h1").unwrap();
+    static ref CONTENT_SELECTORS: SelectorChain =
+        SelectorChain::new(&["div.chapter-content", "div.chapter-inner"]).unwrap();
+    static ref STYLE_SELECTOR: Selector = Selector::parse("style").unwrap();
+
+    // RoyalRoad hides "this story was stolen" paragraphs with a randomly named
+    // class that is only declared in the page's own stylesheet
+    static ref HIDDEN_CLASS_REGEX: Regex =
+        Regex::new(r"\.([A-Za-z0-9_-]+)\s*\{[^}]*display:\s*none").unwrap();
+}
+
+const STATUSES: [&str; 5] = ["ongoing", "completed", "hiatus", "stub", "dropped"];
+
+#[derive(Clone)]
+pub struct RoyalRoadExtractor {
+    site: String,
+}
+
+impl RoyalRoadExtractor {
+    pub fn new(site: &str) -> Self {
+        RoyalRoadExtractor {
+            site: site.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Extractor for RoyalRoadExtractor {
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &TITLE_SELECTOR).unwrap_or_else(|| "no_title".into());
+        let author = first_text(&document, &AUTHOR_SELECTOR).unwrap_or_else(|| "no_author".into());
+        let img_url = document
+            .select(&IMAGE_SELECTOR)
+            .next()
+            .and_then(|img| img.value().attr("src"))
+            .map(|src| absolute_url(&self.site, src.trim()));
+
+        let description = document
+            .select(&DESCRIPTION_SELECTOR)
+            .next()
+            .map(paragraphs_text);
+        let genres = all_texts(&document, &TAG_SELECTOR);
+        let language = page_language(&document);
+        // The status is one of several labels, next to the fiction's type and warnings
+        let status = document
+            .select(&LABEL_SELECTOR)
+            .map(|label| label.text().collect::<String>().trim().to_lowercase())
+            .find(|label| STATUSES.contains(&label.as_str()));
+
+        // The chapter table is already ordered from oldest to newest
+        let download_urls: Vec<String> = document
+            .select(&CHAPTER_LINK_SELECTOR)
+            .filter_map(|link| link.value().attr("href"))
+            .map(|href| absolute_url(&self.site, href))
+            .collect();
+
+        if download_urls.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+
+        Ok(Overview {
+            title,
+            author,
+            img_url,
+            description,
+            alternate_titles: Vec::new(),
+            genres,
+            language,
+            status,
+            download_urls,
+            volumes: HashMap::new(),
+            chapter_titles: HashMap::new(),
+        })
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &CHAPTER_TITLE_SELECTOR)
+            .ok_or(ExtractError::MissingElement("chapter title"))?;
+
+        let hidden_classes: HashSet<String> = document
+            .select(&STYLE_SELECTOR)
+            .flat_map(|style| {
+                let css: String = style.text().collect();
+                HIDDEN_CLASS_REGEX
+                    .captures_iter(&css)
+                    .map(|capture| capture[1].to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        trace!(classes = ?hidden_classes, "found classes hidden by the stylesheet");
+
+        let content_element = CONTENT_SELECTORS
+            .first(&document)
+            .ok_or(ExtractError::MissingElement("chapter content"))?;
+
+        let body: String = content_element
+            .children()
+            .filter_map(ElementRef::wrap)
+            .filter(|element| {
+                !element
+                    .value()
+                    .classes()
+                    .any(|class| hidden_classes.contains(class))
+            })
+            .map(|element| element.html())
+            .collect();
+
+        let content = chapter_xhtml(&title, &body);
+
+        Ok(Chapter { title, content })
+    }
+
+    fn title_heading(&self) -> Option<Selector> {
+        Some(CHAPTER_TITLE_SELECTOR.clone())
+    }
+}
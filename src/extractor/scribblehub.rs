@@ -0,0 +1,155 @@
+use crate::downloader::Downloader;
+use crate::extractor::{absolute_url, all_texts, chapter_xhtml, first_text, Extractor};
+use crate::extractor::{page_language, paragraphs_text};
+use crate::extractor::{Chapter, ExtractError, Overview, PageRequest, SelectorChain};
+use async_trait::async_trait;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref TITLE_SELECTOR: Selector = Selector::parse("div.fic_title").unwrap();
+    static ref AUTHOR_SELECTOR: Selector = Selector::parse("span.auth_name_fic").unwrap();
+    static ref IMAGE_SELECTOR: Selector = Selector::parse("div.fic_image img").unwrap();
+    static ref DESCRIPTION_SELECTOR: Selector = Selector::parse("div.wi_fic_desc").unwrap();
+    static ref GENRE_SELECTOR: Selector = Selector::parse("a.fic_genre, a.stag").unwrap();
+    static ref STATUS_SELECTOR: Selector =
+        Selector::parse("ul.widget_fic_similar li span:last-child").unwrap();
+    static ref CHAPTER_LINK_SELECTOR: Selector = Selector::parse("ol.toc_ol li a.toc_a").unwrap();
+    static ref NEXT_PAGE_SELECTOR: Selector =
+        Selector::parse("ul.simple-pagination a.next").unwrap();
+    static ref CHAPTER_TITLE_SELECTOR: Selector = Selector::parse("div.chapter-title").unwrap();
+    static ref CONTENT_SELECTORS: SelectorChain =
+        SelectorChain::new(&["div#chp_raw", "div.chp_raw"]).unwrap();
+}
+
+const STATUSES: [&str; 4] = ["ongoing", "completed", "hiatus", "dropped"];
+
+/// scribblehub.com, the table of contents on the series page is paginated and
+/// lists the newest chapters first
+#[derive(Clone)]
+pub struct ScribbleHubExtractor {
+    site: String,
+}
+
+impl ScribbleHubExtractor {
+    pub fn new(site: &str) -> Self {
+        ScribbleHubExtractor {
+            site: site.to_string(),
+        }
+    }
+
+    /// Chapter links on one page of the table of contents, oldest first
+    fn chapter_urls(&self, document: &Html) -> Vec<String> {
+        let mut urls: Vec<String> = document
+            .select(&CHAPTER_LINK_SELECTOR)
+            .filter_map(|link| link.value().attr("href"))
+            .map(|href| absolute_url(&self.site, href.trim()))
+            .collect();
+        urls.reverse();
+        urls
+    }
+}
+
+#[async_trait]
+impl Extractor for ScribbleHubExtractor {
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &TITLE_SELECTOR).unwrap_or_else(|| "no_title".into());
+        let author = first_text(&document, &AUTHOR_SELECTOR).unwrap_or_else(|| "no_author".into());
+        let img_url = document
+            .select(&IMAGE_SELECTOR)
+            .next()
+            .and_then(|img| img.value().attr("src"))
+            .map(|src| absolute_url(&self.site, src.trim()));
+        let description = document
+            .select(&DESCRIPTION_SELECTOR)
+            .next()
+            .map(paragraphs_text)
+            .filter(|description| !description.is_empty());
+        let genres = all_texts(&document, &GENRE_SELECTOR);
+        let language = page_language(&document);
+        let status = document
+            .select(&STATUS_SELECTOR)
+            .map(|span| span.text().collect::<String>())
+            .filter_map(|text| {
+                let text = text.trim().to_lowercase();
+                STATUSES
+                    .iter()
+                    .find(|status| text.starts_with(*status))
+                    .map(|status| status.to_string())
+            })
+            .next();
+
+        let download_urls = self.chapter_urls(&document);
+        if download_urls.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+
+        Ok(Overview {
+            title,
+            author,
+            img_url,
+            description,
+            alternate_titles: Vec::new(),
+            genres,
+            language,
+            status,
+            download_urls,
+            volumes: HashMap::new(),
+            chapter_titles: HashMap::new(),
+        })
+    }
+
+    fn next_chapter_list_request(&self, html: &str) -> Option<PageRequest> {
+        Html::parse_document(html)
+            .select(&NEXT_PAGE_SELECTOR)
+            .next()
+            .and_then(|link| link.value().attr("href"))
+            .map(|href| PageRequest::Get(absolute_url(&self.site, href.trim())))
+    }
+
+    async fn extract_chapter_list(
+        &self,
+        html: &str,
+        overview: &mut Overview,
+        _downloader: &Downloader,
+    ) -> Result<(), ExtractError> {
+        // Every page of the table of contents has older chapters than the last
+        let older = self.chapter_urls(&Html::parse_document(html));
+        if older.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+        overview.download_urls.splice(0..0, older);
+        Ok(())
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &CHAPTER_TITLE_SELECTOR)
+            .ok_or(ExtractError::MissingElement("chapter title"))?;
+        let body: String = CONTENT_SELECTORS
+            .first(&document)
+            .ok_or(ExtractError::MissingElement("chapter content"))?
+            .children()
+            .filter_map(ElementRef::wrap)
+            .map(|element| element.html())
+            .collect();
+
+        Ok(Chapter {
+            title: title.clone(),
+            content: chapter_xhtml(&title, &body),
+        })
+    }
+
+    fn title_heading(&self) -> Option<Selector> {
+        Some(CHAPTER_TITLE_SELECTOR.clone())
+    }
+}
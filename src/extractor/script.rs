@@ -0,0 +1,444 @@
+use crate::downloader::Downloader;
+use crate::extractor::plugin::{add_chapters, ChapterDetails, ChapterLink, NovelDetails};
+use crate::extractor::{absolute_url, Chapter, ExtractError, Extractor, Overview, PageRequest};
+use crate::filters::StripRule;
+use crate::progress;
+use crate::titles::clean_title;
+use async_trait::async_trait;
+use reqwest::Url;
+use rhai::serde::from_dynamic;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// How much a script can do in one call before it's stopped, so one stuck in
+/// a loop doesn't hang the build
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+/// Where scripts are looked for without `--script-dir`, i.e.
+/// `~/.config/box2epub/extractors` on Linux
+pub fn scripts_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("box2epub").join("extractors"))
+}
+
+/// A page handed to a script. Scraper's documents can't be shared between
+/// threads, so the page is parsed again for every lookup.
+#[derive(Clone)]
+struct Page {
+    html: Arc<str>,
+    /// The novel's url, which links are resolved against
+    site: Arc<str>,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+impl Page {
+    fn parse_selector(selector: &str) -> ScriptResult<Selector> {
+        Selector::parse(selector)
+            .map_err(|err| format!("Invalid selector {}: {:?}", selector, err).into())
+    }
+
+    /// Calls `f` with the first element matching `selector`, `()` when
+    /// nothing does
+    fn with_first(
+        &self,
+        selector: &str,
+        f: impl FnOnce(ElementRef) -> Option<String>,
+    ) -> ScriptResult<Dynamic> {
+        let selector = Self::parse_selector(selector)?;
+        let document = Html::parse_document(&self.html);
+        let found = document.select(&selector).next().and_then(f);
+        Ok(found.map_or(Dynamic::UNIT, Dynamic::from))
+    }
+
+    fn text(&mut self, selector: &str) -> ScriptResult<Dynamic> {
+        self.with_first(selector, |element| {
+            Some(element.text().collect::<String>().trim().to_string())
+                .filter(|text| !text.is_empty())
+        })
+    }
+
+    fn all_text(&mut self) -> String {
+        let document = Html::parse_document(&self.html);
+        document
+            .root_element()
+            .text()
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+
+    fn own_text(&mut self, selector: &str) -> ScriptResult<Dynamic> {
+        self.with_first(selector, |element| {
+            Some(
+                element
+                    .children()
+                    .filter_map(|child| child.value().as_text().map(|text| text.to_string()))
+                    .collect::<String>()
+                    .trim()
+                    .to_string(),
+            )
+            .filter(|text| !text.is_empty())
+        })
+    }
+
+    fn texts(&mut self, selector: &str) -> ScriptResult<Array> {
+        let selector = Self::parse_selector(selector)?;
+        let document = Html::parse_document(&self.html);
+        Ok(document
+            .select(&selector)
+            .map(|element| element.text().collect::<String>().trim().to_string())
+            .filter(|text| !text.is_empty())
+            .map(Dynamic::from)
+            .collect())
+    }
+
+    fn inner_html(&mut self, selector: &str) -> ScriptResult<Dynamic> {
+        self.with_first(selector, |element| Some(element.inner_html()))
+    }
+
+    fn attr(&mut self, selector: &str, name: &str) -> ScriptResult<Dynamic> {
+        self.with_first(selector, |element| {
+            element
+                .value()
+                .attr(name)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        })
+    }
+
+    /// Every element matching `selector`, as a page of its own
+    fn select(&mut self, selector: &str) -> ScriptResult<Array> {
+        let selector = Self::parse_selector(selector)?;
+        let document = Html::parse_document(&self.html);
+        Ok(document
+            .select(&selector)
+            .map(|element| {
+                Dynamic::from(Page {
+                    html: element.html().into(),
+                    site: self.site.clone(),
+                })
+            })
+            .collect())
+    }
+
+    /// The links matching `selector` as `#{url, title}`, with absolute urls
+    fn links(&mut self, selector: &str) -> ScriptResult<Array> {
+        let selector = Self::parse_selector(selector)?;
+        let document = Html::parse_document(&self.html);
+        Ok(document
+            .select(&selector)
+            .filter_map(|link| {
+                let href = link.value().attr("href")?;
+                let mut map = Map::new();
+                map.insert("url".into(), absolute_url(&self.site, href.trim()).into());
+                map.insert(
+                    "title".into(),
+                    link.text().collect::<String>().trim().to_string().into(),
+                );
+                Some(Dynamic::from_map(map))
+            })
+            .collect())
+    }
+}
+
+/// What a script's `chapter_list_request` returns, a url to get or a form to
+/// post to one
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScriptRequest {
+    Get(String),
+    PostForm {
+        url: String,
+        #[serde(default)]
+        form: Vec<(String, String)>,
+    },
+}
+
+/// Extractor written in [Rhai](https://rhai.rs), for sites box2epub doesn't
+/// know that need more than the CSS selectors of a `ConfigExtractor` but not a
+/// whole `PluginExtractor`. Scripts are `.rhai` files in the extractors
+/// directory, the one whose `domains` has the novel's site is used. See
+/// `extractors/madara.rhai` in the repository for one.
+///
+/// A script defines these functions:
+///
+/// - `domains()`, the domains it handles, subdomains included:
+///   `["some-site.com"]`
+/// - `overview(page)`, given the novel's page: `#{title: "...", author: "...",
+///   cover: "...", description: "...", alternate_titles: ["..."], genres:
+///   ["..."], status: "...", language: "en", chapters: page.links("ul.chapters
+///   a")}`, only `title` and `chapters` are required. A chapter is a url or
+///   `#{url: "...", title: "..."}`.
+/// - `chapter(page)`, given a chapter's page: `#{title: "...", content:
+///   page.html("div.content")}`
+///
+/// and optionally:
+///
+/// - `chapter_list_request(page)`, for sites that paginate the chapter list or
+///   load it with AJAX: the url of its next part or `#{url: "...", form:
+///   [["name", "value"]]}` to post a form, `()` when there's no more. `page`
+///   is the one fetched last, starting with the novel's.
+/// - `chapter_list(page, chapters)`, given a page from `chapter_list_request`
+///   and the urls found so far, the whole list with that page's chapters in it
+/// - `strip()`, junk to remove from chapters as `css:<selector>` or
+///   `re:<regex>`
+/// - `clean_title(title)`, a chapter title with the site's quirks removed,
+///   before the usual cleanup
+///
+/// A page has `text(selector)`, `own_text(selector)` (leaving out children's),
+/// `html(selector)` (the inside of the element), `attr(selector, name)`, which
+/// are `()` when nothing matches, and `texts(selector)`, `links(selector)` and
+/// `select(selector)` (each match as a page) for every match. `text()` is all
+/// of the page's text, `page.source` its markup and `page.site` the novel's
+/// url. Scripts can `throw` to say what went wrong, and their functions see
+/// the constants at the top of the script as `global::NAME`.
+#[derive(Clone)]
+pub struct ScriptExtractor {
+    name: String,
+    site: String,
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+    domains: Vec<String>,
+    strip: Vec<StripRule>,
+}
+
+impl ScriptExtractor {
+    pub fn from_file(path: impl AsRef<Path>, site: &str) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let engine = engine();
+        let ast = engine
+            .compile(std::fs::read_to_string(path)?)
+            .map_err(|err| err.to_string())?;
+        let mut script = ScriptExtractor {
+            name: path
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+            site: site.to_string(),
+            engine: Arc::new(engine),
+            ast: Arc::new(ast),
+            domains: Vec::new(),
+            strip: Vec::new(),
+        };
+        script.domains = script.call_into("domains", ())?;
+        if script.defines("strip") {
+            let rules: Vec<String> = script.call_into("strip", ())?;
+            script.strip = rules
+                .iter()
+                .map(|rule| rule.parse())
+                .collect::<Result<_, String>>()?;
+        }
+        Ok(script)
+    }
+
+    /// The script's file name without `.rhai`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the script handles the site at `url`
+    pub fn handles(&self, url: &str) -> bool {
+        let host = match Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+        {
+            Some(host) => host,
+            None => return false,
+        };
+        self.domains.iter().any(|domain| {
+            let domain = domain.trim_start_matches("www.");
+            host == domain || host.ends_with(&format!(".{}", domain))
+        })
+    }
+
+    fn defines(&self, function: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|metadata| metadata.name == function)
+    }
+
+    fn page(&self, html: &str) -> Page {
+        Page {
+            html: html.into(),
+            site: self.site.as_str().into(),
+        }
+    }
+
+    fn call(&self, function: &str, args: impl FuncArgs) -> Result<Dynamic, ExtractError> {
+        self.engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, function, args)
+            .map_err(|err| {
+                ExtractError::Plugin(format!(
+                    "The script {}'s {} failed: {}",
+                    self.name, function, err
+                ))
+            })
+    }
+
+    fn call_into<T: for<'de> Deserialize<'de>>(
+        &self,
+        function: &str,
+        args: impl FuncArgs,
+    ) -> Result<T, ExtractError> {
+        let value = self.call(function, args)?;
+        from_dynamic(&value).map_err(|err| {
+            ExtractError::Plugin(format!(
+                "The script {}'s {} returned something unexpected: {}",
+                self.name, function, err
+            ))
+        })
+    }
+}
+
+/// The engine scripts run in, with the page API registered. Scripts get
+/// nothing but the pages they're handed, `print` goes to the progress output.
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    // Rhai's release limits, debug builds would reject scripts that work in
+    // release ones otherwise
+    engine.set_max_expr_depths(64, 32);
+    engine.on_print(|text| progress::info(text));
+    engine
+        .register_type_with_name::<Page>("Page")
+        .register_fn("text", Page::text)
+        .register_fn("text", Page::all_text)
+        .register_fn("own_text", Page::own_text)
+        .register_fn("texts", Page::texts)
+        .register_fn("html", Page::inner_html)
+        .register_fn("attr", Page::attr)
+        .register_fn("select", Page::select)
+        .register_fn("links", Page::links)
+        .register_get("source", |page: &mut Page| page.html.to_string())
+        .register_get("site", |page: &mut Page| page.site.to_string());
+    engine
+}
+
+/// The script in `dir` that handles the site at `url`. Scripts that can't be
+/// loaded are reported and passed over.
+pub fn find_script(dir: &Path, url: &str) -> Option<ScriptExtractor> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return None,
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("rhai")))
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .find_map(|path| match ScriptExtractor::from_file(&path, url) {
+            Ok(script) if script.handles(url) => Some(script),
+            Ok(_) => None,
+            Err(err) => {
+                progress::warn(format!(
+                    "Couldn't load the script {}: {}",
+                    path.display(),
+                    err
+                ));
+                None
+            }
+        })
+}
+
+#[async_trait]
+impl Extractor for ScriptExtractor {
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let details: NovelDetails = self.call_into("overview", (self.page(html),))?;
+        let overview = details.into_overview(&self.site);
+        // Sites that load the list with AJAX don't have it on the novel's page
+        if overview.download_urls.is_empty() && self.next_chapter_list_request(html).is_none() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+        Ok(overview)
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let details: ChapterDetails = self.call_into("chapter", (self.page(html),))?;
+        Ok(details.into_chapter())
+    }
+
+    fn next_chapter_list_request(&self, html: &str) -> Option<PageRequest> {
+        if !self.defines("chapter_list_request") {
+            return None;
+        }
+        let request = self
+            .call("chapter_list_request", (self.page(html),))
+            .and_then(|request| {
+                if request.is_unit() {
+                    return Ok(None);
+                }
+                from_dynamic::<ScriptRequest>(&request)
+                    .map(Some)
+                    .map_err(|err| ExtractError::Plugin(err.to_string()))
+            });
+        match request {
+            Ok(request) => request.map(|request| match request {
+                ScriptRequest::Get(url) => PageRequest::Get(absolute_url(&self.site, &url)),
+                ScriptRequest::PostForm { url, form } => PageRequest::PostForm {
+                    url: absolute_url(&self.site, &url),
+                    form,
+                },
+            }),
+            Err(err) => {
+                progress::warn(err.to_string());
+                None
+            }
+        }
+    }
+
+    async fn extract_chapter_list(
+        &self,
+        html: &str,
+        overview: &mut Overview,
+        _downloader: &Downloader,
+    ) -> Result<(), ExtractError> {
+        if !self.defines("chapter_list") {
+            return Ok(());
+        }
+        let found: Array = overview
+            .download_urls
+            .iter()
+            .map(|url| Dynamic::from(url.clone()))
+            .collect();
+        let chapters: Vec<ChapterLink> =
+            self.call_into("chapter_list", (self.page(html), found))?;
+        overview.download_urls.clear();
+        add_chapters(&self.site, chapters, overview);
+        if overview.download_urls.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+        Ok(())
+    }
+
+    fn strip_rules(&self) -> Vec<StripRule> {
+        self.strip.clone()
+    }
+
+    fn clean_chapter_title(&self, title: &str, novel_title: &str) -> String {
+        if !self.defines("clean_title") {
+            return clean_title(title, novel_title);
+        }
+        match self.call_into::<String>("clean_title", (title.to_string(),)) {
+            Ok(cleaned) => clean_title(&cleaned, novel_title),
+            Err(err) => {
+                progress::warn(err.to_string());
+                clean_title(title, novel_title)
+            }
+        }
+    }
+}
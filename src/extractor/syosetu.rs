@@ -0,0 +1,196 @@
+use crate::downloader::Downloader;
+use crate::extractor::{absolute_url, chapter_xhtml, first_text, paragraphs_text, Extractor};
+use crate::extractor::{Chapter, ExtractError, Overview, PageRequest};
+use async_trait::async_trait;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+
+lazy_static! {
+    // The site was redesigned in 2024, the old markup is still around in saved
+    // copies so both are matched
+    static ref TITLE_SELECTOR: Selector =
+        Selector::parse("h1.p-novel__title, p.novel_title").unwrap();
+    static ref AUTHOR_SELECTOR: Selector =
+        Selector::parse("div.p-novel__author, div.novel_writername").unwrap();
+    static ref DESCRIPTION_SELECTOR: Selector = Selector::parse("div#novel_ex").unwrap();
+    // Volume headings and chapter links, in the order they're listed
+    static ref INDEX_ENTRY_SELECTOR: Selector = Selector::parse(
+        "div.p-eplist__chapter-title, a.p-eplist__subtitle, div.chapter_title, dd.subtitle a"
+    )
+    .unwrap();
+    static ref NEXT_PAGE_SELECTOR: Selector =
+        Selector::parse("a.c-pager__item--next, a.novelview_pager-next").unwrap();
+
+    static ref CHAPTER_TITLE_SELECTOR: Selector =
+        Selector::parse("h1.p-novel__title--rensai, p.novel_subtitle").unwrap();
+    // The foreword, the chapter and the afterword, each is optional but the
+    // chapter itself
+    static ref TEXT_SELECTOR: Selector = Selector::parse(
+        "div.js-novel-text, div#novel_p, div#novel_honbun, div#novel_a"
+    )
+    .unwrap();
+}
+
+/// ncode.syosetu.com (小説家になろう). Chapters are grouped into volumes
+/// (章) on the index, which is paginated for long novels. Short stories don't
+/// have an index, their page is the only chapter. Furigana is kept as the
+/// `<ruby>` markup the site uses.
+#[derive(Clone)]
+pub struct SyosetuExtractor {
+    site: String,
+}
+
+impl SyosetuExtractor {
+    pub fn new(site: &str) -> Self {
+        SyosetuExtractor {
+            site: site.to_string(),
+        }
+    }
+
+    /// Chapter links on a page of the index and the volume each is in. Chapters
+    /// before the first heading on the page are still in `volume`, the one the
+    /// last page ended with.
+    fn chapter_urls(
+        &self,
+        document: &Html,
+        mut volume: Option<String>,
+    ) -> (Vec<String>, HashMap<String, String>) {
+        let mut urls = Vec::new();
+        let mut volumes = HashMap::new();
+        for entry in document.select(&INDEX_ENTRY_SELECTOR) {
+            match entry.value().attr("href") {
+                Some(href) => {
+                    let url = absolute_url(&self.site, href.trim());
+                    if let Some(volume) = &volume {
+                        volumes.insert(url.clone(), volume.clone());
+                    }
+                    urls.push(url);
+                }
+                None => {
+                    volume = Some(entry.text().collect::<String>().trim().to_string())
+                        .filter(|title| !title.is_empty());
+                }
+            }
+        }
+        (urls, volumes)
+    }
+}
+
+#[async_trait]
+impl Extractor for SyosetuExtractor {
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &TITLE_SELECTOR).unwrap_or_else(|| "no_title".into());
+        // "作者：name"
+        let author = first_text(&document, &AUTHOR_SELECTOR)
+            .map(|author| {
+                let author = author.trim_start_matches("作者");
+                author
+                    .trim_start_matches(&['：', ':'][..])
+                    .trim()
+                    .to_string()
+            })
+            .filter(|author| !author.is_empty())
+            .unwrap_or_else(|| "no_author".into());
+        let description = document
+            .select(&DESCRIPTION_SELECTOR)
+            .next()
+            .map(paragraphs_text)
+            .filter(|description| !description.is_empty());
+
+        let (mut download_urls, volumes) = self.chapter_urls(&document, None);
+        if download_urls.is_empty() {
+            if document.select(&TEXT_SELECTOR).next().is_none() {
+                return Err(ExtractError::MissingElement("chapter links"));
+            }
+            // A short story, the text is right there
+            download_urls.push(self.site.clone());
+        }
+
+        Ok(Overview {
+            title,
+            author,
+            img_url: None,
+            description,
+            alternate_titles: Vec::new(),
+            genres: Vec::new(),
+            language: Some("ja".to_string()),
+            status: None,
+            download_urls,
+            volumes,
+            chapter_titles: HashMap::new(),
+        })
+    }
+
+    fn next_chapter_list_request(&self, html: &str) -> Option<PageRequest> {
+        Html::parse_document(html)
+            .select(&NEXT_PAGE_SELECTOR)
+            .next()
+            .and_then(|link| link.value().attr("href"))
+            .map(|href| PageRequest::Get(absolute_url(&self.site, href.trim())))
+    }
+
+    async fn extract_chapter_list(
+        &self,
+        html: &str,
+        overview: &mut Overview,
+        _downloader: &Downloader,
+    ) -> Result<(), ExtractError> {
+        let volume = overview
+            .download_urls
+            .last()
+            .and_then(|url| overview.volumes.get(url))
+            .cloned();
+        let (urls, volumes) = self.chapter_urls(&Html::parse_document(html), volume);
+        if urls.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+        overview.download_urls.extend(urls);
+        overview.volumes.extend(volumes);
+        Ok(())
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &CHAPTER_TITLE_SELECTOR)
+            .or_else(|| first_text(&document, &TITLE_SELECTOR))
+            .ok_or(ExtractError::MissingElement("chapter title"))?;
+        let texts: Vec<ElementRef> = document.select(&TEXT_SELECTOR).collect();
+        if texts.is_empty() {
+            return Err(ExtractError::MissingElement("chapter text"));
+        }
+        // The author's foreword and afterword are set apart from the chapter
+        let body: String = texts
+            .iter()
+            .map(|text| {
+                let class = text.value().attr("class").unwrap_or_default();
+                let id = text.value().id().unwrap_or_default();
+                let is_note = id == "novel_p"
+                    || id == "novel_a"
+                    || class.contains("p-novel__text--preface")
+                    || class.contains("p-novel__text--afterword");
+                if is_note {
+                    format!(
+                        r#"<div class="author-note"><hr/>{}<hr/></div>"#,
+                        text.inner_html()
+                    )
+                } else {
+                    text.inner_html()
+                }
+            })
+            .collect();
+
+        Ok(Chapter {
+            title: title.clone(),
+            content: chapter_xhtml(&title, &body),
+        })
+    }
+}
@@ -0,0 +1,192 @@
+use crate::downloader::Downloader;
+use crate::extractor::{chapter_xhtml, Chapter, ExtractError, Extractor, Overview, PageRequest};
+use crate::language::language_code;
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref STORY_ID_REGEX: Regex = Regex::new(r"/story/(\d+)").unwrap();
+    // Chapters ("parts") are at wattpad.com/<id>-<slug>
+    static ref PART_ID_REGEX: Regex = Regex::new(r"wattpad\.com/(\d+)").unwrap();
+}
+
+const API_URL: &str = "https://www.wattpad.com/api/v3";
+/// Fields of a story the API is asked for, it leaves out most of them otherwise
+const STORY_FIELDS: &str =
+    "title,description,completed,cover,tags,language(name),user(name),parts(url,title)";
+
+/// A story, as the API returns it
+#[derive(Deserialize)]
+struct Story {
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    completed: bool,
+    #[serde(default)]
+    cover: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    language: Option<Language>,
+    #[serde(default)]
+    user: Option<User>,
+    parts: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+struct Language {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct User {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Part {
+    url: String,
+    title: Option<String>,
+}
+
+/// What the API returns for a chapter's url, the story it's in
+#[derive(Deserialize)]
+struct PartStory {
+    group: Story,
+}
+
+/// A chapter's title and where to get its text, which is html
+#[derive(Deserialize)]
+struct PartDetails {
+    title: String,
+    #[serde(default)]
+    pages: u32,
+    text_url: TextUrl,
+}
+
+#[derive(Deserialize)]
+struct TextUrl {
+    text: String,
+}
+
+/// wattpad.com, its pages are rendered by JavaScript so everything comes from
+/// the API the site itself uses. Either the story's url or one of its
+/// chapters' urls can be passed. The text of long chapters is split into
+/// pages, which are fetched one by one.
+#[derive(Clone)]
+pub struct WattpadExtractor {
+    site: String,
+}
+
+impl WattpadExtractor {
+    pub fn new(site: &str) -> Self {
+        WattpadExtractor {
+            site: site.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Extractor for WattpadExtractor {
+    fn overview_request(&self) -> Option<PageRequest> {
+        if let Some(capture) = STORY_ID_REGEX.captures(&self.site) {
+            Some(PageRequest::Get(format!(
+                "{}/stories/{}?fields={}",
+                API_URL, &capture[1], STORY_FIELDS
+            )))
+        } else {
+            PART_ID_REGEX.captures(&self.site).map(|capture| {
+                PageRequest::Get(format!(
+                    "{}/story_parts/{}?fields=group({})",
+                    API_URL, &capture[1], STORY_FIELDS
+                ))
+            })
+        }
+    }
+
+    fn chapter_request(&self, url: &str) -> PageRequest {
+        match PART_ID_REGEX.captures(url) {
+            Some(capture) => PageRequest::Get(format!(
+                "{}/story_parts/{}?fields=title,pages,text_url",
+                API_URL, &capture[1]
+            )),
+            None => PageRequest::Get(url.to_string()),
+        }
+    }
+
+    async fn extract_overview(
+        &self,
+        json: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let story = match serde_json::from_str::<PartStory>(json) {
+            Ok(part) => part.group,
+            Err(_) => serde_json::from_str::<Story>(json)
+                .map_err(|err| ExtractError::Malformed(err.to_string()))?,
+        };
+        if story.parts.is_empty() {
+            return Err(ExtractError::MissingElement("chapters"));
+        }
+        Ok(Overview {
+            title: story.title.trim().to_string(),
+            author: story
+                .user
+                .map(|user| user.name)
+                .unwrap_or_else(|| "no_author".into()),
+            img_url: story.cover.filter(|cover| !cover.is_empty()),
+            description: story
+                .description
+                .map(|description| description.trim().to_string())
+                .filter(|description| !description.is_empty()),
+            alternate_titles: Vec::new(),
+            genres: story.tags,
+            language: story
+                .language
+                .and_then(|language| language_code(&language.name))
+                .map(str::to_string),
+            status: Some(
+                if story.completed {
+                    "completed"
+                } else {
+                    "ongoing"
+                }
+                .to_string(),
+            ),
+            download_urls: story.parts.iter().map(|part| part.url.clone()).collect(),
+            volumes: HashMap::new(),
+            chapter_titles: story
+                .parts
+                .into_iter()
+                .filter_map(|part| Some((part.url, part.title?.trim().to_string())))
+                .collect(),
+        })
+    }
+
+    async fn extract_chapter(
+        &self,
+        json: &str,
+        downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let part: PartDetails =
+            serde_json::from_str(json).map_err(|err| ExtractError::Malformed(err.to_string()))?;
+        let text_url = Url::parse(&part.text_url.text)
+            .map_err(|err| ExtractError::Malformed(format!("Bad text url: {}", err)))?;
+        let mut text = String::new();
+        for page in 1..=part.pages.max(1) {
+            let mut url = text_url.clone();
+            if page > 1 {
+                url.query_pairs_mut().append_pair("page", &page.to_string());
+            }
+            text.push_str(&downloader.get_text(url.as_str()).await?);
+        }
+        let title = part.title.trim().to_string();
+        Ok(Chapter {
+            content: chapter_xhtml(&title, &text),
+            title,
+        })
+    }
+}
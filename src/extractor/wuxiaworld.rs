@@ -0,0 +1,194 @@
+use crate::downloader::Downloader;
+use crate::extractor::{absolute_url, all_texts, chapter_xhtml, first_text, Extractor};
+use crate::extractor::{page_language, paragraphs_text};
+use crate::extractor::{Chapter, ExtractError, Overview, SelectorChain};
+use crate::filters::StripRule;
+use crate::sanitizer::escape_text;
+use async_trait::async_trait;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref TITLE_SELECTOR: Selector = Selector::parse("div.novel-body h2").unwrap();
+    static ref DETAIL_TERM_SELECTOR: Selector = Selector::parse("div.novel-body dt").unwrap();
+    static ref IMAGE_SELECTOR: Selector = Selector::parse("div.novel-left img").unwrap();
+    static ref DESCRIPTION_SELECTOR: Selector =
+        Selector::parse("div.novel-bottom div.fr-view").unwrap();
+    static ref GENRE_SELECTOR: Selector = Selector::parse("div.genres a").unwrap();
+    static ref VOLUME_SELECTOR: Selector = Selector::parse("#accordion div.panel").unwrap();
+    static ref VOLUME_TITLE_SELECTOR: Selector =
+        Selector::parse("div.panel-heading span.title").unwrap();
+    static ref CHAPTER_LINK_SELECTOR: Selector = Selector::parse("li.chapter-item a").unwrap();
+
+    static ref CHAPTER_TITLE_SELECTOR: Selector = Selector::parse("div.caption h4").unwrap();
+    static ref CONTENT_SELECTORS: SelectorChain =
+        SelectorChain::new(&["div#chapter-content", "div.fr-view"]).unwrap();
+    // Translator notes shown as tooltips on the word they're about
+    static ref NOTE_SELECTOR: Selector = Selector::parse(
+        "[data-original-title], [data-footnote], span.footnote[title]"
+    )
+    .unwrap();
+}
+
+/// wuxiaworld.com, the chapter list is split into volumes and translators
+/// annotate words with tooltip notes, which are turned into notes at the end
+/// of the chapter since EPUB readers can't show tooltips
+#[derive(Clone)]
+pub struct WuxiaworldExtractor {
+    site: String,
+}
+
+impl WuxiaworldExtractor {
+    pub fn new(site: &str) -> Self {
+        WuxiaworldExtractor {
+            site: site.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Extractor for WuxiaworldExtractor {
+    async fn extract_overview(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Overview, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &TITLE_SELECTOR).unwrap_or_else(|| "no_title".into());
+        let author = detail(&document, "Author").unwrap_or_else(|| "no_author".into());
+        let img_url = document
+            .select(&IMAGE_SELECTOR)
+            .next()
+            .and_then(|img| img.value().attr("src"))
+            .map(|src| absolute_url(&self.site, src.trim()));
+        let description = document
+            .select(&DESCRIPTION_SELECTOR)
+            .next()
+            .map(paragraphs_text)
+            .filter(|description| !description.is_empty());
+        let genres = all_texts(&document, &GENRE_SELECTOR);
+        let language = page_language(&document);
+        let status = detail(&document, "Status");
+
+        // Volumes are listed oldest first, and so are the chapters in them
+        let mut download_urls = Vec::new();
+        let mut volumes = HashMap::new();
+        for panel in document.select(&VOLUME_SELECTOR) {
+            let volume = panel
+                .select(&VOLUME_TITLE_SELECTOR)
+                .next()
+                .map(|title| title.text().collect::<String>().trim().to_string())
+                .filter(|title| !title.is_empty());
+            for link in panel.select(&CHAPTER_LINK_SELECTOR) {
+                if let Some(href) = link.value().attr("href") {
+                    let url = absolute_url(&self.site, href.trim());
+                    if let Some(volume) = &volume {
+                        volumes.insert(url.clone(), volume.clone());
+                    }
+                    download_urls.push(url);
+                }
+            }
+        }
+        if download_urls.is_empty() {
+            return Err(ExtractError::MissingElement("chapter links"));
+        }
+
+        Ok(Overview {
+            title,
+            author,
+            img_url,
+            description,
+            alternate_titles: Vec::new(),
+            genres,
+            language,
+            status,
+            download_urls,
+            volumes,
+            chapter_titles: HashMap::new(),
+        })
+    }
+
+    async fn extract_chapter(
+        &self,
+        html: &str,
+        _downloader: &Downloader,
+    ) -> Result<Chapter, ExtractError> {
+        let document = Html::parse_document(html);
+        let title = first_text(&document, &CHAPTER_TITLE_SELECTOR)
+            .ok_or(ExtractError::MissingElement("chapter title"))?;
+        let content = CONTENT_SELECTORS
+            .first(&document)
+            .ok_or(ExtractError::MissingElement("chapter content"))?;
+
+        let mut body: String = content
+            .children()
+            .filter_map(ElementRef::wrap)
+            .map(|element| element.html())
+            .collect();
+
+        // Each annotated word gets a number, and its note is listed under it at
+        // the end of the chapter
+        let mut notes = Vec::new();
+        for element in content.select(&NOTE_SELECTOR) {
+            let attributes = element.value();
+            let note = attributes
+                .attr("data-original-title")
+                .or_else(|| attributes.attr("data-footnote"))
+                .or_else(|| attributes.attr("title"))
+                .map(str::trim)
+                .unwrap_or_default();
+            if note.is_empty() {
+                continue;
+            }
+            let number = notes.len() + 1;
+            let marked = format!(
+                r##"{}<sup><a id="note-ref-{1}" href="#note-{1}">[{1}]</a></sup>"##,
+                escape_text(&element.text().collect::<String>()),
+                number
+            );
+            body = body.replacen(&element.html(), &marked, 1);
+            notes.push(format!(
+                r##"<p id="note-{0}"><a href="#note-ref-{0}">[{0}]</a> {1}</p>"##,
+                number,
+                escape_text(note)
+            ));
+        }
+        if !notes.is_empty() {
+            body.push_str(&format!(
+                r#"<div class="translator-notes"><hr/><p><strong>Translator's notes</strong></p>{}</div>"#,
+                notes.concat()
+            ));
+        }
+
+        Ok(Chapter {
+            title: title.clone(),
+            content: chapter_xhtml(&title, &body),
+        })
+    }
+
+    fn strip_rules(&self) -> Vec<StripRule> {
+        vec![
+            // Previous and next chapter buttons at the top and bottom of the text
+            StripRule::Selector(Selector::parse("a.chapter-nav, div.chapter-nav").unwrap()),
+            StripRule::Regex(Regex::new(r"(?i)^\s*(previous|next) chapter\s*$").unwrap()),
+        ]
+    }
+}
+
+/// The novel's details are a list of terms ("Author:", "Status:") followed
+/// by their values
+fn detail(document: &Html, term: &str) -> Option<String> {
+    document
+        .select(&DETAIL_TERM_SELECTOR)
+        .find(|dt| {
+            dt.text()
+                .collect::<String>()
+                .trim()
+                .trim_end_matches(':')
+                .eq_ignore_ascii_case(term)
+        })
+        .and_then(|dt| dt.next_siblings().find_map(ElementRef::wrap))
+        .map(|dd| dd.text().collect::<String>().trim().to_string())
+        .filter(|value| !value.is_empty())
+}
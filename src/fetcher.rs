@@ -0,0 +1,233 @@
+use crate::downloader::{RateLimit, TokenBucket};
+use async_trait::async_trait;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Default for `BookOptions::connect_timeout`
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Default for `BookOptions::read_timeout`
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Why a request couldn't be sent or its response couldn't be read
+pub type FetchError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The server stopped sending anything for longer than the read timeout
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timed out, nothing was received for {:?}", self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Maximum download speed, i.e. `500k` or `2M` bytes per second
+#[derive(Debug, Clone, Copy)]
+pub struct Bandwidth {
+    pub bytes_per_second: u32,
+}
+
+impl FromStr for Bandwidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid bandwidth {}, expected something like 500k or 2M",
+                s
+            )
+        };
+        let number = s
+            .trim()
+            .trim_end_matches("/s")
+            .trim_end_matches(&['B', 'b'][..]);
+        let (number, multiplier) = match number.chars().last().map(|c| c.to_ascii_lowercase()) {
+            Some('k') => (&number[..number.len() - 1], 1024.0),
+            Some('m') => (&number[..number.len() - 1], 1024.0 * 1024.0),
+            Some('g') => (&number[..number.len() - 1], 1024.0 * 1024.0 * 1024.0),
+            _ => (number, 1.0),
+        };
+        let bytes = number.trim().parse::<f64>().map_err(|_| invalid())? * multiplier;
+        if !bytes.is_finite() || bytes < 1.0 || bytes > f64::from(u32::MAX) {
+            return Err(invalid());
+        }
+        Ok(Bandwidth {
+            bytes_per_second: bytes as u32,
+        })
+    }
+}
+
+/// A request the `Downloader` wants sent
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub url: String,
+    /// Url encoded form to POST, the request is a GET without one
+    pub form: Option<Vec<(String, String)>>,
+    /// Cookies and conditional request headers, on top of whatever the
+    /// fetcher sends by itself
+    pub headers: HeaderMap,
+}
+
+/// Whatever the site answered, errors statuses included
+#[derive(Debug)]
+pub struct Response {
+    /// Url the response came from, after redirects
+    pub url: String,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: header::HeaderName) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header(header::CONTENT_TYPE)
+    }
+}
+
+/// Sends requests for the `Downloader`, which takes care of retries, rate
+/// limits, cookies and caching on top of it. `ReqwestFetcher` goes to the
+/// network, other implementations can serve pages from anywhere.
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    /// Sends the request and reads the whole response. Error statuses are
+    /// responses too, only failing to get one at all is an error.
+    async fn fetch(&self, request: Request) -> Result<Response, FetchError>;
+}
+
+/// Fetches over HTTP with reqwest. The connect timeout is up to the client,
+/// reqwest can't time out a connection that stops sending halfway through the
+/// body by itself so that's done here.
+pub struct ReqwestFetcher {
+    client: reqwest::Client,
+    read_timeout: Duration,
+    /// Bytes that can be read, shared by every request
+    bandwidth: Option<Mutex<TokenBucket>>,
+}
+
+impl ReqwestFetcher {
+    pub fn new(client: reqwest::Client) -> Self {
+        ReqwestFetcher {
+            client,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            bandwidth: None,
+        }
+    }
+
+    /// How long to wait for the response to start, and then for each part of
+    /// its body
+    pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// How fast all the responses together can be read, reading stops for a
+    /// bit once a second's worth came in faster than that
+    pub fn max_bandwidth(mut self, bandwidth: Option<Bandwidth>) -> Self {
+        self.bandwidth = bandwidth.map(|bandwidth| {
+            Mutex::new(TokenBucket::new(RateLimit {
+                requests: bandwidth.bytes_per_second,
+                per: Duration::from_secs(1),
+            }))
+        });
+        self
+    }
+}
+
+#[async_trait]
+impl Fetcher for ReqwestFetcher {
+    async fn fetch(&self, request: Request) -> Result<Response, FetchError> {
+        let builder = match &request.form {
+            Some(form) => self.client.post(&request.url).form(form),
+            None => self.client.get(&request.url),
+        };
+        let timed_out = |_| TimedOut(self.read_timeout);
+        let mut resp =
+            tokio::time::timeout(self.read_timeout, builder.headers(request.headers).send())
+                .await
+                .map_err(timed_out)??;
+        let url = resp.url().to_string();
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let mut body = Vec::new();
+        while let Some(chunk) = tokio::time::timeout(self.read_timeout, resp.chunk())
+            .await
+            .map_err(timed_out)??
+        {
+            body.extend_from_slice(&chunk);
+            if let Some(bandwidth) = &self.bandwidth {
+                let wait = bandwidth.lock().unwrap().take_many(chunk.len() as f64);
+                tokio::time::delay_for(wait).await;
+            }
+        }
+        Ok(Response {
+            url,
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// Serves pages and images from memory, i.e. to run extractors and the whole
+/// pipeline in tests without a live site. Urls it doesn't have are a 404.
+#[derive(Default)]
+pub struct MemoryFetcher {
+    /// Url to its Content-Type and body
+    pages: HashMap<String, (String, Vec<u8>)>,
+    requested: Mutex<Vec<String>>,
+}
+
+impl MemoryFetcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves `html` at `url`
+    pub fn page(self, url: &str, html: impl Into<String>) -> Self {
+        self.file(url, "text/html; charset=utf-8", html.into().into_bytes())
+    }
+
+    /// Serves `data` at `url` with the given Content-Type, i.e. for images
+    pub fn file(mut self, url: &str, content_type: &str, data: Vec<u8>) -> Self {
+        self.pages
+            .insert(url.to_string(), (content_type.to_string(), data));
+        self
+    }
+
+    /// Every url requested so far, in order
+    pub fn requested(&self) -> Vec<String> {
+        self.requested.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Fetcher for MemoryFetcher {
+    async fn fetch(&self, request: Request) -> Result<Response, FetchError> {
+        self.requested.lock().unwrap().push(request.url.clone());
+        let mut headers = HeaderMap::new();
+        let (status, body) = match self.pages.get(&request.url) {
+            Some((content_type, data)) => {
+                headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(content_type)?);
+                (StatusCode::OK, data.clone())
+            }
+            None => (StatusCode::NOT_FOUND, Vec::new()),
+        };
+        Ok(Response {
+            url: request.url,
+            status,
+            headers,
+            body,
+        })
+    }
+}
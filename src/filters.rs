@@ -0,0 +1,147 @@
+use ego_tree::NodeId;
+use html5ever::tendril::StrTendril;
+use regex::Regex;
+use scraper::{Html, Node, Selector};
+use std::str::FromStr;
+
+lazy_static! {
+    /// What's stripped from every chapter, whatever the site
+    static ref BUILTIN_RULES: Vec<StripRule> = vec![
+        StripRule::Selector(Selector::parse("script, noscript, iframe, form, ins.adsbygoogle").unwrap()),
+        StripRule::Regex(
+            Regex::new(
+                r"(?i)(please )?(read|find|visit)( this| the)?( latest| original| more)?( chapters?| novels?)?( of this novel)? (at|on) [a-z0-9-]+\s?(\.|dot)\s?(com|net|org|co)\b[^.!\n]*[.!]?"
+            )
+            .unwrap(),
+        ),
+    ];
+}
+
+/// Elements that are removed when a regex leaves them without any text
+const REMOVABLE_WHEN_EMPTY: [&str; 15] = [
+    "p", "div", "span", "a", "em", "strong", "b", "i", "li", "h1", "h2", "h3", "h4", "h5", "h6",
+];
+
+/// Something to remove from chapters: elements matching a CSS selector, or
+/// text matching a regex
+#[derive(Clone, Debug)]
+pub enum StripRule {
+    Selector(Selector),
+    Regex(Regex),
+}
+
+impl FromStr for StripRule {
+    type Err = String;
+
+    /// Parses `css:<selector>` or `re:<regex>`, anything without a prefix is
+    /// taken as a selector
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(pattern) = s.strip_prefix("re:") {
+            Regex::new(pattern)
+                .map(StripRule::Regex)
+                .map_err(|err| format!("Invalid regex {}: {}", pattern, err))
+        } else {
+            let selector = s.strip_prefix("css:").unwrap_or(s);
+            Selector::parse(selector)
+                .map(StripRule::Selector)
+                .map_err(|err| format!("Invalid selector {}: {:?}", selector, err))
+        }
+    }
+}
+
+/// Removes watermarks, ads and scripts from chapters before they're sanitized.
+/// The built in rules run first, then the extractor's and then the user's.
+#[derive(Clone, Debug, Default)]
+pub struct ContentFilter {
+    rules: Vec<StripRule>,
+}
+
+impl ContentFilter {
+    pub fn new(extractor_rules: Vec<StripRule>, user_rules: &[StripRule]) -> Self {
+        let mut rules = BUILTIN_RULES.clone();
+        rules.extend(extractor_rules);
+        rules.extend_from_slice(user_rules);
+        ContentFilter { rules }
+    }
+
+    /// Only `rules`, without the built in ones
+    pub fn from_rules(rules: Vec<StripRule>) -> Self {
+        ContentFilter { rules }
+    }
+
+    /// Applies every rule to the chapter's html. Elements that were only there
+    /// for text a regex removed are removed too.
+    pub fn apply(&self, html: &str) -> String {
+        if self.rules.is_empty() {
+            return html.to_string();
+        }
+        let mut document = Html::parse_document(html);
+
+        for rule in &self.rules {
+            match rule {
+                StripRule::Selector(selector) => {
+                    let ids: Vec<NodeId> = document.select(selector).map(|e| e.id()).collect();
+                    for id in ids {
+                        if let Some(mut node) = document.tree.get_mut(id) {
+                            node.detach();
+                        }
+                    }
+                }
+                StripRule::Regex(regex) => strip_text(&mut document, regex),
+            }
+        }
+
+        document.root_element().html()
+    }
+}
+
+fn strip_text(document: &mut Html, regex: &Regex) {
+    let matches: Vec<(NodeId, String)> = document
+        .tree
+        .nodes()
+        .filter_map(|node| match node.value() {
+            Node::Text(text) if regex.is_match(&text.text) => {
+                Some((node.id(), regex.replace_all(&text.text, "").into_owned()))
+            }
+            _ => None,
+        })
+        .collect();
+
+    for (id, stripped) in matches {
+        let emptied = stripped.trim().is_empty();
+        if let Some(mut node) = document.tree.get_mut(id) {
+            if let Node::Text(text) = node.value() {
+                text.text = StrTendril::from(stripped);
+            }
+        }
+        if emptied {
+            remove_empty_ancestors(document, id);
+        }
+    }
+}
+
+/// Removes the paragraphs (and the like) around a text node that's now empty,
+/// so no blank lines are left behind where a watermark was
+fn remove_empty_ancestors(document: &mut Html, id: NodeId) {
+    let mut empty = id;
+    while let Some(parent) = document.tree.get(empty).and_then(|node| node.parent()) {
+        let removable = match parent.value() {
+            Node::Element(element) => REMOVABLE_WHEN_EMPTY.contains(&element.name()),
+            _ => false,
+        };
+        let has_content = parent.descendants().any(|node| match node.value() {
+            Node::Text(text) => !text.text.trim().is_empty(),
+            Node::Element(element) => element.name() == "img",
+            _ => false,
+        });
+        if !removable || has_content {
+            break;
+        }
+        empty = parent.id();
+    }
+    if empty != id {
+        if let Some(mut node) = document.tree.get_mut(empty) {
+            node.detach();
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::sanitizer::escape_text;
+use crate::transform::ContentTransform;
+use ego_tree::NodeId;
+use html5ever::tendril::StrTendril;
+use html5ever::{Attribute, LocalName, Namespace, QualName};
+use regex::Regex;
+use scraper::node::{Element, Text};
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::HashMap;
+
+/// What notes start with, i.e. "TN" in "[TN: ...]"
+const NOTE_LABELS: &str = r"T/?N|TL/?N|TL note|translator'?s? note|E/?N|ED|editor'?s? note|PR/?N|A/?N|author'?s? note|note";
+
+lazy_static! {
+    // "[TN: ...]" or "(TN: ...)", the note can't have the closing bracket in
+    // it. The space before goes too so the note's number follows the word.
+    static ref NOTE_REGEX: Regex = Regex::new(&format!(
+        r"(?i)\s*\[\s*(?:{0})\s*[:：]\s*(?P<bracketed>[^\]]+?)\s*\]|\s*\(\s*(?:{0})\s*[:：]\s*(?P<parenthesized>[^)]+?)\s*\)",
+        NOTE_LABELS
+    ))
+    .unwrap();
+    // A note's label without the brackets, for spans that are notes as a whole
+    static ref NOTE_LABEL_REGEX: Regex =
+        Regex::new(&format!(r"(?i)^(?:{})\s*[:：]\s*", NOTE_LABELS)).unwrap();
+    static ref NOTE_SPAN_SELECTOR: Selector = Selector::parse(
+        "span.tn, span.translator-note, span.tl-note, span.footnote, span.note"
+    )
+    .unwrap();
+}
+
+const HTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+const EPUB_NAMESPACE: &str = "http://www.idpf.org/2007/ops";
+
+/// Turns translator notes written into the text, like "[TN: a jian is a
+/// straight sword]" or a `<span class="tn">`, into EPUB 3 footnotes. The note
+/// is replaced by a numbered link and listed at the end of the chapter, which
+/// readers that support it show as a popup instead of jumping there.
+pub struct Footnotes;
+
+impl ContentTransform for Footnotes {
+    fn apply(&self, html: &str) -> String {
+        let mut document = Html::parse_document(html);
+        let mut notes = HashMap::new();
+        take_note_spans(&mut document, &mut notes);
+        take_inline_notes(&mut document, &mut notes);
+        if notes.is_empty() {
+            return html.to_string();
+        }
+        declare_epub_namespace(&mut document);
+
+        // Numbered in the order they're in the chapter, spans and inline
+        // notes were taken out separately
+        let markers: Vec<NodeId> = document
+            .tree
+            .root()
+            .descendants()
+            .map(|node| node.id())
+            .filter(|id| notes.contains_key(id))
+            .collect();
+        let mut footnotes = Vec::new();
+        for (i, id) in markers.into_iter().enumerate() {
+            let number = i + 1;
+            let note = notes.remove(&id).unwrap_or_default();
+            footnotes.push(format!(
+                r##"<aside epub:type="footnote" id="footnote-{0}"><p><a href="#noteref-{0}">{0}.</a> {1}</p></aside>"##,
+                number,
+                escape_text(&note)
+            ));
+            if let Some(mut sup) = document.tree.get_mut(id) {
+                let mut link = sup.append(element(
+                    "a",
+                    &[
+                        ("epub:type", "noteref"),
+                        ("id", &format!("noteref-{}", number)),
+                        ("href", &format!("#footnote-{}", number)),
+                    ],
+                ));
+                link.append(text(&number.to_string()));
+            }
+        }
+        let html = document.root_element().html();
+        match html.rfind("</body>") {
+            Some(end) => format!(
+                r#"{}<hr class="footnotes"/>{}{}"#,
+                &html[..end],
+                footnotes.concat(),
+                &html[end..]
+            ),
+            None => format!(r#"{}<hr class="footnotes"/>{}"#, html, footnotes.concat()),
+        }
+    }
+}
+
+/// Swaps spans holding a note for a marker, spans inside another one are part
+/// of its note
+fn take_note_spans(document: &mut Html, notes: &mut HashMap<NodeId, String>) {
+    let spans: Vec<(NodeId, String)> = document
+        .select(&NOTE_SPAN_SELECTOR)
+        .filter(|span| {
+            !span
+                .ancestors()
+                .filter_map(ElementRef::wrap)
+                .any(|ancestor| NOTE_SPAN_SELECTOR.matches(&ancestor))
+        })
+        .map(|span| (span.id(), span_note(&span.text().collect::<String>())))
+        .filter(|(_, note)| !note.is_empty())
+        .collect();
+    for (id, note) in spans {
+        if let Some(mut span) = document.tree.get_mut(id) {
+            let marker = span.insert_before(marker()).id();
+            span.detach();
+            notes.insert(marker, note);
+        }
+    }
+}
+
+/// The note in a span's text, without the brackets and label around it
+fn span_note(text: &str) -> String {
+    let text = text.trim();
+    let text = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+        .or_else(|| {
+            text.strip_prefix('(')
+                .and_then(|text| text.strip_suffix(')'))
+        })
+        .unwrap_or(text);
+    NOTE_LABEL_REGEX.replace(text.trim(), "").trim().to_string()
+}
+
+/// Swaps "[TN: ...]" in the text for markers. A note has to be in a single
+/// text node, so one with markup in it (i.e. an italic word) is left alone.
+fn take_inline_notes(document: &mut Html, notes: &mut HashMap<NodeId, String>) {
+    let ids: Vec<NodeId> = document
+        .tree
+        .root()
+        .descendants()
+        .filter(|node| matches!(node.value(), Node::Text(text) if NOTE_REGEX.is_match(text)))
+        .map(|node| node.id())
+        .collect();
+    for id in ids {
+        let mut node = match document.tree.get_mut(id) {
+            Some(node) => node,
+            None => continue,
+        };
+        let whole = match node.value() {
+            Node::Text(text) => text.text.to_string(),
+            _ => continue,
+        };
+        // The text before each note goes in front of its marker, what's
+        // after the last one stays in the node
+        let mut start = 0;
+        for captures in NOTE_REGEX.captures_iter(&whole) {
+            let found = captures.get(0).unwrap();
+            let note = captures
+                .name("bracketed")
+                .or_else(|| captures.name("parenthesized"))
+                .map_or("", |note| note.as_str());
+            if found.start() > start {
+                node.insert_before(text(&whole[start..found.start()]));
+            }
+            let marker = node.insert_before(marker()).id();
+            notes.insert(marker, note.to_string());
+            start = found.end();
+        }
+        if let Node::Text(rest) = node.value() {
+            rest.text = StrTendril::from(&whole[start..]);
+        }
+    }
+}
+
+/// An empty `<sup>` where a note was taken out, its link is added once the
+/// notes are numbered. Being an element of its own, nothing that was in the
+/// chapter can be taken for one.
+fn marker() -> Node {
+    element("sup", &[])
+}
+
+fn element(name: &str, attrs: &[(&str, &str)]) -> Node {
+    let name = QualName::new(None, Namespace::from(HTML_NAMESPACE), LocalName::from(name));
+    let attrs = attrs
+        .iter()
+        .map(|(name, value)| Attribute {
+            // Prefixed names are written as they are, like `xmlns:epub`
+            name: QualName::new(None, Namespace::from(""), LocalName::from(*name)),
+            value: StrTendril::from(*value),
+        })
+        .collect();
+    Node::Element(Element::new(name, attrs))
+}
+
+fn text(text: &str) -> Node {
+    Node::Text(Text {
+        text: StrTendril::from(text),
+    })
+}
+
+/// Adds the `epub` prefix to the `<html>` element, templates don't have to
+/// declare it
+fn declare_epub_namespace(document: &mut Html) {
+    let id = document.root_element().id();
+    if let Some(mut html) = document.tree.get_mut(id) {
+        if let Node::Element(element) = html.value() {
+            if element.attr("xmlns:epub").is_none() {
+                element.attrs.insert(
+                    QualName::new(None, Namespace::from(""), LocalName::from("xmlns:epub")),
+                    StrTendril::from(EPUB_NAMESPACE),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::book::{Book, EpubOptions};
+use crate::error::Result;
+use crate::progress;
+use crate::text::{xhtml_to_text, TextStyle};
+
+use serde::Serialize;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+/// File format the book is written in. The Kindle formats are converted from
+/// the EPUB with calibre or kindlegen, everything else is made by box2epub.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Epub,
+    Mobi,
+    Azw3,
+    Markdown,
+    Text,
+    /// The overview and every chapter as one JSON document
+    Json,
+    /// The overview and then every chapter, a JSON object per line
+    Ndjson,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Epub => "epub",
+            OutputFormat::Mobi => "mobi",
+            OutputFormat::Azw3 => "azw3",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Text => "txt",
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+        }
+    }
+
+    /// Media type of the file, for email attachments and downloads
+    pub fn mimetype(self) -> &'static str {
+        match self {
+            OutputFormat::Epub => "application/epub+zip",
+            OutputFormat::Mobi => "application/x-mobipocket-ebook",
+            OutputFormat::Azw3 => "application/vnd.amazon.ebook",
+            OutputFormat::Markdown => "text/markdown",
+            OutputFormat::Text => "text/plain",
+            OutputFormat::Json => "application/json",
+            OutputFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// Whether the book is written as an EPUB, or converted from one
+    pub fn builds_epub(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Epub | OutputFormat::Mobi | OutputFormat::Azw3
+        )
+    }
+
+    pub fn is_json(self) -> bool {
+        self == OutputFormat::Json || self == OutputFormat::Ndjson
+    }
+
+    fn text_style(self) -> Option<TextStyle> {
+        match self {
+            OutputFormat::Markdown => Some(TextStyle::Markdown),
+            OutputFormat::Text => Some(TextStyle::Plain),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "epub" => Ok(OutputFormat::Epub),
+            "mobi" => Ok(OutputFormat::Mobi),
+            "azw3" => Ok(OutputFormat::Azw3),
+            "md" | "markdown" => Ok(OutputFormat::Markdown),
+            "txt" | "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            _ => Err(format!(
+                "Unknown format {}, expected epub, mobi, azw3, md, txt, json or ndjson",
+                s
+            )),
+        }
+    }
+}
+
+/// Writes the book to `path` in the given format, `epub_options` are used for
+/// EPUB and the formats converted from it
+pub fn write_book(
+    book: &Book,
+    format: OutputFormat,
+    epub_options: &EpubOptions,
+    path: &Path,
+) -> Result<()> {
+    if format == OutputFormat::Epub {
+        return book.save_epub(path, epub_options);
+    }
+    if let Some(style) = format.text_style() {
+        return write_text(book, style, path);
+    }
+    if format.is_json() {
+        return write_json(book, format == OutputFormat::Ndjson, path);
+    }
+
+    // Converters pick the format from the file extension, so everything
+    // happens under proper names in a temporary directory
+    let dir = tempfile::Builder::new().prefix("box2epub").tempdir()?;
+    let epub_path = dir.path().join("book.epub");
+    book.save_epub(&epub_path, epub_options)?;
+
+    let converted_path = dir.path().join(format!("book.{}", format.extension()));
+    progress::info(format!("Converting to {}", format.extension()));
+    let converted = match run_ebook_convert(&epub_path, &converted_path) {
+        Err(Failure::Missing) if format == OutputFormat::Mobi => {
+            run_kindlegen(&epub_path, &converted_path)
+                .map_err(|err| err.describe("calibre's ebook-convert or kindlegen", format))
+        }
+        result => result.map_err(|err| err.describe("calibre's ebook-convert", format)),
+    };
+    converted?;
+
+    std::fs::copy(&converted_path, path)?;
+    Ok(())
+}
+
+/// Writes every chapter into a single Markdown or text file. Markdown keeps
+/// images, they're copied into an `images` directory next to the file.
+fn write_text(book: &Book, style: TextStyle, path: &Path) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let overview = &book.overview;
+    match style {
+        TextStyle::Markdown => write!(file, "# {}\n\nby {}\n", overview.title, overview.author)?,
+        TextStyle::Plain => write!(
+            file,
+            "{}\n{}\n\nby {}\n",
+            overview.title,
+            "=".repeat(overview.title.chars().count()),
+            overview.author
+        )?,
+    }
+    if let Some(description) = &overview.description {
+        write!(file, "\n{}\n", description)?;
+    }
+
+    for chapter in &book.chapters {
+        let heading = match style {
+            TextStyle::Markdown => format!("## {}", chapter.title),
+            TextStyle::Plain => format!(
+                "{}\n{}",
+                chapter.title,
+                "-".repeat(chapter.title.chars().count())
+            ),
+        };
+        let text = xhtml_to_text(&chapter.content()?, style);
+        write!(file, "\n\n{}\n\n{}\n", heading, text)?;
+    }
+    file.flush()?;
+
+    if style == TextStyle::Markdown {
+        copy_images(book, path.parent().unwrap_or_else(|| Path::new("")))?;
+    }
+    Ok(())
+}
+
+/// Writes each chapter to its own Markdown or text file in `dir`, named after
+/// its number (`0001.md`, `0002.md`, ...)
+pub fn write_chapter_files(book: &Book, format: OutputFormat, dir: &Path) -> Result<()> {
+    let style = format
+        .text_style()
+        .ok_or("Only Markdown and text can be split into chapter files")?;
+    std::fs::create_dir_all(dir)?;
+    for chapter in &book.chapters {
+        let heading = match style {
+            TextStyle::Markdown => format!("# {}", chapter.title),
+            TextStyle::Plain => format!(
+                "{}\n{}",
+                chapter.title,
+                "=".repeat(chapter.title.chars().count())
+            ),
+        };
+        let text = xhtml_to_text(&chapter.content()?, style);
+        let path = dir.join(format!("{:04}.{}", chapter.number, format.extension()));
+        std::fs::write(path, format!("{}\n\n{}\n", heading, text))?;
+    }
+
+    if style == TextStyle::Markdown {
+        copy_images(book, dir)?;
+    }
+    Ok(())
+}
+
+/// Copies chapter images to where Markdown files in `dir` expect them
+fn copy_images(book: &Book, dir: &Path) -> io::Result<()> {
+    for image in book.chapters.iter().flat_map(|chapter| &chapter.images) {
+        let path = dir.join(&image.path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(&image.file, path)?;
+    }
+    Ok(())
+}
+
+/// A chapter as it's written out in JSON
+#[derive(Serialize)]
+struct ChapterRecord<'a> {
+    number: usize,
+    title: &'a str,
+    url: &'a str,
+    /// Sanitized xhtml, image paths point inside the EPUB
+    content: String,
+}
+
+/// A chapter that couldn't be downloaded, for NDJSON
+#[derive(Serialize)]
+struct SkippedRecord<'a> {
+    /// What went wrong, i.e. "chapter 3: ..."
+    error: &'a str,
+}
+
+/// Writes the overview and every chapter as JSON. Chapters are written one at a
+/// time, so the whole book never has to be in memory.
+///
+/// With `lines` every record gets its own line instead (NDJSON), the overview
+/// first with `"type": "overview"`, then the chapters with `"type": "chapter"`
+/// and last the chapters that were skipped with `"type": "skipped"`.
+fn write_json(book: &Book, lines: bool, path: &Path) -> Result<()> {
+    #[derive(Serialize)]
+    struct Tagged<T: Serialize> {
+        #[serde(rename = "type")]
+        kind: &'static str,
+        #[serde(flatten)]
+        record: T,
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    if lines {
+        serde_json::to_writer(
+            &mut file,
+            &Tagged {
+                kind: "overview",
+                record: &book.overview,
+            },
+        )?;
+        writeln!(file)?;
+    } else {
+        write!(file, "{{\"overview\":")?;
+        serde_json::to_writer(&mut file, &book.overview)?;
+        write!(file, ",\"skipped\":")?;
+        serde_json::to_writer(&mut file, &book.skipped)?;
+        write!(file, ",\"chapters\":[")?;
+    }
+
+    for (i, chapter) in book.chapters.iter().enumerate() {
+        let record = ChapterRecord {
+            number: chapter.number,
+            title: &chapter.title,
+            url: &chapter.url,
+            content: chapter.content()?,
+        };
+        if lines {
+            serde_json::to_writer(
+                &mut file,
+                &Tagged {
+                    kind: "chapter",
+                    record,
+                },
+            )?;
+            writeln!(file)?;
+        } else {
+            if i > 0 {
+                write!(file, ",")?;
+            }
+            serde_json::to_writer(&mut file, &record)?;
+        }
+    }
+
+    if lines {
+        for error in &book.skipped {
+            serde_json::to_writer(
+                &mut file,
+                &Tagged {
+                    kind: "skipped",
+                    record: SkippedRecord { error },
+                },
+            )?;
+            writeln!(file)?;
+        }
+    } else {
+        writeln!(file, "]}}")?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Why a converter didn't produce a book
+enum Failure {
+    /// It isn't installed
+    Missing,
+    Failed(String),
+}
+
+impl Failure {
+    fn describe(self, converter: &str, format: OutputFormat) -> String {
+        match self {
+            Failure::Missing => format!(
+                "Writing {} needs {} installed and on the PATH",
+                format.extension(),
+                converter
+            ),
+            Failure::Failed(reason) => {
+                format!("Couldn't convert to {}: {}", format.extension(), reason)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::NotFound {
+            Failure::Missing
+        } else {
+            Failure::Failed(err.to_string())
+        }
+    }
+}
+
+fn run_ebook_convert(epub_path: &Path, converted_path: &Path) -> Result<(), Failure> {
+    let output = Command::new("ebook-convert")
+        .arg(epub_path)
+        .arg(converted_path)
+        .output()?;
+    if !output.status.success() {
+        return Err(Failure::Failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn run_kindlegen(epub_path: &Path, converted_path: &Path) -> Result<(), Failure> {
+    let output = Command::new("kindlegen")
+        .arg(epub_path)
+        .arg("-o")
+        .arg(converted_path.file_name().unwrap())
+        .output()?;
+    // kindlegen exits with 1 when there were only warnings, whether the book
+    // was made is the only reliable sign of success
+    if !converted_path.exists() {
+        return Err(Failure::Failed(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
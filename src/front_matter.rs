@@ -0,0 +1,55 @@
+use crate::extractor::Overview;
+use crate::sanitizer::{escape_attribute, escape_text};
+
+/// First page of the book, with its title and author and where and when it
+/// was downloaded
+pub fn title_page_xhtml(overview: &Overview, source: Option<&str>, date: &str) -> String {
+    let downloaded = match source {
+        Some(source) => format!(
+            r#"Downloaded from <a href="{}">{}</a> on {}"#,
+            escape_attribute(source),
+            escape_text(source),
+            escape_text(date)
+        ),
+        None => format!("Downloaded on {}", escape_text(date)),
+    };
+    format!(
+        r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+    <head>
+        <title>{title}</title>
+        <link rel="stylesheet" type="text/css" href="stylesheet.css"/>
+    </head>
+    <body class="title-page">
+        <h1>{title}</h1>
+        <p class="author">{author}</p>
+        <p class="source">{downloaded}</p>
+    </body>
+</html>"#,
+        title = escape_text(&overview.title),
+        author = escape_text(&overview.author),
+        downloaded = downloaded
+    )
+}
+
+/// Page with the novel's description, a paragraph for every line of it
+pub fn synopsis_xhtml(description: &str) -> String {
+    let paragraphs: String = description
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| format!("<p>{}</p>", escape_text(line)))
+        .collect();
+    format!(
+        r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+    <head>
+        <title>Synopsis</title>
+        <link rel="stylesheet" type="text/css" href="stylesheet.css"/>
+    </head>
+    <body>
+        <h1>Synopsis</h1>
+        {}
+    </body>
+</html>"#,
+        paragraphs
+    )
+}
@@ -0,0 +1,118 @@
+use crate::cache::{hash, user_cache_dir};
+
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What's needed to ask the server whether a cached response is still good
+#[derive(Serialize, Deserialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+}
+
+/// A response read back from the cache
+pub struct CachedResponse {
+    validators: Validators,
+    pub data: Vec<u8>,
+}
+
+impl CachedResponse {
+    pub fn content_type(&self) -> Option<&str> {
+        self.validators.content_type.as_deref()
+    }
+
+    /// Makes a request with these headers conditional, so the server answers
+    /// 304 Not Modified instead of sending the same page again
+    pub fn add_validators(&self, headers: &mut HeaderMap) {
+        let value = |value: &Option<String>| {
+            value
+                .as_deref()
+                .and_then(|value| HeaderValue::from_str(value).ok())
+        };
+        if let Some(etag) = value(&self.validators.etag) {
+            headers.insert(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = value(&self.validators.last_modified) {
+            headers.insert(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+}
+
+/// Keeps GET responses that came with an `ETag` or `Last-Modified` header on
+/// disk, so later runs only download pages (and images) that changed.
+///
+/// Every response is stored as two files named after the hash of its url, the
+/// body and the headers needed to revalidate it.
+#[derive(Clone)]
+pub struct HttpCache {
+    dir: PathBuf,
+}
+
+impl HttpCache {
+    /// Cache in the user's cache directory (i.e. `~/.cache/box2epub/http` on
+    /// Linux), shared by every novel
+    pub fn for_user() -> Option<Self> {
+        user_cache_dir().map(|dir| Self::in_dir(dir.join("http")))
+    }
+
+    pub fn in_dir(dir: impl AsRef<Path>) -> Self {
+        HttpCache {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub async fn get(&self, url: &str) -> Option<CachedResponse> {
+        let (validators_path, body_path) = self.paths(url);
+        let validators = tokio::fs::read(validators_path).await.ok()?;
+        let validators = serde_json::from_slice(&validators).ok()?;
+        let data = tokio::fs::read(body_path).await.ok()?;
+        Some(CachedResponse { validators, data })
+    }
+
+    /// Stores a response, unless it has no validators or asks not to be stored.
+    /// Returns whether it was stored.
+    pub async fn put(&self, url: &str, headers: &HeaderMap, data: &[u8]) -> io::Result<bool> {
+        let header_value = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let no_store = matches!(header_value(header::CACHE_CONTROL), Some(cache_control) if cache_control.contains("no-store"));
+        let validators = Validators {
+            etag: header_value(header::ETAG),
+            last_modified: header_value(header::LAST_MODIFIED),
+            content_type: header_value(header::CONTENT_TYPE),
+        };
+        if no_store || (validators.etag.is_none() && validators.last_modified.is_none()) {
+            return Ok(false);
+        }
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        // The body goes first, validators without a body are never used
+        let (validators_path, body_path) = self.paths(url);
+        write_atomically(&body_path, data).await?;
+        let validators = serde_json::to_vec(&validators)?;
+        write_atomically(&validators_path, &validators).await?;
+        Ok(true)
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let name = hash(url);
+        (
+            self.dir.join(format!("{}.json", name)),
+            self.dir.join(format!("{}.body", name)),
+        )
+    }
+}
+
+/// Writes to a temporary file first so getting killed halfway through a write
+/// doesn't leave a truncated file behind
+async fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, data).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
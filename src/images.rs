@@ -0,0 +1,225 @@
+use crate::cache::hash;
+use crate::downloader::{Downloader, Error};
+use crate::progress;
+use crate::sanitizer::decode_entities;
+use futures::future;
+use image::imageops::FilterType;
+use image::{GenericImageView, ImageOutputFormat};
+use regex::{Captures, Regex};
+use reqwest::Url;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use tracing::debug;
+
+lazy_static! {
+    // Only meant for xhtml coming out of the sanitizer, which always quotes
+    // attributes with `"`
+    static ref IMG_SRC_REGEX: Regex = Regex::new(r#"(<img\b[^>]*?\ssrc=")([^"]*)(")"#).unwrap();
+}
+
+/// An image from a chapter, stored in the EPUB so it shows up offline
+#[derive(Clone)]
+pub struct Image {
+    /// Path inside the EPUB, what the `<img>` tag now points at
+    pub path: String,
+    pub mimetype: String,
+    pub data: Vec<u8>,
+}
+
+/// Downloads every image in the (sanitized) chapter and points the `<img>` tags
+/// at the copies that will be stored in the EPUB.
+///
+/// Images that can't be downloaded keep pointing at their original url.
+pub async fn embed_images(
+    downloader: &Downloader,
+    xhtml: &str,
+    page_url: &str,
+) -> (String, Vec<Image>) {
+    let page_url = Url::parse(page_url).ok();
+    let urls: HashSet<String> = IMG_SRC_REGEX
+        .captures_iter(xhtml)
+        .filter_map(|capture| absolute_url(page_url.as_ref(), &capture[2]))
+        .collect();
+    if urls.is_empty() {
+        return (xhtml.to_string(), Vec::new());
+    }
+
+    let downloads = future::join_all(urls.into_iter().map(|url| async move {
+        let image = download_image(downloader, &url).await;
+        (url, image)
+    }))
+    .await;
+
+    let mut paths = HashMap::new();
+    let mut images = Vec::new();
+    for (url, image) in downloads {
+        match image {
+            Ok(Some(image)) => {
+                paths.insert(url, image.path.clone());
+                images.push(image);
+            }
+            Ok(None) => progress::warn(format!(
+                "Skipping image {}, not a supported image type",
+                url
+            )),
+            Err(err) => progress::warn(format!("Couldn't download image {}: {}", url, err)),
+        }
+    }
+
+    let xhtml = IMG_SRC_REGEX
+        .replace_all(xhtml, |capture: &Captures| {
+            match absolute_url(page_url.as_ref(), &capture[2]).and_then(|url| paths.get(&url)) {
+                Some(path) => format!("{}{}{}", &capture[1], path, &capture[3]),
+                None => capture[0].to_string(),
+            }
+        })
+        .into_owned();
+
+    (xhtml, images)
+}
+
+/// Resolves a (possibly relative and entity encoded) `src` against the chapter
+/// url, `data:` urls and the like are left alone. `file:` urls are only
+/// followed from chapters that were read from disk themselves.
+fn absolute_url(page_url: Option<&Url>, src: &str) -> Option<String> {
+    let src = decode_entities(src.trim());
+    let url = match page_url {
+        Some(page_url) => page_url.join(&src),
+        None => Url::parse(&src),
+    }
+    .ok()?;
+    match url.scheme() {
+        "http" | "https" => Some(url.into()),
+        "file" if matches!(page_url, Some(page_url) if page_url.scheme() == "file") => {
+            Some(url.into())
+        }
+        _ => None,
+    }
+}
+
+/// Re-encodes an image in a format not every reader can show (i.e. WebP or GIF)
+/// as JPEG, or PNG if it has transparency. Returns the new mimetype and data.
+///
+/// Only the first frame of an animation is kept.
+pub fn convert_image(data: &[u8]) -> image::ImageResult<(&'static str, Vec<u8>)> {
+    let image = image::load_from_memory(data)?;
+    let mut converted = Vec::new();
+    if image.color().has_alpha() {
+        image.write_to(&mut converted, ImageOutputFormat::Png)?;
+        Ok(("image/png", converted))
+    } else {
+        image.write_to(&mut converted, ImageOutputFormat::Jpeg(90))?;
+        Ok(("image/jpeg", converted))
+    }
+}
+
+/// JPEG quality that images scaled down by `ImageOptions` are saved with when
+/// it doesn't have one
+pub const DEFAULT_IMAGE_QUALITY: u8 = 85;
+
+/// JPEG quality from 1 to 100
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageQuality(pub u8);
+
+impl FromStr for ImageQuality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().parse() {
+            Ok(quality) if (1..=100).contains(&quality) => Ok(ImageQuality(quality)),
+            _ => Err(format!(
+                "{} isn't an image quality, it goes from 1 to 100",
+                s
+            )),
+        }
+    }
+}
+
+/// How the cover and the chapters' images are shrunk before going into the
+/// book, by default they're left as they were downloaded. Only JPEG and PNG
+/// images are touched, animated GIFs would lose their animation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImageOptions {
+    /// Longest side in pixels, bigger images are scaled down to it
+    pub max_size: Option<u32>,
+    /// Recompress JPEGs with this quality, even the ones that aren't too big
+    pub quality: Option<ImageQuality>,
+}
+
+impl ImageOptions {
+    /// The image scaled down and recompressed as asked. Recompressing alone
+    /// has to make the image smaller, otherwise it's kept as it was.
+    ///
+    /// Decoding and encoding is CPU bound, so it's meant to run off the
+    /// threads doing the downloads.
+    pub fn shrink(&self, image: Image) -> Image {
+        if self.max_size.is_none() && self.quality.is_none() {
+            return image;
+        }
+        let jpeg = match image.mimetype.as_str() {
+            "image/jpeg" => true,
+            "image/png" => false,
+            _ => return image,
+        };
+        match self.shrink_data(&image.data, jpeg) {
+            Ok(Some((data, scaled))) if scaled || data.len() < image.data.len() => {
+                debug!(
+                    path = image.path.as_str(),
+                    from = image.data.len(),
+                    to = data.len(),
+                    "shrunk the image"
+                );
+                Image { data, ..image }
+            }
+            Ok(_) => image,
+            Err(err) => {
+                progress::warn(format!("Couldn't shrink image {}: {}", image.path, err));
+                image
+            }
+        }
+    }
+
+    /// The image's new data and whether it was scaled down, `None` when
+    /// there's nothing to do
+    fn shrink_data(&self, data: &[u8], jpeg: bool) -> image::ImageResult<Option<(Vec<u8>, bool)>> {
+        let image = image::load_from_memory(data)?;
+        let (width, height) = image.dimensions();
+        let scale_to = self.max_size.filter(|&max| width.max(height) > max);
+        if scale_to.is_none() && !(jpeg && self.quality.is_some()) {
+            return Ok(None);
+        }
+        let image = match scale_to {
+            Some(max) => image.resize(max, max, FilterType::Lanczos3),
+            None => image,
+        };
+        let mut shrunk = Vec::new();
+        if jpeg {
+            let quality = self
+                .quality
+                .map_or(DEFAULT_IMAGE_QUALITY, |quality| quality.0);
+            image.write_to(&mut shrunk, ImageOutputFormat::Jpeg(quality))?;
+        } else {
+            image.write_to(&mut shrunk, ImageOutputFormat::Png)?;
+        }
+        Ok(Some((shrunk, scale_to.is_some())))
+    }
+}
+
+async fn download_image(downloader: &Downloader, url: &str) -> Result<Option<Image>, Error> {
+    let (mimetype, data) = downloader.get_bytes(url).await?;
+    let mimetype = mimetype.unwrap_or_default();
+    let extension = match mimetype.as_str() {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Image {
+        path: format!("images/{}.{}", hash(url), extension),
+        mimetype,
+        data,
+    }))
+}
@@ -0,0 +1,398 @@
+use crate::book::{BookOptions, CoverSource};
+use crate::extractor::Overview;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::tty::IsTty;
+use crossterm::{cursor, execute};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Span, Spans};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+use std::io;
+
+/// Width of the labels in front of the title, author and cover
+const LABEL_WIDTH: u16 = 9;
+
+/// What was picked on the `--interactive` screen, to go in `BookOptions`
+pub struct Choices {
+    pub title: String,
+    pub author: String,
+    /// `None` keeps the novel's own cover
+    pub cover: Option<CoverSource>,
+    /// Numbers of the chapters to put in the book, counted the way
+    /// `ChapterSelection` does
+    pub chapters: Vec<usize>,
+}
+
+/// Shows what was found on the novel's page and lets the title, author and
+/// cover be changed and chapters be left out, before anything is downloaded.
+/// What `options` already sets is picked to begin with. `None` when it was
+/// quit without making the book.
+///
+/// It takes over the terminal until then, so it has to be run off the async
+/// threads.
+pub fn choose(overview: &Overview, options: &BookOptions) -> io::Result<Option<Choices>> {
+    if !io::stdout().is_tty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--interactive needs a terminal",
+        ));
+    }
+    let mut screen = Screen::new(overview, options);
+    terminal::enable_raw_mode()?;
+    let choices = execute!(io::stdout(), EnterAlternateScreen)
+        .and_then(|_| Terminal::new(CrosstermBackend::new(io::stdout())))
+        .and_then(|mut terminal| screen.run(&mut terminal));
+    // The terminal is put back the way it was even when drawing failed
+    let restored = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show)
+        .and(terminal::disable_raw_mode());
+    let choices = choices?;
+    restored?;
+    Ok(choices)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Focus {
+    Title,
+    Author,
+    Cover,
+    Chapters,
+}
+
+impl Focus {
+    fn next(self) -> Self {
+        match self {
+            Focus::Title => Focus::Author,
+            Focus::Author => Focus::Cover,
+            Focus::Cover => Focus::Chapters,
+            Focus::Chapters => Focus::Title,
+        }
+    }
+
+    fn previous(self) -> Self {
+        match self {
+            Focus::Title => Focus::Chapters,
+            Focus::Author => Focus::Title,
+            Focus::Cover => Focus::Author,
+            Focus::Chapters => Focus::Cover,
+        }
+    }
+}
+
+/// Covers to pick from with ← and →, in that order
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CoverChoice {
+    Site,
+    Generated,
+    Omit,
+    Image,
+}
+
+const COVER_CHOICES: [CoverChoice; 4] = [
+    CoverChoice::Site,
+    CoverChoice::Generated,
+    CoverChoice::Omit,
+    CoverChoice::Image,
+];
+
+enum Action {
+    Continue,
+    Build,
+    Quit,
+}
+
+struct Screen<'a> {
+    overview: &'a Overview,
+    title: String,
+    author: String,
+    cover: CoverChoice,
+    /// File or url typed in for `CoverChoice::Image`
+    cover_source: String,
+    /// Whether each chapter goes in the book, in the order of the chapter list
+    selected: Vec<bool>,
+    focus: Focus,
+    list: ListState,
+    /// How many chapters fit in the list, for Page Up and Page Down
+    page: usize,
+    /// Shown instead of the keys until the next one is pressed
+    message: Option<&'static str>,
+}
+
+impl<'a> Screen<'a> {
+    fn new(overview: &'a Overview, options: &BookOptions) -> Self {
+        let (cover, cover_source) = match &options.cover {
+            None => (CoverChoice::Site, String::new()),
+            Some(CoverSource::Generated) => (CoverChoice::Generated, String::new()),
+            Some(CoverSource::Omit) => (CoverChoice::Omit, String::new()),
+            Some(CoverSource::Image(source)) => (CoverChoice::Image, source.clone()),
+        };
+        let selected = (1..=overview.download_urls.len())
+            .map(|number| match &options.chapters {
+                Some(selection) => selection.contains(number),
+                None => true,
+            })
+            .collect();
+        let mut list = ListState::default();
+        list.select(Some(0));
+        Screen {
+            overview,
+            title: overview.title.clone(),
+            author: overview.author.clone(),
+            cover,
+            cover_source,
+            selected,
+            focus: Focus::Title,
+            list,
+            page: 1,
+            message: None,
+        }
+    }
+
+    fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> io::Result<Option<Choices>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let key = match event::read()? {
+                // Windows reports keys being let go of too
+                Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                _ => continue,
+            };
+            self.message = None;
+            match self.handle(key) {
+                Action::Continue => {}
+                Action::Quit => return Ok(None),
+                Action::Build => {
+                    if let Some(choices) = self.choices() {
+                        return Ok(Some(choices));
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, key: KeyEvent) -> Action {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c') if control => return Action::Quit,
+            KeyCode::Enter => return Action::Build,
+            KeyCode::Tab => self.focus = self.focus.next(),
+            KeyCode::BackTab => self.focus = self.focus.previous(),
+            _ if self.focus == Focus::Chapters => self.handle_list(key.code),
+            KeyCode::Up => self.focus = self.focus.previous(),
+            KeyCode::Down => self.focus = self.focus.next(),
+            KeyCode::Left if self.focus == Focus::Cover => self.change_cover(-1),
+            KeyCode::Right if self.focus == Focus::Cover => self.change_cover(1),
+            KeyCode::Char(c) if !control => {
+                if let Some(field) = self.field() {
+                    field.push(c);
+                }
+            }
+            KeyCode::Backspace => {
+                if let Some(field) = self.field() {
+                    field.pop();
+                }
+            }
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn handle_list(&mut self, code: KeyCode) {
+        let last = self.selected.len().saturating_sub(1);
+        let current = self.list.selected().unwrap_or(0);
+        let next = match code {
+            KeyCode::Up if current == 0 => {
+                self.focus = Focus::Cover;
+                return;
+            }
+            KeyCode::Up => current - 1,
+            KeyCode::Down => current + 1,
+            KeyCode::PageUp => current.saturating_sub(self.page),
+            KeyCode::PageDown => current + self.page,
+            KeyCode::Home => 0,
+            KeyCode::End => last,
+            KeyCode::Char(' ') => {
+                if let Some(selected) = self.selected.get_mut(current) {
+                    *selected = !*selected;
+                }
+                current + 1
+            }
+            KeyCode::Char('a') => {
+                self.selected
+                    .iter_mut()
+                    .for_each(|selected| *selected = true);
+                current
+            }
+            KeyCode::Char('n') => {
+                self.selected
+                    .iter_mut()
+                    .for_each(|selected| *selected = false);
+                current
+            }
+            _ => current,
+        };
+        self.list.select(Some(next.min(last)));
+    }
+
+    /// The text being typed in, if the focus is on one
+    fn field(&mut self) -> Option<&mut String> {
+        match self.focus {
+            Focus::Title => Some(&mut self.title),
+            Focus::Author => Some(&mut self.author),
+            Focus::Cover if self.cover == CoverChoice::Image => Some(&mut self.cover_source),
+            _ => None,
+        }
+    }
+
+    fn change_cover(&mut self, by: isize) {
+        let current = COVER_CHOICES
+            .iter()
+            .position(|&choice| choice == self.cover)
+            .unwrap_or(0) as isize;
+        let count = COVER_CHOICES.len() as isize;
+        self.cover = COVER_CHOICES[(current + by).rem_euclid(count) as usize];
+    }
+
+    fn cover_label(&self) -> String {
+        match (self.cover, &self.overview.img_url) {
+            (CoverChoice::Site, Some(url)) => format!("the novel's, {}", url),
+            (CoverChoice::Site, None) => "the novel's, there's none on its page".to_string(),
+            (CoverChoice::Generated, _) => "made with the title and author".to_string(),
+            (CoverChoice::Omit, _) => "none".to_string(),
+            (CoverChoice::Image, _) => format!("file or url: {}", self.cover_source),
+        }
+    }
+
+    /// What's picked, or `None` with a message when it can't make a book
+    fn choices(&mut self) -> Option<Choices> {
+        let chapters: Vec<usize> = self
+            .selected
+            .iter()
+            .enumerate()
+            .filter(|(_, selected)| **selected)
+            .map(|(i, _)| i + 1)
+            .collect();
+        let title = self.title.trim();
+        let author = self.author.trim();
+        let cover_source = self.cover_source.trim();
+        self.message = if title.is_empty() {
+            Some("The book needs a title")
+        } else if author.is_empty() {
+            Some("The book needs an author")
+        } else if self.cover == CoverChoice::Image && cover_source.is_empty() {
+            Some("Type in the cover's file or url, or pick another cover with ← and →")
+        } else if chapters.is_empty() {
+            Some("Pick at least one chapter")
+        } else {
+            None
+        };
+        if self.message.is_some() {
+            return None;
+        }
+        let cover = match self.cover {
+            CoverChoice::Site => None,
+            CoverChoice::Generated => Some(CoverSource::Generated),
+            CoverChoice::Omit => Some(CoverSource::Omit),
+            CoverChoice::Image => Some(CoverSource::Image(cover_source.to_string())),
+        };
+        Some(Choices {
+            title: title.to_string(),
+            author: author.to_string(),
+            cover,
+            chapters,
+        })
+    }
+
+    fn draw<B: Backend>(&mut self, frame: &mut Frame<B>) {
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(5),
+                Constraint::Min(3),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+
+        let fields = [
+            (Focus::Title, "Title", self.title.clone()),
+            (Focus::Author, "Author", self.author.clone()),
+            (Focus::Cover, "Cover", self.cover_label()),
+        ];
+        let lines: Vec<Spans> = fields
+            .iter()
+            .map(|(focus, label, value)| {
+                let style = if *focus == self.focus {
+                    Style::default().add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(Color::DarkGray)
+                };
+                Spans::from(vec![
+                    Span::styled(format!("{:1$}", label, LABEL_WIDTH as usize), style),
+                    Span::raw(value.clone()),
+                ])
+            })
+            .collect();
+        let details = Block::default().borders(Borders::ALL).title(" Book ");
+        frame.render_widget(Paragraph::new(lines).block(details), areas[0]);
+        // The cursor goes at the end of the text being typed in
+        if let Some((row, (_, _, value))) = fields
+            .iter()
+            .enumerate()
+            .find(|(_, (focus, _, _))| *focus == self.focus)
+        {
+            let typing = match self.focus {
+                Focus::Cover => self.cover == CoverChoice::Image,
+                _ => true,
+            };
+            if typing {
+                let x = areas[0].x + 1 + LABEL_WIDTH + Span::raw(value.as_str()).width() as u16;
+                frame.set_cursor(
+                    x.min(areas[0].right().saturating_sub(2)),
+                    areas[0].y + 1 + row as u16,
+                );
+            }
+        }
+
+        let overview = self.overview;
+        let items: Vec<ListItem> = overview
+            .download_urls
+            .iter()
+            .zip(&self.selected)
+            .enumerate()
+            .map(|(i, (url, selected))| {
+                let title = overview.chapter_titles.get(url).unwrap_or(url);
+                let check = if *selected { 'x' } else { ' ' };
+                ListItem::new(format!("[{}] {:>5}  {}", check, i + 1, title))
+            })
+            .collect();
+        let title = format!(
+            " Chapters, {} of {} ",
+            self.selected.iter().filter(|selected| **selected).count(),
+            self.selected.len()
+        );
+        let highlight = if self.focus == Focus::Chapters {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default()
+        };
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(list, areas[1], &mut self.list);
+        self.page = areas[1].height.saturating_sub(2).max(1) as usize;
+
+        let help = match (self.message, self.focus) {
+            (Some(message), _) => Span::styled(message, Style::default().fg(Color::Yellow)),
+            (None, Focus::Chapters) => Span::raw(
+                "Space leave in/out  a all  n none  Tab details  Enter make the book  Esc quit",
+            ),
+            (None, Focus::Cover) => {
+                Span::raw("←→ pick the cover  Tab chapters  Enter make the book  Esc quit")
+            }
+            (None, _) => Span::raw("Type to change it  Tab next  Enter make the book  Esc quit"),
+        };
+        frame.render_widget(Paragraph::new(Spans::from(help)), areas[2]);
+    }
+}
@@ -0,0 +1,138 @@
+/// Guesses which language `text` is written in, as the two letter code EPUB
+/// readers expect (`en`, `fr`, ...). `None` if the guess isn't reliable.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let info = whatlang::detect(text)?;
+    if !info.is_reliable() {
+        return None;
+    }
+    iso_639_1(info.lang().code())
+}
+
+/// whatlang names languages by their three letter ISO 639-3 code
+fn iso_639_1(code: &str) -> Option<&'static str> {
+    let code = match code {
+        "afr" => "af",
+        "aka" => "ak",
+        "amh" => "am",
+        "ara" => "ar",
+        "aze" => "az",
+        "bel" => "be",
+        "ben" => "bn",
+        "bul" => "bg",
+        "cat" => "ca",
+        "ces" => "cs",
+        "cmn" => "zh",
+        "dan" => "da",
+        "deu" => "de",
+        "ell" => "el",
+        "eng" => "en",
+        "epo" => "eo",
+        "est" => "et",
+        "fin" => "fi",
+        "fra" => "fr",
+        "guj" => "gu",
+        "heb" => "he",
+        "hin" => "hi",
+        "hrv" => "hr",
+        "hun" => "hu",
+        "hye" => "hy",
+        "ind" => "id",
+        "ita" => "it",
+        "jav" => "jv",
+        "jpn" => "ja",
+        "kan" => "kn",
+        "kat" => "ka",
+        "khm" => "km",
+        "kor" => "ko",
+        "lat" => "la",
+        "lav" => "lv",
+        "lit" => "lt",
+        "mal" => "ml",
+        "mar" => "mr",
+        "mkd" => "mk",
+        "mya" => "my",
+        "nep" => "ne",
+        "nld" => "nl",
+        "nob" => "nb",
+        "ori" => "or",
+        "pan" => "pa",
+        "pes" => "fa",
+        "pol" => "pl",
+        "por" => "pt",
+        "ron" => "ro",
+        "rus" => "ru",
+        "sin" => "si",
+        "slk" => "sk",
+        "slv" => "sl",
+        "sna" => "sn",
+        "spa" => "es",
+        "srp" => "sr",
+        "swe" => "sv",
+        "tam" => "ta",
+        "tel" => "te",
+        "tgl" => "tl",
+        "tha" => "th",
+        "tuk" => "tk",
+        "tur" => "tr",
+        "ukr" => "uk",
+        "urd" => "ur",
+        "uzb" => "uz",
+        "vie" => "vi",
+        "yid" => "yi",
+        "zul" => "zu",
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Two letter code for a language's English name, as sites that show the
+/// language instead of tagging the page with it write it (`English`, `Spanish`)
+pub fn language_code(name: &str) -> Option<&'static str> {
+    let code = match name.trim().to_lowercase().as_str() {
+        "afrikaans" => "af",
+        "arabic" => "ar",
+        "bulgarian" => "bg",
+        "catalan" => "ca",
+        "chinese" => "zh",
+        "croatian" => "hr",
+        "czech" => "cs",
+        "danish" => "da",
+        "dutch" => "nl",
+        "english" => "en",
+        "esperanto" => "eo",
+        "estonian" => "et",
+        "farsi" | "persian" => "fa",
+        "filipino" | "tagalog" => "tl",
+        "finnish" => "fi",
+        "french" => "fr",
+        "german" => "de",
+        "greek" => "el",
+        "hebrew" => "he",
+        "hindi" => "hi",
+        "hungarian" => "hu",
+        "icelandic" => "is",
+        "indonesian" => "id",
+        "italian" => "it",
+        "japanese" => "ja",
+        "korean" => "ko",
+        "latvian" => "lv",
+        "lithuanian" => "lt",
+        "malay" => "ms",
+        "norwegian" => "no",
+        "polish" => "pl",
+        "portuguese" => "pt",
+        "romanian" => "ro",
+        "russian" => "ru",
+        "serbian" => "sr",
+        "slovak" => "sk",
+        "slovenian" => "sl",
+        "spanish" => "es",
+        "swedish" => "sv",
+        "thai" => "th",
+        "turkish" => "tr",
+        "ukrainian" => "uk",
+        "vietnamese" => "vi",
+        _ => return None,
+    };
+    Some(code)
+}
@@ -1,4 +1,56 @@
+pub mod allowlist;
+pub mod archive;
+pub mod book;
+#[cfg(feature = "browser")]
+pub mod browser;
+pub mod cache;
+pub mod calibre;
+pub mod charset;
+pub mod checkpoint;
+pub mod colors;
+pub mod content_size;
+pub mod cookies;
+pub mod cover;
+pub mod delivery;
+pub mod downloader;
+pub mod duplicates;
+pub mod enrich;
+pub mod epub_zip;
+pub mod error;
+pub mod existing;
 pub mod extractor;
+pub mod fetcher;
+pub mod filters;
+pub mod footnotes;
+pub mod format;
+pub mod front_matter;
+pub mod http_cache;
+pub mod images;
+pub mod interactive;
+pub mod language;
+pub mod library;
+pub mod manifest;
+pub mod metadata;
+pub mod minify;
+pub mod mirror;
+pub mod naming;
+pub mod opds;
+pub mod ordering;
+pub mod paragraphs;
+pub mod progress;
+pub mod robots;
+pub mod sanitizer;
+pub mod selection;
+pub mod spool;
+pub mod template;
+pub mod text;
+pub mod titles;
+pub mod toc;
+pub mod transform;
+pub mod typography;
+pub mod urls;
+pub mod validate;
+pub mod watch;
 
 #[macro_use]
 extern crate lazy_static;
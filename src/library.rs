@@ -0,0 +1,105 @@
+use crate::book::Book;
+use crate::naming::file_stem;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Index of the books in a `--library` directory, in `library.json` at its
+/// top. It's what `update` and `watch` go by to find the books and where
+/// they came from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LibraryIndex {
+    pub books: Vec<LibraryBook>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryBook {
+    /// The novel's page the book is made from
+    pub site: String,
+    /// Pages on other sites with the rest of the novel, see
+    /// `BookOptions::more_sources`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub more_sources: Vec<String>,
+    pub title: String,
+    pub author: String,
+    /// Where the book is, relative to the library
+    pub path: PathBuf,
+    /// How many chapters are in the book
+    pub chapters: usize,
+    /// Title of the book's last chapter
+    #[serde(default)]
+    pub last_chapter: Option<String>,
+    /// When the book was last written, i.e. `2021-03-14T09:26:53Z`
+    pub updated: String,
+}
+
+impl LibraryBook {
+    /// The entry for `book`, made from `site` and written to `path` in the
+    /// library at `dir`
+    pub fn new(dir: &Path, site: &str, book: &Book, path: &Path) -> Self {
+        LibraryBook {
+            site: site.to_string(),
+            more_sources: Vec::new(),
+            title: book.overview.title.clone(),
+            author: book.overview.author.clone(),
+            path: path.strip_prefix(dir).unwrap_or(path).to_path_buf(),
+            chapters: book.chapters.len(),
+            last_chapter: book.chapters.last().map(|chapter| chapter.title.clone()),
+            updated: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        }
+    }
+}
+
+impl LibraryIndex {
+    /// The index of the library at `dir`, empty when there's none yet
+    pub fn load(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = index_path(dir);
+        if !path.exists() {
+            return Ok(LibraryIndex::default());
+        }
+        let file = File::open(&path)?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| {
+            format!(
+                "Couldn't read the library index {}: {}",
+                path.display(),
+                err
+            )
+            .into()
+        })
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::create_dir_all(dir)?;
+        let file = File::create(index_path(dir))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// The book made from the novel at `site`
+    pub fn find(&self, site: &str) -> Option<&LibraryBook> {
+        self.books.iter().find(|book| book.site == site)
+    }
+
+    /// Adds the book, or replaces the one made from the same novel
+    pub fn record(&mut self, book: LibraryBook) {
+        match self.books.iter_mut().find(|entry| entry.site == book.site) {
+            Some(entry) => *entry = book,
+            None => self.books.push(book),
+        }
+    }
+}
+
+/// Where the index of the library at `dir` is
+pub fn index_path(dir: &Path) -> PathBuf {
+    dir.join("library.json")
+}
+
+/// Folder of the library at `dir` a book with this title goes in
+pub fn book_dir(dir: &Path, title: &str) -> PathBuf {
+    match file_stem(title) {
+        stem if stem.is_empty() => dir.join("untitled"),
+        stem => dir.join(stem),
+    }
+}
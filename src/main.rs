@@ -1,147 +1,985 @@
-use box2epub::extractor::Extractor;
-use box2epub::extractor::{BoxnExtractor, RwnExtractor};
-
-use futures::future;
-use futures::stream::{self, StreamExt};
-
-use epub_builder::EpubBuilder;
-use epub_builder::EpubContent;
-use epub_builder::ReferenceType;
-use epub_builder::ZipLibrary;
-
-// Don't overwhelm the server with too many connections at once
-const MAX_PARALLEL: usize = 8;
-const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 5.1; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/60.0.3112.90 Safari/537.36";
-
-/// EPUB only accepts xhtml, so this converts html to xhtml (i.e. <br> to <br />)
-/// Turns out `prettier` formatting does a pretty good job of this so let's just
-/// use this (slow) heavy-handed solution for now.
-async fn sanitize_html(html: String) -> String {
-    use std::process::Stdio;
-    use tokio::io::AsyncWriteExt;
-    use tokio::process::Command;
-    let mut prettier_cmd = Command::new("npx")
-        .args(vec!["prettier", "--parser", "html"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .spawn()
-        .expect("Couldn't start npx");
-    {
-        let stdin = prettier_cmd.stdin.as_mut().unwrap();
-        stdin.write_all(html.as_bytes()).await.unwrap();
-    }
-
-    String::from_utf8(prettier_cmd.wait_with_output().await.unwrap().stdout)
-        .unwrap()
-        // TODO: handle html entity conversion properly
-        .replace("&nbsp;", "&#160;")
-        .replace(char::is_control, "")
-}
+mod cli;
+
+use box2epub::archive::ChapterArchive;
+use box2epub::book::{self, Book, BookOptions, EpubOptions, OnError};
+#[cfg(feature = "browser")]
+use box2epub::browser::BrowserFetcher;
+use box2epub::cache::{self, user_cache_dir};
+use box2epub::calibre::SeriesPosition;
+use box2epub::checkpoint::{checkpoint_dir, Checkpoint};
+use box2epub::colors::StripColors;
+use box2epub::cookies::CookieJar;
+use box2epub::delivery::{delivery_config_path, Delivery};
+use box2epub::downloader::RetryPolicy;
+use box2epub::duplicates::{find_duplicates, DEFAULT_SIMILARITY};
+use box2epub::error::Result;
+use box2epub::existing::ExistingEpub;
+use box2epub::extractor::{plugins_dir, scripts_dir, ConfigExtractor, Overview};
+use box2epub::fetcher::Fetcher;
+use box2epub::footnotes::Footnotes;
+use box2epub::format::{self, OutputFormat};
+use box2epub::interactive;
+use box2epub::library::{self, LibraryBook, LibraryIndex};
+use box2epub::manifest::{manifest_path, Manifest};
+use box2epub::metadata::Metadata;
+use box2epub::minify::Minify;
+use box2epub::mirror::LocalMirror;
+use box2epub::naming;
+use box2epub::opds::{self, Library};
+use box2epub::progress;
+use box2epub::selection::ChapterSelection;
+use box2epub::template::ChapterTemplate;
+use box2epub::transform;
+use box2epub::typography::FixTypography;
+use box2epub::validate::validate_book;
+use box2epub::watch::run_hook;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use indicatif::HumanBytes;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::task;
+use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
-    // Normalize the site to have slash at the end
-    let site = {
-        let raw_site = std::env::args().nth(1).expect("Url argument missing");
-        let last_char = raw_site
-            .chars()
-            .last()
-            .expect("Argument should at least have one character");
-        if last_char == '/' {
-            raw_site
-        } else {
-            raw_site + "/"
+async fn main() {
+    // Printed with Display rather than returned, the error's Debug output is
+    // for people reading the code, not the ones running it
+    if let Err(err) = run_command(cli::Command::parse()).await {
+        progress::warn(format!("Error: {}", err));
+        std::process::exit(1);
+    }
+}
+
+async fn run_command(command: cli::Command) -> Result<()> {
+    match command {
+        cli::Command::Build(args) => build(&args).await,
+        cli::Command::ListChapters(args, json) => list_chapters(&args, json).await,
+        cli::Command::Verify(path, args) => verify(&path, &args).await,
+        cli::Command::Watch(watch_options, args) => watch(watch_options, args).await,
+        cli::Command::Serve(dir, address) => serve(dir, address).await,
+        cli::Command::Cache(command) => cache(command),
+    }
+}
+
+async fn build(args: &cli::Args) -> Result<()> {
+    // JSON goes to stdout, where it can't be mixed up with messages
+    let json_dry_run = args.dry_run && args.format.is_json();
+    progress::set_quiet(args.quiet || json_dry_run);
+    init_logging(args)?;
+    let one_book = args.sites.len() == 1 || args.merge;
+    if !one_book && (args.title.is_some() || args.author.is_some() || args.cover.is_some()) {
+        return Err("--title, --author and --cover are for making one book, not a batch".into());
+    }
+    // Without urls every book in the library is updated
+    if args.sites.is_empty() {
+        let library = args
+            .library
+            .as_deref()
+            .ok_or("There are no novels to make books from")?;
+        let index = LibraryIndex::load(library)?;
+        if index.books.is_empty() {
+            return Err(format!("There are no books in {} yet", library.display()).into());
         }
-    };
+        let sites: Vec<String> = index.books.into_iter().map(|book| book.site).collect();
+        return run_batch(args, &sites).await;
+    }
+    if one_book {
+        run(args, &args.sites[0]).await?;
+        return Ok(());
+    }
+    run_batch(args, &args.sites).await
+}
 
-    let extractor_num = std::env::args().nth(2).expect("Extractor argument missing");
+/// A chapter on the novel's page, as `list-chapters --json` prints it
+#[derive(Serialize)]
+struct ListedChapter<'a> {
+    number: usize,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+}
 
-    if extractor_num == "1" {
-        run(BoxnExtractor::new(site.as_str()), site.as_str()).await
-    } else if extractor_num == "2" {
-        run(RwnExtractor::new(site.as_str()), site.as_str()).await
-    } else {
-        panic!("No extractor exists")
-    }
-}
-
-async fn run(
-    extractor: impl Extractor + Send + Sync + Clone + 'static,
-    site: &str,
-) -> Result<(), Box<dyn std::error::Error + 'static>> {
-    let http_client = reqwest::Client::builder().user_agent(USER_AGENT).build()?;
-    let home_html = http_client.get(site).send().await?.text().await?;
-    let overview = extractor.extract_overview(&home_html);
-
-    let download_tasks = stream::iter(overview.download_urls.iter().map(|url| {
-        let http_client = http_client.clone();
-        let url = url.clone();
-        let extractor = extractor.clone();
-        tokio::spawn(async move {
-            println!("Downloading {}", url);
-            let chapter_html = http_client
-                .get(&url)
-                .send()
-                .await
-                .unwrap()
-                .text()
-                .await
-                .unwrap();
-            let mut chapter = extractor.extract_chapter(&chapter_html);
-            chapter.content = sanitize_html(chapter.content).await;
-            future::ready(chapter).await
+/// Prints every chapter found on the novel's page with its number, the one
+/// `--chapters` picks it by, and its title when the chapter list has it
+async fn list_chapters(args: &cli::Args, json: bool) -> Result<()> {
+    progress::set_quiet(args.quiet || json);
+    init_logging(args)?;
+    let (options, site) = book_options(args, &args.sites[0])?;
+    let overview = book::fetch_overview(&site, &options).await?;
+    let chapters: Vec<ListedChapter> = overview
+        .download_urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| ListedChapter {
+            number: i + 1,
+            url,
+            title: overview.chapter_titles.get(url).map(String::as_str),
         })
-    }))
-    .buffered(std::cmp::min(MAX_PARALLEL, num_cpus::get()));
-
-    let mut builder = EpubBuilder::new(ZipLibrary::new()?)?;
-    builder.metadata("author", overview.author)?;
-    builder.metadata("title", overview.title)?;
-    if let Some(image_url) = overview.img_url {
-        let resp = http_client.get(&image_url).send().await?;
-        let mimetype_opt = resp
-            .headers()
-            .get(reqwest::header::CONTENT_TYPE)
-            .map(|v| v.to_str().unwrap().to_owned());
-        if let Some(mimetype) = mimetype_opt {
-            let image_bytes = resp.bytes().await?;
-            if mimetype == "image/png" {
-                builder.add_cover_image("cover.png", image_bytes.as_ref(), mimetype)?;
-            } else if mimetype == "image/jpeg" {
-                builder.add_cover_image("cover.jpg", image_bytes.as_ref(), mimetype)?;
-            } else {
-                println!("Cover photo mimetype not supported: {}", mimetype);
+        .filter(|chapter| match &args.chapters {
+            Some(selection) => selection.contains(chapter.number),
+            None => true,
+        })
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&chapters)?);
+        return Ok(());
+    }
+    for chapter in &chapters {
+        match chapter.title {
+            Some(title) => println!("{:>5}  {}  {}", chapter.number, chapter.url, title),
+            None => println!("{:>5}  {}", chapter.number, chapter.url),
+        }
+    }
+    Ok(())
+}
+
+/// Checks the novel, or every book in the `--library`, for new chapters every
+/// `--every` and adds them to the book, until Ctrl-C. A check that fails is
+/// retried at the next one.
+async fn watch(watch: cli::Watch, mut args: cli::Args) -> Result<()> {
+    progress::set_quiet(args.quiet);
+    init_logging(&args)?;
+    if args.format != OutputFormat::Epub {
+        return Err("Only EPUBs can be kept up to date".into());
+    }
+    loop {
+        // The library is read again every time, for books added since
+        let books = match (&watch.book, &args.library) {
+            (Some(book), _) => vec![(book.clone(), args.sites[0].clone())],
+            (None, Some(dir)) => match LibraryIndex::load(dir) {
+                Ok(index) => index
+                    .books
+                    .into_iter()
+                    .map(|book| (dir.join(&book.path), book.site))
+                    .collect(),
+                Err(err) => {
+                    progress::warn(err.to_string());
+                    Vec::new()
+                }
+            },
+            (None, None) => return Err("There's no book to keep up to date".into()),
+        };
+        for (book, site) in &books {
+            if watch.book.is_none() {
+                progress::info(format!("Checking {}", book.display()));
             }
+            args.output = Some(book.clone());
+            match add_new_chapters(&mut args, book, site).await {
+                Ok(Some(new_chapters)) => {
+                    if let Some(command) = &watch.exec {
+                        if let Err(err) = run_hook(command, book, new_chapters).await {
+                            progress::warn(err.to_string());
+                        }
+                    }
+                }
+                Ok(None) => progress::info("No new chapters"),
+                Err(err) => progress::warn(format!("Couldn't check for new chapters: {}", err)),
+            }
+        }
+        progress::info(format!("Checking again in {}", watch.every));
+        tokio::select! {
+            _ = tokio::time::delay_for(watch.every.duration()) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+/// Makes the book if it isn't there yet or adds the chapters that came out
+/// since to it, returns how many were added. `None` when there weren't any.
+async fn add_new_chapters(args: &mut cli::Args, book: &Path, site: &str) -> Result<Option<usize>> {
+    let new_chapters = if book.exists() {
+        let existing = ExistingEpub::open(book)?;
+        let (options, url) = book_options(args, site)?;
+        let overview = book::fetch_overview(&url, &options).await?;
+        let new_chapters = (1..)
+            .zip(&overview.download_urls)
+            .filter(|&(number, url)| !existing.contains(number, url))
+            .count();
+        if new_chapters == 0 {
+            return Ok(None);
+        }
+        progress::info(format!("Found {} new chapters", new_chapters));
+        args.update = Some(book.to_path_buf());
+        new_chapters
+    } else {
+        args.update = None;
+        0
+    };
+    if run(args, site).await? {
+        return Err("Interrupted".into());
+    }
+    match new_chapters {
+        0 => Ok(Some(ExistingEpub::open(book)?.len())),
+        _ => Ok(Some(new_chapters)),
+    }
+}
+
+/// Downloads the chapters of the book at `path` again and prints the ones
+/// that changed, going by the manifest written next to it
+async fn verify(path: &Path, args: &cli::Args) -> Result<()> {
+    progress::set_quiet(args.quiet);
+    init_logging(args)?;
+    let manifest = Manifest::load(manifest_path(path))?;
+    let input = args.sites.first().unwrap_or(&manifest.site);
+    let (mut options, site) = book_options(args, input)?;
+    if args.sites.is_empty() {
+        options.more_sources = manifest.more_sources.clone();
+    }
+    // The cache has the chapters as they were
+    options.cache = false;
+    options.finish_on_interrupt = false;
+    // Only the book's chapters are downloaded, new ones are for `update`
+    if options.chapters.is_none() {
+        let overview = book::fetch_overview(&site, &options).await?;
+        let numbers: Vec<usize> = overview
+            .download_urls
+            .iter()
+            .enumerate()
+            .filter(|(_, url)| manifest.chapters.iter().any(|chapter| &chapter.url == *url))
+            .map(|(i, _)| i + 1)
+            .collect();
+        if numbers.is_empty() {
+            return Err("None of the book's chapters are on the site anymore".into());
         }
+        options.chapters = Some(ChapterSelection::numbers(numbers));
+    }
+    let book = Book::from_url(&site, &options).await?;
+    for err in &book.skipped {
+        progress::warn(format!("Couldn't check {}", err));
     }
 
-    builder.inline_toc();
+    let current = Manifest::for_book(&site, &book)?;
+    let changes = manifest.changes(&current, &book.overview.download_urls);
+    if changes.is_empty() {
+        progress::info(format!(
+            "None of the {} chapters changed",
+            current.chapters.len()
+        ));
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    Err(format!("{} of the book's chapters changed", changes.len()).into())
+}
 
-    download_tasks
-        .enumerate()
-        .for_each(|(i, task)| {
-            let chapter = task.unwrap();
-            let content = {
-                if i == 0 {
-                    EpubContent::new(&format!("c{}.xhtml", i), chapter.content.as_bytes())
-                        .title(chapter.title)
-                        // First chapter requires reftype to be set
-                        .reftype(ReferenceType::Text)
-                } else {
-                    EpubContent::new(&format!("c{}.xhtml", i), chapter.content.as_bytes())
-                        .title(chapter.title)
-                }
+async fn serve(dir: PathBuf, address: SocketAddr) -> Result<()> {
+    if !dir.is_dir() {
+        return Err(format!("{} isn't a directory", dir.display()).into());
+    }
+    progress::info(format!(
+        "Serving the books in {} at http://{}/opds, Ctrl-C to stop",
+        dir.display(),
+        address
+    ));
+    Ok(opds::serve(Library::new(dir), address).await?)
+}
+
+fn cache(command: cli::CacheCommand) -> Result<()> {
+    let dir = user_cache_dir().ok_or("There's no cache directory on this system")?;
+    match command {
+        cli::CacheCommand::Path => println!("{}", dir.display()),
+        cli::CacheCommand::Clear => {
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+            progress::info(format!("Cleared {}", dir.display()));
+        }
+        cli::CacheCommand::Ls => {
+            for novel in cache::cached_novels(&dir)? {
+                let name = match &novel.site {
+                    Some(site) => site.clone(),
+                    None => format!("unknown novel in {}", novel.dir.display()),
+                };
+                let cached = novel
+                    .modified
+                    .map(|modified| {
+                        chrono::DateTime::<chrono::Local>::from(modified)
+                            .format(", last on %Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_default();
+                println!(
+                    "{}: {} chapters, {}{}",
+                    name,
+                    novel.chapters,
+                    HumanBytes(novel.size),
+                    cached
+                );
+            }
+        }
+        cli::CacheCommand::Size => {
+            let total = cache::dir_size(&dir)?;
+            let http = cache::dir_size(&dir.join("http"))?;
+            let checkpoints = cache::dir_size(&dir.join("checkpoints"))?;
+            println!("Chapters     {}", HumanBytes(total - http - checkpoints));
+            println!("Pages        {}", HumanBytes(http));
+            println!("Checkpoints  {}", HumanBytes(checkpoints));
+            println!("Total        {}", HumanBytes(total));
+        }
+        cli::CacheCommand::Prune(older_than) => {
+            let cutoff = SystemTime::now()
+                .checked_sub(older_than.duration())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let pruned = cache::prune(&dir, cutoff)?;
+            progress::info(format!(
+                "Removed {} files ({}) cached more than {} ago",
+                pruned.files,
+                HumanBytes(pruned.bytes),
+                older_than
+            ));
+        }
+        cli::CacheCommand::Purge(url) => {
+            let removed = cache::purge(&dir, &url)?;
+            if removed.is_empty() {
+                progress::info(format!("Nothing is cached for {}", url));
+            }
+            for path in removed {
+                progress::info(format!("Removed {}", path.display()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Makes a book for every novel, `--jobs` of them at a time. One that fails
+/// doesn't stop the others.
+async fn run_batch(args: &cli::Args, sites: &[String]) -> Result<()> {
+    let mut failed = Vec::new();
+    let count = sites.len();
+    let mut sites = sites.iter().enumerate();
+    let mut running = FuturesUnordered::new();
+    let mut interrupted = false;
+    loop {
+        // Books being made when it's interrupted are finished with what they
+        // downloaded so far, only new ones aren't started
+        while running.len() < args.jobs && !interrupted {
+            let (i, site) = match sites.next() {
+                Some(next) => next,
+                None => break,
             };
+            progress::info(format!("[{}/{}] {}", i + 1, count, site));
+            running.push(run(args, site).map(move |result| (site, result)));
+        }
+        let (site, result) = match running.next().await {
+            Some(done) => done,
+            None => break,
+        };
+        match result {
+            Ok(false) => {}
+            Ok(true) => {
+                if !interrupted {
+                    progress::warn("Interrupted, not starting the rest of the batch");
+                }
+                interrupted = true;
+            }
+            Err(err) => {
+                progress::warn(format!("Couldn't make a book from {}: {}", site, err));
+                failed.push(site);
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        return Ok(());
+    }
+    progress::warn(format!("{} of {} novels failed:", failed.len(), count));
+    for site in &failed {
+        progress::warn(format!("  {}", site));
+    }
+    Err(format!("{} novels failed", failed.len()).into())
+}
+
+/// Sets up debugging output for `--verbose` and `--log-file`, nothing is logged
+/// without them
+fn init_logging(args: &cli::Args) -> Result<()> {
+    if args.verbose == 0 && args.log_file.is_none() {
+        return Ok(());
+    }
+    let filter = match args.verbose {
+        0 | 1 => "box2epub=debug",
+        _ => "trace",
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(filter));
+    match &args.log_file {
+        Some(path) => {
+            let file = File::create(path)?;
+            builder
+                .with_ansi(false)
+                .with_writer(move || file.try_clone().expect("Couldn't write to the log file"))
+                .init();
+        }
+        None => builder.with_writer(progress::LogWriter::default).init(),
+    }
+    Ok(())
+}
+
+/// Makes the book for the novel at `input`, returns whether downloading it was
+/// interrupted with Ctrl-C
+async fn run(args: &cli::Args, input: &str) -> Result<bool> {
+    let (mut options, site) = book_options(args, input)?;
+    let site = site.as_str();
+    if args.library.is_some() && args.format != OutputFormat::Epub {
+        return Err("Only EPUBs can be kept in a library".into());
+    }
+    if args.library.is_some() && (args.split_chapters || args.split_every.is_some()) {
+        return Err("Books split into several files can't be kept in a library".into());
+    }
+    // A book the library already has is updated where it is
+    let in_library = match &args.library {
+        Some(dir) if args.update.is_none() => LibraryIndex::load(dir)?
+            .find(site)
+            .map(|book| (dir.join(&book.path), book.more_sources.clone()))
+            .filter(|(path, _)| path.exists()),
+        _ => None,
+    };
+    if args.merge {
+        options.more_sources = more_sources(&args.sites[1..])?;
+    } else if let Some((_, more_sources)) = &in_library {
+        options.more_sources = more_sources.clone();
+    }
+    let update = args.update.clone().or(in_library.map(|(path, _)| path));
+    // Kept until the book is written, so a build that crashed or was stopped can
+    // be picked up with --resume
+    let checkpoint = checkpoint_dir(site);
+    if args.resume && checkpoint.is_none() {
+        return Err("There's no cache directory on this system to keep checkpoints in".into());
+    }
+    options.checkpoint = checkpoint.clone();
+    options.resume = args.resume;
+    if update.is_some() && args.split_every.is_some() {
+        return Err("Books split with --split-every can't be updated".into());
+    }
+    if let Some(path) = &update {
+        options.update = Some(Arc::new(ExistingEpub::open(path)?));
+    }
+
+    if args.interactive {
+        let overview = book::fetch_overview(site, &options).await?;
+        let screen_options = options.clone();
+        let choices =
+            task::spawn_blocking(move || interactive::choose(&overview, &screen_options)).await??;
+        let choices = match choices {
+            Some(choices) => choices,
+            None => {
+                progress::info("Quit without making the book");
+                return Ok(false);
+            }
+        };
+        options.title = Some(choices.title);
+        options.author = Some(choices.author);
+        options.cover = choices.cover;
+        options.chapters = Some(ChapterSelection::numbers(choices.chapters));
+    }
+
+    if args.dry_run {
+        let overview = book::fetch_overview(site, &options).await?;
+        if args.format == OutputFormat::Ndjson {
+            println!("{}", serde_json::to_string(&overview)?);
+        } else if args.format == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&overview)?);
+        } else {
+            print_overview(&overview, args);
+        }
+        return Ok(false);
+    }
+
+    let delivery = delivery(args)?;
+    if delivery.is_some() && args.split_chapters {
+        return Err("Books written a file per chapter can't be sent".into());
+    }
+    if args.add_to_calibre.is_some() && (args.split_chapters || args.format.is_json()) {
+        return Err(format!(
+            "Only whole books can be added to calibre, not {}",
+            if args.split_chapters {
+                "a file per chapter"
+            } else {
+                "JSON"
+            }
+        )
+        .into());
+    }
+
+    let mut epub_options = EpubOptions::default();
+    if let Some(path) = &args.css {
+        epub_options.stylesheet = std::fs::read_to_string(path)?;
+    }
+    epub_options.font = args.font.clone();
+    epub_options.title_page = !args.no_title_page;
+    epub_options.source = Some(site.to_string());
+    epub_options.synopsis = args.synopsis;
+    epub_options.inline_toc = if args.no_inline_toc {
+        None
+    } else {
+        Some(args.toc_position)
+    };
+    epub_options.version = args.epub_version;
+    epub_options.dark_mode = args.dark_mode;
+
+    // Held until the book is written, so another build of the novel can't
+    // start over in the checkpoint this one is using
+    let _checkpoint_lock = checkpoint.as_deref().map(Checkpoint::lock).transpose()?;
+    let mut book = Book::from_url(site, &options).await?;
+    if let Some(lang) = &args.lang {
+        book.overview.language = Some(lang.clone());
+    } else if book.overview.language.is_none() {
+        match book.detect_language() {
+            Some(lang) => {
+                progress::info(format!("Detected the language as {}", lang));
+                book.overview.language = Some(lang.to_string());
+            }
+            None => {
+                progress::warn("Couldn't tell which language the book is in, use --lang to set it")
+            }
+        }
+    }
 
-            builder.add_content(content).unwrap();
+    // Japanese books are read vertically
+    epub_options.vertical = args.vertical.unwrap_or_else(
+        || matches!(&book.overview.language, Some(lang) if lang == "ja" || lang.starts_with("ja-")),
+    );
 
-            future::ready(())
+    if !book.skipped.is_empty() {
+        progress::warn(match args.on_error {
+            OnError::Placeholder => format!(
+                "{} chapters couldn't be downloaded and have a placeholder instead:",
+                book.skipped.len()
+            ),
+            _ => format!("Skipped {} chapters:", book.skipped.len()),
+        });
+        for err in &book.skipped {
+            progress::warn(format!("  {}", err));
+        }
+    }
+
+    if !book.suspicious.is_empty() {
+        progress::warn(format!(
+            "{} chapters have less or more text than expected, check them in the book \
+             (--min-chapter-size and --max-chapter-size change what's expected):",
+            book.suspicious.len()
+        ));
+        for chapter in &book.suspicious {
+            progress::warn(format!("  {}", chapter));
+        }
+    }
+
+    let duplicates = find_duplicates(&book.chapters, DEFAULT_SIMILARITY)?;
+    if !duplicates.is_empty() {
+        progress::warn(if args.drop_duplicates {
+            format!(
+                "Left out {} chapters that are copies of earlier ones:",
+                duplicates.len()
+            )
+        } else {
+            format!(
+                "{} chapters are copies of earlier ones, --drop-duplicates leaves them out:",
+                duplicates.len()
+            )
+        });
+        for duplicate in &duplicates {
+            progress::warn(format!("  {}", duplicate));
+        }
+        if args.drop_duplicates {
+            let numbers: HashSet<usize> = duplicates
+                .iter()
+                .map(|duplicate| duplicate.number)
+                .collect();
+            book.chapters
+                .retain(|chapter| !numbers.contains(&chapter.number));
+        }
+    }
+
+    if book.interrupted {
+        progress::warn(format!(
+            "Writing a partial book with the {} chapters downloaded so far",
+            book.chapters.len()
+        ));
+        book.overview.title = format!("{} (partial)", book.overview.title);
+    }
+
+    // A batch writes every book into the output directory under its own name
+    let batch_dir = if args.sites.len() > 1 && !args.merge {
+        args.output.as_deref()
+    } else {
+        None
+    };
+    // Named after the novel's title, or the way --name-template says
+    let name = match &args.name_template {
+        Some(template) => template.render(&book, site),
+        None => match naming::file_stem(&book.overview.title) {
+            stem if stem.is_empty() => PathBuf::from("output"),
+            stem => PathBuf::from(stem),
+        },
+    };
+    let default_path = match (&args.library, batch_dir) {
+        (Some(library), _) => library::book_dir(library, &book.overview.title).join(&name),
+        (None, Some(dir)) => dir.join(&name),
+        (None, None) => name,
+    };
+
+    if args.split_chapters {
+        let dir = match batch_dir {
+            Some(_) => None,
+            None => args.output.clone(),
+        }
+        .unwrap_or(default_path);
+        format::write_chapter_files(&book, args.format, &dir)?;
+        progress::info(format!("Wrote chapters to {}", dir.display()));
+        if !book.interrupted {
+            remove_checkpoint(checkpoint.as_deref())?;
+        }
+        return Ok(book.interrupted);
+    }
+
+    if args.format.builds_epub() {
+        let problems = validate_book(&book, &epub_options);
+        if !problems.is_empty() {
+            progress::warn(format!("Found {} problems in the book:", problems.len()));
+            for problem in &problems {
+                progress::warn(format!("  {}", problem));
+            }
+            if args.strict {
+                return Err("Not writing a book that doesn't pass validation (--strict)".into());
+            }
+        }
+    }
+
+    let output = match batch_dir {
+        Some(_) => None,
+        None => args.output.clone(),
+    }
+    .or_else(|| match args.format {
+        OutputFormat::Epub => update.clone(),
+        _ => None,
+    })
+    .unwrap_or_else(|| {
+        // Not `set_extension`, which would cut a title like "Vol. 2" short
+        let mut path = default_path.into_os_string();
+        path.push(format!(".{}", args.format.extension()));
+        PathBuf::from(path)
+    });
+    if let Some(parent) = output.parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+
+    let interrupted = book.interrupted;
+    let series = book.overview.title.clone();
+    let volumes = match args.split_every {
+        Some(chapters_per_volume) => book.into_volumes(chapters_per_volume),
+        None => vec![book],
+    };
+    let volume_count = volumes.len();
+    for (i, volume) in volumes.into_iter().enumerate() {
+        let output = if volume_count > 1 {
+            volume_path(&output, i + 1)
+        } else {
+            output.clone()
+        };
+        // Written next to the output first so a failed build doesn't clobber the
+        // book being updated
+        let mut partial_output = output.clone().into_os_string();
+        partial_output.push(".part");
+        // Compressing a big book takes a while, it's kept off the threads
+        // downloading the other books of a batch
+        let write_format = args.format;
+        let write_options = epub_options.clone();
+        let write_path = PathBuf::from(&partial_output);
+        let volume = task::spawn_blocking(move || {
+            format::write_book(&volume, write_format, &write_options, &write_path).map(|_| volume)
         })
-        .await;
+        .await??;
+        std::fs::rename(&partial_output, &output)?;
+        let mut manifest = Manifest::for_book(site, &volume)?;
+        manifest.more_sources = options.more_sources.clone();
+        manifest.save(manifest_path(&output))?;
+        progress::info(format!("Wrote {}", output.display()));
+        if let Some(dir) = &args.library {
+            let mut index = LibraryIndex::load(dir)?;
+            let mut entry = LibraryBook::new(dir, site, &volume, &output);
+            entry.more_sources = options.more_sources.clone();
+            index.record(entry);
+            index.save(dir)?;
+        }
+        if let Some(delivery) = &delivery {
+            delivery
+                .send(&output, args.format.mimetype(), &volume.overview.title)
+                .await
+                .map_err(|err| format!("Couldn't send {}: {}", output.display(), err))?;
+            progress::info(format!("Sent {} to {}", output.display(), delivery.to));
+        }
+        if let Some(library) = &args.add_to_calibre {
+            let position = Some(SeriesPosition {
+                name: &series,
+                index: i + 1,
+            })
+            .filter(|_| volume_count > 1);
+            library
+                .add(&output, &volume.overview, site, position)
+                .map_err(|err| format!("Couldn't add {} to calibre: {}", output.display(), err))?;
+            progress::info(format!("Added {} to calibre", output.display()));
+        }
+    }
 
-    let epub_file = std::fs::File::create("output.epub")?;
-    builder.generate(epub_file)?;
+    if !interrupted {
+        remove_checkpoint(checkpoint.as_deref())?;
+    }
+    Ok(interrupted)
+}
 
+/// The novel's pages on the sites after the first one for `--merge`, only the
+/// first can be a copy on disk
+fn more_sources(inputs: &[String]) -> Result<Vec<String>> {
+    inputs
+        .iter()
+        .map(|input| {
+            if input.starts_with("file:") || !input.contains("://") {
+                Err(format!(
+                    "Only the first novel of --merge can be read from disk, not {}",
+                    input
+                )
+                .into())
+            } else {
+                Ok(book::normalize_site(input))
+            }
+        })
+        .collect()
+}
+
+/// Throws away the checkpoint of a book that's been written, `--resume` has
+/// nothing left to do
+fn remove_checkpoint(dir: Option<&Path>) -> Result<()> {
+    if let Some(dir) = dir {
+        Checkpoint::remove(dir)?;
+    }
     Ok(())
 }
+
+/// Where to email the book for `--send-to`, an address (with `--smtp`) or
+/// the name of a profile in the delivery config file
+fn delivery(args: &cli::Args) -> Result<Option<Delivery>> {
+    let to = match &args.send_to {
+        Some(to) => to,
+        None => return Ok(None),
+    };
+    if to.contains('@') {
+        let smtp = args
+            .smtp
+            .clone()
+            .ok_or("--smtp has to be set to send to an address")?;
+        return Ok(Some(Delivery::new(to, args.from.as_deref(), smtp)?));
+    }
+    let config = delivery_config_path().ok_or("There's no config directory on this system")?;
+    Ok(Some(Delivery::from_profile(config, to)?))
+}
+
+/// The headless Chromium for `--render-js`
+#[cfg(feature = "browser")]
+fn renderer(chrome: Option<PathBuf>, user_agent: Option<String>) -> Result<Arc<dyn Fetcher>> {
+    Ok(Arc::new(
+        BrowserFetcher::new().chrome(chrome).user_agent(user_agent),
+    ))
+}
+
+/// The headless Chromium for `--render-js`
+#[cfg(not(feature = "browser"))]
+fn renderer(_: Option<PathBuf>, _: Option<String>) -> Result<Arc<dyn Fetcher>> {
+    Err("--render-js needs box2epub built with the browser feature (cargo build --features browser)".into())
+}
+
+/// Options for downloading the novel at `input` (a url, or a copy of the site on
+/// disk) and the url of its page
+fn book_options(args: &cli::Args, input: &str) -> Result<(BookOptions, String)> {
+    let mut options = BookOptions::default();
+    // Paths and file: urls are a copy of the site on disk, i.e. from wget
+    let local = if input.starts_with("file:") {
+        Some(LocalMirror::for_file_url(input)?)
+    } else if !input.contains("://") && Path::new(input).exists() {
+        Some(LocalMirror::locate(Path::new(input))?)
+    } else {
+        None
+    };
+    let site = match local {
+        Some((mirror, url)) => {
+            progress::info(format!("Reading {} from {}", url, input));
+            options.mirror = Some(mirror);
+            url
+        }
+        None => book::normalize_site(input),
+    };
+    if let Some(path) = &args.site_config {
+        progress::info(format!("Using the extractor from {}", path.display()));
+        options.extractor = Some(Arc::new(ConfigExtractor::from_file(path, &site)?));
+    }
+    options.site_type = args.site_type;
+    options.plugin_dir = args.plugin_dir.clone().or_else(plugins_dir);
+    options.script_dir = args.script_dir.clone().or_else(scripts_dir);
+    let mut retry_policy = RetryPolicy::default();
+    if let Some(retries) = args.retries {
+        retry_policy.attempts = retries + 1;
+    }
+    options.retry_policy = retry_policy;
+    if let Some(seconds) = args.connect_timeout {
+        options.connect_timeout = Duration::from_secs(seconds);
+    }
+    if let Some(seconds) = args.read_timeout {
+        options.read_timeout = Duration::from_secs(seconds);
+    }
+    if let Some(concurrency) = args.concurrency {
+        options.concurrency = concurrency;
+    }
+    if let Some(concurrency) = args.process_concurrency {
+        options.process_concurrency = concurrency;
+    }
+    if let Some(per_host) = args.per_host {
+        options.per_host_limit = per_host;
+    }
+    options.rate_limit = args.rate;
+    options.max_bandwidth = args.max_bandwidth;
+    options.respect_robots = !args.ignore_robots;
+    let mut cookies = match &args.cookie_file {
+        Some(path) => CookieJar::from_netscape_file(path)?,
+        None => CookieJar::default(),
+    };
+    for cookie in &args.cookies {
+        cookies.add_header(&site, cookie)?;
+    }
+    options.cookies = cookies;
+    if let Some(user_agent) = &args.user_agent {
+        options.user_agent = user_agent.clone();
+    }
+    options.proxy = args.proxy.clone();
+    options.cache = !args.no_cache;
+    if let Some(path) = &args.archive {
+        let archive = ChapterArchive::open(path)
+            .map_err(|err| format!("Couldn't open the archive {}: {}", path.display(), err))?;
+        options.archive = Some(archive);
+    }
+    if args.render_js {
+        options.renderer = Some(renderer(args.chrome.clone(), args.user_agent.clone())?);
+    }
+    options.enrich = !args.no_enrich;
+    options.generate_cover = !args.no_generated_cover;
+    let metadata = match &args.metadata {
+        Some(path) => Metadata::from_file(path)?,
+        None => Metadata::default(),
+    };
+    options.title = args.title.clone().or(metadata.title);
+    options.author = args.author.clone().or(metadata.author);
+    options.cover = args.cover.clone().or(metadata.cover);
+    options.chapter_order = args.sort_by;
+    options.chapters = args.chapters.clone();
+    options.on_error = args.on_error;
+    if let Some(min) = args.min_chapter_size {
+        options.content_size.min = min;
+    }
+    if let Some(max) = args.max_chapter_size {
+        options.content_size.max = max;
+    }
+    options.images.max_size = args.max_image_size.map(NonZeroU32::get);
+    options.images.quality = args.image_quality;
+    options.strip = args.strip.clone();
+    // First, so the others see paragraphs where the site had divs and breaks
+    if let Some(paragraphs) = args.paragraphs {
+        options.transforms.push(Arc::new(paragraphs));
+    }
+    // Typography is fixed first so the file's replacements see the repaired text
+    if args.fix_typography {
+        options.transforms.push(Arc::new(FixTypography));
+    }
+    if let Some(path) = &args.transforms {
+        options.transforms.extend(transform::from_file(path)?);
+    }
+    // After the file's replacements, which can turn a translator's own style
+    // of notes into "[TN: ...]"
+    if args.footnotes {
+        options.transforms.push(Arc::new(Footnotes));
+    }
+    if args.strip_colors {
+        options.transforms.push(Arc::new(StripColors));
+    }
+    options.allowlist = !args.no_allowlist;
+    // Last, so whitespace the others leave behind goes too
+    if args.minify {
+        options.transforms.push(Arc::new(Minify));
+    }
+    if let Some(path) = &args.template {
+        options.template = Some(ChapterTemplate::from_file(path)?);
+    }
+    options.title_from = args.title_from;
+    options.finish_on_interrupt = true;
+    Ok((options, site))
+}
+
+/// `book.epub` becomes `book-vol2.epub` for the second volume
+fn volume_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map_or_else(|| "output".into(), |stem| stem.to_string_lossy());
+    let name = match path.extension() {
+        Some(extension) => format!("{}-vol{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}-vol{}", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+/// Prints what `--dry-run` found, with the first and last few chapters to check
+/// their order
+fn print_overview(overview: &Overview, args: &cli::Args) {
+    const SHOWN_CHAPTERS: usize = 5;
+
+    println!("Title:    {}", overview.title);
+    println!("Author:   {}", overview.author);
+    if !overview.alternate_titles.is_empty() {
+        println!("Also:     {}", overview.alternate_titles.join(", "));
+    }
+    println!(
+        "Cover:    {}",
+        overview.img_url.as_deref().unwrap_or("none found")
+    );
+    if !overview.genres.is_empty() {
+        println!("Genres:   {}", overview.genres.join(", "));
+    }
+    if let Some(language) = &overview.language {
+        println!("Language: {}", language);
+    }
+    if let Some(status) = &overview.status {
+        println!("Status:   {}", status);
+    }
+    if let Some(description) = &overview.description {
+        println!("\n{}", description);
+    }
+
+    let chapters: Vec<(usize, &String)> = overview
+        .download_urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| (i + 1, url))
+        .filter(|(number, _)| match &args.chapters {
+            Some(selection) => selection.contains(*number),
+            None => true,
+        })
+        .collect();
+    println!(
+        "\n{} chapters found, {} selected",
+        overview.download_urls.len(),
+        chapters.len()
+    );
+    for (i, (number, url)) in chapters.iter().enumerate() {
+        if i == SHOWN_CHAPTERS && chapters.len() > SHOWN_CHAPTERS * 2 {
+            println!("  ...");
+        }
+        if i < SHOWN_CHAPTERS || i >= chapters.len().saturating_sub(SHOWN_CHAPTERS) {
+            println!("  {:>5}  {}", number, url);
+        }
+    }
+}
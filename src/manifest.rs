@@ -0,0 +1,125 @@
+use crate::book::Book;
+use crate::cache::hash;
+use crate::text::{xhtml_to_text, TextStyle};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Hashes of every chapter in a book, kept next to it so a later `verify` can
+/// tell which chapters the site changed since
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The novel's page the book was made from
+    pub site: String,
+    /// Pages on other sites with the rest of the novel, see
+    /// `BookOptions::more_sources`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub more_sources: Vec<String>,
+    pub chapters: Vec<ManifestChapter>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestChapter {
+    pub number: usize,
+    pub url: String,
+    pub title: String,
+    /// SHA-256 of the chapter's text. Only the text counts, so cleaning up
+    /// the markup differently in a later version doesn't look like an edit.
+    pub sha256: String,
+}
+
+/// How a chapter in a manifest differs from the site now
+#[derive(Debug, Clone)]
+pub enum ChapterChange {
+    Changed(ManifestChapter),
+    Removed(ManifestChapter),
+}
+
+impl fmt::Display for ChapterChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (change, chapter) = match self {
+            ChapterChange::Changed(chapter) => ("changed", chapter),
+            ChapterChange::Removed(chapter) => ("removed", chapter),
+        };
+        write!(
+            f,
+            "{:<8} {:>5}  {} ({})",
+            change, chapter.number, chapter.title, chapter.url
+        )
+    }
+}
+
+impl Manifest {
+    pub fn for_book(site: &str, book: &Book) -> io::Result<Self> {
+        let chapters = book
+            .chapters
+            .iter()
+            .map(|chapter| {
+                let text = xhtml_to_text(&chapter.content()?, TextStyle::Plain);
+                Ok(ManifestChapter {
+                    number: chapter.number,
+                    url: chapter.url.clone(),
+                    title: chapter.title.clone(),
+                    sha256: hash(&text),
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Manifest {
+            site: site.to_string(),
+            more_sources: Vec::new(),
+            chapters,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|err| format!("Couldn't open the manifest {}: {}", path.display(), err))?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Chapters of this manifest that are different in `current` or that the
+    /// site doesn't list anymore (they're missing from `listed`). Chapters are
+    /// matched by their url since their numbers shift when the site inserts
+    /// one, ones that are in neither (i.e. they failed to download) are left
+    /// out.
+    pub fn changes(&self, current: &Manifest, listed: &[String]) -> Vec<ChapterChange> {
+        let current: HashMap<&str, &ManifestChapter> = current
+            .chapters
+            .iter()
+            .map(|chapter| (chapter.url.as_str(), chapter))
+            .collect();
+        self.chapters
+            .iter()
+            .filter_map(|chapter| match current.get(chapter.url.as_str()) {
+                Some(current) if current.sha256 != chapter.sha256 => {
+                    Some(ChapterChange::Changed((*current).clone()))
+                }
+                Some(_) => None,
+                None if !listed.contains(&chapter.url) => {
+                    Some(ChapterChange::Removed(chapter.clone()))
+                }
+                None => None,
+            })
+            .collect()
+    }
+}
+
+/// Where the manifest for the book at `path` goes, `book.epub` has
+/// `book.epub.manifest.json`
+pub fn manifest_path(path: &Path) -> PathBuf {
+    let mut manifest = OsString::from(path.as_os_str());
+    manifest.push(".manifest.json");
+    PathBuf::from(manifest)
+}
@@ -0,0 +1,55 @@
+use crate::book::{is_http_url, CoverSource};
+use serde::Deserialize;
+use std::error::Error;
+use std::path::Path;
+
+/// Title, author and cover to use instead of what the extractor finds, for
+/// sites that show a placeholder like "Updating". `--metadata` reads them from
+/// a TOML file, `--title`, `--author` and `--cover` go over what's in it:
+///
+/// ```toml
+/// title = "The Novel's Title"
+/// author = "Its Author"
+/// # A file (next to this one when it's relative) or an http(s) url, or
+/// # generated or none
+/// cover = "cover.jpg"
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub cover: Option<CoverSource>,
+}
+
+/// `Metadata` as written in the file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MetadataConfig {
+    title: Option<String>,
+    author: Option<String>,
+    cover: Option<String>,
+}
+
+impl Metadata {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)?;
+        let config: MetadataConfig = toml::from_str(&config)
+            .map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+        let cover = match config.cover {
+            Some(cover) => match cover.parse()? {
+                CoverSource::Image(source) if !is_http_url(&source) => {
+                    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                    Some(CoverSource::Image(dir.join(source).display().to_string()))
+                }
+                cover => Some(cover),
+            },
+            None => None,
+        };
+        Ok(Metadata {
+            title: config.title,
+            author: config.author,
+            cover,
+        })
+    }
+}
@@ -0,0 +1,127 @@
+use crate::transform::ContentTransform;
+use ego_tree::{NodeId, NodeRef};
+use html5ever::tendril::StrTendril;
+use regex::Regex;
+use scraper::{Html, Node};
+
+lazy_static! {
+    // Only what html counts as whitespace, a no-break space is text
+    static ref WHITESPACE_REGEX: Regex = Regex::new("[ \t\n\r\x0C]+").unwrap();
+}
+
+/// Elements whose whitespace is kept as it is
+const PRESERVED_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Elements whitespace next to, or at the start and end of, isn't shown
+const BLOCK_ELEMENTS: [&str; 40] = [
+    "html",
+    "head",
+    "title",
+    "meta",
+    "link",
+    "body",
+    "p",
+    "div",
+    "br",
+    "hr",
+    "pre",
+    "blockquote",
+    "address",
+    "center",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ul",
+    "ol",
+    "li",
+    "dl",
+    "dt",
+    "dd",
+    "table",
+    "caption",
+    "thead",
+    "tbody",
+    "tfoot",
+    "tr",
+    "td",
+    "th",
+    "section",
+    "article",
+    "aside",
+    "nav",
+    "figure",
+    "figcaption",
+];
+
+/// Takes out what's only there to make the markup readable: comments, and
+/// whitespace that readers show as a single space or not at all. Sites that
+/// indent their markup can make a book with thousands of chapters a lot
+/// bigger than its text. Whitespace in `<pre>` is left alone.
+pub struct Minify;
+
+impl ContentTransform for Minify {
+    fn apply(&self, html: &str) -> String {
+        let mut document = Html::parse_document(html);
+        // Text nodes to change, `None` to take them out
+        let mut changes: Vec<(NodeId, Option<String>)> = Vec::new();
+        for node in document.tree.root().descendants() {
+            match node.value() {
+                Node::Comment(_) => changes.push((node.id(), None)),
+                Node::Text(text) if !is_preserved(node) => {
+                    let mut minified = WHITESPACE_REGEX.replace_all(text, " ").into_owned();
+                    if at_block_edge(node, node.prev_sibling()) {
+                        minified = minified.trim_start_matches(' ').to_string();
+                    }
+                    if at_block_edge(node, node.next_sibling()) {
+                        minified = minified.trim_end_matches(' ').to_string();
+                    }
+                    if minified.is_empty() {
+                        changes.push((node.id(), None));
+                    } else if minified != **text {
+                        changes.push((node.id(), Some(minified)));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if changes.is_empty() {
+            return html.to_string();
+        }
+
+        for (id, minified) in changes {
+            if let Some(mut node) = document.tree.get_mut(id) {
+                match minified {
+                    Some(minified) => {
+                        if let Node::Text(text) = node.value() {
+                            text.text = StrTendril::from(minified);
+                        }
+                    }
+                    None => node.detach(),
+                }
+            }
+        }
+        document.root_element().html()
+    }
+}
+
+fn is_preserved(node: NodeRef<Node>) -> bool {
+    node.ancestors().any(|ancestor| {
+        matches!(ancestor.value(), Node::Element(element) if PRESERVED_ELEMENTS.contains(&element.name()))
+    })
+}
+
+/// Whether whitespace between `node` and its `sibling` on one side isn't
+/// shown, because the sibling is a block or there's none and the node is at
+/// the start or end of one
+fn at_block_edge(node: NodeRef<Node>, sibling: Option<NodeRef<Node>>) -> bool {
+    match sibling.or_else(|| node.parent()) {
+        Some(next_to) => matches!(
+            next_to.value(),
+            Node::Element(element) if BLOCK_ELEMENTS.contains(&element.name())
+        ),
+        None => true,
+    }
+}
@@ -0,0 +1,170 @@
+use crate::fetcher::{FetchError, Fetcher, Request, Response};
+
+use async_trait::async_trait;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::{StatusCode, Url};
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A site saved to disk, i.e. with `wget --mirror`, that pages and images are
+/// read from instead of the network. Urls that weren't saved are a 404.
+///
+/// wget saves `https://host/path/` as `<root>/host/path/index.html`, so when a
+/// mirror has a root the site's own urls are looked up under it. `file://`
+/// urls are read as they are.
+#[derive(Clone, Debug)]
+pub struct LocalMirror {
+    root: Option<PathBuf>,
+}
+
+impl LocalMirror {
+    /// Finds the mirror a saved page (or its directory) is in, along with the
+    /// url it was saved from. Pages that aren't laid out like a wget mirror
+    /// are read with a `file://` url.
+    pub fn locate(path: &Path) -> io::Result<(LocalMirror, String)> {
+        let mut path = path.canonicalize()?;
+        // wget saves urls ending in a slash as index.html
+        if path.file_name() == Some(OsStr::new("index.html")) {
+            path.pop();
+        }
+        // The first directory named like a host is where the site starts
+        for ancestor in path.ancestors() {
+            let host = match ancestor.file_name().and_then(|name| name.to_str()) {
+                Some(name) if looks_like_host(name) => name,
+                _ => continue,
+            };
+            let rest = path.strip_prefix(ancestor).unwrap_or(&path);
+            let mut url = format!("https://{}/", host);
+            for component in rest.iter() {
+                url.push_str(&component.to_string_lossy());
+                url.push('/');
+            }
+            if path.is_file() {
+                url.pop();
+            }
+            let root = ancestor.parent().map(Path::to_path_buf);
+            return Ok((LocalMirror { root }, url));
+        }
+
+        let url = if path.is_dir() {
+            Url::from_directory_path(&path)
+        } else {
+            Url::from_file_path(&path)
+        }
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "not an absolute path"))?;
+        Ok((LocalMirror { root: None }, url.into()))
+    }
+
+    /// Mirror for a `file://` url given on the command line
+    pub fn for_file_url(url: &str) -> io::Result<(LocalMirror, String)> {
+        let path = Url::parse(url)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file url"))?;
+        Self::locate(&path)
+    }
+
+    /// Where `url` was saved, if it's in the mirror. Directories stand for
+    /// their index.html.
+    pub fn path_for(&self, url: &str) -> Option<PathBuf> {
+        let url = Url::parse(url).ok()?;
+        let path = match url.scheme() {
+            "file" => url.to_file_path().ok()?,
+            "http" | "https" => {
+                let mut path = self.root.as_ref()?.join(url.host_str()?);
+                for segment in url.path_segments()?.filter(|segment| !segment.is_empty()) {
+                    path.push(percent_decode(segment));
+                }
+                if let Some(query) = url.query() {
+                    let mut name = path.file_name()?.to_os_string();
+                    name.push("?");
+                    name.push(query);
+                    path.set_file_name(name);
+                }
+                path
+            }
+            _ => return None,
+        };
+        let path = if path.is_dir() {
+            path.join("index.html")
+        } else {
+            path
+        };
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+#[async_trait]
+impl Fetcher for LocalMirror {
+    async fn fetch(&self, request: Request) -> Result<Response, FetchError> {
+        let mut headers = HeaderMap::new();
+        let (status, body) = match self.path_for(&request.url) {
+            // Forms can't be sent to a copy on disk
+            Some(_) if request.form.is_some() => (StatusCode::METHOD_NOT_ALLOWED, Vec::new()),
+            Some(path) => {
+                if let Some(content_type) = content_type(&path) {
+                    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+                }
+                (StatusCode::OK, tokio::fs::read(&path).await?)
+            }
+            None => (StatusCode::NOT_FOUND, Vec::new()),
+        };
+        Ok(Response {
+            url: request.url,
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+fn looks_like_host(name: &str) -> bool {
+    name.contains('.')
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == ':')
+}
+
+pub(crate) fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = segment.get(i + 1..i + 3);
+        match (
+            bytes[i],
+            hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+        ) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Guesses the Content-Type from the extension, wget doesn't keep it
+fn content_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let content_type = match extension.as_str() {
+        "html" | "htm" | "xhtml" | "php" => "text/html",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        _ => return None,
+    };
+    Some(content_type)
+}
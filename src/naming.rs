@@ -0,0 +1,117 @@
+use crate::book::Book;
+use reqwest::Url;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Variables a `NameTemplate` can have
+const VARIABLES: [&str; 7] = [
+    "title", "author", "chapters", "site", "status", "language", "date",
+];
+
+/// Name for the files books are written to instead of their title, i.e.
+/// `{title} - {author} [{chapters}ch]`. Characters file names can't have are
+/// replaced in the values, and a `/` in the template itself puts the book in
+/// a directory: `{author}/{title}`.
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Variable(String),
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("Unclosed {{ in the name template {}", template))?;
+            let name = rest[start + 1..end].trim();
+            if !VARIABLES.contains(&name) {
+                return Err(format!(
+                    "Unknown variable {{{}}} in the name template, expected one of {}",
+                    name,
+                    VARIABLES.join(", ")
+                ));
+            }
+            parts.push(Part::Variable(name.to_string()));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        if parts.is_empty() {
+            return Err("The name template is empty".to_string());
+        }
+        Ok(NameTemplate { parts })
+    }
+}
+
+impl NameTemplate {
+    /// The path, without an extension, to write the novel at `site` to.
+    /// It's relative, directories that come out empty are left out.
+    pub fn render(&self, book: &Book, site: &str) -> PathBuf {
+        let overview = &book.overview;
+        let mut name = String::new();
+        for part in &self.parts {
+            let value = match part {
+                Part::Text(text) => {
+                    name.push_str(text);
+                    continue;
+                }
+                Part::Variable(variable) => match variable.as_str() {
+                    "title" => overview.title.clone(),
+                    "author" if overview.author == "no_author" => String::new(),
+                    "author" => overview.author.clone(),
+                    "chapters" => book.chapters.len().to_string(),
+                    "site" => Url::parse(site)
+                        .ok()
+                        .and_then(|url| url.host_str().map(str::to_string))
+                        .unwrap_or_default(),
+                    "status" => overview.status.clone().unwrap_or_default(),
+                    "language" => overview.language.clone().unwrap_or_default(),
+                    "date" => chrono::Local::now().format("%Y-%m-%d").to_string(),
+                    _ => unreachable!("the variables were checked when parsing"),
+                },
+            };
+            // A value can't make directories, only the template can
+            name.push_str(&value.replace('/', "_"));
+        }
+
+        let components: Vec<&str> = name.split('/').collect();
+        let (file, dirs) = components.split_last().unwrap_or((&"", &[]));
+        let mut path: PathBuf = dirs
+            .iter()
+            .map(|dir| file_stem(dir))
+            .filter(|dir| !dir.is_empty())
+            .collect();
+        let file = file_stem(file);
+        path.push(if file.is_empty() { "output" } else { &file });
+        path
+    }
+}
+
+/// Turns a novel title into something safe to use as a file name, without an
+/// extension. It's empty when nothing is left.
+pub fn file_stem(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    name.trim().trim_matches('.').trim().to_string()
+}
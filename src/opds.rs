@@ -0,0 +1,393 @@
+use crate::format::OutputFormat;
+use crate::sanitizer::{decode_entities, escape_attribute, escape_text};
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::{Regex, RegexBuilder};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::debug;
+use zip::ZipArchive;
+
+lazy_static! {
+    static ref DC_ELEMENT_REGEX: Regex =
+        RegexBuilder::new(r"<dc:(title|creator|description|language)\b[^>]*>(.*?)</dc:")
+            .dot_matches_new_line(true)
+            .build()
+            .unwrap();
+}
+
+/// What the catalog is served as
+const FEED_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+
+/// Characters that don't have to be escaped in a path segment of a link
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// A directory of books to serve, looked through again on every request so
+/// books written while serving show up. An EPUB is only opened again to read
+/// its metadata once it's been written to.
+pub struct Library {
+    dir: PathBuf,
+    /// Metadata of the EPUBs by their path, with when they were written when
+    /// it was read
+    metadata: Mutex<HashMap<String, (SystemTime, Metadata)>>,
+}
+
+/// A book in the library, with the files it was written to: `novel.epub` and
+/// `novel.azw3` are the same book
+struct LibraryBook {
+    /// Path of the files without their extension, relative to the library
+    id: String,
+    title: String,
+    author: Option<String>,
+    summary: Option<String>,
+    language: Option<String>,
+    /// When the newest of the files was written
+    updated: SystemTime,
+    files: Vec<LibraryFile>,
+}
+
+struct LibraryFile {
+    /// Relative to the library, with `/` between directories
+    path: String,
+    format: OutputFormat,
+}
+
+/// What the EPUB's package document says about the book
+#[derive(Default, Clone)]
+struct Metadata {
+    title: Option<String>,
+    author: Option<String>,
+    summary: Option<String>,
+    language: Option<String>,
+}
+
+impl Library {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Library {
+            dir: dir.into(),
+            metadata: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Every EPUB, and every book converted from one, in the library or a
+    /// directory in it. The newest come first.
+    fn books(&self) -> io::Result<Vec<LibraryBook>> {
+        let mut files = Vec::new();
+        find_books(&self.dir, "", &mut files)?;
+        // Books that are gone don't need their metadata anymore
+        let paths: HashSet<&str> = files.iter().map(|file| file.path.as_str()).collect();
+        self.metadata
+            .lock()
+            .unwrap()
+            .retain(|path, _| paths.contains(path.as_str()));
+
+        let mut grouped: BTreeMap<String, Vec<LibraryFile>> = BTreeMap::new();
+        for file in files {
+            let id = file.path[..file.path.len() - file.format.extension().len() - 1].to_string();
+            grouped.entry(id).or_default().push(file);
+        }
+
+        let mut books: Vec<LibraryBook> = grouped
+            .into_iter()
+            .map(|(id, files)| {
+                let updated = files
+                    .iter()
+                    .filter_map(|file| self.full_path(&file.path).metadata().ok()?.modified().ok())
+                    .max()
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                let metadata = files
+                    .iter()
+                    .find(|file| file.format == OutputFormat::Epub)
+                    .and_then(|file| self.metadata(&file.path))
+                    .unwrap_or_default();
+                let title = metadata
+                    .title
+                    .unwrap_or_else(|| id.rsplit('/').next().unwrap_or_default().to_string());
+                LibraryBook {
+                    id,
+                    title,
+                    author: metadata.author,
+                    summary: metadata.summary,
+                    language: metadata.language,
+                    updated,
+                    files,
+                }
+            })
+            .collect();
+        books.sort_by_key(|book| Reverse(book.updated));
+        Ok(books)
+    }
+
+    /// The EPUB's metadata, read again only when it was written to since
+    fn metadata(&self, path: &str) -> Option<Metadata> {
+        let full_path = self.full_path(path);
+        let modified = full_path.metadata().and_then(|file| file.modified()).ok()?;
+        if let Some((read, metadata)) = self.metadata.lock().unwrap().get(path) {
+            if *read == modified {
+                return Some(metadata.clone());
+            }
+        }
+        match read_metadata(&full_path) {
+            Ok(metadata) => {
+                self.metadata
+                    .lock()
+                    .unwrap()
+                    .insert(path.to_string(), (modified, metadata.clone()));
+                Some(metadata)
+            }
+            Err(err) => {
+                debug!(book = %path, %err, "couldn't read the metadata");
+                None
+            }
+        }
+    }
+
+    fn full_path(&self, path: &str) -> PathBuf {
+        path.split('/')
+            .fold(self.dir.clone(), |full, component| full.join(component))
+    }
+
+    /// The OPDS acquisition feed listing every book
+    pub fn feed(&self) -> io::Result<String> {
+        let books = self.books()?;
+        let title = self
+            .dir
+            .canonicalize()
+            .ok()
+            .and_then(|dir| {
+                dir.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "box2epub".to_string());
+        let updated = books
+            .iter()
+            .map(|book| book.updated)
+            .max()
+            .unwrap_or_else(SystemTime::now);
+
+        let mut feed = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:dc=\"http://purl.org/dc/terms/\">\n  \
+             <id>urn:box2epub:library</id>\n  \
+             <title>{title}</title>\n  \
+             <updated>{updated}</updated>\n  \
+             <author><name>box2epub</name></author>\n  \
+             <link rel=\"self\" href=\"/opds\" type=\"{feed_type}\"/>\n  \
+             <link rel=\"start\" href=\"/opds\" type=\"{feed_type}\"/>\n",
+            title = escape_text(&title),
+            updated = timestamp(updated),
+            feed_type = FEED_TYPE,
+        );
+        for book in books {
+            feed.push_str(&format!(
+                "  <entry>\n    \
+                 <title>{}</title>\n    \
+                 <id>urn:box2epub:book:{}</id>\n    \
+                 <updated>{}</updated>\n",
+                escape_text(&book.title),
+                escape_text(&encode_path(&book.id)),
+                timestamp(book.updated),
+            ));
+            if let Some(author) = &book.author {
+                feed.push_str(&format!(
+                    "    <author><name>{}</name></author>\n",
+                    escape_text(author)
+                ));
+            }
+            if let Some(language) = &book.language {
+                feed.push_str(&format!(
+                    "    <dc:language>{}</dc:language>\n",
+                    escape_text(language)
+                ));
+            }
+            if let Some(summary) = &book.summary {
+                feed.push_str(&format!(
+                    "    <summary>{}</summary>\n",
+                    escape_text(summary)
+                ));
+            }
+            for file in &book.files {
+                feed.push_str(&format!(
+                    "    <link rel=\"http://opds-spec.org/acquisition\" href=\"/books/{}\" type=\"{}\"/>\n",
+                    escape_attribute(&encode_path(&file.path)),
+                    file.format.mimetype(),
+                ));
+            }
+            feed.push_str("  </entry>\n");
+        }
+        feed.push_str("</feed>\n");
+        Ok(feed)
+    }
+
+    /// The file a download link points to. Only books in the feed can be
+    /// downloaded, so a path can't lead out of the library.
+    fn file(&self, path: &str) -> io::Result<Option<(PathBuf, OutputFormat)>> {
+        let mut files = Vec::new();
+        find_books(&self.dir, "", &mut files)?;
+        Ok(files
+            .into_iter()
+            .find(|file| file.path == path)
+            .map(|file| (self.full_path(&file.path), file.format)))
+    }
+}
+
+/// Adds the books in `dir` and the directories in it to `files`, `prefix` is
+/// the path of `dir` in the library
+fn find_books(dir: &Path, prefix: &str, files: &mut Vec<LibraryFile>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            find_books(&entry.path(), &format!("{}/", path), files)?;
+            continue;
+        }
+        let format = Path::new(&name)
+            .extension()
+            .and_then(|extension| extension.to_str()?.parse::<OutputFormat>().ok())
+            .filter(|format| format.builds_epub());
+        if let Some(format) = format {
+            files.push(LibraryFile { path, format });
+        }
+    }
+    Ok(())
+}
+
+/// Reads the title, author, description and language from the EPUB's package
+/// document
+fn read_metadata(path: &Path) -> Result<Metadata, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let name = archive
+        .file_names()
+        .find(|name| name.ends_with(".opf"))
+        .ok_or("no package document")?
+        .to_string();
+    let mut opf = String::new();
+    archive.by_name(&name)?.read_to_string(&mut opf)?;
+
+    let mut metadata = Metadata::default();
+    for capture in DC_ELEMENT_REGEX.captures_iter(&opf) {
+        let value = Some(decode_entities(capture[2].trim())).filter(|value| !value.is_empty());
+        let field = match &capture[1] {
+            "title" => &mut metadata.title,
+            "creator" => &mut metadata.author,
+            "description" => &mut metadata.summary,
+            _ => &mut metadata.language,
+        };
+        if field.is_none() {
+            *field = value;
+        }
+    }
+    Ok(metadata)
+}
+
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Serves the library's catalog at `/opds` (and `/`) and its books under
+/// `/books/` until Ctrl-C is pressed
+pub async fn serve(library: Library, address: SocketAddr) -> Result<(), Box<dyn Error>> {
+    let library = Arc::new(library);
+    let make_service = make_service_fn(move |_| {
+        let library = library.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| respond(library.clone(), request))) }
+    });
+    Server::try_bind(&address)
+        .map_err(|err| format!("Couldn't listen on {}: {}", address, err))?
+        .serve(make_service)
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await?;
+    Ok(())
+}
+
+async fn respond(
+    library: Arc<Library>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    debug!(method = %request.method(), path = request.uri().path(), "request");
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let path = request.uri().path().to_string();
+    let response = match path.as_str() {
+        "/" | "/opds" => match tokio::task::spawn_blocking(move || library.feed()).await {
+            Ok(Ok(feed)) => Response::builder()
+                .header(header::CONTENT_TYPE, FEED_TYPE)
+                .body(Body::from(feed))
+                .unwrap(),
+            _ => status(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        _ => match path.strip_prefix("/books/") {
+            Some(file) => {
+                let file = percent_decode_str(file).decode_utf8_lossy().into_owned();
+                download(library, file).await
+            }
+            None => status(StatusCode::NOT_FOUND),
+        },
+    };
+    Ok(response)
+}
+
+async fn download(library: Arc<Library>, path: String) -> Response<Body> {
+    let found = match tokio::task::spawn_blocking(move || library.file(&path)).await {
+        Ok(Ok(found)) => found,
+        _ => return status(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let (path, format) = match found {
+        Some(found) => found,
+        None => return status(StatusCode::NOT_FOUND),
+    };
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => data,
+        Err(_) => return status(StatusCode::NOT_FOUND),
+    };
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Response::builder()
+        .header(header::CONTENT_TYPE, format.mimetype())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename*=UTF-8''{}",
+                utf8_percent_encode(&file_name, PATH_SEGMENT)
+            ),
+        )
+        .body(Body::from(data))
+        .unwrap()
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(status.canonical_reason().unwrap_or_default()))
+        .unwrap()
+}
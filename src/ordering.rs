@@ -0,0 +1,211 @@
+use regex::Regex;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+lazy_static! {
+    static ref CHAPTER_NUMBER_REGEX: Regex =
+        Regex::new(r"(?i)(?:^|[^a-z])(?:chapter|chap|ch|episode|ep)[-_/]?(\d+)(?:[-_.](\d+))?")
+            .unwrap();
+    // "Chapter 12: The Duel" or "Ch. 12.5"
+    static ref TITLE_NUMBER_REGEX: Regex =
+        Regex::new(r"(?i)^\s*(?:chapter|chap\.?|ch\.?|episode|ep\.?)\s*(\d+)(?:[-.](\d+))?")
+            .unwrap();
+}
+
+/// How many gaps and duplicates `OrderReport::warnings` lists before summing up
+const LISTED_PROBLEMS: usize = 5;
+
+/// Order the chapters go in the book
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChapterOrder {
+    /// The order the site lists them in
+    Site,
+    /// Sorted by the chapter number in their urls, chapters without one stay
+    /// after the chapter they came after on the site
+    Number,
+}
+
+impl FromStr for ChapterOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "site" => Ok(ChapterOrder::Site),
+            "number" => Ok(ChapterOrder::Number),
+            _ => Err(format!("Unknown order {}, expected site or number", s)),
+        }
+    }
+}
+
+/// Chapter number in a url, along with the part for split chapters, i.e.
+/// `(12, 5)` for `.../chapter-12-5/`
+pub fn chapter_number(url: &str) -> Option<(u32, u32)> {
+    let capture = CHAPTER_NUMBER_REGEX.captures_iter(url).last()?;
+    let number = capture[1].parse().ok()?;
+    let part = capture
+        .get(2)
+        .and_then(|part| part.as_str().parse().ok())
+        .unwrap_or(0);
+    Some((number, part))
+}
+
+/// Chapter number of a chapter in the chapter list, from its title when it
+/// starts with one or else from its url. Titles go first because some sites
+/// have ids in their urls (i.e. Royal Road's `/chapter/123456/`).
+pub fn listed_chapter_number(url: &str, title: Option<&str>) -> Option<(u32, u32)> {
+    let from_title = title
+        .and_then(|title| TITLE_NUMBER_REGEX.captures(title))
+        .and_then(|capture| {
+            let number = capture[1].parse().ok()?;
+            let part = capture
+                .get(2)
+                .and_then(|part| part.as_str().parse().ok())
+                .unwrap_or(0);
+            Some((number, part))
+        });
+    from_title.or_else(|| chapter_number(url))
+}
+
+/// What looks wrong about the order of a chapter list, going by the numbers
+/// in its urls
+#[derive(Debug, Default)]
+pub struct OrderReport {
+    /// Chapter numbers missing from the list
+    pub gaps: Vec<RangeInclusive<u32>>,
+    /// Chapter numbers that more than one url has
+    pub duplicates: Vec<(u32, u32)>,
+    /// Chapters listed after one with a higher number
+    pub out_of_order: usize,
+}
+
+impl OrderReport {
+    pub fn is_ok(&self) -> bool {
+        self.gaps.is_empty() && self.duplicates.is_empty() && self.out_of_order == 0
+    }
+
+    /// The problems, worded for the user
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.gaps.is_empty() {
+            let gaps: Vec<String> = self
+                .gaps
+                .iter()
+                .take(LISTED_PROBLEMS)
+                .map(|gap| {
+                    if gap.start() == gap.end() {
+                        gap.start().to_string()
+                    } else {
+                        format!("{}-{}", gap.start(), gap.end())
+                    }
+                })
+                .collect();
+            warnings.push(format!(
+                "Chapters missing from the site's list: {}{}",
+                gaps.join(", "),
+                more(self.gaps.len())
+            ));
+        }
+        if !self.duplicates.is_empty() {
+            let duplicates: Vec<String> = self
+                .duplicates
+                .iter()
+                .take(LISTED_PROBLEMS)
+                .map(|&number| format_number(number))
+                .collect();
+            warnings.push(format!(
+                "Chapters listed more than once: {}{}",
+                duplicates.join(", "),
+                more(self.duplicates.len())
+            ));
+        }
+        if self.out_of_order > 0 {
+            warnings.push(format!(
+                "{} chapters are listed out of order, --sort-by number sorts them",
+                self.out_of_order
+            ));
+        }
+        warnings
+    }
+}
+
+fn more(count: usize) -> String {
+    if count > LISTED_PROBLEMS {
+        format!(" and {} more", count - LISTED_PROBLEMS)
+    } else {
+        String::new()
+    }
+}
+
+fn format_number((number, part): (u32, u32)) -> String {
+    if part == 0 {
+        number.to_string()
+    } else {
+        format!("{}.{}", number, part)
+    }
+}
+
+/// Checks the chapter list for gaps, duplicates and chapters out of order.
+/// Returns `None` when too few urls have a number to tell.
+pub fn check_order(urls: &[String]) -> Option<OrderReport> {
+    let numbers: Vec<(u32, u32)> = urls.iter().filter_map(|url| chapter_number(url)).collect();
+    if numbers.len() < 2 || numbers.len() * 2 < urls.len() {
+        return None;
+    }
+
+    let mut report = OrderReport::default();
+    let mut seen = HashSet::new();
+    for window in numbers.windows(2) {
+        if window[1] < window[0] {
+            report.out_of_order += 1;
+        }
+    }
+    for &number in &numbers {
+        if !seen.insert(number) && !report.duplicates.contains(&number) {
+            report.duplicates.push(number);
+        }
+    }
+
+    // Ids that only go up (i.e. RoyalRoad's) aren't chapter numbers, gaps in
+    // them don't mean anything
+    let mut whole: Vec<u32> = numbers.iter().map(|&(number, _)| number).collect();
+    whole.sort_unstable();
+    whole.dedup();
+    let span = (whole[whole.len() - 1] - whole[0]) as usize + 1;
+    if span <= whole.len() * 2 {
+        for pair in whole.windows(2) {
+            if pair[1] > pair[0] + 1 {
+                report.gaps.push(pair[0] + 1..=pair[1] - 1);
+            }
+        }
+    }
+    Some(report)
+}
+
+/// Sorts chapters by the number in their url. Chapters without one (i.e. a
+/// prologue or an announcement) keep their place after the chapter before them.
+pub fn sort_by_number(urls: &mut [String]) {
+    let mut previous = (0, 0);
+    let mut unnumbered = 0;
+    let mut keyed: Vec<((u32, u32, usize), String)> = urls
+        .iter()
+        .map(|url| {
+            let key = match chapter_number(url) {
+                Some(number) => {
+                    previous = number;
+                    unnumbered = 0;
+                    (number.0, number.1, 0)
+                }
+                None => {
+                    unnumbered += 1;
+                    (previous.0, previous.1, unnumbered)
+                }
+            };
+            (key, url.clone())
+        })
+        .collect();
+    keyed.sort_by_key(|(key, _)| *key);
+    for (url, (_, sorted)) in urls.iter_mut().zip(keyed) {
+        *url = sorted;
+    }
+}
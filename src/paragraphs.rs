@@ -0,0 +1,290 @@
+use crate::transform::ContentTransform;
+use ego_tree::{NodeId, NodeRef};
+use html5ever::{LocalName, Namespace, QualName};
+use scraper::node::Element;
+use scraper::{Html, Node};
+use std::str::FromStr;
+
+const HTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+
+/// Elements that go inside a paragraph
+const INLINE_ELEMENTS: [&str; 33] = [
+    "a", "abbr", "b", "bdi", "bdo", "big", "br", "cite", "code", "del", "dfn", "em", "font", "i",
+    "img", "ins", "kbd", "mark", "q", "rb", "rp", "rt", "rtc", "ruby", "s", "samp", "small",
+    "span", "strong", "sub", "sup", "tt", "u",
+];
+
+/// Elements whose text, when it's split up with `<br>`s, is made into
+/// paragraphs
+const CONTAINERS: [&str; 7] = [
+    "body",
+    "div",
+    "section",
+    "article",
+    "main",
+    "aside",
+    "blockquote",
+];
+
+/// Turns paragraphs sites mark up some other way into `<p>`s, readers only
+/// space out and indent those. `divs` turns `<div>`s with nothing but text in
+/// them into paragraphs, and `breaks` splits text into paragraphs where it has
+/// that many `<br>`s in a row.
+#[derive(Debug, Clone, Copy)]
+pub struct Paragraphs {
+    pub divs: bool,
+    /// How many `<br>`s in a row end a paragraph, `None` leaves them alone
+    pub breaks: Option<usize>,
+}
+
+impl Default for Paragraphs {
+    fn default() -> Self {
+        Paragraphs {
+            divs: true,
+            breaks: Some(2),
+        }
+    }
+}
+
+impl FromStr for Paragraphs {
+    type Err = String;
+
+    /// Parses the heuristics to use separated by commas: `divs`, and
+    /// `breaks=N` (or `breaks` for 2)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut paragraphs = Paragraphs {
+            divs: false,
+            breaks: None,
+        };
+        for heuristic in s.split(',').map(str::trim) {
+            match heuristic.split_once('=') {
+                None if heuristic == "divs" => paragraphs.divs = true,
+                None if heuristic == "breaks" => paragraphs.breaks = Some(2),
+                Some(("breaks", count)) => match count.trim().parse() {
+                    Ok(count) if count > 0 => paragraphs.breaks = Some(count),
+                    _ => return Err(format!("Invalid number of breaks {}", count)),
+                },
+                _ => {
+                    return Err(format!(
+                        "Unknown paragraph heuristic {}, expected divs or breaks=N",
+                        heuristic
+                    ))
+                }
+            }
+        }
+        Ok(paragraphs)
+    }
+}
+
+impl ContentTransform for Paragraphs {
+    fn apply(&self, html: &str) -> String {
+        let mut document = Html::parse_document(html);
+        let mut changed = false;
+        if self.divs {
+            changed |= divs_to_paragraphs(&mut document);
+        }
+        if let Some(breaks) = self.breaks {
+            changed |= split_at_breaks(&mut document, breaks);
+        }
+        if !changed {
+            return html.to_string();
+        }
+        document.root_element().html()
+    }
+}
+
+/// Renames `<div>`s that only have text and inline elements in them to `<p>`
+fn divs_to_paragraphs(document: &mut Html) -> bool {
+    let ids: Vec<NodeId> = document
+        .tree
+        .root()
+        .descendants()
+        .filter(|node| is_element(*node, "div") && is_paragraph_like(*node))
+        .map(|node| node.id())
+        .collect();
+    for &id in &ids {
+        if let Some(mut node) = document.tree.get_mut(id) {
+            if let Node::Element(element) = node.value() {
+                element.name = html_name("p");
+            }
+        }
+    }
+    !ids.is_empty()
+}
+
+/// Whether the element's content is inline and there's something to read in it
+fn is_paragraph_like(node: NodeRef<Node>) -> bool {
+    let mut has_content = false;
+    for child in node.children() {
+        match child.value() {
+            Node::Text(text) => has_content |= !text.trim().is_empty(),
+            Node::Element(element) if INLINE_ELEMENTS.contains(&element.name()) => {
+                has_content |= element.name() != "br"
+            }
+            Node::Element(_) => return false,
+            _ => {}
+        }
+    }
+    has_content
+}
+
+/// What a container's children are split into
+enum Part {
+    /// Text and inline elements that make up a paragraph
+    Inline(Vec<NodeId>),
+    Block(NodeId),
+}
+
+/// Makes paragraphs out of text separated by `breaks` or more `<br>`s, in
+/// containers and paragraphs that have them
+fn split_at_breaks(document: &mut Html, breaks: usize) -> bool {
+    let ids: Vec<NodeId> = document
+        .tree
+        .root()
+        .descendants()
+        .filter(|node| match node.value() {
+            Node::Element(element) => CONTAINERS.contains(&element.name()) || element.name() == "p",
+            _ => false,
+        })
+        .map(|node| node.id())
+        .collect();
+    let mut changed = false;
+    for id in ids {
+        let (parts, separators) = match document.tree.get(id) {
+            Some(node) => split(node, breaks),
+            None => continue,
+        };
+        if separators.is_empty() {
+            continue;
+        }
+        changed = true;
+        for separator in separators {
+            if let Some(mut node) = document.tree.get_mut(separator) {
+                node.detach();
+            }
+        }
+        if matches!(document.tree.get(id), Some(node) if is_element(node, "p")) {
+            split_paragraph(document, id, parts);
+        } else {
+            wrap_paragraphs(document, parts);
+        }
+    }
+    changed
+}
+
+/// The container's children, split at runs of `breaks` or more `<br>`s. The
+/// `<br>`s in those runs are returned apart from them.
+fn split(node: NodeRef<Node>, breaks: usize) -> (Vec<Part>, Vec<NodeId>) {
+    let mut parts = Vec::new();
+    let mut separators = Vec::new();
+    let mut inline = Vec::new();
+    // `<br>`s in a row, and the whitespace between them
+    let mut run: Vec<NodeRef<Node>> = Vec::new();
+    let mut end_run =
+        |run: &mut Vec<NodeRef<Node>>, inline: &mut Vec<NodeId>, parts: &mut Vec<Part>| {
+            let count = run.iter().filter(|node| is_element(**node, "br")).count();
+            if count >= breaks {
+                separators.extend(
+                    run.drain(..)
+                        .filter(|node| is_element(*node, "br"))
+                        .map(|node| node.id()),
+                );
+                if !inline.is_empty() {
+                    parts.push(Part::Inline(std::mem::take(inline)));
+                }
+            } else {
+                inline.extend(run.drain(..).map(|node| node.id()));
+            }
+        };
+    for child in node.children() {
+        let is_whitespace = matches!(child.value(), Node::Text(text) if text.trim().is_empty());
+        if is_element(child, "br") || (is_whitespace && !run.is_empty()) {
+            run.push(child);
+            continue;
+        }
+        end_run(&mut run, &mut inline, &mut parts);
+        match child.value() {
+            Node::Element(element) if !INLINE_ELEMENTS.contains(&element.name()) => {
+                if !inline.is_empty() {
+                    parts.push(Part::Inline(std::mem::take(&mut inline)));
+                }
+                parts.push(Part::Block(child.id()));
+            }
+            _ => inline.push(child.id()),
+        }
+    }
+    end_run(&mut run, &mut inline, &mut parts);
+    if !inline.is_empty() {
+        parts.push(Part::Inline(inline));
+    }
+    (parts, separators)
+}
+
+/// Splits the paragraph into one for every part, the first keeps the
+/// paragraph's attributes
+fn split_paragraph(document: &mut Html, id: NodeId, parts: Vec<Part>) {
+    let mut previous = id;
+    for part in parts.into_iter().skip(1) {
+        let nodes = match part {
+            Part::Inline(nodes) => nodes,
+            Part::Block(node) => vec![node],
+        };
+        let paragraph = new_paragraph(document, nodes);
+        if let Some(mut node) = document.tree.get_mut(previous) {
+            node.insert_id_after(paragraph);
+        }
+        previous = paragraph;
+    }
+}
+
+/// Wraps every part with text in it in a paragraph
+fn wrap_paragraphs(document: &mut Html, parts: Vec<Part>) {
+    for part in parts {
+        let nodes = match part {
+            Part::Inline(nodes) => nodes,
+            Part::Block(_) => continue,
+        };
+        let has_content = nodes.iter().any(|&id| match document.tree.get(id) {
+            Some(node) => !matches!(node.value(), Node::Text(text) if text.trim().is_empty()),
+            None => false,
+        });
+        if !has_content {
+            continue;
+        }
+        let first = nodes[0];
+        let paragraph = document.tree.orphan(new_p()).id();
+        if let Some(mut node) = document.tree.get_mut(first) {
+            node.insert_id_before(paragraph);
+        }
+        move_into(document, paragraph, nodes);
+    }
+}
+
+fn new_paragraph(document: &mut Html, nodes: Vec<NodeId>) -> NodeId {
+    let paragraph = document.tree.orphan(new_p()).id();
+    move_into(document, paragraph, nodes);
+    paragraph
+}
+
+fn move_into(document: &mut Html, parent: NodeId, nodes: Vec<NodeId>) {
+    for id in nodes {
+        if let Some(mut node) = document.tree.get_mut(id) {
+            node.detach();
+        }
+        if let Some(mut parent) = document.tree.get_mut(parent) {
+            parent.append_id(id);
+        }
+    }
+}
+
+fn new_p() -> Node {
+    Node::Element(Element::new(html_name("p"), Vec::new()))
+}
+
+fn html_name(name: &str) -> QualName {
+    QualName::new(None, Namespace::from(HTML_NAMESPACE), LocalName::from(name))
+}
+
+fn is_element(node: NodeRef<Node>, name: &str) -> bool {
+    matches!(node.value(), Node::Element(element) if element.name() == name)
+}
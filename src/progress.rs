@@ -0,0 +1,112 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fmt::Display;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    // Bar that's on screen right now, messages have to go through it so they're
+    // printed above the bar instead of mangling it
+    static ref CURRENT_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+}
+
+/// Hides the progress bar and informational messages, warnings are still printed
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints a message about what's going on, unless running quietly
+pub fn info(message: impl Display) {
+    if is_quiet() {
+        return;
+    }
+    match CURRENT_BAR.lock().unwrap().as_ref() {
+        Some(bar) => bar.println(message.to_string()),
+        None => println!("{}", message),
+    }
+}
+
+/// Prints something that went wrong, even when running quietly
+pub fn warn(message: impl Display) {
+    match CURRENT_BAR.lock().unwrap().as_ref() {
+        Some(bar) => bar.println(message.to_string()),
+        None => eprintln!("{}", message),
+    }
+}
+
+/// Writes log lines to stderr without mangling the progress bar. Meant to be
+/// made once per log event, what was written is printed when it's dropped.
+#[derive(Default)]
+pub struct LogWriter {
+    buffer: Vec<u8>,
+}
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let text = String::from_utf8_lossy(&self.buffer);
+        warn(text.trim_end());
+    }
+}
+
+/// Progress bar showing how many chapters are done, the download speed and an
+/// ETA. It's removed from the screen when dropped. Only one is shown at a
+/// time, the ones for books of a batch made while it's on screen are hidden.
+pub struct ChapterProgress {
+    bar: ProgressBar,
+    /// Whether it's the bar on screen
+    shown: bool,
+}
+
+impl ChapterProgress {
+    pub fn start(total: usize) -> Self {
+        let mut current = CURRENT_BAR.lock().unwrap();
+        if is_quiet() || current.is_some() {
+            return ChapterProgress {
+                bar: ProgressBar::hidden(),
+                shown: false,
+            };
+        }
+
+        let bar = ProgressBar::new(total as u64);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{bar:40} {pos}/{len} chapters ({per_sec}, ETA {eta})"),
+        );
+        *current = Some(bar.clone());
+        ChapterProgress { bar, shown: true }
+    }
+
+    /// Marks another chapter as done, whether it was downloaded or skipped
+    pub fn inc(&self) {
+        self.bar.inc(1);
+    }
+}
+
+impl Drop for ChapterProgress {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+        if self.shown {
+            *CURRENT_BAR.lock().unwrap() = None;
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::progress;
+use reqwest::Url;
+use std::time::Duration;
+
+/// The name `robots.txt` rules for box2epub are listed under, rules for `*`
+/// apply when there are none
+pub const ROBOTS_USER_AGENT: &str = "box2epub";
+
+/// Longest `Crawl-delay` that's waited between requests, anything longer
+/// (i.e. a typo, or `1e30`) would stall the download for good
+pub const MAX_CRAWL_DELAY: Duration = Duration::from_secs(60);
+
+/// An `Allow` or `Disallow` line
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Start of the paths it's about, `*` matches anything and a trailing `$`
+    /// the end of the path
+    pattern: String,
+    allow: bool,
+}
+
+/// What a site's `robots.txt` asks of box2epub: which paths to stay away from
+/// and how long to wait between requests
+#[derive(Debug, Clone, Default)]
+pub struct Robots {
+    rules: Vec<Rule>,
+    pub crawl_delay: Option<Duration>,
+}
+
+impl Robots {
+    /// Rules that allow everything, for sites without a `robots.txt`
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Reads the groups for `user_agent`, or the `*` groups when none are for
+    /// it. Lines it doesn't know (i.e. `Sitemap`) are skipped.
+    pub fn parse(text: &str, user_agent: &str) -> Self {
+        let mut named = Robots::default();
+        let mut any = Robots::default();
+        let mut has_named = false;
+        // Whether the current group is for box2epub and for everyone, a group
+        // can have several user-agent lines
+        let mut group = (false, false);
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let colon = match line.find(':') {
+                Some(colon) => colon,
+                None => continue,
+            };
+            let key = line[..colon].trim().to_lowercase();
+            let value = line[colon + 1..].trim();
+            if key == "user-agent" {
+                if !in_agents {
+                    group = (false, false);
+                    in_agents = true;
+                }
+                if value.eq_ignore_ascii_case(user_agent) {
+                    group.0 = true;
+                    has_named = true;
+                } else if value == "*" {
+                    group.1 = true;
+                }
+                continue;
+            }
+            in_agents = false;
+            let mut targets = Vec::new();
+            if group.0 {
+                targets.push(&mut named);
+            }
+            if group.1 {
+                targets.push(&mut any);
+            }
+            for robots in targets {
+                match key.as_str() {
+                    // An empty `Disallow` allows everything, it isn't a rule
+                    "allow" | "disallow" if !value.is_empty() => robots.rules.push(Rule {
+                        pattern: value.to_string(),
+                        allow: key == "allow",
+                    }),
+                    // NaN isn't more than 0 either
+                    "crawl-delay" => match value.parse::<f64>() {
+                        Ok(seconds) if seconds > MAX_CRAWL_DELAY.as_secs_f64() => {
+                            progress::warn(format!(
+                                "robots.txt asks for {} seconds between requests, waiting {:?} instead",
+                                value, MAX_CRAWL_DELAY
+                            ));
+                            robots.crawl_delay = Some(MAX_CRAWL_DELAY);
+                        }
+                        Ok(seconds) if seconds > 0.0 => {
+                            robots.crawl_delay = Some(Duration::from_secs_f64(seconds));
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+        }
+        if has_named {
+            named
+        } else {
+            any
+        }
+    }
+
+    /// Whether `url` can be fetched. The longest matching rule decides, an
+    /// `Allow` wins a tie.
+    pub fn allows(&self, url: &Url) -> bool {
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        let deciding = self
+            .rules
+            .iter()
+            .filter(|rule| matches_pattern(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow));
+        match deciding {
+            Some(rule) => rule.allow,
+            None => true,
+        }
+    }
+}
+
+/// Whether `path` starts with something `pattern` matches
+fn matches_pattern(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match path.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        // The last part has to be at the very end when the pattern is anchored
+        if anchored && i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(start) => rest = &rest[start + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
@@ -0,0 +1,163 @@
+use ego_tree::iter::Edge;
+use html5ever::data::NAMED_ENTITIES;
+use regex::{Captures, Regex};
+use scraper::{Html, Node};
+
+const XHTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+
+lazy_static! {
+    static ref ENTITY_REGEX: Regex =
+        Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[A-Za-z][A-Za-z0-9]*)(;?)").unwrap();
+}
+
+/// Elements that can't have children, these have to be self-closed in xhtml
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// EPUB only accepts xhtml, so this converts html to xhtml (i.e. <br> to <br />)
+///
+/// The html is parsed the same way a browser would (so unclosed tags and other
+/// sloppy markup get fixed up) and then written back out as xml. Entities are
+/// decoded by the parser and written out as plain UTF-8, so only `&amp;`, `&lt;`,
+/// `&gt;` and `&quot;` ever appear in the output.
+pub fn sanitize_html(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let mut xhtml = String::with_capacity(html.len());
+
+    for edge in document.tree.root().traverse() {
+        match edge {
+            Edge::Open(node) => match node.value() {
+                Node::Element(element) => {
+                    let name = element.name();
+                    xhtml.push('<');
+                    xhtml.push_str(name);
+                    if name == "html" && element.attr("xmlns").is_none() {
+                        xhtml.push_str(&format!(r#" xmlns="{}""#, XHTML_NAMESPACE));
+                    }
+                    for (attr_name, value) in element.attrs() {
+                        // Sloppy html can produce attribute names that aren't valid xml
+                        if !is_xml_name(attr_name) {
+                            continue;
+                        }
+                        xhtml.push(' ');
+                        xhtml.push_str(attr_name);
+                        xhtml.push_str("=\"");
+                        push_escaped(&mut xhtml, value, true);
+                        xhtml.push('"');
+                    }
+                    if VOID_ELEMENTS.contains(&name) {
+                        xhtml.push_str(" />");
+                    } else {
+                        xhtml.push('>');
+                    }
+                }
+                Node::Text(text) => push_escaped(&mut xhtml, &text.text, false),
+                // Comments and doctypes aren't needed by readers, and `--` inside
+                // a comment is invalid xml, so just drop them
+                _ => {}
+            },
+            Edge::Close(node) => {
+                if let Node::Element(element) = node.value() {
+                    let name = element.name();
+                    if !VOID_ELEMENTS.contains(&name) {
+                        xhtml.push_str("</");
+                        xhtml.push_str(name);
+                        xhtml.push('>');
+                    }
+                }
+            }
+        }
+    }
+
+    xhtml
+}
+
+/// Decodes html entities (numeric ones and every named entity in the HTML5 table)
+/// into plain UTF-8.
+///
+/// `sanitize_html` already gets this for free from the html parser, this is for
+/// text pulled out of raw html some other way (i.e. with a regex) that ends up
+/// in the EPUB metadata.
+pub fn decode_entities(text: &str) -> String {
+    ENTITY_REGEX
+        .replace_all(text, |capture: &Captures| {
+            let entity = &capture[1];
+            let has_semicolon = !capture[2].is_empty();
+            let decoded = if let Some(number) = entity.strip_prefix('#') {
+                let code_point = match number.strip_prefix(|c| c == 'x' || c == 'X') {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => number.parse(),
+                };
+                // Out of range or surrogate code points become the replacement character
+                Some(
+                    code_point
+                        .ok()
+                        .and_then(std::char::from_u32)
+                        .map_or_else(|| '\u{FFFD}'.to_string(), |c| c.to_string()),
+                )
+            } else {
+                // Some legacy entities are allowed without the semicolon, the
+                // table has separate entries for those
+                let key = if has_semicolon {
+                    format!("{};", entity)
+                } else {
+                    entity.to_string()
+                };
+                NAMED_ENTITIES
+                    .get(key.as_str())
+                    .filter(|(first, _)| *first != 0)
+                    .map(|&(first, second)| {
+                        std::iter::once(first)
+                            .chain(Some(second).filter(|&c| c != 0))
+                            .filter_map(std::char::from_u32)
+                            .collect()
+                    })
+            };
+            // Not an entity after all, leave it untouched
+            decoded.unwrap_or_else(|| capture[0].to_string())
+        })
+        .into_owned()
+}
+
+/// Escapes text to go into an xhtml document
+pub fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    push_escaped(&mut escaped, text, false);
+    escaped
+}
+
+/// Escapes text to go into a quoted attribute value
+pub fn escape_attribute(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    push_escaped(&mut escaped, text, true);
+    escaped
+}
+
+/// Escapes characters that are special in xml and drops control characters
+/// that xml doesn't allow at all
+fn push_escaped(xhtml: &mut String, text: &str, in_attribute: bool) {
+    for c in text.chars() {
+        match c {
+            '&' => xhtml.push_str("&amp;"),
+            '<' => xhtml.push_str("&lt;"),
+            '>' => xhtml.push_str("&gt;"),
+            '"' if in_attribute => xhtml.push_str("&quot;"),
+            '\t' | '\n' | '\r' => xhtml.push(c),
+            c if c.is_control() => {}
+            c => xhtml.push(c),
+        }
+    }
+}
+
+/// Loose check for a valid xml attribute name, good enough to filter out the junk
+/// html parsers let through (i.e. `"`, `'` or `/` ending up in a name)
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' || c == ':' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_' || c == ':' || c == '-' || c == '.')
+}
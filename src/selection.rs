@@ -0,0 +1,66 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Chapters picked on the command line, i.e. `200-350` or `1,5,10-20`.
+///
+/// Chapters are numbered from 1 in the order the extractor lists them. A range
+/// without an end (`200-`) runs to the last chapter.
+#[derive(Debug, Clone)]
+pub struct ChapterSelection {
+    ranges: Vec<RangeInclusive<usize>>,
+}
+
+impl ChapterSelection {
+    /// Exactly these chapters
+    pub fn numbers(numbers: impl IntoIterator<Item = usize>) -> Self {
+        ChapterSelection {
+            ranges: numbers.into_iter().map(|number| number..=number).collect(),
+        }
+    }
+
+    pub fn contains(&self, number: usize) -> bool {
+        self.ranges.iter().any(|range| range.contains(&number))
+    }
+}
+
+impl FromStr for ChapterSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_number = |number: &str| {
+            number
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid chapter number: {}", number))
+        };
+
+        let mut ranges = Vec::new();
+        for part in s.split(',').filter(|part| !part.trim().is_empty()) {
+            let range = match part.find('-') {
+                Some(dash) => {
+                    let start = parse_number(&part[..dash])?;
+                    let end = &part[dash + 1..];
+                    let end = if end.trim().is_empty() {
+                        usize::MAX
+                    } else {
+                        parse_number(end)?
+                    };
+                    start..=end
+                }
+                None => {
+                    let number = parse_number(part)?;
+                    number..=number
+                }
+            };
+            if range.is_empty() || *range.start() == 0 {
+                return Err(format!("Invalid chapter range: {}", part));
+            }
+            ranges.push(range);
+        }
+
+        if ranges.is_empty() {
+            return Err("No chapters selected".to_string());
+        }
+        Ok(ChapterSelection { ranges })
+    }
+}
@@ -0,0 +1,76 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// Temporary directory that chapters and images are written to as soon as
+/// they're downloaded, so only the chapters being worked on have to fit in
+/// memory no matter how long the novel is.
+///
+/// Clones share the directory, it's removed once the last one is dropped
+/// unless it's one given to `Spool::in_dir`.
+#[derive(Clone)]
+pub struct Spool {
+    dir: Arc<SpoolDir>,
+    tmp_files: Arc<AtomicUsize>,
+}
+
+enum SpoolDir {
+    Temporary(TempDir),
+    /// Outlives the spool, i.e. a checkpoint's
+    Kept(PathBuf),
+}
+
+/// An image that was written to the spool
+pub struct SpooledImage {
+    /// Path inside the EPUB
+    pub path: String,
+    pub mimetype: String,
+    /// Where the image is on disk
+    pub file: PathBuf,
+}
+
+impl Spool {
+    pub fn new() -> io::Result<Self> {
+        let dir = tempfile::Builder::new().prefix("box2epub").tempdir()?;
+        Ok(Spool {
+            dir: Arc::new(SpoolDir::Temporary(dir)),
+            tmp_files: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Spool that writes to `dir` and leaves it behind, for files that have
+    /// to survive the process being killed
+    pub fn in_dir(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Spool {
+            dir: Arc::new(SpoolDir::Kept(dir)),
+            tmp_files: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        match self.dir.as_ref() {
+            SpoolDir::Temporary(dir) => dir.path(),
+            SpoolDir::Kept(dir) => dir,
+        }
+    }
+
+    /// Writes `data` to `name` (relative to the spool directory) and returns
+    /// where it ended up
+    pub async fn write(&self, name: &str, data: &[u8]) -> io::Result<PathBuf> {
+        let path = self.dir().join(name);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Chapters downloaded at the same time can share an image, each write
+        // goes through its own temporary file so they can't clobber each other
+        let tmp_number = self.tmp_files.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = path.with_extension(format!("{}.tmp", tmp_number));
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(path)
+    }
+}
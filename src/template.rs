@@ -0,0 +1,143 @@
+use crate::sanitizer::escape_text;
+use regex::Regex;
+use scraper::{Html, Selector};
+use std::error::Error;
+use std::path::Path;
+
+/// Template chapters are wrapped in unless `--template` gives another one
+pub const DEFAULT_CHAPTER_TEMPLATE: &str = include_str!("../assets/chapter.xhtml");
+
+lazy_static! {
+    // {{ title }}
+    static ref VARIABLE_REGEX: Regex = Regex::new(r"\{\{\s*([A-Za-z_]*)\s*\}\}").unwrap();
+    static ref BODY_SELECTOR: Selector = Selector::parse("body").unwrap();
+    pub(crate) static ref DEFAULT_TEMPLATE: ChapterTemplate =
+        ChapterTemplate::parse(DEFAULT_CHAPTER_TEMPLATE).unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variable {
+    Title,
+    Content,
+    Number,
+    Url,
+    NovelTitle,
+    Author,
+}
+
+const VARIABLES: [(&str, Variable); 6] = [
+    ("title", Variable::Title),
+    ("content", Variable::Content),
+    ("number", Variable::Number),
+    ("url", Variable::Url),
+    ("novel_title", Variable::NovelTitle),
+    ("author", Variable::Author),
+];
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Variable(Variable),
+}
+
+/// The xhtml document a chapter's content is wrapped in. `{{ content }}` is
+/// replaced by the chapter's markup, and `{{ title }}`, `{{ number }}`,
+/// `{{ url }}`, `{{ novel_title }}` and `{{ author }}` by the escaped text.
+#[derive(Debug, Clone)]
+pub struct ChapterTemplate {
+    parts: Vec<Part>,
+}
+
+/// What goes into a template, values that aren't known are left empty
+#[derive(Default)]
+pub struct ChapterValues<'a> {
+    pub title: &'a str,
+    pub content: &'a str,
+    pub number: Option<usize>,
+    pub url: &'a str,
+    pub novel_title: &'a str,
+    pub author: &'a str,
+}
+
+impl ChapterTemplate {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut end = 0;
+        for capture in VARIABLE_REGEX.captures_iter(source) {
+            let whole = capture.get(0).unwrap();
+            let name = &capture[1];
+            let variable = VARIABLES
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|&(_, variable)| variable)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown variable {} in the template, expected one of {}",
+                        whole.as_str(),
+                        VARIABLES
+                            .iter()
+                            .map(|(name, _)| *name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })?;
+            parts.push(Part::Text(source[end..whole.start()].to_string()));
+            parts.push(Part::Variable(variable));
+            end = whole.end();
+        }
+        parts.push(Part::Text(source[end..].to_string()));
+
+        if !parts
+            .iter()
+            .any(|part| matches!(part, Part::Variable(Variable::Content)))
+        {
+            return Err("The template has no {{ content }} for the chapter to go in".to_string());
+        }
+        Ok(ChapterTemplate { parts })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Couldn't read the template {}: {}", path.display(), err))?;
+        Ok(Self::parse(&source).map_err(|err| format!("{}: {}", path.display(), err))?)
+    }
+
+    pub fn render(&self, values: &ChapterValues) -> String {
+        let mut xhtml = String::with_capacity(values.content.len() + 512);
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => xhtml.push_str(text),
+                Part::Variable(Variable::Content) => xhtml.push_str(values.content),
+                Part::Variable(Variable::Number) => {
+                    if let Some(number) = values.number {
+                        xhtml.push_str(&number.to_string());
+                    }
+                }
+                Part::Variable(variable) => xhtml.push_str(&escape_text(match variable {
+                    Variable::Title => values.title,
+                    Variable::Url => values.url,
+                    Variable::NovelTitle => values.novel_title,
+                    _ => values.author,
+                })),
+            }
+        }
+        xhtml
+    }
+}
+
+impl Default for ChapterTemplate {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE.clone()
+    }
+}
+
+/// The markup inside a chapter's `<body>`, to wrap in another template
+pub fn chapter_body(xhtml: &str) -> String {
+    let document = Html::parse_document(xhtml);
+    document
+        .select(&BODY_SELECTOR)
+        .next()
+        .map(|body| body.inner_html())
+        .unwrap_or_default()
+}
@@ -0,0 +1,213 @@
+use ego_tree::NodeRef;
+use scraper::{Html, Node};
+
+/// Flavor of plain text to turn chapters into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextStyle {
+    Markdown,
+    Plain,
+}
+
+/// Turns a chapter's xhtml into Markdown or plain text. Only the body is
+/// converted, headings, emphasis, links, images, lists and quotes are kept
+/// in Markdown and flattened in plain text.
+pub fn xhtml_to_text(xhtml: &str, style: TextStyle) -> String {
+    let document = Html::parse_document(xhtml);
+    let mut writer = TextWriter {
+        style,
+        out: String::new(),
+    };
+    for child in document.tree.root().children() {
+        writer.node(child);
+    }
+    tidy(&writer.out)
+}
+
+struct TextWriter {
+    style: TextStyle,
+    out: String,
+}
+
+impl TextWriter {
+    fn markdown(&self) -> bool {
+        self.style == TextStyle::Markdown
+    }
+
+    /// Starts a new paragraph, unless one was just started
+    fn block(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push_str(if self.out.ends_with('\n') {
+                "\n"
+            } else {
+                "\n\n"
+            });
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let at_line_start = self.out.is_empty() || self.out.ends_with('\n');
+        let words = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        // Whitespace between inline elements still separates words
+        let space_before = words.is_empty() || text.starts_with(char::is_whitespace);
+        if space_before && !at_line_start && !self.out.ends_with(' ') {
+            self.out.push(' ');
+        }
+        if words.is_empty() {
+            return;
+        }
+        if self.markdown() {
+            self.out.push_str(&escape_markdown(&words));
+        } else {
+            self.out.push_str(&words);
+        }
+        if text.ends_with(char::is_whitespace) {
+            self.out.push(' ');
+        }
+    }
+
+    /// Writes the children into their own string, for things that get prefixed
+    /// line by line (quotes and list items)
+    fn nested(&mut self, node: NodeRef<Node>) -> String {
+        let mut nested = TextWriter {
+            style: self.style,
+            out: String::new(),
+        };
+        nested.children(node);
+        tidy(&nested.out)
+    }
+
+    fn children(&mut self, node: NodeRef<Node>) {
+        for child in node.children() {
+            self.node(child);
+        }
+    }
+
+    fn node(&mut self, node: NodeRef<Node>) {
+        let element = match node.value() {
+            Node::Text(text) => return self.text(text),
+            Node::Element(element) => element,
+            Node::Document | Node::Fragment => return self.children(node),
+            _ => return,
+        };
+
+        match element.name() {
+            "head" | "script" | "style" => {}
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.block();
+                if self.markdown() {
+                    let level = element.name()[1..].parse().unwrap_or(1);
+                    self.out.push_str(&"#".repeat(level));
+                    self.out.push(' ');
+                }
+                self.children(node);
+                self.block();
+            }
+            "br" => self
+                .out
+                .push_str(if self.markdown() { "\\\n" } else { "\n" }),
+            "hr" => {
+                self.block();
+                self.out
+                    .push_str(if self.markdown() { "---" } else { "* * *" });
+                self.block();
+            }
+            "em" | "i" if self.markdown() => self.wrap(node, "*"),
+            "strong" | "b" if self.markdown() => self.wrap(node, "**"),
+            "a" if self.markdown() => match element.attr("href") {
+                Some(href) => {
+                    self.out.push('[');
+                    self.children(node);
+                    self.out.push_str(&format!("]({})", href));
+                }
+                None => self.children(node),
+            },
+            "img" => {
+                if self.markdown() {
+                    if let Some(src) = element.attr("src") {
+                        let alt = element.attr("alt").unwrap_or("");
+                        self.out
+                            .push_str(&format!("![{}]({})", escape_markdown(alt), src));
+                    }
+                }
+            }
+            "blockquote" => {
+                let quote = self.nested(node);
+                self.block();
+                let prefix = if self.markdown() { "> " } else { "    " };
+                let quote: Vec<String> = quote
+                    .lines()
+                    .map(|line| format!("{}{}", prefix, line).trim_end().to_string())
+                    .collect();
+                self.out.push_str(&quote.join("\n"));
+                self.block();
+            }
+            "ul" | "ol" => {
+                self.block();
+                let ordered = element.name() == "ol";
+                let items = node.children().filter(|child| {
+                    matches!(child.value(), Node::Element(element) if element.name() == "li")
+                });
+                for (i, item) in items.enumerate() {
+                    let marker = if ordered {
+                        format!("{}. ", i + 1)
+                    } else {
+                        "- ".to_string()
+                    };
+                    let indent = " ".repeat(marker.len());
+                    for (j, line) in self.nested(item).lines().enumerate() {
+                        let prefix = if j == 0 {
+                            marker.as_str()
+                        } else {
+                            indent.as_str()
+                        };
+                        self.out.push_str(format!("{}{}", prefix, line).trim_end());
+                        self.out.push('\n');
+                    }
+                }
+                self.block();
+            }
+            "p" | "div" | "section" | "article" | "table" | "tr" | "pre" | "body" | "html" => {
+                self.block();
+                self.children(node);
+                self.block();
+            }
+            _ => self.children(node),
+        }
+    }
+
+    fn wrap(&mut self, node: NodeRef<Node>, marker: &str) {
+        self.out.push_str(marker);
+        self.children(node);
+        self.out.push_str(marker);
+    }
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Trims lines and removes runs of blank lines
+fn tidy(text: &str) -> String {
+    let mut tidied = String::with_capacity(text.len());
+    let mut blank_lines = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if !tidied.is_empty() {
+            tidied.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+        }
+        blank_lines = 0;
+        tidied.push_str(line);
+    }
+    tidied
+}
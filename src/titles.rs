@@ -0,0 +1,153 @@
+use crate::extractor::first_text;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use std::str::FromStr;
+
+lazy_static! {
+    // What sites put between the chapter, novel and site names in `<title>`
+    static ref SEPARATOR_REGEX: Regex = Regex::new(r"\s+[-–—|:«»]+\s+").unwrap();
+    static ref JUNK_REGEX: Regex = Regex::new(
+        r"(?ix)^(
+            read\b.*\b(online|free|novels?)\b.* |
+            .*\b(free\ online|online\ free)\b.* |
+            [a-z0-9-]+\.(com|net|org|io|co)
+        )$"
+    )
+    .unwrap();
+    static ref CHAPTER_NUMBER_REGEX: Regex =
+        Regex::new(r"(?i)^(?:chapter|chap\.?|ch\.?)\s*(\d+(?:\.\d+)?)$").unwrap();
+    static ref CONTENT_HEADING_SELECTOR: Selector =
+        Selector::parse("body h1, body h2, body h3, body h4").unwrap();
+    static ref PAGE_TITLE_SELECTOR: Selector = Selector::parse("title").unwrap();
+}
+
+/// Left off the end of urls for `TitleSource::Url`, a dot followed by
+/// anything else can be part of the title (i.e. "chapter-12.5")
+const PAGE_EXTENSIONS: [&str; 5] = ["html", "htm", "xhtml", "php", "aspx"];
+
+/// Longest heading taken for a title, anything longer is text that happens
+/// to be in a heading
+const MAX_HEADING_TITLE: usize = 200;
+
+/// Where chapter titles come from, instead of wherever the extractor finds
+/// them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TitleSource {
+    /// The chapter's heading, see `Extractor::title_heading`
+    Heading,
+    /// The page's `<title>`, with the site's name and SEO filler cleaned off
+    PageTitle,
+    /// The last part of the chapter's url, i.e. `chapter-12-the-duel`
+    Url,
+}
+
+impl FromStr for TitleSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "heading" => Ok(TitleSource::Heading),
+            "page-title" => Ok(TitleSource::PageTitle),
+            "url" => Ok(TitleSource::Url),
+            _ => Err(format!(
+                "Unknown title source {}, expected heading, page-title or url",
+                s
+            )),
+        }
+    }
+}
+
+/// The chapter's title from `source`, `None` when there isn't one there.
+/// `html` is the chapter's page and `content` its extracted xhtml, the
+/// heading is the first one at `heading` on the page or else the first one in
+/// the content.
+pub fn find_title(
+    source: TitleSource,
+    html: &str,
+    content: &str,
+    url: &str,
+    heading: Option<&Selector>,
+) -> Option<String> {
+    match source {
+        TitleSource::Heading => match heading {
+            Some(selector) => first_text(&Html::parse_document(html), selector),
+            None => Html::parse_document(content)
+                .select(&CONTENT_HEADING_SELECTOR)
+                .map(|heading| collapse_whitespace(&heading.text().collect::<String>()))
+                .find(|text| !text.is_empty()),
+        }
+        .filter(|title| title.len() <= MAX_HEADING_TITLE),
+        TitleSource::PageTitle => first_text(&Html::parse_document(html), &PAGE_TITLE_SELECTOR),
+        TitleSource::Url => title_from_url(url),
+    }
+}
+
+/// "chapter-12-the-duel.html" becomes "Chapter 12 The Duel". Urls ending in
+/// an id without any words don't have a title.
+fn title_from_url(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let segment = url
+        .path_segments()?
+        .rev()
+        .find(|segment| !segment.is_empty())?;
+    let segment = percent_decode_str(segment).decode_utf8().ok()?;
+    let slug = match segment.rfind('.') {
+        Some(dot) if PAGE_EXTENSIONS.contains(&&segment[dot + 1..]) => &segment[..dot],
+        _ => &segment,
+    }
+    .replace(&['-', '_', '+'][..], " ");
+    if !slug.chars().any(char::is_alphabetic) {
+        return None;
+    }
+    let words: Vec<String> = slug
+        .split_whitespace()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    Some(words.join(" "))
+}
+
+/// Turns a page title like "Chapter 12 – Novel Name – Read Free Online" into
+/// "Chapter 12". Parts that are the novel's name or look like the site's name or
+/// SEO filler are dropped, and "Chapter 12 - The Duel" becomes "Chapter 12: The Duel".
+///
+/// Falls back to the title with its whitespace collapsed if nothing is left.
+pub fn clean_title(title: &str, novel_title: &str) -> String {
+    let title = collapse_whitespace(title);
+    let novel_title = collapse_whitespace(novel_title).to_lowercase();
+
+    let parts: Vec<&str> = SEPARATOR_REGEX
+        .split(&title)
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .filter(|part| part.to_lowercase() != novel_title && !JUNK_REGEX.is_match(part))
+        .collect();
+
+    match parts.as_slice() {
+        [] => title,
+        [first, rest @ ..] => {
+            let first = match CHAPTER_NUMBER_REGEX.captures(first) {
+                Some(capture) => format!("Chapter {}", &capture[1]),
+                None => first.to_string(),
+            };
+            if rest.is_empty() {
+                first
+            } else if first.starts_with("Chapter ") && rest.len() == 1 {
+                format!("{}: {}", first, rest[0])
+            } else {
+                format!("{} - {}", first, rest.join(" - "))
+            }
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
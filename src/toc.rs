@@ -0,0 +1,178 @@
+use crate::sanitizer::{escape_attribute, escape_text};
+
+use regex::Regex;
+use std::ops::Range;
+use std::str::FromStr;
+
+lazy_static! {
+    // "Book 3, Chapter 12", "Volume 2 - Chapter 5: Title", "Arc IV Chapter 1"
+    static ref VOLUME_PREFIX_REGEX: Regex = Regex::new(
+        r"(?i)^((?:book|volume|vol\.?|arc|part)\s*(?:\d+|[ivxlc]+\b))\s*(?:[,:|-]|–|—)?\s*(\S.*)$"
+    )
+    .unwrap();
+}
+
+/// Consecutive chapters that belong to the same volume (or arc, book, ...)
+#[derive(Debug, PartialEq)]
+pub struct VolumeGroup {
+    /// `None` for chapters that aren't part of a volume
+    pub title: Option<String>,
+    /// Indexes of the chapters in the group
+    pub chapters: Range<usize>,
+}
+
+/// Splits "Book 3, Chapter 12" into the volume ("Book 3") and the rest
+/// ("Chapter 12")
+pub fn split_volume(title: &str) -> Option<(&str, &str)> {
+    let capture = VOLUME_PREFIX_REGEX.captures(title.trim())?;
+    Some((capture.get(1)?.as_str(), capture.get(2)?.as_str()))
+}
+
+/// Groups chapters by the volume their titles start with, for a two level
+/// table of contents. Returns `None` if there aren't at least two volumes with
+/// a few chapters each, the table of contents is better off flat then.
+pub fn group_by_volume<S: AsRef<str>>(titles: &[S]) -> Option<Vec<VolumeGroup>> {
+    let groups = group_consecutive(
+        titles
+            .iter()
+            .map(|title| split_volume(title.as_ref()).map(|(volume, _)| volume)),
+    );
+
+    let volumes: Vec<&VolumeGroup> = groups
+        .iter()
+        .filter(|group| group.title.is_some())
+        .collect();
+    let chapters_in_volumes: usize = volumes.iter().map(|group| group.chapters.len()).sum();
+    // Every chapter being its own "Part 1", "Part 2", ... isn't a volume structure
+    if volumes.len() >= 2 && chapters_in_volumes >= volumes.len() * 2 {
+        Some(groups)
+    } else {
+        None
+    }
+}
+
+/// Groups chapters by the volume the site lists them under. Returns `None`
+/// unless there are at least two volumes.
+pub fn group_by_site_volume(volumes: &[Option<&str>]) -> Option<Vec<VolumeGroup>> {
+    let groups = group_consecutive(volumes.iter().copied());
+    if groups.iter().filter(|group| group.title.is_some()).count() >= 2 {
+        Some(groups)
+    } else {
+        None
+    }
+}
+
+/// Puts consecutive chapters in the same volume in a group
+fn group_consecutive<'a>(volumes: impl Iterator<Item = Option<&'a str>>) -> Vec<VolumeGroup> {
+    let mut groups: Vec<VolumeGroup> = Vec::new();
+    for (i, volume) in volumes.enumerate() {
+        match groups.last_mut() {
+            Some(group) if same_volume(group.title.as_deref(), volume) => {
+                group.chapters.end = i + 1;
+            }
+            _ => groups.push(VolumeGroup {
+                title: volume.map(str::to_string),
+                chapters: i..i + 1,
+            }),
+        }
+    }
+    groups
+}
+
+fn same_volume(a: Option<&str>, b: Option<&str>) -> bool {
+    let normalize = |title: &str| title.split_whitespace().collect::<String>().to_lowercase();
+    match (a, b) {
+        (Some(a), Some(b)) => normalize(a) == normalize(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Page that opens a volume, what its entry in the table of contents points at
+pub fn volume_xhtml(title: &str) -> String {
+    let title = escape_text(title);
+    format!(
+        r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+    <head>
+        <title>{0}</title>
+        <link rel="stylesheet" type="text/css" href="stylesheet.css"/>
+    </head>
+    <body>
+        <h1>{0}</h1>
+    </body>
+</html>"#,
+        title
+    )
+}
+
+/// Where the table of contents page goes in the book
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TocPosition {
+    /// Before the first chapter, after the title page and synopsis
+    Start,
+    /// After the last chapter
+    End,
+}
+
+impl FromStr for TocPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => Ok(TocPosition::Start),
+            "end" => Ok(TocPosition::End),
+            _ => Err(format!(
+                "Unknown table of contents position {}, expected start or end",
+                s
+            )),
+        }
+    }
+}
+
+/// An entry on the table of contents page, volumes have their chapters as
+/// children
+pub struct TocEntry {
+    pub href: String,
+    pub title: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// The table of contents as a page of the book, with a link to every volume
+/// and chapter
+pub fn toc_xhtml(entries: &[TocEntry]) -> String {
+    format!(
+        r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+    <head>
+        <title>Contents</title>
+        <link rel="stylesheet" type="text/css" href="stylesheet.css"/>
+    </head>
+    <body>
+        <div class="toc">
+            <h1>Contents</h1>
+            {}
+        </div>
+    </body>
+</html>"#,
+        toc_list(entries)
+    )
+}
+
+fn toc_list(entries: &[TocEntry]) -> String {
+    let items: String = entries
+        .iter()
+        .map(|entry| {
+            let children = if entry.children.is_empty() {
+                String::new()
+            } else {
+                toc_list(&entry.children)
+            };
+            format!(
+                r#"<li><a href="{}">{}</a>{}</li>"#,
+                escape_attribute(&entry.href),
+                escape_text(&entry.title),
+                children
+            )
+        })
+        .collect();
+    format!("<ol>{}</ol>", items)
+}
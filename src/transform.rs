@@ -0,0 +1,184 @@
+use crate::allowlist::Allowlist;
+use crate::filters::{ContentFilter, StripRule};
+use crate::sanitizer::sanitize_html;
+use html5ever::tendril::StrTendril;
+use regex::Regex;
+use scraper::{Html, Node};
+use serde::Deserialize;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A step chapters go through between being extracted and going into the
+/// book. It gets the chapter's html and returns it changed, transforms of
+/// your own go in `BookOptions::transforms`.
+pub trait ContentTransform: Send + Sync {
+    fn apply(&self, html: &str) -> String;
+}
+
+impl ContentTransform for ContentFilter {
+    fn apply(&self, html: &str) -> String {
+        ContentFilter::apply(self, html)
+    }
+}
+
+/// Turns the chapter into the xhtml EPUB needs, see `sanitize_html`. It's
+/// always the last step so nothing after it can break the markup.
+pub struct Sanitize;
+
+impl ContentTransform for Sanitize {
+    fn apply(&self, html: &str) -> String {
+        sanitize_html(html)
+    }
+}
+
+/// Replaces text matching a regex, i.e. to fix a translator's misspelling of a
+/// name. Only the text is touched, never the tags or their attributes.
+/// `$1`, `$name` and the like in the replacement are the regex's groups.
+pub struct Replace {
+    regex: Regex,
+    replacement: String,
+}
+
+impl Replace {
+    pub fn new(regex: Regex, replacement: &str) -> Self {
+        Replace {
+            regex,
+            replacement: replacement.to_string(),
+        }
+    }
+}
+
+impl ContentTransform for Replace {
+    fn apply(&self, html: &str) -> String {
+        let mut document = Html::parse_document(html);
+        let ids: Vec<_> = document
+            .tree
+            .nodes()
+            .filter(
+                |node| matches!(node.value(), Node::Text(text) if self.regex.is_match(&text.text)),
+            )
+            .map(|node| node.id())
+            .collect();
+        if ids.is_empty() {
+            return html.to_string();
+        }
+        for id in ids {
+            if let Some(mut node) = document.tree.get_mut(id) {
+                if let Node::Text(text) = node.value() {
+                    let replaced = self
+                        .regex
+                        .replace_all(&text.text, self.replacement.as_str())
+                        .into_owned();
+                    text.text = StrTendril::from(replaced);
+                }
+            }
+        }
+        document.root_element().html()
+    }
+}
+
+/// Transforms run one after the other, each on what the one before returned
+#[derive(Clone, Default)]
+pub struct TransformPipeline {
+    transforms: Vec<Arc<dyn ContentTransform>>,
+}
+
+impl TransformPipeline {
+    pub fn new() -> Self {
+        TransformPipeline::default()
+    }
+
+    /// Adds a transform to run after the ones already there
+    pub fn then(mut self, transform: Arc<dyn ContentTransform>) -> Self {
+        self.transforms.push(transform);
+        self
+    }
+
+    /// What every chapter goes through: the strip rules, then `transforms` in
+    /// order, then the `Allowlist` when `allowlist` is set, then sanitizing
+    pub fn for_chapters(
+        filter: ContentFilter,
+        transforms: &[Arc<dyn ContentTransform>],
+        allowlist: bool,
+    ) -> Self {
+        let pipeline = transforms.iter().fold(
+            TransformPipeline::new().then(Arc::new(filter)),
+            |pipeline, transform| pipeline.then(transform.clone()),
+        );
+        // After the transforms, so nothing they add gets past it
+        let pipeline = if allowlist {
+            pipeline.then(Arc::new(Allowlist))
+        } else {
+            pipeline
+        };
+        pipeline.then(Arc::new(Sanitize))
+    }
+
+    pub fn run(&self, html: &str) -> String {
+        self.transforms
+            .iter()
+            .fold(html.to_string(), |html, transform| transform.apply(&html))
+    }
+}
+
+/// A transform as written in the config file
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum TransformConfig {
+    Replace {
+        replace: String,
+        #[serde(default)]
+        with: String,
+    },
+    Strip {
+        strip: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct TransformsConfig {
+    #[serde(default)]
+    transform: Vec<TransformConfig>,
+}
+
+/// Reads transforms from a TOML file, they run in the order they're listed:
+///
+/// ```toml
+/// [[transform]]
+/// replace = "Mister (\\w+)"
+/// with = "Mr. $1"
+///
+/// [[transform]]
+/// strip = "css:div.translator-ad"
+/// ```
+///
+/// `replace` is a regex for text to replace `with`, which is empty when left
+/// out. `strip` is a rule like `--strip` takes.
+pub fn from_file(path: impl AsRef<Path>) -> Result<Vec<Arc<dyn ContentTransform>>, Box<dyn Error>> {
+    let config = std::fs::read_to_string(path)?;
+    from_toml(&config)
+}
+
+pub fn from_toml(config: &str) -> Result<Vec<Arc<dyn ContentTransform>>, Box<dyn Error>> {
+    let config: TransformsConfig = toml::from_str(config)?;
+    config
+        .transform
+        .into_iter()
+        .map(
+            |transform| -> Result<Arc<dyn ContentTransform>, Box<dyn Error>> {
+                match transform {
+                    TransformConfig::Replace { replace, with } => {
+                        let regex = Regex::new(&replace)
+                            .map_err(|err| format!("Invalid regex {}: {}", replace, err))?;
+                        Ok(Arc::new(Replace::new(regex, &with)))
+                    }
+                    TransformConfig::Strip { strip } => {
+                        let rule: StripRule = strip.parse()?;
+                        Ok(Arc::new(ContentFilter::from_rules(vec![rule])))
+                    }
+                }
+            },
+        )
+        .collect()
+}
@@ -0,0 +1,179 @@
+use crate::transform::ContentTransform;
+use ego_tree::NodeId;
+use encoding_rs::WINDOWS_1252;
+use html5ever::tendril::StrTendril;
+use regex::{Captures, Regex};
+use scraper::{Html, Node};
+
+lazy_static! {
+    // UTF-8 that was read as Windows-1252, i.e. "â€™" for "’": a lead byte
+    // followed by the right number of continuation bytes, each as the
+    // character Windows-1252 has for it
+    static ref MOJIBAKE_REGEX: Regex = {
+        let continuation = "[\u{80}-\u{BF}€‚ƒ„…†‡ˆ‰Š‹ŒŽ‘’“”•–—˜™š›œžŸ]";
+        Regex::new(&format!(
+            "[\u{C2}-\u{DF}]{c}|[\u{E0}-\u{EF}]{c}{{2}}|[\u{F0}-\u{F4}]{c}{{3}}",
+            c = continuation
+        ))
+        .unwrap()
+    };
+    static ref ELLIPSIS_REGEX: Regex = Regex::new(r"\.\s?\.\s?\.").unwrap();
+    static ref DASH_REGEX: Regex = Regex::new(r"-{2,3}").unwrap();
+}
+
+/// Elements whose text is left exactly as it is
+const VERBATIM_ELEMENTS: [&str; 5] = ["pre", "code", "kbd", "script", "style"];
+
+/// Elements that start a new line, quotes right after one open
+const BLOCK_ELEMENTS: [&str; 16] = [
+    "p",
+    "div",
+    "br",
+    "hr",
+    "li",
+    "blockquote",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "td",
+    "th",
+    "dt",
+    "dd",
+];
+
+/// Cleans up the typography of scraped text: repairs mojibake, curls straight
+/// quotes, turns `--` into em dashes and `...` into ellipses, and collapses
+/// runs of blank paragraphs into one.
+pub struct FixTypography;
+
+impl ContentTransform for FixTypography {
+    fn apply(&self, html: &str) -> String {
+        let mut document = Html::parse_document(html);
+        fix_text(&mut document);
+        collapse_blank_paragraphs(&mut document);
+        document.root_element().html()
+    }
+}
+
+fn fix_text(document: &mut Html) {
+    // Quotes are curled by the character before them, which can be in the
+    // text node before (i.e. `"<em>Run</em>"`)
+    let mut previous = None;
+    let mut fixed: Vec<(NodeId, String)> = Vec::new();
+    for node in document.tree.root().descendants() {
+        let text = match node.value() {
+            Node::Text(text) => text,
+            Node::Element(element) => {
+                if BLOCK_ELEMENTS.contains(&element.name()) {
+                    previous = None;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let verbatim = node.ancestors().any(|ancestor| match ancestor.value() {
+            Node::Element(element) => VERBATIM_ELEMENTS.contains(&element.name()),
+            _ => false,
+        });
+        if verbatim {
+            previous = text.text.chars().last().or(previous);
+            continue;
+        }
+        let text = fix_dashes_and_ellipses(&fix_mojibake(&text.text));
+        let text = curl_quotes(&text, previous);
+        previous = text.chars().last().or(previous);
+        fixed.push((node.id(), text));
+    }
+
+    for (id, text) in fixed {
+        if let Some(mut node) = document.tree.get_mut(id) {
+            if let Node::Text(node_text) = node.value() {
+                if *node_text.text != *text {
+                    node_text.text = StrTendril::from(text);
+                }
+            }
+        }
+    }
+}
+
+/// Undoes UTF-8 that was decoded as Windows-1252, sequences that don't come
+/// out as valid UTF-8 were meant the way they are
+pub fn fix_mojibake(text: &str) -> String {
+    MOJIBAKE_REGEX
+        .replace_all(text, |capture: &Captures| {
+            let (bytes, _, unmappable) = WINDOWS_1252.encode(&capture[0]);
+            match std::str::from_utf8(&bytes) {
+                Ok(fixed) if !unmappable => fixed.to_string(),
+                _ => capture[0].to_string(),
+            }
+        })
+        // The last byte of ” isn't a character in Windows-1252, so it usually
+        // got lost along the way
+        .replace("â€", "”")
+}
+
+fn fix_dashes_and_ellipses(text: &str) -> String {
+    let text = ELLIPSIS_REGEX.replace_all(text, "…");
+    DASH_REGEX.replace_all(&text, "—").into_owned()
+}
+
+/// Turns straight quotes into curly ones. A quote opens after whitespace, an
+/// opening bracket or a dash (or at the start of the text), and closes
+/// anywhere else, which makes apostrophes come out right too.
+fn curl_quotes(text: &str, mut previous: Option<char>) -> String {
+    let mut curled = String::with_capacity(text.len());
+    for c in text.chars() {
+        let opens = match previous {
+            None => true,
+            Some(p) => p.is_whitespace() || ['(', '[', '{', '—', '–', '“', '‘'].contains(&p),
+        };
+        curled.push(match c {
+            '"' if opens => '“',
+            '"' => '”',
+            '\'' if opens => '‘',
+            '\'' => '’',
+            c => c,
+        });
+        previous = Some(c);
+    }
+    curled
+}
+
+/// Leaves one blank paragraph where there were several in a row, i.e. from
+/// `<p>&nbsp;</p>` spacers
+fn collapse_blank_paragraphs(document: &mut Html) {
+    let is_blank = |node: ego_tree::NodeRef<Node>| match node.value() {
+        Node::Element(element) if element.name() == "p" => {
+            node.descendants()
+                .all(|descendant| match descendant.value() {
+                    Node::Text(text) => text.text.trim().is_empty(),
+                    Node::Element(element) => ["p", "br", "span"].contains(&element.name()),
+                    _ => true,
+                })
+        }
+        _ => false,
+    };
+
+    let mut extra = Vec::new();
+    for node in document.tree.root().descendants() {
+        if !is_blank(node) {
+            continue;
+        }
+        // Whitespace between the paragraphs doesn't count
+        let previous = node.prev_siblings().find(|sibling| match sibling.value() {
+            Node::Text(text) => !text.text.trim().is_empty(),
+            _ => true,
+        });
+        if matches!(previous, Some(previous) if is_blank(previous)) {
+            extra.push(node.id());
+        }
+    }
+    for id in extra {
+        if let Some(mut node) = document.tree.get_mut(id) {
+            node.detach();
+        }
+    }
+}
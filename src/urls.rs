@@ -0,0 +1,109 @@
+use regex::Regex;
+use reqwest::Url;
+use std::collections::HashSet;
+
+lazy_static! {
+    // Ways sites number the pages of a chapter: .../page/2, .../2, ..._2.html
+    static ref PAGE_PATH_REGEXES: Vec<Regex> = vec![
+        Regex::new(r"^(.*)/page/(\d+)/?()$").unwrap(),
+        Regex::new(r"^(.*)/(\d+)/?()$").unwrap(),
+        Regex::new(r"^(.*)[-_](\d+)(\.[A-Za-z]+)$").unwrap(),
+    ];
+}
+
+/// Query parameters sites number the pages of a chapter with, i.e. `?page=2`
+const PAGE_PARAMS: [&str; 4] = ["page", "p", "pg", "part"];
+
+/// Query parameters added by analytics and share buttons, they don't change
+/// which chapter a url points at
+const TRACKING_PARAMS: [&str; 9] = [
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "utm_term",
+    "utm_content",
+    "fbclid",
+    "gclid",
+    "ref",
+    "_ga",
+];
+
+/// Strips fragments and tracking query parameters, so links to the same
+/// chapter compare equal. Urls that don't parse are left alone.
+pub fn clean_chapter_url(url: &str) -> String {
+    let mut parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_string(),
+    };
+    parsed.set_fragment(None);
+
+    let query: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(key, _)| !TRACKING_PARAMS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(query);
+    }
+
+    parsed.into()
+}
+
+/// Which page of the chapter at `chapter_url` the url is, for sites that split
+/// chapters over several pages. It has to be the chapter's url with a page
+/// number added, so links to other chapters are never taken for one.
+pub fn chapter_page(url: &str, chapter_url: &str) -> Option<usize> {
+    let trim = |url: &str| clean_chapter_url(url).trim_end_matches('/').to_string();
+    let chapter_url = trim(chapter_url);
+    let url = trim(url);
+    let parsed = Url::parse(&url).ok()?;
+
+    // The page number in the query
+    let (pages, query): (Vec<_>, Vec<_>) = parsed
+        .query_pairs()
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .partition(|(key, _)| PAGE_PARAMS.contains(&key.to_lowercase().as_str()));
+    if let [(_, page)] = pages.as_slice() {
+        let mut base = parsed.clone();
+        if query.is_empty() {
+            base.set_query(None);
+        } else {
+            base.query_pairs_mut().clear().extend_pairs(query);
+        }
+        if let Ok(page) = page.parse() {
+            if trim(base.as_str()) == chapter_url {
+                return Some(page);
+            }
+        }
+    }
+
+    // The page number in the path
+    let mut without_query = parsed;
+    let query = without_query.query().map(|query| format!("?{}", query));
+    without_query.set_query(None);
+    PAGE_PATH_REGEXES.iter().find_map(|regex| {
+        let captures = regex.captures(without_query.as_str())?;
+        let base = format!(
+            "{}{}{}",
+            &captures[1],
+            &captures[3],
+            query.as_deref().unwrap_or("")
+        );
+        if trim(&base) == chapter_url {
+            captures[2].parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Cleans up the chapter list, only the first link to each chapter is kept
+pub fn normalize_chapter_urls(urls: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    urls.iter()
+        .map(|url| clean_chapter_url(url))
+        .filter(|url| seen.insert(url.clone()))
+        .collect()
+}
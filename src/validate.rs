@@ -0,0 +1,380 @@
+use crate::book::{Book, EpubOptions};
+
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+lazy_static! {
+    static ref IMG_SRC_REGEX: Regex = Regex::new(r#"<img\b[^>]*?\ssrc="([^"]*)""#).unwrap();
+}
+
+/// Entities xml knows without a DTD, anything else (i.e. `&nbsp;`) breaks the
+/// chapter in strict readers
+const XML_ENTITIES: [&str; 5] = ["amp", "lt", "gt", "quot", "apos"];
+
+/// Something about the book that's likely to make a reader reject it or show
+/// it wrong
+#[derive(Debug, Clone)]
+pub struct Problem {
+    /// Number and title of the chapter the problem is in, `None` for the book
+    pub chapter: Option<(usize, String)>,
+    pub message: String,
+}
+
+impl Problem {
+    fn book(message: impl Into<String>) -> Self {
+        Problem {
+            chapter: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.chapter {
+            Some((number, title)) => write!(f, "chapter {} ({}): {}", number, title, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Checks the book before it's written as an EPUB: every chapter has to be
+/// well-formed xhtml, everything the chapters point at has to be in the book
+/// and the metadata readers require has to be there
+pub fn validate_book(book: &Book, options: &EpubOptions) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let overview = &book.overview;
+    if overview.title.trim().is_empty() || overview.title == "no_title" {
+        problems.push(Problem::book("The book has no title"));
+    }
+    if overview.author.trim().is_empty() || overview.author == "no_author" {
+        problems.push(Problem::book("The book has no author"));
+    }
+    if overview.language.as_deref().unwrap_or_default().is_empty() {
+        problems.push(Problem::book(
+            "The book has no language, EPUBs require one (set it with --lang)",
+        ));
+    }
+    if let Some(cover) = &book.cover {
+        if cover.mimetype != "image/png" && cover.mimetype != "image/jpeg" {
+            problems.push(Problem::book(format!(
+                "The cover is {}, not PNG or JPEG",
+                cover.mimetype
+            )));
+        }
+    }
+    if let Some(font) = &options.font {
+        if !font.is_file() {
+            problems.push(Problem::book(format!(
+                "The font {} doesn't exist",
+                font.display()
+            )));
+        }
+    }
+    if book.chapters.is_empty() {
+        problems.push(Problem::book("The book has no chapters"));
+    }
+
+    let mut numbers = HashSet::new();
+    // Image path in the EPUB to its type, chapters sharing an image have to
+    // agree on it since it's only stored once
+    let mut image_types: HashMap<&str, &str> = HashMap::new();
+    for chapter in &book.chapters {
+        let mut report = |message: String| {
+            problems.push(Problem {
+                chapter: Some((chapter.number, chapter.title.clone())),
+                message,
+            })
+        };
+        if !numbers.insert(chapter.number) {
+            report(format!(
+                "more than one chapter would be stored as c{}.xhtml",
+                chapter.number
+            ));
+        }
+
+        let content = match chapter.content() {
+            Ok(content) => content,
+            Err(err) => {
+                report(format!("couldn't read it back from the spool: {}", err));
+                continue;
+            }
+        };
+        if let Err(err) = check_xhtml(&content) {
+            report(format!("malformed xhtml, {}", err));
+        }
+
+        for image in &chapter.images {
+            if !image.file.is_file() {
+                report(format!(
+                    "the image {} is missing from the spool",
+                    image.path
+                ));
+            }
+            match image_types.insert(&image.path, &image.mimetype) {
+                Some(mimetype) if mimetype != image.mimetype => report(format!(
+                    "the image {} is stored as both {} and {}",
+                    image.path, mimetype, image.mimetype
+                )),
+                _ => {}
+            }
+        }
+        for capture in IMG_SRC_REGEX.captures_iter(&content) {
+            let src = &capture[1];
+            if src.starts_with("http:") || src.starts_with("https:") {
+                report(format!(
+                    "the image {} couldn't be downloaded, readers won't show it offline",
+                    src
+                ));
+            } else if !src.starts_with("data:")
+                && !chapter.images.iter().any(|image| image.path == src)
+            {
+                report(format!("the image {} isn't in the book", src));
+            }
+        }
+    }
+    problems
+}
+
+/// Checks that `xhtml` is a well-formed xml document: one root element, every
+/// tag closed in the right order, quoted and unique attributes and no entities
+/// xml doesn't know. The error says what's wrong and on which line.
+pub fn check_xhtml(xhtml: &str) -> Result<(), String> {
+    XmlChecker {
+        xml: xhtml,
+        position: 0,
+    }
+    .check()
+    .map_err(|(position, message)| format!("line {}: {}", line_at(xhtml, position), message))
+}
+
+fn line_at(text: &str, position: usize) -> usize {
+    text[..position].matches('\n').count() + 1
+}
+
+/// Where an error was found and what it is
+type XmlError = (usize, String);
+
+struct XmlChecker<'a> {
+    xml: &'a str,
+    position: usize,
+}
+
+impl<'a> XmlChecker<'a> {
+    fn check(&mut self) -> Result<(), XmlError> {
+        // Open elements and where they were opened
+        let mut open: Vec<(&str, usize)> = Vec::new();
+        let mut seen_root = false;
+        while self.position < self.xml.len() {
+            let rest = &self.xml[self.position..];
+            let start = self.position;
+            if rest.starts_with("<?") {
+                self.skip_past("?>", "processing instruction")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->", "comment")?;
+            } else if rest.starts_with("<![CDATA[") {
+                if open.is_empty() {
+                    return Err((start, "CDATA outside of the root element".into()));
+                }
+                self.skip_past("]]>", "CDATA section")?;
+            } else if rest.starts_with("<!") {
+                if seen_root {
+                    return Err((start, "DOCTYPE after the root element".into()));
+                }
+                self.skip_past(">", "DOCTYPE")?;
+            } else if rest.starts_with("</") {
+                self.position += 2;
+                let name = self.name()?;
+                self.skip_whitespace();
+                self.expect('>')?;
+                match open.pop() {
+                    Some((opened, _)) if opened == name => {}
+                    Some((opened, line_start)) => {
+                        return Err((
+                            start,
+                            format!(
+                                "</{}> closes <{}> from line {}",
+                                name,
+                                opened,
+                                line_at(self.xml, line_start)
+                            ),
+                        ))
+                    }
+                    None => return Err((start, format!("</{}> closes nothing", name))),
+                }
+            } else if rest.starts_with('<') {
+                if open.is_empty() && seen_root {
+                    return Err((start, "more than one root element".into()));
+                }
+                self.position += 1;
+                let name = self.name()?;
+                if !self.attributes()? {
+                    open.push((name, start));
+                }
+                seen_root = true;
+            } else {
+                let end = rest.find('<').map_or(self.xml.len(), |i| start + i);
+                let text = &self.xml[start..end];
+                if open.is_empty() && !text.trim().is_empty() {
+                    return Err((start, "text outside of the root element".into()));
+                }
+                self.check_text(text, start)?;
+                self.position = end;
+            }
+        }
+
+        match open.last() {
+            Some((name, start)) => Err((*start, format!("<{}> is never closed", name))),
+            None if !seen_root => Err((0, "no root element".into())),
+            None => Ok(()),
+        }
+    }
+
+    fn skip_past(&mut self, end: &str, what: &str) -> Result<(), XmlError> {
+        match self.xml[self.position..].find(end) {
+            Some(i) => {
+                self.position += i + end.len();
+                Ok(())
+            }
+            None => Err((self.position, format!("unterminated {}", what))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.xml[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.xml[self.position..].chars().next()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), XmlError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += c.len_utf8();
+                Ok(())
+            }
+            Some(c) => Err((
+                self.position,
+                format!("expected '{}' but found '{}'", expected, c),
+            )),
+            None => Err((
+                self.position,
+                format!("expected '{}' but the document ended", expected),
+            )),
+        }
+    }
+
+    /// Reads an element or attribute name
+    fn name(&mut self) -> Result<&'a str, XmlError> {
+        let xml: &'a str = self.xml;
+        let rest = &xml[self.position..];
+        let length = rest
+            .char_indices()
+            .find(|&(i, c)| !is_name_char(c) || (i == 0 && !is_name_start(c)))
+            .map_or(rest.len(), |(i, _)| i);
+        if length == 0 {
+            return Err((self.position, "expected a name".into()));
+        }
+        self.position += length;
+        Ok(&rest[..length])
+    }
+
+    /// Reads the attributes up to the end of the start tag, returns whether the
+    /// tag closes itself (`<br/>`)
+    fn attributes(&mut self) -> Result<bool, XmlError> {
+        let mut names = HashSet::new();
+        loop {
+            let had_whitespace = {
+                let before = self.position;
+                self.skip_whitespace();
+                self.position > before
+            };
+            match self.peek() {
+                Some('>') => {
+                    self.position += 1;
+                    return Ok(false);
+                }
+                Some('/') => {
+                    self.position += 1;
+                    self.expect('>')?;
+                    return Ok(true);
+                }
+                None => return Err((self.position, "unterminated tag".into())),
+                Some(_) if !had_whitespace => {
+                    return Err((self.position, "attributes need spaces between them".into()))
+                }
+                Some(_) => {}
+            }
+
+            let start = self.position;
+            let name = self.name()?;
+            if !names.insert(name) {
+                return Err((start, format!("the {} attribute is repeated", name)));
+            }
+            self.skip_whitespace();
+            if self.peek() != Some('=') {
+                return Err((start, format!("the {} attribute has no value", name)));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+            let quote = match self.peek() {
+                Some(c) if c == '"' || c == '\'' => c,
+                _ => {
+                    return Err((
+                        start,
+                        format!("the {} attribute's value isn't quoted", name),
+                    ))
+                }
+            };
+            self.position += 1;
+            let value_start = self.position;
+            let value_end = match self.xml[value_start..].find(quote) {
+                Some(i) => value_start + i,
+                None => return Err((start, format!("the {} attribute is never closed", name))),
+            };
+            let value = &self.xml[value_start..value_end];
+            if let Some(i) = value.find('<') {
+                return Err((value_start + i, format!("'<' in the {} attribute", name)));
+            }
+            self.check_text(value, value_start)?;
+            self.position = value_end + 1;
+        }
+    }
+
+    /// Checks every `&` in text starts an entity xml knows
+    fn check_text(&self, text: &str, offset: usize) -> Result<(), XmlError> {
+        for (i, _) in text.match_indices('&') {
+            let entity = text[i + 1..].split(';').next().unwrap_or_default();
+            let terminated = text[i + 1..].contains(';');
+            let known = if let Some(number) = entity.strip_prefix("#x") {
+                u32::from_str_radix(number, 16).is_ok()
+            } else if let Some(number) = entity.strip_prefix('#') {
+                number.parse::<u32>().is_ok()
+            } else {
+                XML_ENTITIES.contains(&entity)
+            };
+            if !terminated || !known {
+                let message =
+                    if terminated && !entity.is_empty() && entity.chars().all(is_name_char) {
+                        format!("&{}; isn't an xml entity", entity)
+                    } else {
+                        "a bare & has to be written as &amp;".to_string()
+                    };
+                return Err((offset + i, message));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':'
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_numeric() || c == '-' || c == '.'
+}
@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Longest interval there is, much longer and the time it's up can't be
+/// represented
+const MAX_INTERVAL_SECONDS: u64 = 3650 * 24 * 60 * 60;
+
+/// How long `watch` waits between checks for new chapters, i.e. `30m` or `6h`
+#[derive(Debug, Clone, Copy)]
+pub struct Interval {
+    count: u64,
+    /// `s`, `m`, `h` or `d`
+    unit: char,
+}
+
+impl Interval {
+    pub fn hours(count: u64) -> Self {
+        Interval { count, unit: 'h' }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.count * unit_seconds(self.unit))
+    }
+}
+
+fn unit_seconds(unit: char) -> u64 {
+    match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        _ => 24 * 60 * 60,
+    }
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    /// Parses a number followed by `s`, `m`, `h` or `d`, up to 3650 days
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid interval {}, i.e. 30m or 6h", s);
+        let s = s.trim();
+        let unit = s.chars().last().ok_or_else(invalid)?;
+        if !['s', 'm', 'h', 'd'].contains(&unit) {
+            return Err(invalid());
+        }
+        let count: u64 = s[..s.len() - 1].trim().parse().map_err(|_| invalid())?;
+        if count == 0 {
+            return Err(invalid());
+        }
+        match count.checked_mul(unit_seconds(unit)) {
+            Some(seconds) if seconds <= MAX_INTERVAL_SECONDS => Ok(Interval { count, unit }),
+            _ => Err(format!("Interval {} is too long, 3650d is the most", s)),
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.count, self.unit)
+    }
+}
+
+/// Runs the user's `--exec` command with the shell after the book was
+/// written, i.e. to copy it to an e-reader. The book's path and how many
+/// chapters were added are in `BOX2EPUB_BOOK` and `BOX2EPUB_NEW_CHAPTERS`.
+pub async fn run_hook(
+    command: &str,
+    book: &Path,
+    new_chapters: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let status = shell
+        .arg(command)
+        .env("BOX2EPUB_BOOK", book)
+        .env("BOX2EPUB_NEW_CHAPTERS", new_chapters.to_string())
+        .status()
+        .await
+        .map_err(|err| format!("Couldn't run {}: {}", command, err))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} exited with {}", command, status).into())
+    }
+}
@@ -0,0 +1,113 @@
+//! Checks what the `Allowlist` lets through into chapters, rule by rule.
+
+use box2epub::allowlist::Allowlist;
+use box2epub::transform::ContentTransform;
+
+/// A chapter like the default template makes, with `head` and `body` in it
+fn chapter(head: &str, body: &str) -> String {
+    format!(
+        r#"<html xmlns="http://www.w3.org/1999/xhtml"><head><title>1. Low Tide</title><link rel="stylesheet" type="text/css" href="stylesheet.css"/>{}</head><body>{}</body></html>"#,
+        head, body
+    )
+}
+
+fn allowed(head: &str, body: &str) -> String {
+    Allowlist.apply(&chapter(head, body))
+}
+
+#[test]
+fn chapters_that_pass_are_left_alone() {
+    let html = chapter(
+        "",
+        r#"<p class="note">The ledger was <em>open</em>.</p><img src="images/1.png" alt="A page" width="300"/>"#,
+    );
+    assert_eq!(Allowlist.apply(&html), html);
+}
+
+#[test]
+fn scripts_and_embedded_pages_are_removed_with_their_content() {
+    let html = allowed(
+        "<script>track()</script>",
+        r#"<p>Text</p><iframe src="https://ads.example.com/">Ad</iframe><noscript>Enable JS</noscript>"#,
+    );
+    assert!(html.contains("<p>Text</p>"));
+    for gone in &["script", "track()", "iframe", "Ad", "Enable JS"] {
+        assert!(!html.contains(gone), "{} is still in {}", gone, html);
+    }
+}
+
+#[test]
+fn unknown_elements_are_unwrapped() {
+    let html = allowed("", "<p><font color=\"red\">Red</font> text</p>");
+    assert!(html.contains("<p>Red text</p>"), "{}", html);
+}
+
+#[test]
+fn event_handlers_are_removed() {
+    let html = allowed("", r#"<p onclick="track()" class="note">Text</p>"#);
+    assert!(html.contains(r#"<p class="note">Text</p>"#), "{}", html);
+}
+
+#[test]
+fn meta_refresh_doesnt_redirect() {
+    let html = allowed(
+        r#"<meta http-equiv="refresh" content="0; url=https://example.com/"/>"#,
+        "<p>Text</p>",
+    );
+    assert!(!html.contains("http-equiv"), "{}", html);
+}
+
+#[test]
+fn only_the_books_own_stylesheets_are_linked() {
+    let html = allowed(
+        r#"<link rel="stylesheet" href="https://example.com/site.css"/><link rel="stylesheet" href="//example.com/cdn.css"/><link rel="prefetch" href="next.xhtml"/>"#,
+        "<p>Text</p>",
+    );
+    // The order of the attributes isn't kept
+    assert!(html.contains(r#"href="stylesheet.css""#), "{}", html);
+    assert!(html.contains(r#"type="text/css""#), "{}", html);
+    for gone in &["site.css", "cdn.css", "prefetch"] {
+        assert!(!html.contains(gone), "{} is still in {}", gone, html);
+    }
+}
+
+#[test]
+fn styles_dont_load_anything() {
+    let html = allowed(
+        "<style>@import \"https://example.com/site.css\";</style>",
+        r#"<p style="color: red; background: url(https://example.com/pixel.gif)">Text</p><p style="background-image: u\rl(pixel.gif)">More</p>"#,
+    );
+    assert!(
+        html.contains(r#"<p style="color: red">Text</p>"#),
+        "{}",
+        html
+    );
+    assert!(html.contains("<p>More</p>"), "{}", html);
+    assert!(!html.contains("@import"), "{}", html);
+}
+
+#[test]
+fn urls_that_run_code_are_removed() {
+    let html = allowed(
+        "",
+        r#"<a href="javascript:track()">1</a><a href="VBScript:track">2</a><a href="java	script:track()">3</a><a href="data:text/html,&lt;script&gt;track()&lt;/script&gt;">4</a><img src="data:image/svg+xml,&lt;svg/&gt;" alt="5"/><a href="c2.xhtml">6</a><img src="data:image/png;base64,iVBORw0KGgo=" alt="7"/>"#,
+    );
+    for number in 1..=4 {
+        assert!(html.contains(&format!("<a>{}</a>", number)), "{}", html);
+    }
+    assert!(html.contains(r#"<img alt="5">"#), "{}", html);
+    assert!(html.contains(r#"<a href="c2.xhtml">6</a>"#), "{}", html);
+    assert!(html.contains("data:image/png"), "{}", html);
+}
+
+#[test]
+fn tracking_pixels_are_removed() {
+    let html = allowed(
+        "",
+        r#"<img src="https://example.com/a.gif" width="1" height="1"/><img src="https://example.com/b.gif" width="1"/><img src="https://example.com/c.gif" style="height: 0px"/><img src="images/1.png" width="300"/>"#,
+    );
+    for gone in &["a.gif", "b.gif", "c.gif"] {
+        assert!(!html.contains(gone), "{} is still in {}", gone, html);
+    }
+    assert!(html.contains("images/1.png"), "{}", html);
+}
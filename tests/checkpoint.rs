@@ -0,0 +1,20 @@
+//! Checks that a checkpoint is kept to one build at a time, so two builds of
+//! the same novel can't start over on each other's chapters.
+
+use box2epub::checkpoint::Checkpoint;
+
+#[test]
+fn a_locked_checkpoint_turns_other_builds_away() {
+    let cache = tempfile::tempdir().unwrap();
+    let dir = cache.path().join("checkpoints").join("novel");
+    let lock = Checkpoint::lock(&dir).unwrap();
+    let err = Checkpoint::lock(&dir).err().expect("locked twice");
+    assert!(err.to_string().contains("Another build"), "{}", err);
+
+    // Starting over in the checkpoint leaves the lock alone
+    Checkpoint::start(&dir, "https://example.com/novel/").unwrap();
+    assert!(Checkpoint::lock(&dir).is_err());
+
+    drop(lock);
+    Checkpoint::lock(&dir).unwrap();
+}
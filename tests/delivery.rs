@@ -0,0 +1,71 @@
+//! Checks the email books are sent in, without an SMTP server.
+
+use box2epub::delivery::{Delivery, SmtpServer};
+
+fn delivery() -> Delivery {
+    let smtp: SmtpServer = "smtps://smtp.example.com".parse().unwrap();
+    Delivery::new("me@kindle.com", Some("me@example.com"), smtp).unwrap()
+}
+
+/// The message's headers, up to the first empty line
+fn headers(message: &str) -> Vec<&str> {
+    message
+        .split("\r\n")
+        .take_while(|line| !line.is_empty())
+        .collect()
+}
+
+#[test]
+fn line_breaks_in_the_title_dont_add_headers() {
+    let message = delivery().message(
+        "Title\r\nBcc: x",
+        "Title\r\nBcc: x.epub",
+        "application/epub+zip",
+        b"book",
+    );
+    assert!(
+        !message.split("\r\n").any(|line| line.starts_with("Bcc:")),
+        "{}",
+        message
+    );
+    assert!(headers(&message).contains(&"Subject: Title  Bcc: x"));
+    assert!(message.contains("filename=\"Title  Bcc: x.epub\""));
+}
+
+#[test]
+fn titles_that_arent_ascii_are_encoded() {
+    let message = delivery().message("Título\n", "book.epub", "application/epub+zip", b"book");
+    assert!(headers(&message).contains(&"Subject: =?UTF-8?B?VMOtdHVsbyA=?="));
+}
+
+#[test]
+fn long_titles_are_split_into_short_encoded_words() {
+    let title = "Ελληνικά: ο φύλακας του φάρου και τα πλοία που δεν γύρισαν ποτέ 🌊";
+    let message = delivery().message(title, "book.epub", "application/epub+zip", b"book");
+    let lines = headers(&message);
+    let start = lines
+        .iter()
+        .position(|line| line.starts_with("Subject: "))
+        .unwrap();
+    let mut words = vec![&lines[start]["Subject: ".len()..]];
+    words.extend(
+        lines[start + 1..]
+            .iter()
+            .take_while(|line| line.starts_with(' '))
+            .map(|line| &line[1..]),
+    );
+    assert!(words.len() > 1, "{}", message);
+
+    let mut decoded = Vec::new();
+    for word in words {
+        assert!(word.len() <= 75, "{} is too long", word);
+        let encoded = word
+            .strip_prefix("=?UTF-8?B?")
+            .and_then(|word| word.strip_suffix("?="))
+            .unwrap_or_else(|| panic!("{} isn't an encoded word", word));
+        // Every word is whole characters on its own
+        let text = String::from_utf8(base64::decode(encoded).unwrap()).unwrap();
+        decoded.push(text);
+    }
+    assert_eq!(decoded.concat(), title);
+}
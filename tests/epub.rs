@@ -0,0 +1,580 @@
+//! Builds a small book from the pages in `tests/fixtures/epub` with a
+//! `MemoryFetcher` and checks what's in the EPUB: the package document's
+//! manifest and spine, the table of contents and that every page is
+//! well-formed, so a change to how books are put together can't quietly make
+//! ones readers reject.
+
+use box2epub::book::{book_identifier, placeholder_title, Book, BookOptions, EpubOptions};
+use box2epub::book::{EpubVersion, OnError};
+use box2epub::existing::ExistingEpub;
+use box2epub::fetcher::MemoryFetcher;
+use box2epub::sanitizer::decode_entities;
+use box2epub::validate::check_xhtml;
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::Arc;
+use zip::{CompressionMethod, ZipArchive};
+
+const NOVEL_URL: &str = "https://www.royalroad.com/fiction/51730/the-tidewater-ledger/";
+const COVER_URL: &str =
+    "https://www.royalroadcdn.com/public/covers-large/51730-the-tidewater-ledger.png";
+const IMAGE_URL: &str = "https://www.royalroadcdn.com/public/chapters/51730-ledger-page.png";
+const CHAPTER_URLS: [&str; 3] = [
+    "https://www.royalroad.com/fiction/51730/the-tidewater-ledger/chapter/1001/1-low-tide",
+    "https://www.royalroad.com/fiction/51730/the-tidewater-ledger/chapter/1002/2-salt-and-ink",
+    "https://www.royalroad.com/fiction/51730/the-tidewater-ledger/chapter/1003/3-high-water",
+];
+const CHAPTER_TITLES: [&str; 3] = ["1. Low Tide", "2. Salt & Ink", "3. High Water"];
+
+lazy_static::lazy_static! {
+    static ref ROOTFILE_REGEX: Regex = Regex::new(r#"<rootfile\b[^>]*\sfull-path="([^"]+)""#).unwrap();
+    static ref ITEM_REGEX: Regex = Regex::new(r"<item\b[^>]*>").unwrap();
+    static ref ITEMREF_REGEX: Regex = Regex::new(r"<itemref\b[^>]*>").unwrap();
+    static ref SPINE_REGEX: Regex = Regex::new(r"<spine\b[^>]*>").unwrap();
+    static ref ATTRIBUTE_REGEX: Regex = Regex::new(r#"([\w:-]+)="([^"]*)""#).unwrap();
+    static ref NAV_POINT_REGEX: Regex = RegexBuilder::new(
+        r#"<navLabel>\s*<text>(.*?)</text>\s*</navLabel>\s*<content src="([^"]*)""#
+    )
+    .dot_matches_new_line(true)
+    .build()
+    .unwrap();
+    static ref CHAPTER_PAGE_REGEX: Regex = Regex::new(r"(^|/)c\d+\.xhtml$").unwrap();
+    static ref IMG_SRC_REGEX: Regex = Regex::new(r#"<img\b[^>]*?\ssrc="([^"]*)""#).unwrap();
+}
+
+fn page(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("epub")
+        .join(name);
+    fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+}
+
+fn png() -> Vec<u8> {
+    let mut data = Vec::new();
+    DynamicImage::ImageRgb8(RgbImage::new(8, 8))
+        .write_to(&mut data, ImageOutputFormat::Png)
+        .unwrap();
+    data
+}
+
+async fn build_book() -> Book {
+    build_book_without(&[], OnError::Abort).await
+}
+
+/// The book with the chapters numbered in `missing` gone from the site
+async fn build_book_without(missing: &[usize], on_error: OnError) -> Book {
+    let mut fetcher = MemoryFetcher::new()
+        .page(NOVEL_URL, page("novel.html"))
+        .file(COVER_URL, "image/png", png())
+        .file(IMAGE_URL, "image/png", png());
+    for (number, url) in (1..).zip(CHAPTER_URLS.iter()) {
+        if !missing.contains(&number) {
+            fetcher = fetcher.page(url, page(&format!("chapter-{}.html", number)));
+        }
+    }
+    let options = BookOptions {
+        fetcher: Some(Arc::new(fetcher)),
+        cache: false,
+        enrich: false,
+        respect_robots: false,
+        on_error,
+        ..BookOptions::default()
+    };
+    Book::from_url(NOVEL_URL, &options).await.unwrap()
+}
+
+/// The EPUB's files by name, in the order they're in the archive
+struct Epub {
+    names: Vec<String>,
+    files: HashMap<String, Vec<u8>>,
+    /// Whether the first file is stored without compression
+    first_stored: bool,
+}
+
+impl Epub {
+    fn build(book: &Book, options: &EpubOptions) -> Self {
+        let mut data = Vec::new();
+        book.build_epub_with(&mut data, options).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        let mut names = Vec::new();
+        let mut files = HashMap::new();
+        let mut first_stored = false;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).unwrap();
+            if i == 0 {
+                first_stored = file.compression() == CompressionMethod::Stored;
+            }
+            let mut content = Vec::new();
+            file.read_to_end(&mut content).unwrap();
+            names.push(file.name().to_string());
+            files.insert(file.name().to_string(), content);
+        }
+        Epub {
+            names,
+            files,
+            first_stored,
+        }
+    }
+
+    fn text(&self, name: &str) -> String {
+        let data = self
+            .files
+            .get(name)
+            .unwrap_or_else(|| panic!("The EPUB has no {}", name));
+        String::from_utf8(data.clone()).unwrap_or_else(|_| panic!("{} isn't UTF-8", name))
+    }
+}
+
+/// An `<item>` of the package document's manifest
+struct Item {
+    id: String,
+    /// Path in the archive
+    path: String,
+    media_type: String,
+    properties: String,
+}
+
+/// The package document's manifest and spine
+struct Package {
+    document: String,
+    manifest: Vec<Item>,
+    /// Paths of the spine's pages in reading order
+    spine: Vec<String>,
+    /// Path of the NCX table of contents
+    ncx: String,
+}
+
+fn attributes(tag: &str) -> HashMap<String, String> {
+    ATTRIBUTE_REGEX
+        .captures_iter(tag)
+        .map(|capture| (capture[1].to_string(), decode_entities(&capture[2])))
+        .collect()
+}
+
+fn read_package(epub: &Epub) -> Package {
+    let container = epub.text("META-INF/container.xml");
+    let path = ROOTFILE_REGEX
+        .captures(&container)
+        .expect("container.xml doesn't point at the package document")[1]
+        .to_string();
+    let document = epub.text(&path);
+    let dir = match path.rfind('/') {
+        Some(end) => &path[..=end],
+        None => "",
+    };
+
+    let manifest: Vec<Item> = ITEM_REGEX
+        .find_iter(&document)
+        .map(|tag| {
+            let mut attributes = attributes(tag.as_str());
+            let mut take = |name: &str| attributes.remove(name).unwrap_or_default();
+            Item {
+                id: take("id"),
+                path: format!("{}{}", dir, take("href")),
+                media_type: take("media-type"),
+                properties: take("properties"),
+            }
+        })
+        .collect();
+    let by_id: HashMap<&str, &Item> = manifest
+        .iter()
+        .map(|item| (item.id.as_str(), item))
+        .collect();
+    let spine = ITEMREF_REGEX
+        .find_iter(&document)
+        .map(|tag| {
+            let idref = attributes(tag.as_str())
+                .remove("idref")
+                .expect("A spine itemref has no idref");
+            let item = by_id
+                .get(idref.as_str())
+                .unwrap_or_else(|| panic!("The spine's {} isn't in the manifest", idref));
+            assert_eq!(
+                item.media_type, "application/xhtml+xml",
+                "The spine's {} isn't a page",
+                item.path
+            );
+            item.path.clone()
+        })
+        .collect();
+    let toc = SPINE_REGEX
+        .find(&document)
+        .and_then(|tag| attributes(tag.as_str()).remove("toc"))
+        .expect("The spine doesn't point at the NCX");
+    let ncx = by_id
+        .get(toc.as_str())
+        .unwrap_or_else(|| panic!("The NCX {} isn't in the manifest", toc))
+        .path
+        .clone();
+    Package {
+        document,
+        manifest,
+        spine,
+        ncx,
+    }
+}
+
+/// Title and path of every entry in the NCX table of contents, in order
+fn toc(epub: &Epub, package: &Package) -> Vec<(String, String)> {
+    let ncx = epub.text(&package.ncx);
+    let dir = match package.ncx.rfind('/') {
+        Some(end) => &package.ncx[..=end],
+        None => "",
+    };
+    NAV_POINT_REGEX
+        .captures_iter(&ncx)
+        .map(|capture| {
+            let src = capture[2].split('#').next().unwrap_or_default();
+            (
+                decode_entities(capture[1].trim()),
+                format!("{}{}", dir, src),
+            )
+        })
+        .collect()
+}
+
+/// What every EPUB has to get right for readers to open it
+fn check_structure(epub: &Epub, version: EpubVersion) -> Package {
+    assert_eq!(epub.names[0], "mimetype", "The mimetype has to come first");
+    assert!(
+        epub.first_stored,
+        "The mimetype has to be stored uncompressed"
+    );
+    assert_eq!(epub.text("mimetype"), "application/epub+zip");
+
+    let package = read_package(epub);
+    let expected_version = match version {
+        EpubVersion::V2 => r#"version="2.0""#,
+        EpubVersion::V3 => r#"version="3.0""#,
+    };
+    assert!(
+        package.document.contains(expected_version),
+        "The package document isn't {}",
+        expected_version
+    );
+
+    let mut ids = HashSet::new();
+    let mut paths = HashSet::new();
+    for item in &package.manifest {
+        assert!(ids.insert(&item.id), "{} is in the manifest twice", item.id);
+        assert!(
+            paths.insert(item.path.as_str()),
+            "{} is in the manifest twice",
+            item.path
+        );
+        assert!(
+            epub.files.contains_key(&item.path),
+            "The manifest's {} isn't in the EPUB",
+            item.path
+        );
+    }
+    for name in &epub.names {
+        let packaging =
+            name == "mimetype" || name.starts_with("META-INF/") || name.ends_with(".opf");
+        assert!(
+            packaging || paths.contains(name.as_str()),
+            "{} isn't in the manifest",
+            name
+        );
+    }
+
+    let navs = package
+        .manifest
+        .iter()
+        .filter(|item| {
+            item.properties
+                .split_whitespace()
+                .any(|property| property == "nav")
+        })
+        .count();
+    match version {
+        EpubVersion::V2 => assert_eq!(navs, 0, "EPUB 2 has no navigation document"),
+        EpubVersion::V3 => assert_eq!(navs, 1, "EPUB 3 needs one navigation document"),
+    }
+
+    for (title, path) in toc(epub, &package) {
+        assert!(
+            paths.contains(path.as_str()),
+            "The table of contents' {} points at {}, which isn't in the manifest",
+            title,
+            path
+        );
+    }
+
+    for name in &epub.names {
+        if name.ends_with(".xhtml") || name.ends_with(".opf") || name.ends_with(".ncx") {
+            if let Err(err) = check_xhtml(&epub.text(name)) {
+                panic!("{} isn't well-formed: {}", name, err);
+            }
+        }
+    }
+    package
+}
+
+fn chapter_paths(package: &Package) -> Vec<&str> {
+    package
+        .spine
+        .iter()
+        .map(String::as_str)
+        .filter(|path| CHAPTER_PAGE_REGEX.is_match(path))
+        .collect()
+}
+
+/// Titles of the chapters in the table of contents, in order
+fn chapter_titles(epub: &Epub, package: &Package) -> Vec<String> {
+    toc(epub, package)
+        .into_iter()
+        .filter(|(_, path)| chapter_paths(package).contains(&path.as_str()))
+        .map(|(title, _)| title)
+        .collect()
+}
+
+/// How many images are in the manifest, the cover's included
+fn image_count(package: &Package) -> usize {
+    package
+        .manifest
+        .iter()
+        .filter(|item| item.media_type.starts_with("image/"))
+        .count()
+}
+
+#[tokio::test]
+async fn epub3() {
+    let book = build_book().await;
+    let epub = Epub::build(&book, &EpubOptions::default());
+    let package = check_structure(&epub, EpubVersion::V3);
+
+    let pages: Vec<&str> = package
+        .spine
+        .iter()
+        .map(|path| path.rsplit('/').next().unwrap_or(path))
+        .collect();
+    assert_eq!(
+        pages,
+        [
+            "title.xhtml",
+            "contents.xhtml",
+            "c1.xhtml",
+            "c2.xhtml",
+            "c3.xhtml"
+        ]
+    );
+    let chapters: Vec<(String, String)> = toc(&epub, &package)
+        .into_iter()
+        .filter(|(_, path)| chapter_paths(&package).contains(&path.as_str()))
+        .collect();
+    let expected: Vec<(String, String)> = CHAPTER_TITLES
+        .iter()
+        .zip(chapter_paths(&package))
+        .map(|(title, path)| (title.to_string(), path.to_string()))
+        .collect();
+    assert_eq!(chapters, expected);
+
+    let document = &package.document;
+    assert!(document.contains("<dc:title>The Tidewater Ledger</dc:title>"));
+    assert!(document.contains("Odile Marsh</dc:creator>"));
+    assert!(document.contains("<dc:language>en</dc:language>"));
+    assert!(
+        document.contains(&book_identifier(NOVEL_URL)),
+        "The book's identifier isn't the one made from its url"
+    );
+    assert!(
+        package
+            .manifest
+            .iter()
+            .any(|item| item.properties.contains("cover-image") && item.media_type == "image/png"),
+        "The cover isn't in the manifest"
+    );
+
+    // The chapter's image is in the book, where the chapter points
+    let chapter = &chapter_paths(&package)[1];
+    let dir = &chapter[..chapter.rfind('/').map_or(0, |end| end + 1)];
+    let content = epub.text(chapter);
+    let srcs: Vec<String> = IMG_SRC_REGEX
+        .captures_iter(&content)
+        .map(|capture| format!("{}{}", dir, &capture[1]))
+        .collect();
+    assert_eq!(srcs.len(), 1, "Chapter 2's image is missing");
+    let image = package
+        .manifest
+        .iter()
+        .find(|item| item.path == srcs[0])
+        .unwrap_or_else(|| panic!("Chapter 2's image {} isn't in the manifest", srcs[0]));
+    assert_eq!(image.media_type, "image/png");
+}
+
+#[tokio::test]
+async fn epub2() {
+    let book = build_book().await;
+    let options = EpubOptions {
+        version: EpubVersion::V2,
+        title_page: false,
+        inline_toc: None,
+        ..EpubOptions::default()
+    };
+    let epub = Epub::build(&book, &options);
+    let package = check_structure(&epub, EpubVersion::V2);
+
+    assert_eq!(chapter_paths(&package).len(), 3);
+    assert_eq!(
+        package.spine,
+        chapter_paths(&package),
+        "Only the chapters should be in the spine"
+    );
+    for path in chapter_paths(&package) {
+        assert!(
+            epub.text(path).contains("XHTML 1.1"),
+            "{} isn't XHTML 1.1",
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn placeholder_stands_in_for_a_missing_chapter() {
+    let book = build_book_without(&[2], OnError::Placeholder).await;
+    assert_eq!(book.skipped.len(), 1);
+    let epub = Epub::build(&book, &EpubOptions::default());
+    let package = check_structure(&epub, EpubVersion::V3);
+
+    let chapters = chapter_paths(&package);
+    assert_eq!(chapters.len(), 3, "The placeholder isn't in the spine");
+    assert_eq!(
+        chapter_titles(&epub, &package),
+        [CHAPTER_TITLES[0], &placeholder_title(2), CHAPTER_TITLES[2]]
+    );
+    let placeholder = epub.text(chapters[1]);
+    assert!(
+        placeholder.contains(&format!(r#"href="{}""#, CHAPTER_URLS[1])),
+        "The placeholder doesn't link to the chapter: {}",
+        placeholder
+    );
+    // Only the cover, the image was in the missing chapter
+    assert_eq!(image_count(&package), 1);
+}
+
+#[tokio::test]
+async fn split_every_makes_a_book_of_each_volume() {
+    let volumes = build_book().await.into_volumes(2);
+    assert_eq!(volumes.len(), 2);
+
+    let mut identifiers = HashSet::new();
+    let mut titles = Vec::new();
+    let mut images = Vec::new();
+    for (number, volume) in (1..).zip(&volumes) {
+        let epub = Epub::build(volume, &EpubOptions::default());
+        let package = check_structure(&epub, EpubVersion::V3);
+        assert!(
+            package.document.contains(&format!(
+                "<dc:title>The Tidewater Ledger (Volume {})</dc:title>",
+                number
+            )),
+            "Volume {} isn't titled as one",
+            number
+        );
+        assert!(
+            identifiers.insert(volume.identifier.clone()),
+            "Volume {} has the identifier of another one",
+            number
+        );
+        titles.push(chapter_titles(&epub, &package));
+        images.push(image_count(&package));
+    }
+    assert!(!identifiers.contains(&book_identifier(NOVEL_URL)));
+    assert_eq!(
+        titles,
+        [
+            vec![CHAPTER_TITLES[0], CHAPTER_TITLES[1]],
+            vec![CHAPTER_TITLES[2]]
+        ]
+    );
+    // Every volume has the cover, chapter 2's image is only in the first
+    assert_eq!(images, [2, 1]);
+}
+
+#[tokio::test]
+async fn update_reads_the_book_back() {
+    let book = build_book().await;
+    let file = tempfile::NamedTempFile::new().unwrap();
+    book.build_epub(file.as_file()).unwrap();
+
+    let existing = ExistingEpub::open(file.path()).unwrap();
+    assert_eq!(existing.len(), 3);
+    assert!((1..=3).all(|number| existing.contains(number, CHAPTER_URLS[number - 1])));
+    assert_eq!(
+        existing.identifier(),
+        Some(book_identifier(NOVEL_URL).as_str())
+    );
+}
+
+#[tokio::test]
+async fn update_renumbers_chapters_after_an_inserted_one() {
+    let book = build_book().await;
+    let file = tempfile::NamedTempFile::new().unwrap();
+    book.build_epub(file.as_file()).unwrap();
+
+    // The site puts a chapter between the first two. Only it can be
+    // downloaded, the others have to come out of the EPUB.
+    let inserted_url =
+        "https://www.royalroad.com/fiction/51730/the-tidewater-ledger/chapter/1004/1-5-slack-water";
+    let second_row =
+        r#"<tr class="chapter-row"><td><a href="/fiction/51730/the-tidewater-ledger/chapter/1002/"#;
+    let novel = page("novel.html").replacen(
+        second_row,
+        &format!(
+            r#"<tr class="chapter-row"><td><a href="{}">1.5. Slack Water</a></td><td><time>1 day ago</time></td></tr>
+{}"#,
+            &inserted_url["https://www.royalroad.com".len()..],
+            second_row
+        ),
+        1,
+    );
+    let fetcher = MemoryFetcher::new()
+        .page(NOVEL_URL, novel)
+        .file(COVER_URL, "image/png", png())
+        .page(
+            inserted_url,
+            page("chapter-1.html").replace("1. Low Tide", "1.5. Slack Water"),
+        );
+    let options = BookOptions {
+        fetcher: Some(Arc::new(fetcher)),
+        cache: false,
+        enrich: false,
+        respect_robots: false,
+        update: Some(Arc::new(ExistingEpub::open(file.path()).unwrap())),
+        ..BookOptions::default()
+    };
+    let updated = Book::from_url(NOVEL_URL, &options).await.unwrap();
+
+    let chapters: Vec<(usize, &str, &str)> = updated
+        .chapters
+        .iter()
+        .map(|chapter| (chapter.number, chapter.url.as_str(), chapter.title.as_str()))
+        .collect();
+    assert_eq!(
+        chapters,
+        [
+            (1, CHAPTER_URLS[0], CHAPTER_TITLES[0]),
+            (2, inserted_url, "1.5. Slack Water"),
+            (3, CHAPTER_URLS[1], CHAPTER_TITLES[1]),
+            (4, CHAPTER_URLS[2], CHAPTER_TITLES[2]),
+        ]
+    );
+    assert!(
+        updated.chapters[2]
+            .content()
+            .unwrap()
+            .contains("The ink was brown with age."),
+        "Chapter 3 doesn't have what was chapter 2"
+    );
+
+    let epub = Epub::build(&updated, &EpubOptions::default());
+    let package = check_structure(&epub, EpubVersion::V3);
+    assert_eq!(chapter_paths(&package).len(), 4);
+}
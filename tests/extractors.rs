@@ -0,0 +1,432 @@
+//! Runs every extractor on pages saved from its site and compares what it
+//! finds with what it found when they were saved, so a selector or regex that
+//! stops matching fails here instead of making a broken book.
+//!
+//! Each site has a directory in `tests/fixtures` with a `fixture.toml`:
+//!
+//! ```toml
+//! url = "https://boxnovel.com/novel/some-novel/"
+//! chapter = "https://boxnovel.com/novel/some-novel/chapter-1/"
+//! # Only needed when the url doesn't tell which site it is
+//! site_type = "madara"
+//! # Or a script, config or plugin from the repository to use instead of a
+//! # built in extractor
+//! script = "extractors/madara.rhai"
+//! ```
+//!
+//! along with the pages fetched for it (`novel`, `list-1`, ... for the rest of
+//! the chapter list and `chapter`, as `.html` or `.json`) and `expected.json`.
+//! Pages the extractor fetches itself are saved as `fetched-1`, ... with
+//! `fetched.toml` saying which url each one is from.
+//! After changing an extractor on purpose,
+//! `BOX2EPUB_FIXTURES=bless cargo test --test extractors` rewrites
+//! `expected.json` from the saved pages. `BOX2EPUB_FIXTURES=record` downloads
+//! the pages from the live site again first, without scripts, iframes,
+//! comments and anything that looks like a token.
+
+use async_trait::async_trait;
+use box2epub::book::{normalize_site, DEFAULT_USER_AGENT};
+use box2epub::downloader::{Downloader, RetryPolicy};
+use box2epub::extractor::{find_plugin, ConfigExtractor, Extractor, PageRequest};
+use box2epub::extractor::{PluginExtractor, ScriptExtractor, SiteType};
+use box2epub::fetcher::{FetchError, Fetcher, MemoryFetcher, ReqwestFetcher};
+use box2epub::fetcher::{Request, Response};
+use box2epub::filters::ContentFilter;
+use ego_tree::{NodeId, NodeRef};
+use scraper::{Html, Node, Selector};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// How many pages of a chapter list are recorded, the first few are enough to
+/// see that following it works
+const MAX_LIST_PAGES: usize = 3;
+
+lazy_static::lazy_static! {
+    static ref JUNK_SELECTOR: Selector = Selector::parse(
+        r#"script, noscript, iframe, meta[name*="token"], input[name*="token"], input[name*="Token"]"#
+    )
+    .unwrap();
+    static ref BODY_SELECTOR: Selector = Selector::parse("body").unwrap();
+}
+
+#[derive(Deserialize)]
+struct Fixture {
+    url: String,
+    chapter: String,
+    site_type: Option<String>,
+    /// Paths relative to the repository
+    script: Option<String>,
+    config: Option<String>,
+    plugin: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    /// Compares with `expected.json`
+    Check,
+    /// Rewrites `expected.json`
+    Bless,
+    /// Downloads the pages again and rewrites `expected.json`
+    Record,
+}
+
+impl Mode {
+    fn from_env() -> Self {
+        match std::env::var("BOX2EPUB_FIXTURES").as_deref() {
+            Ok("bless") => Mode::Bless,
+            Ok("record") => Mode::Record,
+            Ok(mode) => panic!(
+                "Unknown BOX2EPUB_FIXTURES {}, expected bless or record",
+                mode
+            ),
+            Err(_) => Mode::Check,
+        }
+    }
+}
+
+/// The fixture's pages, read from its directory or recorded into it
+struct Pages {
+    dir: PathBuf,
+    mode: Mode,
+    downloader: Downloader,
+}
+
+impl Pages {
+    /// The page saved as `name`, `None` when there's none
+    async fn get(&self, name: &str, request: &PageRequest) -> Option<String> {
+        if self.mode == Mode::Record {
+            let page = match request {
+                PageRequest::Get(url) => self.downloader.get_text(url).await,
+                PageRequest::PostForm { url, form } => self.downloader.post_form(url, form).await,
+            }
+            .unwrap_or_else(|err| panic!("Couldn't record {}: {}", name, err));
+            let (page, extension) = sanitize(&page);
+            fs::write(self.dir.join(format!("{}.{}", name, extension)), &page).unwrap();
+            return Some(page);
+        }
+        saved_page(&self.dir, name)
+    }
+}
+
+fn saved_page(dir: &Path, name: &str) -> Option<String> {
+    ["html", "json"]
+        .iter()
+        .find_map(|extension| fs::read_to_string(dir.join(format!("{}.{}", name, extension))).ok())
+}
+
+/// Serves what the extractor fetched itself when the fixture was recorded
+fn fetched_pages(dir: &Path) -> MemoryFetcher {
+    let urls: BTreeMap<String, String> = match fs::read_to_string(dir.join("fetched.toml")) {
+        Ok(urls) => toml::from_str(&urls).unwrap(),
+        Err(_) => BTreeMap::new(),
+    };
+    urls.into_iter()
+        .fold(MemoryFetcher::new(), |fetcher, (url, name)| {
+            let page = saved_page(dir, &name).unwrap_or_else(|| panic!("No {} page", name));
+            fetcher.page(&url, page)
+        })
+}
+
+/// Fetches from the live site for the extractor while recording, and keeps
+/// what it got to be saved with the fixture
+struct Recorder {
+    fetcher: ReqwestFetcher,
+    /// Url, page and its extension
+    fetched: Mutex<Vec<(String, String, &'static str)>>,
+}
+
+impl Recorder {
+    fn save(&self, dir: &Path) {
+        let mut urls = BTreeMap::new();
+        for (number, (url, page, extension)) in self.fetched.lock().unwrap().iter().enumerate() {
+            let name = format!("fetched-{}", number + 1);
+            fs::write(dir.join(format!("{}.{}", name, extension)), page).unwrap();
+            urls.insert(url.clone(), name);
+        }
+        if !urls.is_empty() {
+            fs::write(dir.join("fetched.toml"), toml::to_string(&urls).unwrap()).unwrap();
+        }
+    }
+}
+
+#[async_trait]
+impl Fetcher for Recorder {
+    async fn fetch(&self, request: Request) -> Result<Response, FetchError> {
+        let url = request.url.clone();
+        let mut response = self.fetcher.fetch(request).await?;
+        if response.status.is_success() {
+            // The extractor sees what's saved, as with the fixture's own pages
+            let (page, extension) = sanitize(&String::from_utf8_lossy(&response.body));
+            response.body = page.clone().into_bytes();
+            self.fetched.lock().unwrap().push((url, page, extension));
+        }
+        Ok(response)
+    }
+}
+
+/// The page without anything that changes on every request or could identify
+/// who recorded it, and the extension it's saved with. JSON is pretty printed.
+fn sanitize(page: &str) -> (String, &'static str) {
+    if let Ok(json) = serde_json::from_str::<Value>(page) {
+        return (serde_json::to_string_pretty(&json).unwrap(), "json");
+    }
+    // Bits of pages, i.e. a chapter's text from an API, stay bits
+    let fragment = !page.to_ascii_lowercase().contains("<html");
+    let mut document = if fragment {
+        Html::parse_fragment(page)
+    } else {
+        Html::parse_document(page)
+    };
+    let junk: Vec<NodeId> = document
+        .select(&JUNK_SELECTOR)
+        .map(|element| element.id())
+        .chain(
+            document
+                .tree
+                .root()
+                .descendants()
+                .filter(|node| matches!(node.value(), Node::Comment(_)))
+                .map(|node| node.id()),
+        )
+        .collect();
+    for id in junk {
+        if let Some(mut node) = document.tree.get_mut(id) {
+            node.detach();
+        }
+    }
+    let root = document.root_element();
+    let page = if fragment {
+        root.inner_html()
+    } else {
+        root.html()
+    };
+    (page, "html")
+}
+
+/// The chapter's body a line for each of its blocks. Attributes are sorted,
+/// scraper keeps them in a `HashMap` so their order changes from run to run.
+fn body_lines(html: &str) -> Vec<String> {
+    let document = Html::parse_document(html);
+    let body = match document.select(&BODY_SELECTOR).next() {
+        Some(body) => body,
+        None => return Vec::new(),
+    };
+    body.children()
+        .map(|child| {
+            let mut line = String::new();
+            serialize(child, &mut line);
+            line.trim().to_string()
+        })
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+fn serialize(node: NodeRef<Node>, out: &mut String) {
+    match node.value() {
+        Node::Text(text) => out.push_str(&escape(text)),
+        Node::Element(element) => {
+            out.push('<');
+            out.push_str(element.name());
+            let mut attrs: Vec<(&str, &str)> = element.attrs().collect();
+            attrs.sort_unstable();
+            for (name, value) in attrs {
+                out.push_str(&format!(
+                    r#" {}="{}""#,
+                    name,
+                    escape(value).replace('"', "&quot;")
+                ));
+            }
+            if !node.has_children() {
+                out.push_str("/>");
+                return;
+            }
+            out.push('>');
+            for child in node.children() {
+                serialize(child, out);
+            }
+            out.push_str(&format!("</{}>", element.name()));
+        }
+        _ => {}
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap() + "\n"
+}
+
+async fn check_fixture(name: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+    let fixture: Fixture =
+        toml::from_str(&fs::read_to_string(dir.join("fixture.toml")).unwrap()).unwrap();
+    let mode = Mode::from_env();
+    let site = normalize_site(&fixture.url);
+    let in_repository = |path: &str| Path::new(env!("CARGO_MANIFEST_DIR")).join(path);
+    let extractor: Arc<dyn Extractor + Send + Sync> = if let Some(script) = &fixture.script {
+        Arc::new(ScriptExtractor::from_file(in_repository(script), &site).unwrap())
+    } else if let Some(config) = &fixture.config {
+        Arc::new(ConfigExtractor::from_file(in_repository(config), &site).unwrap())
+    } else if let Some(plugin) = &fixture.plugin {
+        Arc::new(PluginExtractor::from_file(in_repository(plugin), &site).unwrap())
+    } else if let Some(site_type) = &fixture.site_type {
+        site_type.parse::<SiteType>().unwrap().extractor(&site)
+    } else {
+        SiteType::from_url(&site)
+            .expect("The url doesn't tell which site it is")
+            .extractor(&site)
+    };
+
+    let (fetcher, recorder) = if mode == Mode::Record {
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            if (matches!(extension, Some("html") | Some("json"))
+                && !path.ends_with("expected.json"))
+                || path.ends_with("fetched.toml")
+            {
+                fs::remove_file(path).unwrap();
+            }
+        }
+        let client = reqwest::Client::builder()
+            .user_agent(DEFAULT_USER_AGENT)
+            .build()
+            .unwrap();
+        let recorder = Arc::new(Recorder {
+            fetcher: ReqwestFetcher::new(client.clone()),
+            fetched: Mutex::new(Vec::new()),
+        });
+        (
+            Arc::new(ReqwestFetcher::new(client)) as Arc<_>,
+            Some(recorder),
+        )
+    } else {
+        (Arc::new(MemoryFetcher::new()) as Arc<_>, None)
+    };
+    let pages = Pages {
+        dir: dir.clone(),
+        mode,
+        downloader: Downloader::new(fetcher, RetryPolicy::default()),
+    };
+    // The one the extractor is handed for what it fetches itself
+    let extractor_fetcher = match &recorder {
+        Some(recorder) => recorder.clone() as Arc<dyn Fetcher>,
+        None => Arc::new(fetched_pages(&dir)) as Arc<_>,
+    };
+    let downloader = &Downloader::new(extractor_fetcher, RetryPolicy::default());
+
+    let request = extractor
+        .overview_request()
+        .unwrap_or_else(|| PageRequest::Get(site.clone()));
+    let mut home = pages.get("novel", &request).await.expect("No novel page");
+    if let Some(url) = extractor.proceed_url(&home) {
+        home = pages
+            .get("novel", &PageRequest::Get(url))
+            .await
+            .expect("No novel page");
+    }
+    let mut overview = extractor.extract_overview(&home, downloader).await.unwrap();
+    let mut next_request = extractor.next_chapter_list_request(&home);
+    for page in 1..=MAX_LIST_PAGES {
+        let html = match &next_request {
+            Some(request) => match pages.get(&format!("list-{}", page), request).await {
+                Some(html) => html,
+                None => break,
+            },
+            None => break,
+        };
+        extractor
+            .extract_chapter_list(&html, &mut overview, downloader)
+            .await
+            .unwrap();
+        next_request = extractor.next_chapter_list_request(&html);
+    }
+
+    let request = extractor.chapter_request(&fixture.chapter);
+    let html = pages
+        .get("chapter", &request)
+        .await
+        .expect("No chapter page");
+    let chapter = extractor.extract_chapter(&html, downloader).await.unwrap();
+    let filter = ContentFilter::new(extractor.strip_rules(), &[]);
+    let found = json!({
+        "overview": overview,
+        "chapter": {
+            "title": chapter.title,
+            "clean_title": extractor.clean_chapter_title(&chapter.title, &overview.title),
+            "content": body_lines(&filter.apply(&chapter.content)),
+        },
+    });
+
+    if let Some(recorder) = recorder {
+        recorder.save(&dir);
+    }
+    let expected_file = dir.join("expected.json");
+    if mode != Mode::Check {
+        fs::write(&expected_file, pretty(&found)).unwrap();
+        return;
+    }
+    let expected: Value = serde_json::from_str(
+        &fs::read_to_string(&expected_file)
+            .unwrap_or_else(|_| panic!("No expected.json for {}, bless it first", name)),
+    )
+    .unwrap();
+    assert_eq!(
+        pretty(&found),
+        pretty(&expected),
+        "The {} extractor found something else than when the fixture was saved, \
+         run with BOX2EPUB_FIXTURES=bless if that's on purpose",
+        name
+    );
+}
+
+macro_rules! fixtures {
+    ($($name:ident),*) => {
+        $(
+            #[tokio::test]
+            async fn $name() {
+                check_fixture(stringify!($name)).await;
+            }
+        )*
+    };
+}
+
+fixtures!(
+    ao3,
+    config,
+    fanfiction,
+    generic,
+    madara,
+    plugin,
+    royalroad,
+    scribblehub,
+    script,
+    syosetu,
+    wattpad,
+    wuxiaworld
+);
+
+#[tokio::test]
+async fn a_plugin_that_never_returns_is_stopped() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join("plugins");
+    let plugin = find_plugin(&dir, "https://endless.example/novel/1/").expect("No plugin");
+    let downloader = Downloader::new(Arc::new(MemoryFetcher::new()), RetryPolicy::default());
+    match plugin.extract_chapter("<p>Text</p>", &downloader).await {
+        Ok(_) => panic!("The plugin returned"),
+        Err(err) => assert!(err.to_string().contains("ran for too long"), "{}", err),
+    }
+}
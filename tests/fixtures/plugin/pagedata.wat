@@ -0,0 +1,87 @@
+;; A plugin for a site that puts each page's details in a
+;; `<script id="page-data" type="application/json">`, already as the JSON
+;; plugins return. Compiled to pagedata.wasm with `wat2wasm pagedata.wat`.
+(module
+  (memory (export "memory") 1)
+  ;; Pages are copied in after the strings
+  (global $heap (mut i32) (i32.const 1024))
+  (data (i32.const 0) "[\"moonpier.example.net\"]")
+  (data (i32.const 64) "<script id=\"page-data\" type=\"application/json\">")
+  (data (i32.const 128) "</script>")
+  (data (i32.const 160) "{\"error\": \"The page has no page-data script\"}")
+
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local $end i32)
+    (local.set $ptr (global.get $heap))
+    (local.set $end (i32.add (local.get $ptr) (local.get $len)))
+    (global.set $heap (local.get $end))
+    ;; Grow the memory by the 64KiB pages missing for it to fit
+    (if (i32.gt_u (local.get $end) (i32.mul (memory.size) (i32.const 65536)))
+      (then
+        (drop (memory.grow
+          (i32.div_u
+            (i32.add
+              (i32.sub (local.get $end) (i32.mul (memory.size) (i32.const 65536)))
+              (i32.const 65535))
+            (i32.const 65536))))))
+    (local.get $ptr))
+
+  (func (export "domains") (result i64)
+    (call $pack (i32.const 0) (i32.const 24)))
+
+  (func (export "extract_overview") (param $ptr i32) (param $len i32) (result i64)
+    (call $page_data (local.get $ptr) (local.get $len)))
+
+  (func (export "extract_chapter") (param $ptr i32) (param $len i32) (result i64)
+    (call $page_data (local.get $ptr) (local.get $len)))
+
+  ;; What's in the page-data script, or the error
+  (func $page_data (param $ptr i32) (param $len i32) (result i64)
+    (local $at i32)
+    (local $start i32)
+    (local.set $at
+      (call $find (local.get $ptr) (local.get $len) (i32.const 64) (i32.const 47)))
+    (if (i32.lt_s (local.get $at) (i32.const 0))
+      (then (return (call $pack (i32.const 160) (i32.const 45)))))
+    (local.set $start (i32.add (local.get $ptr) (i32.add (local.get $at) (i32.const 47))))
+    (local.set $at
+      (call $find
+        (local.get $start)
+        (i32.sub (local.get $len) (i32.add (local.get $at) (i32.const 47)))
+        (i32.const 128)
+        (i32.const 9)))
+    (if (i32.lt_s (local.get $at) (i32.const 0))
+      (then (return (call $pack (i32.const 160) (i32.const 45)))))
+    (call $pack (local.get $start) (local.get $at)))
+
+  ;; Where the needle first is in the haystack, -1 when it isn't
+  (func $find (param $hay i32) (param $hay_len i32) (param $needle i32) (param $needle_len i32) (result i32)
+    (local $i i32)
+    (local $j i32)
+    (block $not_found
+      (loop $next_start
+        (br_if $not_found
+          (i32.gt_s
+            (i32.add (local.get $i) (local.get $needle_len))
+            (local.get $hay_len)))
+        (local.set $j (i32.const 0))
+        (block $mismatch
+          (loop $next_byte
+            (if (i32.eq (local.get $j) (local.get $needle_len))
+              (then (return (local.get $i))))
+            (br_if $mismatch
+              (i32.ne
+                (i32.load8_u (i32.add (local.get $hay) (i32.add (local.get $i) (local.get $j))))
+                (i32.load8_u (i32.add (local.get $needle) (local.get $j)))))
+            (local.set $j (i32.add (local.get $j) (i32.const 1)))
+            (br $next_byte)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next_start)))
+    (i32.const -1))
+
+  ;; A string's pointer and length, as plugins return them
+  (func $pack (param $ptr i32) (param $len i32) (result i64)
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len)))))
@@ -0,0 +1,35 @@
+;; A plugin whose extract_chapter never returns, compiled to endless.wasm with
+;; `wat2wasm endless.wat`
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "[\"endless.example\"]")
+  (func (export "alloc") (param i32) (result i32)
+    i32.const 1024)
+  (func (export "domains") (result i64)
+    i64.const 19)
+  (func (export "extract_overview") (param i32 i32) (result i64)
+    i64.const 19)
+  (func (export "extract_chapter") (param i32 i32) (result i64)
+    (loop $forever
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      (drop (i32.add (local.get 0) (local.get 1)))
+      br $forever)
+    i64.const 0))
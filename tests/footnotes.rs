@@ -0,0 +1,52 @@
+//! Checks how `Footnotes` turns notes in a chapter into EPUB 3 footnotes.
+
+use box2epub::footnotes::Footnotes;
+use box2epub::transform::ContentTransform;
+
+fn chapter(body: &str) -> String {
+    format!(
+        r#"<html xmlns="http://www.w3.org/1999/xhtml"><head><title>1. Low Tide</title></head><body>{}</body></html>"#,
+        body
+    )
+}
+
+#[test]
+fn notes_are_numbered_in_the_order_they_are_in() {
+    let html = Footnotes.apply(&chapter(
+        r#"<p>He drew his jian [TN: a straight sword] and <span class="tn">(TN: the ledger's)</span> seal.</p><p>Low tide (TL note: ebb).</p>"#,
+    ));
+    assert!(
+        html.contains(r#"xmlns:epub="http://www.idpf.org/2007/ops""#),
+        "{}",
+        html
+    );
+    let first = html.find(">1</a></sup>").expect("no first noteref");
+    let second = html.find(">2</a></sup>").expect("no second noteref");
+    let third = html.find(">3</a></sup>").expect("no third noteref");
+    assert!(first < second && second < third, "{}", html);
+    assert!(html.contains("<p>He drew his jian<sup>"), "{}", html);
+    assert!(
+        html.contains("1.</a> a straight sword</p></aside>"),
+        "{}",
+        html
+    );
+    assert!(html.contains("2.</a> the ledger's</p></aside>"), "{}", html);
+    assert!(html.contains("3.</a> ebb</p></aside>"), "{}", html);
+    assert!(!html.contains("TN:"), "{}", html);
+}
+
+#[test]
+fn private_use_characters_in_the_chapter_are_kept() {
+    let body = "<p>Runes \u{E000}0\u{E001} and \u{E001}\u{E000} [TN: old script]</p>";
+    let html = Footnotes.apply(&chapter(body));
+    assert!(
+        html.contains("<p>Runes \u{E000}0\u{E001} and \u{E001}\u{E000}<sup>"),
+        "{}",
+        html
+    );
+    assert!(html.contains("1.</a> old script</p></aside>"), "{}", html);
+
+    // Without a note the chapter is left alone
+    let body = "<p>Runes \u{E000}0\u{E001}</p>";
+    assert_eq!(Footnotes.apply(&chapter(body)), chapter(body));
+}
@@ -0,0 +1,98 @@
+//! Checks how `robots.txt` files are read: whose rules apply, which rule
+//! decides for a url and what comes of `Crawl-delay`.
+
+use box2epub::robots::{Robots, MAX_CRAWL_DELAY, ROBOTS_USER_AGENT};
+use reqwest::Url;
+use std::time::Duration;
+
+fn allows(robots: &Robots, path: &str) -> bool {
+    let url = Url::parse("https://example.com")
+        .unwrap()
+        .join(path)
+        .unwrap();
+    robots.allows(&url)
+}
+
+#[test]
+fn rules_for_box2epub_replace_the_ones_for_everyone() {
+    let robots = Robots::parse(
+        "User-agent: *\n\
+         Disallow: /novel/\n\
+         \n\
+         User-agent: Box2Epub\n\
+         Disallow: /search\n",
+        ROBOTS_USER_AGENT,
+    );
+    assert!(allows(&robots, "/novel/some-novel/"));
+    assert!(!allows(&robots, "/search?q=ledger"));
+
+    let robots = Robots::parse(
+        "User-agent: Googlebot\nDisallow: /\n\nUser-agent: *\nDisallow: /novel/\n",
+        ROBOTS_USER_AGENT,
+    );
+    assert!(!allows(&robots, "/novel/some-novel/"));
+    assert!(allows(&robots, "/"));
+}
+
+#[test]
+fn a_group_can_have_several_user_agents() {
+    let robots = Robots::parse(
+        "User-agent: Googlebot\nUser-agent: box2epub\nDisallow: /private/\n",
+        ROBOTS_USER_AGENT,
+    );
+    assert!(!allows(&robots, "/private/page"));
+}
+
+#[test]
+fn the_longest_rule_decides() {
+    let robots = Robots::parse(
+        "User-agent: *\n\
+         Disallow: /novel/\n\
+         Allow: /novel/some-novel/\n\
+         Disallow: /novel/some-novel/drafts\n\
+         Disallow: # nothing, allows everything\n",
+        ROBOTS_USER_AGENT,
+    );
+    assert!(!allows(&robots, "/novel/other-novel/"));
+    assert!(allows(&robots, "/novel/some-novel/chapter-1/"));
+    assert!(!allows(&robots, "/novel/some-novel/drafts/1"));
+
+    // Allow wins a tie
+    let robots = Robots::parse(
+        "User-agent: *\nDisallow: /novel\nAllow: /novel\n",
+        ROBOTS_USER_AGENT,
+    );
+    assert!(allows(&robots, "/novel/some-novel/"));
+}
+
+#[test]
+fn wildcards_and_anchors() {
+    let robots = Robots::parse(
+        "User-agent: *\nDisallow: /*/print$\nDisallow: /*?sort=\n",
+        ROBOTS_USER_AGENT,
+    );
+    assert!(!allows(&robots, "/novel/some-novel/print"));
+    assert!(allows(&robots, "/novel/some-novel/print/chapter-1"));
+    assert!(!allows(&robots, "/novel/?sort=new"));
+    assert!(allows(&robots, "/novel/?page=2"));
+}
+
+#[test]
+fn crawl_delay() {
+    let crawl_delay = |value: &str| {
+        Robots::parse(
+            &format!("User-agent: *\nCrawl-delay: {}\n", value),
+            ROBOTS_USER_AGENT,
+        )
+        .crawl_delay
+    };
+    assert_eq!(crawl_delay("2.5"), Some(Duration::from_millis(2500)));
+    assert_eq!(crawl_delay("0"), None);
+    assert_eq!(crawl_delay("-1"), None);
+    assert_eq!(crawl_delay("NaN"), None);
+    assert_eq!(crawl_delay("soon"), None);
+    // Waiting that long would never finish, or not even fit in a Duration
+    assert_eq!(crawl_delay("3600"), Some(MAX_CRAWL_DELAY));
+    assert_eq!(crawl_delay("1e30"), Some(MAX_CRAWL_DELAY));
+    assert_eq!(crawl_delay("inf"), Some(MAX_CRAWL_DELAY));
+}